@@ -4,7 +4,9 @@ use std::str::FromStr;
 #[cfg(feature = "serde")]
 use serde_crate::{Deserialize, Serialize};
 
-use crate::ToFoldedHeader;
+use crate::header::decode_header_value;
+use crate::rfc5322::format_phrase;
+use crate::{FromHeader, ToFoldedHeader};
 
 /// Represents an RFC 5322 Address
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -24,29 +26,29 @@ impl FromStr for Address {
     type Err = mailparse::MailParseError;
 
     fn from_str(val: &str) -> Result<Self, mailparse::MailParseError> {
-        let addrs = mailparse::addrparse(val)?.into_inner();
+        let mut addrs = Address::parse_list(val)?;
         if addrs.len() != 1 {
             return Err(mailparse::MailParseError::Generic(
                 "expected a single address",
             ));
         }
+        Ok(addrs.remove(0))
+    }
+}
 
-        match addrs.into_iter().next().unwrap() {
-            mailparse::MailAddr::Group(group) => Ok(Address::new_group(
+/// Encoded-words within the display names of mailboxes are decoded
+impl From<mailparse::MailAddr> for Address {
+    fn from(addr: mailparse::MailAddr) -> Address {
+        let mailbox = |info: mailparse::SingleInfo| Mailbox {
+            name: info.display_name.map(|name| decode_header_value(&name)),
+            address: info.addr,
+        };
+        match addr {
+            mailparse::MailAddr::Group(group) => Address::new_group(
                 group.group_name,
-                group
-                    .addrs
-                    .into_iter()
-                    .map(|i| Mailbox {
-                        name: i.display_name,
-                        address: i.addr,
-                    })
-                    .collect(),
-            )),
-            mailparse::MailAddr::Single(i) => Ok(Address::Mailbox(Mailbox {
-                name: i.display_name,
-                address: i.addr,
-            })),
+                group.addrs.into_iter().map(mailbox).collect(),
+            ),
+            mailparse::MailAddr::Single(info) => Address::Mailbox(mailbox(info)),
         }
     }
 }
@@ -57,6 +59,16 @@ impl Address {
         addr.as_ref().parse()
     }
 
+    /// Parses a comma-separated list of addresses, e.g. the content of a
+    /// "To" field as entered by a user: `A <a@x.org>, b@y.org`
+    pub fn parse_list(list: &str) -> Result<Vec<Address>, mailparse::MailParseError> {
+        Ok(mailparse::addrparse(list)?
+            .into_inner()
+            .into_iter()
+            .map(Address::from)
+            .collect())
+    }
+
     /// Shortcut function to make a new Mailbox with the given address
     /// [unstable]
     pub fn new_mailbox(address: String) -> Address {
@@ -97,6 +109,11 @@ impl fmt::Display for Address {
 
 /// Represents an RFC 5322 mailbox
 #[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(crate = "serde_crate")
+)]
 pub struct Mailbox {
     /// The given name for this address
     pub name: Option<String>,
@@ -125,10 +142,9 @@ impl Mailbox {
 impl fmt::Display for Mailbox {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match self.name {
-            Some(ref name) => {
-                if name.chars().all(|c| c.is_ascii_alphanumeric() || c == ' ') {
-                    write!(fmt, "{} <{}>", name, self.address)
-                } else {
+            Some(ref name) => match format_phrase(name) {
+                Some(phrase) => write!(fmt, "{} <{}>", phrase, self.address),
+                None => {
                     let s = encoded_words::encode(
                         name,
                         None,
@@ -137,7 +153,7 @@ impl fmt::Display for Mailbox {
                     );
                     write!(fmt, "{} <{}>", s, self.address)
                 }
-            }
+            },
             None => write!(fmt, "<{}>", self.address),
         }
     }
@@ -180,6 +196,15 @@ impl FromStr for Mailbox {
     }
 }
 
+/// Parses an address list with `Address::parse_list`
+impl FromHeader for Vec<Address> {
+    type Error = mailparse::MailParseError;
+
+    fn from_header(value: &str) -> Result<Vec<Address>, mailparse::MailParseError> {
+        Address::parse_list(value)
+    }
+}
+
 #[derive(Debug, Clone, Copy, thiserror::Error)]
 pub enum AddressFoldingError {
     #[error("Header value cannot be empty")]
@@ -235,6 +260,40 @@ mod tests {
         let name_addr =
             Mailbox::new_with_name("Joe Blogs".to_string(), "foo@example.org".to_string());
         assert_eq!(name_addr.to_string(), "Joe Blogs <foo@example.org>");
+
+        let named = |name: &str| {
+            Mailbox::new_with_name(name.to_string(), "foo@example.org".to_string()).to_string()
+        };
+        assert_eq!(
+            named("Jean-Luc O'Brien"),
+            "Jean-Luc O'Brien <foo@example.org>"
+        );
+        assert_eq!(
+            named("O'Brien, John"),
+            "\"O'Brien, John\" <foo@example.org>"
+        );
+        assert_eq!(
+            named("Joe Q. Public"),
+            "\"Joe Q. Public\" <foo@example.org>"
+        );
+        assert_eq!(
+            named("Joe \"The \\ Man\""),
+            "\"Joe \\\"The \\\\ Man\\\"\" <foo@example.org>"
+        );
+        assert_eq!(named(" Joe"), "\" Joe\" <foo@example.org>");
+        assert_eq!(
+            named("=?utf-8?q?x?="),
+            "\"=?utf-8?q?x?=\" <foo@example.org>"
+        );
+        assert_eq!(named("Jöe"), "=?utf-8?q?J=C3=B6e?= <foo@example.org>");
+        assert_eq!(
+            named("Joe\r\nBcc: x@example.org"),
+            "=?utf-8?q?Joe=0D=0ABcc=3A_x=40example=2Eorg?= <foo@example.org>"
+        );
+        for name in ["O'Brien, John", "Joe \"The \\ Man\"", " Joe"].iter() {
+            let parsed = named(name).parse::<Mailbox>().unwrap();
+            assert_eq!(parsed.name.as_deref(), Some(*name));
+        }
     }
 
     #[test]
@@ -320,4 +379,33 @@ mod tests {
 
         assert_eq!(s, "=?utf-8?q?=C3=A4_space?= <x@y.org>");
     }
+
+    #[test]
+    fn test_parse_list() {
+        let addresses = Address::parse_list("A <a@x.org>, b@y.org, team: c@z.org;").unwrap();
+        assert_eq!(
+            addresses,
+            vec![
+                Address::new_mailbox_with_name("A".to_string(), "a@x.org".to_string()),
+                Address::new_mailbox("b@y.org".to_string()),
+                Address::new_group(
+                    "team".to_string(),
+                    vec![Mailbox::new("c@z.org".to_string())]
+                ),
+            ]
+        );
+        assert!(Address::from_str("a@x.org, b@y.org").is_err());
+
+        let email = crate::EmailBuilder::new()
+            .from("joe@example.org")
+            .to_addresses(addresses)
+            .body("Hello")
+            .build()
+            .unwrap();
+        assert_eq!(email.envelope.to().len(), 3);
+        assert!(email
+            .message_to_string()
+            .unwrap()
+            .contains("To: A <a@x.org>, <b@y.org>, team: <c@z.org>;\r\n"));
+    }
 }
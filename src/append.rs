@@ -0,0 +1,199 @@
+use std::io::Write;
+
+use mailparse::MailHeaderMap;
+
+use crate::email_builder::Error;
+use crate::{HeaderMap, MimeMessage, MimeMultipartType};
+
+/// Writes a multipart/mixed message incrementally, one part at a time, e.g.
+/// while attachments are still arriving over the network.
+///
+/// Every part is followed by the next delimiter line and flushed right
+/// away, so an interrupted write can be inspected with `Recovery::inspect`
+/// and continued with `resume`. The message is only complete once `close` wrote
+/// the close delimiter.
+#[derive(Debug)]
+pub struct AppendWriter<W: Write> {
+    inner: W,
+    boundary: String,
+    parts: usize,
+}
+
+/// The state of a message written by an `AppendWriter`, as found by
+/// `Recovery::inspect`
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Recovery {
+    /// The multipart boundary of the message
+    pub boundary: String,
+    /// Length of the prefix holding the headers and the complete parts.
+    /// Anything after it is a truncated part and has to be cut off before
+    /// resuming.
+    pub complete_len: usize,
+    /// The number of complete parts
+    pub parts: usize,
+    /// Whether the message was closed
+    pub closed: bool,
+}
+
+impl<W: Write> AppendWriter<W> {
+    /// Starts a message by writing `headers`, with the Content-Type set to
+    /// multipart/mixed.
+    pub fn new(mut inner: W, headers: HeaderMap) -> Result<AppendWriter<W>, Error> {
+        let mut message = MimeMessage::new_blank_message();
+        message.headers = headers;
+        message.message_type = Some(MimeMultipartType::Mixed);
+        message.update_headers();
+
+        write!(
+            inner,
+            "{}\r\n\r\n--{}",
+            message.headers.to_block_string(),
+            message.boundary
+        )?;
+        inner.flush()?;
+        Ok(AppendWriter {
+            inner,
+            boundary: message.boundary,
+            parts: 0,
+        })
+    }
+
+    /// Continues an interrupted message, after `inner` was truncated to
+    /// `recovery.complete_len` bytes.
+    pub fn resume(inner: W, recovery: &Recovery) -> AppendWriter<W> {
+        AppendWriter {
+            inner,
+            boundary: recovery.boundary.clone(),
+            parts: recovery.parts,
+        }
+    }
+
+    /// The number of parts written so far
+    pub fn parts(&self) -> usize {
+        self.parts
+    }
+
+    /// Appends a part, including its headers
+    pub fn write_part(&mut self, part: &MimeMessage) -> Result<(), Error> {
+        write!(
+            self.inner,
+            "\r\n{}\r\n--{}",
+            part.as_string(),
+            self.boundary
+        )?;
+        self.inner.flush()?;
+        self.parts += 1;
+        Ok(())
+    }
+
+    /// Completes the message with the close delimiter, returning the
+    /// underlying writer
+    pub fn close(mut self) -> Result<W, Error> {
+        self.inner.write_all(b"--\r\n")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl Recovery {
+    /// Inspects a message written by an `AppendWriter`, which may have been
+    /// interrupted.
+    pub fn inspect(written: &str) -> Result<Recovery, Error> {
+        let (headers, body_start) =
+            mailparse::parse_headers(written.as_bytes()).map_err(Error::Parse)?;
+        let boundary = headers
+            .get_first_value("Content-Type")
+            .and_then(|value| {
+                mailparse::parse_content_type(&value)
+                    .params
+                    .remove("boundary")
+            })
+            .ok_or(Error::Parse(mailparse::MailParseError::Generic(
+                "missing multipart boundary",
+            )))?;
+
+        let delimiter = format!("\r\n--{}", boundary);
+        let mut recovery = Recovery {
+            boundary,
+            complete_len: body_start,
+            parts: 0,
+            closed: false,
+        };
+        // The first delimiter directly follows the empty body of the message.
+        let mut offset = body_start;
+        let mut first = true;
+        while let Some(position) = written[offset..].find(&delimiter[..]) {
+            let end = offset + position + delimiter.len();
+            let rest = &written[end..];
+            if rest.is_empty() || rest.starts_with('\r') {
+                recovery.complete_len = end;
+                if !first {
+                    recovery.parts += 1;
+                }
+                first = false;
+            } else if rest.starts_with("--") && !first {
+                recovery.complete_len = written.len();
+                recovery.parts += 1;
+                recovery.closed = true;
+                break;
+            }
+            offset = end;
+        }
+        Ok(recovery)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email_builder::text_part;
+    use crate::Header;
+
+    fn headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(Header::new("Subject".to_string(), "Upload".to_string()));
+        headers
+    }
+
+    #[test]
+    fn test_append_writer() {
+        let mut writer = AppendWriter::new(vec![], headers()).unwrap();
+        writer.write_part(&text_part("First")).unwrap();
+        writer.write_part(&text_part("Second")).unwrap();
+        let written = String::from_utf8(writer.close().unwrap()).unwrap();
+
+        let message = MimeMessage::parse(&written).unwrap();
+        assert_eq!(message.message_type, Some(MimeMultipartType::Mixed));
+        assert_eq!(message.children.len(), 2);
+        assert_eq!(message.children[1].body, "Second");
+        assert_eq!(message.as_string(), written);
+
+        let recovery = Recovery::inspect(&written).unwrap();
+        assert!(recovery.closed);
+        assert_eq!(recovery.parts, 2);
+    }
+
+    #[test]
+    fn test_resume() {
+        let mut writer = AppendWriter::new(vec![], headers()).unwrap();
+        writer.write_part(&text_part("First")).unwrap();
+        let mut written = String::from_utf8(writer.inner).unwrap();
+        // Simulate a crash halfway through the second part
+        written.push_str("\r\nContent-Type: text/pl");
+
+        let recovery = Recovery::inspect(&written).unwrap();
+        assert!(!recovery.closed);
+        assert_eq!(recovery.parts, 1);
+        written.truncate(recovery.complete_len);
+
+        let mut writer = AppendWriter::resume(written.into_bytes(), &recovery);
+        writer.write_part(&text_part("Second")).unwrap();
+        assert_eq!(writer.parts(), 2);
+        let written = String::from_utf8(writer.close().unwrap()).unwrap();
+
+        let message = MimeMessage::parse(&written).unwrap();
+        assert_eq!(message.children.len(), 2);
+        assert_eq!(message.children[0].body, "First");
+        assert_eq!(message.children[1].body, "Second");
+    }
+}
@@ -0,0 +1,176 @@
+use crate::body::{self, TextKind};
+use crate::email_builder::LINE_BREAKS_RE;
+use crate::{Header, MimeContentTypeHeader, MimeMessage};
+
+/// Where `MimeMessage::insert_banner` puts the banner
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum BannerPosition {
+    /// Above the content, e.g. for warnings
+    Top,
+    /// Below the content, e.g. for disclaimers
+    Bottom,
+}
+
+/// Adds the banner to every inline text and HTML part within `part`,
+/// returning the number of parts changed
+pub(crate) fn insert_banner(
+    part: &mut MimeMessage,
+    text: &str,
+    html: &str,
+    position: BannerPosition,
+) -> usize {
+    if !part.children.is_empty() {
+        return part
+            .children
+            .iter_mut()
+            .map(|child| insert_banner(child, text, html, position))
+            .sum();
+    }
+    let kind = match body::text_kind(part) {
+        Some(kind) => kind,
+        None => return 0,
+    };
+    let content = match body::decode_body(part).and_then(|content| decode_charset(part, &content)) {
+        Some(content) => content,
+        // Leave content we can't interpret untouched.
+        None => return 0,
+    };
+
+    let content = match kind {
+        TextKind::Plain => {
+            let text = LINE_BREAKS_RE.replace_all(text, "\r\n");
+            match position {
+                BannerPosition::Top => format!("{}\r\n\r\n{}", text, content),
+                BannerPosition::Bottom => format!("{}\r\n\r\n{}", content.trim_end(), text),
+            }
+        }
+        TextKind::Html => match position {
+            BannerPosition::Top => insert_after_body_start(&content, html),
+            BannerPosition::Bottom => body::insert_before_body_end(&content, html),
+        },
+    };
+    encode_utf8(part, &content);
+    1
+}
+
+/// The content of `part` decoded from its charset
+fn decode_charset(part: &MimeMessage, content: &[u8]) -> Option<String> {
+    let charset = body::header_value(part, "Content-Type")
+        .map(|value| mailparse::parse_content_type(&value).charset)
+        .unwrap_or_default();
+    if matches!(&charset.to_ascii_lowercase()[..], "" | "utf-8" | "us-ascii") {
+        return String::from_utf8(content.to_vec()).ok();
+    }
+    // mailparse knows the charsets, but only decodes whole messages
+    let mut raw = format!("Content-Type: text/plain; charset=\"{}\"\r\n\r\n", charset).into_bytes();
+    raw.extend_from_slice(content);
+    mailparse::parse_mail(&raw).ok()?.get_body().ok()
+}
+
+/// Replaces the body of `part` by `content`, declaring it as UTF-8
+fn encode_utf8(part: &mut MimeMessage, content: &str) {
+    let mut content_type = body::header_value(part, "Content-Type")
+        .and_then(|value| value.parse::<MimeContentTypeHeader>().ok())
+        .unwrap_or_else(|| "text/plain".parse().unwrap());
+    content_type
+        .params
+        .insert("charset".to_string(), "utf-8".to_string());
+    let mime = format!(
+        "{}/{}",
+        content_type.content_type.0, content_type.content_type.1
+    );
+    part.headers
+        .replace(Header::new_with_value("Content-Type".into(), content_type).unwrap());
+
+    let encoding = body::header_value(part, "Content-Transfer-Encoding")
+        .map(|value| value.trim().to_ascii_lowercase());
+    if matches!(
+        encoding.as_deref(),
+        Some("base64") | Some("quoted-printable")
+    ) {
+        body::encode_body(part, content.as_bytes());
+    } else {
+        let (encoding, encoded) = body::encode_content(content.as_bytes(), &mime);
+        part.body = encoded;
+        part.headers.replace(Header::new(
+            "Content-Transfer-Encoding".into(),
+            encoding.into(),
+        ));
+    }
+}
+
+/// Inserts `html` right after the opening body tag of `document`, or at the
+/// start if there is none.
+fn insert_after_body_start(document: &str, html: &str) -> String {
+    let lowercase = document.to_ascii_lowercase();
+    let position = lowercase
+        .find("<body")
+        .and_then(|start| lowercase[start..].find('>').map(|end| start + end + 1))
+        .unwrap_or(0);
+    let mut result = String::with_capacity(document.len() + html.len());
+    result.push_str(&document[..position]);
+    result.push_str(html);
+    result.push_str(&document[position..]);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_banner() {
+        let mut message = MimeMessage::parse(
+            "From: joe@external.example\r\n\
+             Content-Type: multipart/mixed; boundary=\"outer\"\r\n\
+             \r\n\
+             --outer\r\n\
+             Content-Type: multipart/alternative; boundary=\"inner\"\r\n\
+             \r\n\
+             --inner\r\n\
+             Content-Type: text/plain; charset=iso-8859-1; format=flowed\r\n\
+             Content-Transfer-Encoding: quoted-printable\r\n\
+             \r\n\
+             Gr=FC=DFe\r\n\
+             --inner\r\n\
+             Content-Type: text/html\r\n\
+             \r\n\
+             <html><body class=\"mail\"><p>Hello</p></body></html>\r\n\
+             --inner--\r\n\
+             --outer\r\n\
+             Content-Type: text/plain\r\n\
+             Content-Disposition: attachment; filename=\"notes.txt\"\r\n\
+             \r\n\
+             Notes\r\n\
+             --outer--\r\n",
+        )
+        .unwrap();
+        let changed = message.insert_banner(
+            "CAUTION: external sender",
+            "<p>CAUTION: external sender</p>",
+            BannerPosition::Top,
+        );
+        assert_eq!(changed, 2);
+
+        let alternative = &message.children[0];
+        let text = &alternative.children[0];
+        assert_eq!(
+            body::header_value(text, "Content-Type").unwrap(),
+            "text/plain; charset=utf-8; format=flowed"
+        );
+        assert_eq!(
+            String::from_utf8(body::decode_body(text).unwrap()).unwrap(),
+            "CAUTION: external sender\r\n\r\nGrüße"
+        );
+        assert_eq!(
+            alternative.children[1].body,
+            "<html><body class=\"mail\"><p>CAUTION: external sender</p><p>Hello</p></body></html>"
+        );
+        assert_eq!(message.children[1].body, "Notes");
+
+        message.insert_banner("Disclaimer", "<p>Disclaimer</p>", BannerPosition::Bottom);
+        assert!(message.children[0].children[1]
+            .body
+            .ends_with("<p>Disclaimer</p></body></html>"));
+    }
+}
@@ -0,0 +1,659 @@
+//! Helpers for rewriting the textual bodies of a message tree.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+#[cfg(feature = "async")]
+use futures_util::io::{AsyncRead, AsyncReadExt};
+
+use regex::{Captures, Regex};
+use uuid::Uuid;
+
+use crate::email_builder::Error;
+use crate::{Header, MimeMessage, MimeMultipartType, PartBuilder};
+
+lazy_static::lazy_static! {
+    static ref TAG_RE: Regex = Regex::new(r"(?is)<([a-z][a-z0-9]*)\b([^>]*)>").unwrap();
+    static ref ATTRIBUTE_RE: Regex = Regex::new(
+        r#"(?i)\b(src|href|background)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))"#
+    ).unwrap();
+    static ref CSS_URL_RE: Regex = Regex::new(r#"(?i)url\(\s*["']?([^"')\s]+)"#).unwrap();
+    static ref DATA_URI_RE: Regex = Regex::new(
+        r#"(?i)(\bsrc\s*=\s*["'])data:(image/[a-z0-9.+-]+);base64,([a-z0-9+/=\s]+)(["'])"#
+    ).unwrap();
+}
+
+/// Number of bytes which get base64 encoded at once when reading from a
+/// stream. A multiple of 3, so the encoded chunks can be concatenated.
+const BASE64_CHUNK_SIZE: usize = 48 * 1024;
+
+/// The kinds of textual body parts
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) enum TextKind {
+    Plain,
+    Html,
+}
+
+/// Returns the value of the first header called `name`, ignoring case
+pub(crate) fn header_value(part: &MimeMessage, name: &str) -> Option<String> {
+    part.headers.nth(name, 0).map(|header| header.get_value())
+}
+
+/// The lowercased MIME type of a part, defaulting to `text/plain` as per
+/// RFC 2045.
+pub(crate) fn mime_type(part: &MimeMessage) -> String {
+    match header_value(part, "Content-Type") {
+        Some(value) => mailparse::parse_content_type(&value).mimetype,
+        None => "text/plain".to_string(),
+    }
+}
+
+/// The kind of an inline text part, `None` for anything else
+pub(crate) fn text_kind(part: &MimeMessage) -> Option<TextKind> {
+    if part.is_attachment() {
+        return None;
+    }
+    match &mime_type(part)[..] {
+        "text/plain" => Some(TextKind::Plain),
+        "text/html" => Some(TextKind::Html),
+        _ => None,
+    }
+}
+
+/// The lowercased Content-Transfer-Encoding of a part
+fn transfer_encoding(part: &MimeMessage) -> String {
+    header_value(part, "Content-Transfer-Encoding")
+        .map(|value| value.trim().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+/// Undoes the transfer encoding of a part's body
+pub(crate) fn decode_body(part: &MimeMessage) -> Option<Vec<u8>> {
+    match &transfer_encoding(part)[..] {
+        "base64" => {
+            let compact: String = part
+                .body
+                .chars()
+                .filter(|c| !c.is_ascii_whitespace())
+                .collect();
+            base64::decode(&compact).ok()
+        }
+        "quoted-printable" => {
+            quoted_printable::decode(&part.body, quoted_printable::ParseMode::Robust).ok()
+        }
+        _ => Some(part.body.clone().into_bytes()),
+    }
+}
+
+/// Undoes the transfer encoding of a part's body into `writer`, a chunk or
+/// line at a time, returning the number of bytes written
+pub(crate) fn decode_body_to<W: Write>(part: &MimeMessage, mut writer: W) -> io::Result<u64> {
+    let mut written = 0;
+    match &transfer_encoding(part)[..] {
+        "base64" => {
+            // A multiple of 4, so every chunk decodes on its own
+            let chunk_size = BASE64_CHUNK_SIZE / 3 * 4;
+            let mut chunk = Vec::with_capacity(chunk_size);
+            let mut bytes = part
+                .body
+                .bytes()
+                .filter(|b| !b.is_ascii_whitespace())
+                .peekable();
+            while bytes.peek().is_some() {
+                chunk.clear();
+                chunk.extend(bytes.by_ref().take(chunk_size));
+                let decoded = base64::decode(&chunk)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                writer.write_all(&decoded)?;
+                written += decoded.len() as u64;
+            }
+        }
+        "quoted-printable" => {
+            let mut soft_break = true;
+            for line in part.body.split('\n') {
+                if !soft_break {
+                    writer.write_all(b"\r\n")?;
+                    written += 2;
+                }
+                let line = line.trim_end_matches(['\r', ' ', '\t']);
+                soft_break = line.ends_with('=');
+                let line = if soft_break {
+                    &line[..line.len() - 1]
+                } else {
+                    line
+                };
+                let decoded = decode_quoted_printable_line(line);
+                writer.write_all(&decoded)?;
+                written += decoded.len() as u64;
+            }
+        }
+        _ => {
+            writer.write_all(part.body.as_bytes())?;
+            written = part.body.len() as u64;
+        }
+    }
+    Ok(written)
+}
+
+/// Decodes a line of quoted-printable text without its line break,
+/// keeping malformed escapes as they are
+fn decode_quoted_printable_line(line: &str) -> Vec<u8> {
+    let bytes = line.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes.get(i + 1..i + 3) {
+            Some(hex) if bytes[i] == b'=' => std::str::from_utf8(hex)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Replaces a part's body, applying its transfer encoding
+pub(crate) fn encode_body(part: &mut MimeMessage, content: &[u8]) {
+    part.body = match &transfer_encoding(part)[..] {
+        "base64" => base64::encode(content),
+        "quoted-printable" => quoted_printable::encode_to_str(content),
+        _ => String::from_utf8_lossy(content).into_owned(),
+    };
+}
+
+/// Picks a transfer encoding for `content` of `content_type`, returning it
+/// together with the encoded body.
+///
+/// ASCII content with CRLF line breaks and lines of up to 998 characters is
+/// left as 7bit, other text is quoted-printable if that stays reasonably
+/// small, and anything else is base64 encoded.
+pub(crate) fn encode_content(content: &[u8], content_type: &str) -> (&'static str, String) {
+    if is_7bit(content) {
+        return ("7bit", String::from_utf8_lossy(content).into_owned());
+    }
+    if content_type.starts_with("text/") {
+        let non_ascii = content.iter().filter(|byte| !byte.is_ascii()).count();
+        // Quoted-printable triples every non-ASCII byte, beyond a sixth of
+        // the content base64 is shorter.
+        if non_ascii * 6 < content.len() {
+            return ("quoted-printable", quoted_printable::encode_to_str(content));
+        }
+    }
+    ("base64", base64::encode(content))
+}
+
+/// Picks a transfer encoding with `encode_content` for every leaf part of
+/// `part` which has none yet. `message/*` parts are left alone, as they
+/// must not be encoded.
+pub(crate) fn apply_transfer_encoding(part: &mut MimeMessage) {
+    if !part.children.is_empty() {
+        for child in part.children.iter_mut() {
+            apply_transfer_encoding(child);
+        }
+        return;
+    }
+    let content_type = mime_type(part);
+    if part.message_type.is_some()
+        || part.body_ref.is_some()
+        || content_type.starts_with("message/")
+        || header_value(part, "Content-Transfer-Encoding").is_some()
+    {
+        return;
+    }
+    let (encoding, body) = encode_content(part.body.as_bytes(), &content_type);
+    part.body = body;
+    part.headers.insert(Header::new(
+        "Content-Transfer-Encoding".into(),
+        encoding.into(),
+    ));
+}
+
+fn is_7bit(content: &[u8]) -> bool {
+    let mut line_len = 0;
+    for (i, &byte) in content.iter().enumerate() {
+        match byte {
+            b'\r' if content.get(i + 1) == Some(&b'\n') => line_len = 0,
+            b'\n' if i > 0 && content[i - 1] == b'\r' => {}
+            0 | b'\r' | b'\n' | 0x80..=0xff => return false,
+            _ => {
+                line_len += 1;
+                if line_len > 998 {
+                    return false;
+                }
+            }
+        }
+    }
+    true
+}
+
+/// Base64 encodes everything `reader` yields, one chunk at a time
+pub(crate) fn encode_base64_reader<R: Read>(mut reader: R) -> io::Result<String> {
+    let mut encoded = String::new();
+    let mut chunk = vec![0; BASE64_CHUNK_SIZE];
+    loop {
+        // Fill the whole chunk, so only the last one may need padding
+        let mut len = 0;
+        while len < chunk.len() {
+            match reader.read(&mut chunk[len..]) {
+                Ok(0) => break,
+                Ok(read) => len += read,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        base64::encode_config_buf(&chunk[..len], base64::STANDARD, &mut encoded);
+        if len < chunk.len() {
+            return Ok(encoded);
+        }
+    }
+}
+
+/// Base64 encodes everything `reader` yields, one chunk at a time
+#[cfg(feature = "async")]
+pub(crate) async fn encode_base64_async_reader<R: AsyncRead + Unpin>(
+    mut reader: R,
+) -> io::Result<String> {
+    let mut encoded = String::new();
+    let mut chunk = vec![0; BASE64_CHUNK_SIZE];
+    loop {
+        let mut len = 0;
+        while len < chunk.len() {
+            match reader.read(&mut chunk[len..]).await {
+                Ok(0) => break,
+                Ok(read) => len += read,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        base64::encode_config_buf(&chunk[..len], base64::STANDARD, &mut encoded);
+        if len < chunk.len() {
+            return Ok(encoded);
+        }
+    }
+}
+
+/// Calls `f` with the decoded content of every inline text/plain and
+/// text/html part, replacing the content with the result.
+///
+/// Returns the kinds of the parts which were visited.
+pub(crate) fn map_text_bodies(
+    message: &mut MimeMessage,
+    f: &mut dyn FnMut(TextKind, String) -> String,
+) -> Vec<TextKind> {
+    // The callback can't fail, so neither can the traversal.
+    try_map_text_bodies(message, &mut |kind, content| Ok(f(kind, content))).unwrap()
+}
+
+/// Like `map_text_bodies`, but stops at the first error returned by `f`.
+pub(crate) fn try_map_text_bodies(
+    message: &mut MimeMessage,
+    f: &mut dyn FnMut(TextKind, String) -> Result<String, Error>,
+) -> Result<Vec<TextKind>, Error> {
+    let mut visited = Vec::new();
+    map_text_bodies_internal(message, f, &mut visited)?;
+    Ok(visited)
+}
+
+fn map_text_bodies_internal(
+    part: &mut MimeMessage,
+    f: &mut dyn FnMut(TextKind, String) -> Result<String, Error>,
+    visited: &mut Vec<TextKind>,
+) -> Result<(), Error> {
+    if !part.children.is_empty() {
+        for child in part.children.iter_mut() {
+            map_text_bodies_internal(child, f, visited)?;
+        }
+        return Ok(());
+    }
+    let kind = match text_kind(part) {
+        Some(kind) => kind,
+        None => return Ok(()),
+    };
+    let content = match decode_body(part).map(String::from_utf8) {
+        Some(Ok(content)) => content,
+        // Leave content we can't interpret untouched.
+        _ => return Ok(()),
+    };
+
+    let content = f(kind, content)?;
+    encode_body(part, content.as_bytes());
+    visited.push(kind);
+    Ok(())
+}
+
+/// Escapes `s` for use in HTML text and attribute values
+pub(crate) fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Inserts `html` right before the closing body tag of `document`, or at the
+/// end if there is none.
+pub(crate) fn insert_before_body_end(document: &str, html: &str) -> String {
+    let position = document
+        .to_ascii_lowercase()
+        .rfind("</body>")
+        .unwrap_or(document.len());
+    let mut result = String::with_capacity(document.len() + html.len());
+    result.push_str(&document[..position]);
+    result.push_str(html);
+    result.push_str(&document[position..]);
+    result
+}
+
+/// Returns the decoded content of every inline text/plain and text/html
+/// part, with text/enriched parts converted to HTML.
+pub(crate) fn text_bodies(message: &MimeMessage) -> Vec<(TextKind, String)> {
+    let mut bodies = vec![];
+    text_bodies_internal(message, &mut bodies);
+    bodies
+}
+
+fn text_bodies_internal(part: &MimeMessage, bodies: &mut Vec<(TextKind, String)>) {
+    if !part.children.is_empty() {
+        for child in part.children.iter() {
+            text_bodies_internal(child, bodies);
+        }
+        return;
+    }
+    // text/enriched is checked like the HTML it converts to
+    let enriched = !part.is_attachment() && mime_type(part) == "text/enriched";
+    let kind = match text_kind(part) {
+        Some(kind) => kind,
+        None if enriched => TextKind::Html,
+        None => return,
+    };
+    if let Some(Ok(content)) = decode_body(part).map(String::from_utf8) {
+        if enriched {
+            bodies.push((kind, crate::enriched_to_html(&content)));
+        } else {
+            bodies.push((kind, content));
+        }
+    }
+}
+
+/// Collects the remote URLs an HTML document loads content from: `src`
+/// and `background` attributes, `<link href>` and CSS `url()`s.
+pub(crate) fn remote_urls(html: &str, urls: &mut Vec<String>) {
+    let mut push = |url: &str| {
+        let url = url.trim().replace("&amp;", "&");
+        let lower = url.to_ascii_lowercase();
+        let is_remote = lower.starts_with("http://")
+            || lower.starts_with("https://")
+            || lower.starts_with("//");
+        if is_remote && !urls.contains(&url) {
+            urls.push(url);
+        }
+    };
+
+    for tag in TAG_RE.captures_iter(html) {
+        let is_link = tag[1].eq_ignore_ascii_case("link");
+        for attribute in ATTRIBUTE_RE.captures_iter(&tag[2]) {
+            if attribute[1].eq_ignore_ascii_case("href") && !is_link {
+                continue;
+            }
+            if let Some(value) = attribute
+                .get(2)
+                .or_else(|| attribute.get(3))
+                .or_else(|| attribute.get(4))
+            {
+                push(value.as_str());
+            }
+        }
+    }
+    for url in CSS_URL_RE.captures_iter(html) {
+        push(&url[1]);
+    }
+}
+
+/// Moves the base64 `data:` image URIs of HTML parts into inline parts
+/// referenced through `cid:` URLs, wrapping each affected HTML part together
+/// with its images into a multipart/related part.
+///
+/// The root part is left alone, as it carries the message headers. The
+/// `Content-ID`s are made from the UUIDs returned by `new_id`.
+pub(crate) fn inline_data_uris(message: &mut MimeMessage, new_id: &mut dyn FnMut() -> Uuid) {
+    for child in message.children.iter_mut() {
+        if child.children.is_empty() {
+            inline_part_data_uris(child, new_id);
+        } else {
+            inline_data_uris(child, new_id);
+        }
+    }
+}
+
+fn inline_part_data_uris(part: &mut MimeMessage, new_id: &mut dyn FnMut() -> Uuid) {
+    if part.is_attachment() || mime_type(part) != "text/html" {
+        return;
+    }
+    let html = match decode_body(part).map(String::from_utf8) {
+        Some(Ok(html)) => html,
+        _ => return,
+    };
+
+    // Identical images are only included once.
+    let mut content_ids: HashMap<String, String> = HashMap::new();
+    let mut images = vec![];
+    let rewritten = DATA_URI_RE.replace_all(&html, |caps: &Captures| {
+        let payload: String = caps[3]
+            .chars()
+            .filter(|c| !c.is_ascii_whitespace())
+            .collect();
+        if let Some(content_id) = content_ids.get(&payload) {
+            return format!("{}cid:{}{}", &caps[1], content_id, &caps[4]);
+        }
+        let data = match base64::decode(&payload) {
+            Ok(data) => data,
+            Err(_) => return caps[0].to_string(),
+        };
+
+        let content_id = format!("{}@localhost", new_id());
+        images.push(
+            PartBuilder::new()
+                .header(("Content-Type", caps[2].to_ascii_lowercase()))
+                .header(("Content-Transfer-Encoding", "base64"))
+                .header(("Content-ID", format!("<{}>", content_id)))
+                .header(("Content-Disposition", "inline"))
+                .body(base64::encode(&data))
+                .build(),
+        );
+        let replacement = format!("{}cid:{}{}", &caps[1], content_id, &caps[4]);
+        content_ids.insert(payload, content_id);
+        replacement
+    });
+    if images.is_empty() {
+        return;
+    }
+
+    let rewritten = rewritten.into_owned();
+    encode_body(part, rewritten.as_bytes());
+    let mut children = vec![part.clone()];
+    children.append(&mut images);
+
+    let mut params = HashMap::new();
+    params.insert("type".to_string(), "text/html".to_string());
+    *part = MimeMessage::new_with_boundary_and_params(
+        String::new(),
+        MimeMultipartType::Related,
+        children,
+        MimeMessage::random_boundary(),
+        Some(params),
+    );
+}
+
+/// Word-wraps the lines of `text` to at most `width` characters.
+///
+/// Quote prefixes (`> `) are repeated on every wrapped line, and words are
+/// never broken, so long URLs stay usable.
+pub(crate) fn wrap_text(text: &str, width: usize) -> String {
+    text.split("\r\n")
+        .map(|line| wrap_line(line, width))
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+fn wrap_line(line: &str, width: usize) -> String {
+    if line.chars().count() <= width {
+        return line.to_string();
+    }
+
+    let prefix_len = line
+        .char_indices()
+        .find(|&(_, c)| c != '>' && c != ' ')
+        .map(|(i, _)| i)
+        .unwrap_or_else(|| line.len());
+    let prefix = &line[..prefix_len];
+    let prefix_width = prefix.chars().count();
+
+    let mut lines = vec![];
+    let mut current = String::from(prefix);
+    let mut current_width = prefix_width;
+    for word in line[prefix_len..]
+        .split(' ')
+        .filter(|word| !word.is_empty())
+    {
+        let word_width = word.chars().count();
+        if current_width > prefix_width && current_width + 1 + word_width > width {
+            lines.push(current);
+            current = String::from(prefix);
+            current_width = prefix_width;
+        }
+        if current_width > prefix_width {
+            current.push(' ');
+            current_width += 1;
+        }
+        current.push_str(word);
+        current_width += word_width;
+    }
+    lines.push(current);
+    lines.join("\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PartBuilder;
+
+    #[test]
+    fn test_map_text_bodies() {
+        let mut message = PartBuilder::new()
+            .child(
+                PartBuilder::new()
+                    .header(("Content-Type", "text/plain; charset=utf-8"))
+                    .header(("Content-Transfer-Encoding", "base64"))
+                    .body(base64::encode("plain"))
+                    .build(),
+            )
+            .child(
+                PartBuilder::new()
+                    .header(("Content-Type", "text/html"))
+                    .header(("Content-Transfer-Encoding", "quoted-printable"))
+                    .body("<p>h=C3=A4</p>")
+                    .build(),
+            )
+            .child(
+                PartBuilder::new()
+                    .header(("Content-Type", "text/plain"))
+                    .header(("Content-Disposition", "attachment"))
+                    .body("attached")
+                    .build(),
+            )
+            .build();
+
+        let visited = map_text_bodies(&mut message, &mut |kind, content| {
+            format!("{:?}:{}", kind, content)
+        });
+
+        assert_eq!(visited, vec![TextKind::Plain, TextKind::Html]);
+        assert_eq!(message.children[0].body, base64::encode("Plain:plain"));
+        assert_eq!(message.children[1].body, "Html:<p>h=C3=A4</p>");
+        assert_eq!(message.children[2].body, "attached");
+    }
+
+    #[test]
+    fn test_insert_before_body_end() {
+        assert_eq!(
+            insert_before_body_end("<html><BODY>a</BODY></html>", "b"),
+            "<html><BODY>ab</BODY></html>"
+        );
+        assert_eq!(insert_before_body_end("<p>a</p>", "b"), "<p>a</p>b");
+    }
+
+    #[test]
+    fn test_wrap_text() {
+        assert_eq!(
+            wrap_text("one two three four\r\n\r\nfive", 9),
+            "one two\r\nthree\r\nfour\r\n\r\nfive"
+        );
+        assert_eq!(
+            wrap_text("> > quoted text here", 12),
+            "> > quoted\r\n> > text\r\n> > here"
+        );
+        assert_eq!(
+            wrap_text("see https://example.org/a/very/long/url now", 10),
+            "see\r\nhttps://example.org/a/very/long/url\r\nnow"
+        );
+    }
+
+    #[test]
+    fn test_inline_data_uris() {
+        let pixel = base64::encode(b"GIF89a");
+        let html = format!(
+            "<img src=\"data:image/gif;base64,{0}\"><img src='data:image/gif;base64,{0}'>",
+            pixel
+        );
+        let mut message = PartBuilder::new()
+            .child(crate::email_builder::html_part(&html))
+            .build();
+        inline_data_uris(&mut message, &mut Uuid::new_v4);
+
+        let related = &message.children[0];
+        assert_eq!(related.message_type, Some(MimeMultipartType::Related));
+        assert_eq!(related.children.len(), 2);
+
+        let image = &related.children[1];
+        assert_eq!(image.body, pixel);
+        let content_id = header_value(image, "Content-ID").unwrap();
+        let content_id = content_id.trim_matches(|c| c == '<' || c == '>');
+        assert_eq!(
+            related.children[0].body,
+            format!("<img src=\"cid:{0}\"><img src='cid:{0}'>", content_id)
+        );
+        assert!(header_value(related, "Content-Type")
+            .unwrap()
+            .contains("type=\"text/html\""));
+    }
+
+    #[test]
+    fn test_remote_urls() {
+        let html = "<link rel=stylesheet href='https://example.org/a.css'>\
+            <a href=\"https://example.org/\">link</a>\
+            <img SRC=\"https://t.example.org/p.gif?a=1&amp;b=2\" alt=x>\
+            <td background=//example.org/bg.png>\
+            <div style=\"background: url('http://example.org/c.png')\"></div>\
+            <img src=\"cid:image@localhost\"><img src=\"https://t.example.org/p.gif?a=1&amp;b=2\">";
+        let mut urls = vec![];
+        remote_urls(html, &mut urls);
+        assert_eq!(
+            urls,
+            vec![
+                "https://example.org/a.css",
+                "https://t.example.org/p.gif?a=1&b=2",
+                "//example.org/bg.png",
+                "http://example.org/c.png",
+            ]
+        );
+    }
+}
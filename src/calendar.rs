@@ -0,0 +1,64 @@
+use std::fmt;
+
+use crate::body::encode_content;
+use crate::email_builder::LINE_BREAKS_RE;
+use crate::{MimeMessage, PartBuilder};
+
+/// The iTIP method of a calendar object (RFC 5546), which tells the
+/// recipient's client what to do with it
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum CalendarMethod {
+    /// Publishes an event without expecting replies
+    Publish,
+    /// Invites the recipients to an event, or updates it
+    Request,
+    /// Answers an invitation
+    Reply,
+    /// Adds instances to a recurring event
+    Add,
+    /// Cancels an event
+    Cancel,
+    /// Asks the organizer for the latest version of an event
+    Refresh,
+    /// Proposes changes to an event
+    Counter,
+    /// Rejects a counter proposal
+    DeclineCounter,
+}
+
+impl CalendarMethod {
+    /// The value of the `METHOD` property and the `method` parameter
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CalendarMethod::Publish => "PUBLISH",
+            CalendarMethod::Request => "REQUEST",
+            CalendarMethod::Reply => "REPLY",
+            CalendarMethod::Add => "ADD",
+            CalendarMethod::Cancel => "CANCEL",
+            CalendarMethod::Refresh => "REFRESH",
+            CalendarMethod::Counter => "COUNTER",
+            CalendarMethod::DeclineCounter => "DECLINECOUNTER",
+        }
+    }
+}
+
+impl fmt::Display for CalendarMethod {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(self.as_str())
+    }
+}
+
+/// Builds the `text/calendar` part for an iCalendar object, with CRLF line
+/// endings as required by RFC 5545
+pub(crate) fn calendar_part(ics: &str, method: CalendarMethod) -> MimeMessage {
+    let ics = LINE_BREAKS_RE.replace_all(ics, "\r\n");
+    let (encoding, body) = encode_content(ics.as_bytes(), "text/calendar");
+    PartBuilder::new()
+        .header((
+            "Content-Type",
+            format!("text/calendar; charset=utf-8; method={}", method),
+        ))
+        .header(("Content-Transfer-Encoding", encoding))
+        .body(body)
+        .build()
+}
@@ -0,0 +1,108 @@
+//! Zip compression of attachments.
+
+use std::io::{Cursor, Write};
+
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::email_builder::Error;
+
+/// Zips `files`, given as name and content pairs, into an in-memory archive.
+pub(crate) fn zip_files(files: &[(&str, &[u8])]) -> Result<Vec<u8>, Error> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (name, content) in files.iter() {
+        writer.start_file(*name, options)?;
+        writer.write_all(content)?;
+    }
+    Ok(writer.finish()?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::EmailBuilder;
+
+    fn unzip(archive: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+        (0..archive.len())
+            .map(|i| {
+                let mut file = archive.by_index(i).unwrap();
+                let mut content = Vec::new();
+                file.read_to_end(&mut content).unwrap();
+                (file.name().to_string(), content)
+            })
+            .collect()
+    }
+
+    fn attachment_body(email: &str) -> Vec<u8> {
+        let start = email.find("base64\r\n\r\n").unwrap() + 10;
+        let end = start + email[start..].find("\r\n").unwrap();
+        base64::decode(&email[start..end]).unwrap()
+    }
+
+    #[test]
+    fn test_zip_files() {
+        let archive = zip_files(&[("a.txt", b"first"), ("b.txt", b"second")]).unwrap();
+        assert_eq!(
+            unzip(&archive),
+            vec![
+                ("a.txt".to_string(), b"first".to_vec()),
+                ("b.txt".to_string(), b"second".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_attachment_zipped() {
+        let email = EmailBuilder::new()
+            .to("user@localhost")
+            .from("user@localhost")
+            .attachment_zipped(&[("a.txt", b"first")], "files.zip")
+            .unwrap()
+            .build()
+            .unwrap()
+            .message_to_string()
+            .unwrap();
+
+        assert!(email.contains("filename=\"files.zip\""));
+        assert!(email.contains("Content-Type: application/zip\r\n"));
+        assert_eq!(
+            unzip(&attachment_body(&email)),
+            vec![("a.txt".to_string(), b"first".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_zip_attachments_over_threshold() {
+        let builder = EmailBuilder::new()
+            .to("user@localhost")
+            .from("user@localhost")
+            .zip_attachments_over(4);
+
+        let small = builder
+            .clone()
+            .attachment(b"tiny", "small.txt", &mime::TEXT_PLAIN)
+            .unwrap()
+            .build()
+            .unwrap()
+            .message_to_string()
+            .unwrap();
+        assert!(small.contains("filename=\"small.txt\""));
+
+        let large = builder
+            .attachment(b"large", "large.txt", &mime::TEXT_PLAIN)
+            .unwrap()
+            .build()
+            .unwrap()
+            .message_to_string()
+            .unwrap();
+        assert!(large.contains("filename=\"large.txt.zip\""));
+        assert_eq!(
+            unzip(&attachment_body(&large)),
+            vec![("large.txt".to_string(), b"large".to_vec())]
+        );
+    }
+}
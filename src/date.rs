@@ -0,0 +1,539 @@
+//! Parsing and formatting of RFC 5322 dates, and checks on the timestamps
+//! found in message headers.
+
+use time::{Date, Duration, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+
+use crate::{FromHeader, MimeMessage, ToHeader};
+
+/// `strftime` style format of RFC 5322 dates with a numeric zone
+#[cfg(test)]
+pub(crate) const RFC822Z_TIME_FORMAT: &str = "%a, %d %b %Y %T %z";
+
+/// Day abbreviations, starting with Monday
+const DAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+/// Lowercased month abbreviations, in order
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// Formats `date` for use in a `Date` header
+pub fn format_date(date: &OffsetDateTime) -> String {
+    let month = MONTHS[date.month() as usize - 1];
+    let offset = date.offset().as_seconds() / 60;
+    format!(
+        "{}, {:02} {}{} {:04} {:02}:{:02}:{:02} {}{:02}{:02}",
+        DAYS[date.weekday().number_days_from_monday() as usize],
+        date.day(),
+        month[..1].to_ascii_uppercase(),
+        &month[1..],
+        date.year(),
+        date.hour(),
+        date.minute(),
+        date.second(),
+        if offset < 0 { '-' } else { '+' },
+        offset.abs() / 60,
+        offset.abs() % 60,
+    )
+}
+
+/// The value of a `Date` header, formatted as an RFC 5322 date-time without
+/// depending on the locale.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct DateHeader {
+    date: OffsetDateTime,
+}
+
+impl DateHeader {
+    /// Creates a header for `date`, shown in its own zone
+    pub fn new(date: OffsetDateTime) -> DateHeader {
+        DateHeader { date }
+    }
+
+    /// Creates a header for the current time in UTC
+    pub fn now_utc() -> DateHeader {
+        DateHeader::new(OffsetDateTime::now_utc())
+    }
+
+    /// Creates a header for the current time in the local zone, or in UTC
+    /// if the local offset can't be determined
+    pub fn now_local() -> DateHeader {
+        DateHeader::new(
+            OffsetDateTime::try_now_local().unwrap_or_else(|_| OffsetDateTime::now_utc()),
+        )
+    }
+
+    /// Shows the date in the zone `minutes` east of UTC, which is clamped to
+    /// the offsets RFC 5322 can express
+    pub fn zone_offset_minutes(self, minutes: i16) -> DateHeader {
+        let minutes = minutes.clamp(-(23 * 60 + 59), 23 * 60 + 59);
+        DateHeader::new(self.date.to_offset(UtcOffset::minutes(minutes)))
+    }
+
+    /// The date of this header
+    pub fn date(&self) -> OffsetDateTime {
+        self.date
+    }
+}
+
+impl From<OffsetDateTime> for DateHeader {
+    fn from(date: OffsetDateTime) -> Self {
+        DateHeader::new(date)
+    }
+}
+
+/// Fails for years before 1900 or after 9999, which RFC 5322 doesn't allow
+impl ToHeader for DateHeader {
+    type Error = ();
+
+    fn to_header(value: DateHeader) -> Result<String, ()> {
+        if !(1900..=9999).contains(&value.date.year()) {
+            return Err(());
+        }
+        Ok(format_date(&value.date))
+    }
+}
+
+/// Accepts what `parse_date` does, giving a date in UTC
+impl FromHeader for DateHeader {
+    type Error = ();
+
+    fn from_header(value: &str) -> Result<DateHeader, ()> {
+        parse_date(value).map(DateHeader::new).ok_or(())
+    }
+}
+
+/// Parses an RFC 5322 date, as found in `Date` or `Received` headers.
+///
+/// The obsolete syntax of section 4.3 is accepted as well: comments, two
+/// digit years, missing seconds and named zones. The result is in UTC.
+pub fn parse_date(value: &str) -> Option<OffsetDateTime> {
+    let value = strip_comments(value);
+    let mut tokens = value
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|token| !token.is_empty())
+        .peekable();
+
+    // The day of the week is optional, and redundant anyway.
+    if tokens
+        .peek()?
+        .starts_with(|c: char| c.is_ascii_alphabetic())
+    {
+        tokens.next();
+    }
+    let day = tokens.next()?.parse().ok()?;
+    let month = tokens.next()?.get(..3)?.to_ascii_lowercase();
+    let month = MONTHS.iter().position(|m| *m == month)? as u8 + 1;
+    let year = parse_year(tokens.next()?)?;
+
+    let mut time = tokens.next()?.split(':');
+    let hour = time.next()?.parse().ok()?;
+    let minute = time.next()?.parse().ok()?;
+    let second = match time.next() {
+        Some(second) => second.parse().ok()?,
+        None => 0,
+    };
+    let offset = match tokens.next() {
+        Some(zone) => parse_zone(zone)?,
+        None => 0,
+    };
+
+    let date = Date::try_from_ymd(year, month, day).ok()?;
+    let time = Time::try_from_hms(hour, minute, second).ok()?;
+    Some(
+        PrimitiveDateTime::new(date, time)
+            .assume_offset(UtcOffset::seconds(offset))
+            .to_offset(UtcOffset::UTC),
+    )
+}
+
+/// Removes (possibly nested) comments
+fn strip_comments(value: &str) -> String {
+    let mut depth = 0;
+    value
+        .chars()
+        .filter(|&c| match c {
+            '(' => {
+                depth += 1;
+                false
+            }
+            ')' if depth > 0 => {
+                depth -= 1;
+                false
+            }
+            _ => depth == 0,
+        })
+        .collect()
+}
+
+fn parse_year(token: &str) -> Option<i32> {
+    if !token.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let year = token.parse().ok()?;
+    Some(match token.len() {
+        2 if year < 50 => 2000 + year,
+        2 | 3 => 1900 + year,
+        _ => year,
+    })
+}
+
+/// The offset of a zone in seconds east of UTC
+fn parse_zone(zone: &str) -> Option<i32> {
+    let sign = match zone.as_bytes()[0] {
+        b'+' => 1,
+        b'-' => -1,
+        _ => {
+            return match &zone.to_ascii_uppercase()[..] {
+                "UT" | "GMT" => Some(0),
+                "EDT" => Some(-4 * 3600),
+                "EST" | "CDT" => Some(-5 * 3600),
+                "CST" | "MDT" => Some(-6 * 3600),
+                "MST" | "PDT" => Some(-7 * 3600),
+                "PST" => Some(-8 * 3600),
+                // Military zones are to be treated as unknown, i.e. UTC.
+                military if military.len() == 1 => Some(0),
+                _ => None,
+            };
+        }
+    };
+    if zone.len() != 5 || !zone[1..].chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = zone[1..3].parse().ok()?;
+    let minutes: i32 = zone[3..].parse().ok()?;
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Something suspicious about the timestamps of a message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum SkewFinding {
+    /// There is no `Date` header
+    MissingDate,
+    /// The `Date` header can't be parsed
+    UnparseableDate(String),
+    /// The `Date` header lies in the future
+    FutureDate(OffsetDateTime),
+    /// The topmost `Received` header lies in the future
+    FutureReceived(OffsetDateTime),
+    /// `Date` and the topmost `Received` timestamp are too far apart
+    Skewed {
+        /// The value of the `Date` header
+        date: OffsetDateTime,
+        /// The timestamp of the topmost `Received` header
+        received: OffsetDateTime,
+    },
+}
+
+/// Compares the `Date` header, the topmost `Received` timestamp and the
+/// current time of messages, flagging future-dated or badly skewed mail.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct SkewChecker {
+    /// How far timestamps may lie in the future
+    max_future: Duration,
+    /// How far `Date` and `Received` may be apart
+    max_skew: Duration,
+}
+
+impl Default for SkewChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SkewChecker {
+    /// Creates a checker tolerating 15 minutes into the future and 24 hours
+    /// between `Date` and `Received`.
+    pub fn new() -> SkewChecker {
+        SkewChecker {
+            max_future: Duration::minutes(15),
+            max_skew: Duration::hours(24),
+        }
+    }
+
+    /// Sets how far timestamps may lie in the future
+    pub fn max_future(mut self, max_future: Duration) -> SkewChecker {
+        self.max_future = max_future;
+        self
+    }
+
+    /// Sets how far `Date` and the topmost `Received` timestamp may be apart
+    pub fn max_skew(mut self, max_skew: Duration) -> SkewChecker {
+        self.max_skew = max_skew;
+        self
+    }
+
+    /// Checks `message` against the current time
+    pub fn check(&self, message: &MimeMessage) -> Vec<SkewFinding> {
+        self.check_at(message, OffsetDateTime::now_utc())
+    }
+
+    /// Checks `message` as if the current time was `now`
+    pub fn check_at(&self, message: &MimeMessage, now: OffsetDateTime) -> Vec<SkewFinding> {
+        let mut findings = vec![];
+
+        let date = match message.headers.find("Date") {
+            None => {
+                findings.push(SkewFinding::MissingDate);
+                None
+            }
+            Some(headers) => {
+                let value = headers[0].get_value();
+                let date = parse_date(&value);
+                match date {
+                    None => findings.push(SkewFinding::UnparseableDate(value)),
+                    Some(date) if date - now > self.max_future => {
+                        findings.push(SkewFinding::FutureDate(date))
+                    }
+                    Some(_) => {}
+                }
+                date
+            }
+        };
+
+        // The topmost Received header was added by the last hop, which is
+        // the one closest to us and thus the most trustworthy.
+        let received = message
+            .headers
+            .find("Received")
+            .and_then(|headers| received_timestamp(&headers[0].get_value()));
+        if let Some(received) = received {
+            if received - now > self.max_future {
+                findings.push(SkewFinding::FutureReceived(received));
+            }
+            if let Some(date) = date {
+                if (received - date).abs() > self.max_skew {
+                    findings.push(SkewFinding::Skewed { date, received });
+                }
+            }
+        }
+
+        findings
+    }
+}
+
+/// When `message` was written: its `Date`, or the timestamp of the bottommost
+/// `Received` header, added by the first hop, if the date is missing or
+/// unparseable
+pub fn origin_date(message: &MimeMessage) -> Option<OffsetDateTime> {
+    message
+        .headers
+        .nth("Date", 0)
+        .and_then(|header| parse_date(&header.get_value()))
+        .or_else(|| {
+            message
+                .headers
+                .get_all("Received")
+                .iter()
+                .rev()
+                .find_map(|header| received_timestamp(&header.get_value()))
+        })
+}
+
+/// How long ago `message` was written as of `now`, see `origin_date`
+pub fn message_age(message: &MimeMessage, now: OffsetDateTime) -> Option<Duration> {
+    origin_date(message).map(|date| now - date)
+}
+
+/// When a queued message is given up on, e.g. by a retry scheduler.
+///
+/// A message is expired once any of the limits is reached. Without limits,
+/// only the `Expires` header of the message is honored.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct ExpiryPolicy {
+    /// How old a message may get, see `message_age`
+    max_age: Option<Duration>,
+    /// How long a message may stay queued
+    max_queued: Option<Duration>,
+    /// Whether the `Expires` header (RFC 4021) is honored
+    honor_expires: bool,
+}
+
+impl Default for ExpiryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExpiryPolicy {
+    /// Creates a policy honoring the `Expires` header, without limits
+    pub fn new() -> ExpiryPolicy {
+        ExpiryPolicy {
+            max_age: None,
+            max_queued: None,
+            honor_expires: true,
+        }
+    }
+
+    /// Sets how old a message may get. Messages without a date are only
+    /// expired by the other limits.
+    pub fn max_age(mut self, max_age: Duration) -> ExpiryPolicy {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Sets how long a message may stay queued
+    pub fn max_queued(mut self, max_queued: Duration) -> ExpiryPolicy {
+        self.max_queued = Some(max_queued);
+        self
+    }
+
+    /// Sets whether the `Expires` header is honored
+    pub fn honor_expires(mut self, honor_expires: bool) -> ExpiryPolicy {
+        self.honor_expires = honor_expires;
+        self
+    }
+
+    /// Whether `message`, queued at `queued_at` if known, is expired
+    pub fn is_expired(&self, message: &MimeMessage, queued_at: Option<OffsetDateTime>) -> bool {
+        self.is_expired_at(message, queued_at, OffsetDateTime::now_utc())
+    }
+
+    /// Whether `message` is expired as if the current time was `now`
+    pub fn is_expired_at(
+        &self,
+        message: &MimeMessage,
+        queued_at: Option<OffsetDateTime>,
+        now: OffsetDateTime,
+    ) -> bool {
+        if self.honor_expires {
+            let expires = message
+                .headers
+                .nth("Expires", 0)
+                .and_then(|header| parse_date(&header.get_value()));
+            if matches!(expires, Some(expires) if expires <= now) {
+                return true;
+            }
+        }
+        if let (Some(max_age), Some(age)) = (self.max_age, message_age(message, now)) {
+            if age >= max_age {
+                return true;
+            }
+        }
+        matches!((self.max_queued, queued_at), (Some(max_queued), Some(queued_at)) if now - queued_at >= max_queued)
+    }
+}
+
+/// The timestamp of a `Received` header, which follows the last `;`
+fn received_timestamp(value: &str) -> Option<OffsetDateTime> {
+    value
+        .rfind(';')
+        .and_then(|position| parse_date(&value[position + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PartBuilder;
+
+    fn now() -> OffsetDateTime {
+        parse_date("Tue, 1 Sep 2020 12:00:00 +0000").unwrap()
+    }
+
+    #[test]
+    fn test_parse_date() {
+        let date = parse_date("Tue, 1 Sep 2020 14:00:00 +0200").unwrap();
+        assert_eq!(date, now());
+        assert_eq!(format_date(&date), "Tue, 01 Sep 2020 12:00:00 +0000");
+        assert_eq!(
+            parse_date("1 sep 20 07:00 (comment) EST (more (nested))").unwrap(),
+            now()
+        );
+        assert!(parse_date("yesterday").is_none());
+        assert!(parse_date("Tue, 31 Sep 2020 12:00:00 +0000").is_none());
+        assert!(parse_date("Tue, 1 Sep 2020 12:00:00 +2400").is_none());
+    }
+
+    #[test]
+    fn test_date_header() {
+        let date = DateHeader::new(now()).zone_offset_minutes(-150);
+        assert_eq!(
+            ToHeader::to_header(date).unwrap(),
+            "Tue, 01 Sep 2020 09:30:00 -0230"
+        );
+        assert_eq!(date.date(), now());
+        assert_eq!(format_date(&now()), now().format(RFC822Z_TIME_FORMAT));
+
+        let old = parse_date("Sun, 1 Jan 1899 00:00:00 +0000").unwrap();
+        assert!(ToHeader::to_header(DateHeader::new(old)).is_err());
+    }
+
+    #[test]
+    fn test_check_skew() {
+        let message = PartBuilder::new()
+            .header((
+                "Received",
+                "from a.example.org by b.example.org; Tue, 1 Sep 2020 11:59:00 +0000",
+            ))
+            .header((
+                "Received",
+                "from c.example.org by a.example.org; Mon, 1 Jan 1990 00:00:00 +0000",
+            ))
+            .header(("Date", "Tue, 1 Sep 2020 11:58:00 +0000"))
+            .build();
+        assert!(SkewChecker::new().check_at(&message, now()).is_empty());
+
+        let message = PartBuilder::new()
+            .header((
+                "Received",
+                "from a.example.org by b.example.org; Tue, 1 Sep 2020 11:59:00 +0000",
+            ))
+            .header(("Date", "Fri, 4 Sep 2020 11:58:00 +0000"))
+            .build();
+        let findings = SkewChecker::new().check_at(&message, now());
+        assert_eq!(
+            findings,
+            vec![
+                SkewFinding::FutureDate(parse_date("Fri, 4 Sep 2020 11:58:00 +0000").unwrap()),
+                SkewFinding::Skewed {
+                    date: parse_date("Fri, 4 Sep 2020 11:58:00 +0000").unwrap(),
+                    received: parse_date("Tue, 1 Sep 2020 11:59:00 +0000").unwrap(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_missing_date() {
+        let findings = SkewChecker::new().check_at(&PartBuilder::new().build(), now());
+        assert_eq!(findings, vec![SkewFinding::MissingDate]);
+
+        let message = PartBuilder::new().header(("Date", "soon")).build();
+        let findings = SkewChecker::new().check_at(&message, now());
+        assert_eq!(
+            findings,
+            vec![SkewFinding::UnparseableDate("soon".to_string())]
+        );
+    }
+    #[test]
+    fn test_expiry() {
+        let message = PartBuilder::new()
+            .header((
+                "Received",
+                "from a.example.org by b.example.org; Tue, 1 Sep 2020 11:00:00 +0000",
+            ))
+            .header((
+                "Received",
+                "from c.example.org by a.example.org; Tue, 1 Sep 2020 10:00:00 +0000",
+            ))
+            .header(("Expires", "Tue, 1 Sep 2020 13:00:00 +0000"))
+            .build();
+        assert_eq!(message_age(&message, now()), Some(Duration::hours(2)));
+
+        let policy = ExpiryPolicy::new();
+        assert!(!policy.is_expired_at(&message, None, now()));
+        assert!(policy.is_expired_at(&message, None, now() + Duration::hours(1)));
+        let policy = ExpiryPolicy::new()
+            .honor_expires(false)
+            .max_age(Duration::hours(2));
+        assert!(policy.is_expired_at(&message, None, now()));
+
+        let policy = ExpiryPolicy::new().max_queued(Duration::minutes(30));
+        let queued_at = now() - Duration::minutes(20);
+        assert!(!policy.is_expired_at(&message, Some(queued_at), now()));
+        assert!(policy.is_expired_at(&message, Some(queued_at), now() + Duration::minutes(10)));
+    }
+}
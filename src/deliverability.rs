@@ -0,0 +1,173 @@
+//! Lints for outgoing mail which commonly trigger spam filters.
+//!
+//! The lints work on the `MimeMessage` of an email, so they can be run from
+//! a `BuildHook` right before sending.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::body::{self, TextKind};
+use crate::MimeMessage;
+
+/// Hosts of well-known URL shorteners, which spammers use to hide links
+const URL_SHORTENERS: &[&str] = &[
+    "bit.ly",
+    "buff.ly",
+    "cutt.ly",
+    "goo.gl",
+    "is.gd",
+    "ow.ly",
+    "rebrand.ly",
+    "shorturl.at",
+    "t.co",
+    "tinyurl.com",
+];
+
+/// Headers outgoing mail shouldn't carry
+const SUSPICIOUS_HEADERS: &[&str] = &[
+    "Bcc",
+    "X-PHP-Originating-Script",
+    "X-Spam-Flag",
+    "X-Spam-Status",
+];
+
+/// Characters of visible text expected for every image
+const MIN_TEXT_PER_IMAGE: usize = 400;
+
+lazy_static! {
+    static ref URL_HOST_RE: Regex = Regex::new(r#"(?i)https?://([^/\s"'<>?#:]+)"#).unwrap();
+    static ref IMG_RE: Regex = Regex::new(r"(?i)<img\b").unwrap();
+    static ref INVISIBLE_RE: Regex =
+        Regex::new(r"(?is)<(style|script|head)\b.*?</(style|script|head)\s*>|<[^>]*>|&[a-z0-9#]+;")
+            .unwrap();
+}
+
+/// What kind of mail is linted
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Profile {
+    /// Mail sent in response to a user's action, like password resets
+    Transactional,
+    /// Newsletters and other mail sent to many recipients
+    Bulk,
+}
+
+/// A deliverability problem found by `lint`
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Finding {
+    /// Bulk mail without a `List-Unsubscribe` header
+    MissingListUnsubscribe,
+    /// An HTML body without a plain text alternative
+    MissingTextAlternative,
+    /// Too little text for the number of images
+    ImageHeavy {
+        /// Number of images
+        images: usize,
+        /// Number of visible text characters
+        text_length: usize,
+    },
+    /// A link through a URL shortener, with the shortener's host
+    UrlShortener(String),
+    /// There is no `Date` header
+    MissingDate,
+    /// A header which outgoing mail shouldn't carry
+    SuspiciousHeader(String),
+}
+
+/// Lints `message` sent as `profile` mail
+pub fn lint(message: &MimeMessage, profile: Profile) -> Vec<Finding> {
+    let mut findings = vec![];
+
+    if message.headers.find("Date").is_none() {
+        findings.push(Finding::MissingDate);
+    }
+    if profile == Profile::Bulk && message.headers.find("List-Unsubscribe").is_none() {
+        findings.push(Finding::MissingListUnsubscribe);
+    }
+    for name in SUSPICIOUS_HEADERS {
+        if message.headers.find(name).is_some() {
+            findings.push(Finding::SuspiciousHeader(name.to_string()));
+        }
+    }
+
+    let bodies = body::text_bodies(message);
+    let has_text = bodies.iter().any(|(kind, _)| *kind == TextKind::Plain);
+    let has_html = bodies.iter().any(|(kind, _)| *kind == TextKind::Html);
+    if has_html && !has_text {
+        findings.push(Finding::MissingTextAlternative);
+    }
+
+    let mut images = 0;
+    let mut html_text_length = 0;
+    let mut shorteners = vec![];
+    for (kind, content) in bodies.iter() {
+        if *kind == TextKind::Html {
+            images += IMG_RE.find_iter(content).count();
+            html_text_length += INVISIBLE_RE
+                .replace_all(content, "")
+                .split_whitespace()
+                .map(|word| word.chars().count())
+                .sum::<usize>();
+        }
+        for url in URL_HOST_RE.captures_iter(content) {
+            let host = url[1].to_ascii_lowercase();
+            if URL_SHORTENERS.contains(&host.as_str()) && !shorteners.contains(&host) {
+                shorteners.push(host);
+            }
+        }
+    }
+    if images > 0 && html_text_length < images * MIN_TEXT_PER_IMAGE {
+        findings.push(Finding::ImageHeavy {
+            images,
+            text_length: html_text_length,
+        });
+    }
+    findings.extend(shorteners.into_iter().map(Finding::UrlShortener));
+
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email_builder::{alternative_part, html_part};
+    use crate::PartBuilder;
+
+    #[test]
+    fn test_lint_clean() {
+        let message = PartBuilder::new()
+            .header(("Date", "Tue, 1 Sep 2020 12:00:00 +0000"))
+            .header(("List-Unsubscribe", "<https://example.org/u>"))
+            .child(alternative_part(
+                "<p>Hello, see https://example.org</p>",
+                "Hello, see https://example.org",
+            ))
+            .build();
+        assert!(lint(&message, Profile::Bulk).is_empty());
+    }
+
+    #[test]
+    fn test_lint_findings() {
+        let message = PartBuilder::new()
+            .header(("Bcc", "joe@example.org"))
+            .child(html_part(
+                "<style>p { color: red }</style><img src=\"a.png\"><p>Buy <a href=\"https://BIT.LY/x\">now</a></p>",
+            ))
+            .build();
+
+        assert_eq!(
+            lint(&message, Profile::Bulk),
+            vec![
+                Finding::MissingDate,
+                Finding::MissingListUnsubscribe,
+                Finding::SuspiciousHeader("Bcc".to_string()),
+                Finding::MissingTextAlternative,
+                Finding::ImageHeavy {
+                    images: 1,
+                    text_length: 6,
+                },
+                Finding::UrlShortener("bit.ly".to_string()),
+            ]
+        );
+        assert!(!lint(&message, Profile::Transactional).contains(&Finding::MissingListUnsubscribe));
+    }
+}
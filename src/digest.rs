@@ -0,0 +1,178 @@
+use std::sync::Arc;
+
+use crate::body::{escape_html, header_value};
+use crate::messages::Catalog;
+use crate::{EmailBuilder, Messages, MimeMessage, PartBuilder};
+
+/// Assembles several messages into a single summary email, e.g. for daily
+/// notification digests.
+///
+/// The summary lists the subject and sender of every message, linked to
+/// the original through a `mid:` URL (RFC 2392). Optionally, full copies
+/// are attached as `message/rfc822` parts.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Digest {
+    /// Subject of the digest email
+    subject: String,
+    /// The summarized messages
+    messages: Vec<MimeMessage>,
+    /// Attach full copies of the messages
+    attach_originals: bool,
+    /// Catalog of the texts in the summary
+    catalog: Option<Catalog>,
+}
+
+impl Digest {
+    /// Creates an empty digest with the given subject
+    pub fn new<S: Into<String>>(subject: S) -> Digest {
+        Digest {
+            subject: subject.into(),
+            messages: vec![],
+            attach_originals: false,
+            catalog: None,
+        }
+    }
+
+    /// Adds a message to the digest
+    pub fn message(mut self, message: MimeMessage) -> Digest {
+        self.messages.push(message);
+        self
+    }
+
+    /// Sets whether full copies of the messages are attached
+    pub fn attach_originals(mut self, enabled: bool) -> Digest {
+        self.attach_originals = enabled;
+        self
+    }
+
+    /// Sets the catalog the texts of the summary are taken from
+    pub fn catalog<M: Messages + 'static>(mut self, messages: M) -> Digest {
+        self.catalog = Some(Catalog(Arc::new(messages)));
+        self
+    }
+
+    /// Returns a builder for the digest email, which only lacks the
+    /// addresses
+    pub fn into_builder(self) -> EmailBuilder {
+        let catalog = Catalog::or_english(&self.catalog);
+        let mut text = String::new();
+        let mut html = String::from("<ul>\r\n");
+        for message in self.messages.iter() {
+            let subject = header_value(message, "Subject").unwrap_or_else(|| catalog.no_subject());
+            let from = header_value(message, "From").unwrap_or_default();
+            let link = header_value(message, "Message-ID").map(|id| mid_url(&id));
+
+            text.push_str(&format!("* {}\r\n", subject));
+            if !from.is_empty() {
+                text.push_str(&format!("  {}\r\n", catalog.digest_from(&from)));
+            }
+            if let Some(ref link) = link {
+                text.push_str(&format!("  {}\r\n", link));
+            }
+
+            let subject = escape_html(&subject);
+            html.push_str("<li>");
+            match link {
+                Some(link) => html.push_str(&format!(
+                    "<a href=\"{}\">{}</a>",
+                    escape_html(&link),
+                    subject
+                )),
+                None => html.push_str(&subject),
+            }
+            if !from.is_empty() {
+                html.push_str(&format!(" &ndash; {}", escape_html(&from)));
+            }
+            html.push_str("</li>\r\n");
+        }
+        html.push_str("</ul>");
+
+        let mut builder = EmailBuilder::new()
+            .subject(self.subject)
+            .alternative(html, text);
+        if self.attach_originals {
+            for message in self.messages.iter() {
+                builder = builder.child(
+                    PartBuilder::new()
+                        .header(("Content-Type", "message/rfc822"))
+                        .header(("Content-Disposition", "attachment"))
+                        .body(message.as_string())
+                        .build(),
+                );
+            }
+        }
+        builder
+    }
+}
+
+/// The `mid:` URL (RFC 2392) of a Message-ID
+fn mid_url(message_id: &str) -> String {
+    let id = message_id
+        .trim()
+        .trim_start_matches('<')
+        .trim_end_matches('>');
+    let mut url = String::from("mid:");
+    for byte in id.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => url.push(byte as char),
+            b'-' | b'.' | b'_' | b'~' | b'!' | b'$' | b'\'' | b'*' | b'+' | b'=' | b'@' => {
+                url.push(byte as char)
+            }
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(subject: &str, id: &str) -> MimeMessage {
+        PartBuilder::new()
+            .header(("From", "Bot <bot@example.org>"))
+            .header(("Subject", subject))
+            .header(("Message-ID", id))
+            .body("Something happened")
+            .build()
+    }
+
+    #[test]
+    fn test_digest() {
+        let email = Digest::new("Your daily digest")
+            .message(notification("Build <failed>", "<1/2@example.org>"))
+            .message(notification("Build fixed", "<3@example.org>"))
+            .into_builder()
+            .from("digest@example.org")
+            .to("joe@example.org")
+            .build()
+            .unwrap();
+        let email = email.message_to_string().unwrap();
+
+        assert!(email.contains("Subject: Your daily digest\r\n"));
+        assert!(email.contains(
+            "* Build <failed>\r\n  From: Bot <bot@example.org>\r\n  mid:1%2F2@example.org\r\n"
+        ));
+        assert!(email.contains(
+            "<li><a href=\"mid:3@example.org\">Build fixed</a> &ndash; Bot &lt;bot@example.org&gt;</li>"
+        ));
+        assert!(!email.contains("message/rfc822"));
+    }
+
+    #[test]
+    fn test_digest_attach_originals() {
+        let original = notification("Build fixed", "<3@example.org>");
+        let email = Digest::new("Digest")
+            .message(original.clone())
+            .attach_originals(true)
+            .into_builder()
+            .from("digest@example.org")
+            .to("joe@example.org")
+            .build()
+            .unwrap();
+        let email = email.message_to_string().unwrap();
+
+        assert!(email.contains("Content-Type: message/rfc822\r\n"));
+        assert!(email.contains(&original.as_string()));
+    }
+}
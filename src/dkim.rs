@@ -0,0 +1,252 @@
+use crate::rfc5322::SerializationConfig;
+use crate::{Header, HeaderMap};
+
+/// The headers RFC 6376, section 5.4.1 recommends to sign
+const DEFAULT_SIGNED_HEADERS: &[&str] = &[
+    "From",
+    "Reply-To",
+    "Subject",
+    "Date",
+    "To",
+    "Cc",
+    "Resent-Date",
+    "Resent-From",
+    "Resent-To",
+    "Resent-Cc",
+    "In-Reply-To",
+    "References",
+    "List-Id",
+    "List-Help",
+    "List-Unsubscribe",
+    "List-Subscribe",
+    "List-Post",
+    "List-Owner",
+    "List-Archive",
+    "Message-ID",
+    "MIME-Version",
+    "Content-Type",
+    "Content-Transfer-Encoding",
+];
+
+/// The headers over-signed by default, which must not be added once the
+/// message is signed
+const DEFAULT_OVER_SIGNED_HEADERS: &[&str] = &["From", "Subject", "Date", "To", "Cc", "Reply-To"];
+
+/// Header canonicalization algorithm of DKIM (RFC 6376, section 3.4)
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum DkimCanonicalization {
+    /// Headers are signed exactly as they are emitted
+    Simple,
+    /// Header names are lowercased and whitespace is normalized, which
+    /// survives refolding
+    Relaxed,
+}
+
+/// Selects the headers to include in a DKIM signature, e.g. from within a
+/// `BuildHook`.
+///
+/// Headers are over-signed by listing them once more than they occur in the
+/// message. Verifiers treat the additional entry as an empty header, so the
+/// signature breaks if a header of that name is added later on.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct DkimHeaders {
+    names: Vec<String>,
+    over_signed: Vec<String>,
+    canonicalization: DkimCanonicalization,
+    serialization_config: SerializationConfig,
+}
+
+/// The header part of a DKIM signature, as prepared by `DkimHeaders`
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct DkimSignedHeaders {
+    /// The value of the `h=` tag
+    pub header_list: String,
+    /// The canonicalized headers to hash, in the order of `header_list`,
+    /// each terminated by CRLF. The DKIM-Signature header itself isn't
+    /// included.
+    pub canonicalized: String,
+}
+
+impl Default for DkimHeaders {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DkimHeaders {
+    /// Selects the headers recommended by RFC 6376, over-signing the
+    /// originator, recipient and subject headers, with relaxed
+    /// canonicalization
+    pub fn new() -> DkimHeaders {
+        DkimHeaders {
+            names: DEFAULT_SIGNED_HEADERS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            over_signed: DEFAULT_OVER_SIGNED_HEADERS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            canonicalization: DkimCanonicalization::Relaxed,
+            serialization_config: SerializationConfig::default(),
+        }
+    }
+
+    /// Signs all headers of `name`
+    pub fn header<S: Into<String>>(mut self, name: S) -> DkimHeaders {
+        let name = name.into();
+        if !contains(&self.names, &name) {
+            self.names.push(name);
+        }
+        self
+    }
+
+    /// Signs all headers of `name` and prevents further ones from being
+    /// added
+    pub fn over_sign<S: Into<String>>(mut self, name: S) -> DkimHeaders {
+        let name = name.into();
+        if !contains(&self.over_signed, &name) {
+            self.over_signed.push(name.clone());
+        }
+        self.header(name)
+    }
+
+    /// Signs the headers of `name` without over-signing them
+    pub fn without_over_signing(mut self, name: &str) -> DkimHeaders {
+        self.over_signed
+            .retain(|signed| !signed.eq_ignore_ascii_case(name));
+        self
+    }
+
+    /// Sets the header canonicalization algorithm, the first half of the
+    /// `c=` tag
+    pub fn canonicalization(mut self, canonicalization: DkimCanonicalization) -> DkimHeaders {
+        self.canonicalization = canonicalization;
+        self
+    }
+
+    /// Sets how the message is serialized, which `Simple` canonicalization
+    /// signs the headers as. Use the one given to
+    /// `EmailBuilder::serialization_config`.
+    pub fn serialization_config(mut self, config: SerializationConfig) -> DkimHeaders {
+        self.serialization_config = config;
+        self
+    }
+
+    /// Determines the `h=` tag for `headers` and canonicalizes the headers
+    /// it lists.
+    ///
+    /// Multiple headers of the same name are signed starting with the last
+    /// one (RFC 6376, section 5.4.2). Headers which aren't present are left
+    /// out, unless over-signed.
+    pub fn prepare(&self, headers: &HeaderMap) -> DkimSignedHeaders {
+        let mut names = vec![];
+        let mut canonicalized = String::new();
+
+        for name in &self.names {
+            let instances = headers
+                .iter()
+                .filter(|header| header.name.eq_ignore_ascii_case(name))
+                .collect::<Vec<_>>();
+            for header in instances.iter().rev() {
+                names.push(name.as_str());
+                canonicalized.push_str(&self.canonicalize(header));
+                canonicalized.push_str("\r\n");
+            }
+            if contains(&self.over_signed, name) {
+                names.push(name.as_str());
+            }
+        }
+
+        DkimSignedHeaders {
+            header_list: names.join(":"),
+            canonicalized,
+        }
+    }
+
+    fn canonicalize(&self, header: &Header) -> String {
+        match self.canonicalization {
+            DkimCanonicalization::Simple => {
+                self.serialization_config.fold_line(&header.to_string())
+            }
+            DkimCanonicalization::Relaxed => {
+                let value = header
+                    .get_raw_value()
+                    .split(&['\r', '\n'][..])
+                    .collect::<String>();
+                let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+                format!("{}:{}", header.name.trim().to_lowercase(), value)
+            }
+        }
+    }
+}
+
+fn contains(names: &[String], name: &str) -> bool {
+    names.iter().any(|other| other.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(Header::new("From".into(), "joe@example.org".into()));
+        headers.insert(Header::new("To".into(), "jane@example.org".into()));
+        headers.insert(Header::new("Subject".into(), "  Hello\r\n\tthere  ".into()));
+        headers.insert(Header::new("X-Trace".into(), "one".into()));
+        headers.insert(Header::new("X-Trace".into(), "two".into()));
+        headers
+    }
+
+    #[test]
+    fn test_prepare() {
+        let signed = DkimHeaders::new()
+            .header("X-Trace")
+            .without_over_signing("To")
+            .prepare(&headers());
+
+        assert_eq!(
+            signed.header_list,
+            "From:From:Reply-To:Subject:Subject:Date:To:Cc:X-Trace:X-Trace"
+        );
+        assert_eq!(
+            signed.canonicalized,
+            "from:joe@example.org\r\n\
+             subject:Hello there\r\n\
+             to:jane@example.org\r\n\
+             x-trace:two\r\n\
+             x-trace:one\r\n"
+        );
+    }
+
+    #[test]
+    fn test_prepare_simple() {
+        let signed = DkimHeaders::new()
+            .over_sign("X-Trace")
+            .canonicalization(DkimCanonicalization::Simple)
+            .prepare(&headers());
+
+        assert!(signed.header_list.ends_with(":X-Trace:X-Trace:X-Trace"));
+        assert!(signed
+            .canonicalized
+            .starts_with("From: joe@example.org\r\nSubject:   Hello\r\n\tthere  \r\n"));
+
+        let config = SerializationConfig::new()
+            .max_line_len(20)
+            .fold_style(crate::FoldStyle::Space);
+        let signed = DkimHeaders::new()
+            .canonicalization(DkimCanonicalization::Simple)
+            .serialization_config(config)
+            .prepare(&headers());
+        let mut message = crate::MimeMessage::new_blank_message();
+        message.headers = headers();
+        let serialized = message.as_string_with_config(&config);
+        for line in signed.canonicalized.split_terminator("\r\n") {
+            assert!(serialized.contains(line));
+        }
+        assert!(signed
+            .canonicalized
+            .contains("Subject:   Hello\r\n there  \r\n"));
+    }
+}
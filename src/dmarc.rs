@@ -0,0 +1,135 @@
+use crate::email::domain_of;
+
+/// Second-level labels which are commonly registry controlled under
+/// country code top-level domains, e.g. `co.uk`
+const SECOND_LEVEL_SUFFIXES: &[&str] = &["ac", "co", "com", "edu", "gov", "net", "or", "org"];
+
+/// How closely two domains have to match to be aligned (RFC 7489,
+/// section 3.1)
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum AlignmentMode {
+    /// The organizational domains have to match, which is the default
+    Relaxed,
+    /// The domains have to match exactly
+    Strict,
+}
+
+/// The DKIM and SPF identifiers an email is sent with, checked for DMARC
+/// alignment with its `From` domain when building with
+/// `EmailBuilder::dmarc_alignment`.
+///
+/// DMARC passes if either the DKIM signing domain or the envelope sender
+/// domain, which SPF authenticates, is aligned.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct DmarcAlignment {
+    dkim_domain: Option<String>,
+    dkim_mode: AlignmentMode,
+    spf_mode: AlignmentMode,
+}
+
+impl Default for DmarcAlignment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DmarcAlignment {
+    /// Creates a check with relaxed alignment, which only considers the
+    /// envelope sender
+    pub fn new() -> DmarcAlignment {
+        DmarcAlignment {
+            dkim_domain: None,
+            dkim_mode: AlignmentMode::Relaxed,
+            spf_mode: AlignmentMode::Relaxed,
+        }
+    }
+
+    /// Sets the domain the email gets DKIM signed with, the `d=` tag
+    pub fn dkim_domain<S: Into<String>>(mut self, domain: S) -> DmarcAlignment {
+        self.dkim_domain = Some(domain.into());
+        self
+    }
+
+    /// Sets the DKIM alignment mode, the `adkim` tag of the DMARC record
+    pub fn dkim_mode(mut self, mode: AlignmentMode) -> DmarcAlignment {
+        self.dkim_mode = mode;
+        self
+    }
+
+    /// Sets the SPF alignment mode, the `aspf` tag of the DMARC record
+    pub fn spf_mode(mut self, mode: AlignmentMode) -> DmarcAlignment {
+        self.spf_mode = mode;
+        self
+    }
+
+    /// Returns true if `from` passes DMARC alignment with the DKIM domain
+    /// or with the domain of `envelope_sender`.
+    pub fn is_aligned(&self, from: &str, envelope_sender: Option<&str>) -> bool {
+        let from_domain = domain_of(from);
+        let dkim_aligned = self
+            .dkim_domain
+            .as_ref()
+            .map(|domain| is_aligned(&from_domain, &domain.to_lowercase(), self.dkim_mode))
+            .unwrap_or(false);
+        let spf_aligned = envelope_sender
+            .map(|sender| is_aligned(&from_domain, &domain_of(sender), self.spf_mode))
+            .unwrap_or(false);
+        dkim_aligned || spf_aligned
+    }
+}
+
+fn is_aligned(from_domain: &str, domain: &str, mode: AlignmentMode) -> bool {
+    let from_domain = from_domain.trim_end_matches('.');
+    let domain = domain.trim_end_matches('.');
+    match mode {
+        AlignmentMode::Strict => from_domain == domain,
+        AlignmentMode::Relaxed => {
+            !from_domain.is_empty()
+                && organizational_domain(from_domain) == organizational_domain(domain)
+        }
+    }
+}
+
+/// Approximates the organizational domain without the public suffix list:
+/// the last two labels, or three under common second-level suffixes of
+/// country code domains like `co.uk`.
+fn organizational_domain(domain: &str) -> &str {
+    let labels = domain.split('.').collect::<Vec<_>>();
+    let count = match labels.as_slice() {
+        [.., second, top] if top.len() == 2 && SECOND_LEVEL_SUFFIXES.contains(second) => 3,
+        _ => 2,
+    };
+    if labels.len() <= count {
+        return domain;
+    }
+    let skipped = labels[..labels.len() - count]
+        .iter()
+        .map(|label| label.len() + 1)
+        .sum::<usize>();
+    &domain[skipped..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_organizational_domain() {
+        assert_eq!(organizational_domain("mail.example.org"), "example.org");
+        assert_eq!(organizational_domain("example.org"), "example.org");
+        assert_eq!(organizational_domain("a.b.example.co.uk"), "example.co.uk");
+        assert_eq!(organizational_domain("localhost"), "localhost");
+    }
+
+    #[test]
+    fn test_is_aligned() {
+        let alignment = DmarcAlignment::new().dkim_domain("example.org");
+        assert!(alignment.is_aligned("joe@news.example.org", None));
+        assert!(alignment.is_aligned("joe@other.org", Some("bounces@mail.other.org")));
+        assert!(!alignment.is_aligned("joe@other.org", Some("bounces@esp.net")));
+
+        let strict = alignment.dkim_mode(AlignmentMode::Strict);
+        assert!(!strict.is_aligned("joe@news.example.org", None));
+        assert!(strict.is_aligned("joe@EXAMPLE.org", None));
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+use crate::email::{Envelope, MessageId};
+use crate::hash::ContentHash;
+use crate::{
+    Address, EncodingPolicy, Header, Mailbox, MimeMessage, MimeMultipartType, SenderPolicy,
+};
+
+/// Snapshot of the state of an `EmailBuilder`, e.g. to persist the content
+/// of a compose window across sessions.
+///
+/// Created by `EmailBuilder::to_draft` and turned back into a builder by
+/// `EmailBuilder::from_draft`. Hooks, HTML transforms and the DMARC
+/// alignment check are not part of the snapshot and have to be registered
+/// again.
+#[derive(PartialEq, Eq, Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(crate = "serde_crate")
+)]
+pub struct Draft {
+    /// The message, including all headers set so far
+    pub message: DraftPart,
+    /// The recipients' addresses
+    pub to: Vec<Address>,
+    /// The sender addresses
+    pub from: Vec<Address>,
+    /// The Cc addresses
+    pub cc: Vec<Address>,
+    /// The Bcc addresses
+    pub bcc: Vec<Address>,
+    /// The Reply-To addresses
+    pub reply_to: Vec<Address>,
+    /// The Disposition-Notification-To addresses
+    pub read_receipt_to: Vec<Address>,
+    /// The Return-Receipt-To addresses
+    pub return_receipt_to: Vec<Address>,
+    /// The In-Reply-To ids
+    pub in_reply_to: Vec<MessageId>,
+    /// The References ids
+    pub references: Vec<MessageId>,
+    /// The Sender address
+    pub sender: Option<Mailbox>,
+    /// How the Sender is picked when there are several From addresses
+    pub sender_policy: SenderPolicy,
+    /// The envelope, if set explicitly
+    pub envelope: Option<Envelope>,
+    /// The envelope sender, if set explicitly
+    pub bounce_address: Option<Mailbox>,
+    /// Whether the Date header was set explicitly
+    pub date_issued: bool,
+    /// Whether the message is built as an unsent draft
+    pub unsent: bool,
+    /// Whether the From and Sender addresses get dropped from To and Cc
+    pub skip_own_addresses: bool,
+    /// The Message-ID, if set explicitly
+    pub message_id: Option<MessageId>,
+    /// Text and HTML signature
+    pub signature: Option<(String, String)>,
+    /// Text and HTML footer
+    pub footer: Option<(String, String)>,
+    /// Width to which plain text parts are wrapped
+    pub text_width: Option<usize>,
+    /// Whether `data:` image URIs get moved into inline parts
+    pub inline_data_uris: bool,
+    /// Whether non-ASCII header values are left unencoded
+    pub raw_headers: bool,
+    /// Whether the Subject gets protected when signing or encrypting
+    pub protect_subject: bool,
+    /// Seed of the boundaries and the Message-ID, if deterministic
+    pub seed: Option<u64>,
+    /// Whether UTF-8 envelope addresses are accepted
+    pub smtputf8: bool,
+    /// Whether address domains get converted to A-labels
+    pub punycode_domains: bool,
+    /// How bodies without a transfer encoding get encoded
+    pub encoding_policy: EncodingPolicy,
+    /// Attachments larger than this many bytes get zipped
+    #[cfg(feature = "zip")]
+    pub zip_threshold: Option<usize>,
+}
+
+/// Snapshot of a `MimeMessage` within a `Draft`
+#[derive(PartialEq, Eq, Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(crate = "serde_crate")
+)]
+pub struct DraftPart {
+    /// The headers as name and raw value pairs, in order
+    pub headers: Vec<(String, String)>,
+    /// The undecoded body
+    pub body: String,
+    /// Reference to a payload held in an `AttachmentStore`
+    pub body_ref: Option<ContentHash>,
+    /// Path of a file holding the payload, loaded when building
+    pub body_path: Option<PathBuf>,
+    /// The multipart type, if any
+    pub message_type: Option<MimeMultipartType>,
+    /// Additional parameters of the multipart type
+    pub message_type_params: Option<HashMap<String, String>>,
+    /// The boundary for multipart messages
+    pub boundary: String,
+    /// The sub-parts
+    pub children: Vec<DraftPart>,
+}
+
+impl<'a> From<&'a MimeMessage> for DraftPart {
+    fn from(message: &'a MimeMessage) -> DraftPart {
+        DraftPart {
+            headers: message
+                .headers
+                .iter()
+                .map(|header| (header.name.clone(), header.get_raw_value().to_string()))
+                .collect(),
+            body: message.body.clone(),
+            body_ref: message.body_ref,
+            body_path: message.body_path.clone(),
+            message_type: message.message_type,
+            message_type_params: message.message_type_params.clone(),
+            boundary: message.boundary.clone(),
+            children: message.children.iter().map(DraftPart::from).collect(),
+        }
+    }
+}
+
+impl From<DraftPart> for MimeMessage {
+    fn from(part: DraftPart) -> MimeMessage {
+        let mut message = MimeMessage::new_blank_message();
+        for (name, value) in part.headers {
+            message.headers.insert(Header::new(name, value));
+        }
+        message.body = part.body;
+        message.body_ref = part.body_ref;
+        message.body_path = part.body_path;
+        message.message_type = part.message_type;
+        message.message_type_params = part.message_type_params;
+        message.boundary = part.boundary;
+        message.children = part.children.into_iter().map(MimeMessage::from).collect();
+        message
+    }
+}
@@ -0,0 +1,262 @@
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use mailparse::{MailHeader, ParsedMail};
+use mime::Mime;
+
+use crate::email_builder::{attachment_part, Error};
+use crate::header::Header;
+use crate::rfc5322::Rfc5322Builder;
+
+/// Loads an existing message and edits it in place.
+///
+/// Only the byte ranges touched by an edit are rewritten, everything else
+/// (folding, line endings, transfer encodings) is kept verbatim, so that
+/// signatures and checksums over untouched content stay valid.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct MessageEditor {
+    raw: Vec<u8>,
+    line_ending: &'static str,
+}
+
+impl MessageEditor {
+    /// Creates an editor for the given raw message.
+    pub fn new<B: Into<Vec<u8>>>(raw: B) -> Result<MessageEditor, Error> {
+        let raw = raw.into();
+        mailparse::parse_mail(&raw).map_err(Error::Parse)?;
+        let line_ending = match raw.iter().position(|&b| b == b'\n') {
+            Some(i) if i > 0 && raw[i - 1] == b'\r' => "\r\n",
+            Some(_) => "\n",
+            None => "\r\n",
+        };
+
+        Ok(MessageEditor { raw, line_ending })
+    }
+
+    /// Loads the message stored at `path`.
+    pub fn from_file(path: &Path) -> Result<MessageEditor, Error> {
+        MessageEditor::new(fs::read(path)?)
+    }
+
+    /// Writes the edited message to `path`.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        fs::write(path, &self.raw)?;
+        Ok(())
+    }
+
+    /// The current bytes of the message.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// Consumes the editor, returning the bytes of the message.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.raw
+    }
+
+    /// Appends a header to the end of the top-level header block.
+    pub fn add_header<A: Into<Header>>(&mut self, header: A) -> Result<(), Error> {
+        let (_, body_start) = mailparse::parse_headers(&self.raw).map_err(Error::Parse)?;
+        let head = &self.raw[..body_start];
+        let eol = self.line_ending.as_bytes();
+        let separator = [eol, eol].concat();
+        // Insert in front of the blank line separating headers and body, or
+        // after the last header if there is no body.
+        let (at, prefix) = if head.ends_with(&separator) || head == eol {
+            (body_start - eol.len(), "")
+        } else if head.ends_with(eol) {
+            (body_start, "")
+        } else {
+            (body_start, "\r\n")
+        };
+
+        let mut builder = Rfc5322Builder::new();
+        builder.emit_raw(prefix);
+        builder.emit_folded(&header.into().to_string());
+        builder.emit_raw("\r\n");
+        let line = self.convert_line_endings(builder.result());
+        self.raw.splice(at..at, line.into_bytes());
+        Ok(())
+    }
+
+    /// Removes all top-level headers called `name`, including their folded
+    /// continuation lines.
+    ///
+    /// Returns the number of removed headers.
+    pub fn remove_header(&mut self, name: &str) -> Result<usize, Error> {
+        let ranges = {
+            let (headers, _) = mailparse::parse_headers(&self.raw).map_err(Error::Parse)?;
+            headers
+                .iter()
+                .filter(|h| h.get_key_ref().trim().eq_ignore_ascii_case(name))
+                .map(|h| self.header_range(h))
+                .collect::<Vec<_>>()
+        };
+
+        for range in ranges.iter().rev() {
+            self.raw.drain(range.clone());
+        }
+        Ok(ranges.len())
+    }
+
+    /// Replaces the attachment called `filename` with a new base64 encoded
+    /// attachment part. Sibling parts and boundaries are left untouched.
+    pub fn replace_attachment(
+        &mut self,
+        filename: &str,
+        body: &[u8],
+        content_type: &Mime,
+    ) -> Result<(), Error> {
+        let range = self.attachment_range(filename)?;
+        let part = attachment_part(body, filename, content_type);
+        let mut content = self.convert_line_endings(&part.as_string());
+        if !content.ends_with(self.line_ending) {
+            content.push_str(self.line_ending);
+        }
+        self.raw.splice(range, content.into_bytes());
+        Ok(())
+    }
+
+    fn attachment_range(&self, filename: &str) -> Result<Range<usize>, Error> {
+        let parsed = mailparse::parse_mail(&self.raw).map_err(Error::Parse)?;
+        let part = find_attachment(&parsed, filename)
+            .ok_or_else(|| Error::AttachmentNotFound(filename.to_string()))?;
+        let start = self.offset_of(part.raw_bytes);
+        Ok(start..start + part.raw_bytes.len())
+    }
+
+    fn header_range(&self, header: &MailHeader) -> Range<usize> {
+        let start = self.offset_of(header.get_key_raw());
+        let value = header.get_value_raw();
+        let mut end = self.offset_of(value) + value.len();
+        // Swallow the line ending terminating the header.
+        if self.raw[end..].starts_with(b"\r") {
+            end += 1;
+        }
+        if self.raw[end..].starts_with(b"\n") {
+            end += 1;
+        }
+        start..end
+    }
+
+    /// Position of a slice handed out by `mailparse` within `self.raw`.
+    fn offset_of(&self, slice: &[u8]) -> usize {
+        slice.as_ptr() as usize - self.raw.as_ptr() as usize
+    }
+
+    fn convert_line_endings(&self, s: &str) -> String {
+        if self.line_ending == "\r\n" {
+            s.to_string()
+        } else {
+            s.replace("\r\n", self.line_ending)
+        }
+    }
+}
+
+fn find_attachment<'a, 'b>(part: &'b ParsedMail<'a>, filename: &str) -> Option<&'b ParsedMail<'a>> {
+    if part.subparts.is_empty() {
+        let disposition = part.get_content_disposition();
+        let name = disposition
+            .params
+            .get("filename")
+            .or_else(|| part.ctype.params.get("name"));
+        return match name {
+            Some(name) if name == filename => Some(part),
+            _ => None,
+        };
+    }
+
+    part.subparts
+        .iter()
+        .find_map(|child| find_attachment(child, filename))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &str = "From: <joe@example.org>\r\n\
+        Subject: Report\r\n\
+        X-Folded: first\r\n\
+        \tsecond\r\n\
+        Content-Type: multipart/mixed; boundary=foo\r\n\
+        \r\n\
+        --foo\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        Hello!\r\n\
+        --foo\r\n\
+        Content-Disposition: attachment; filename=\"a.txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        Content-Transfer-Encoding: base64\r\n\
+        \r\n\
+        b2xk\r\n\
+        --foo--\r\n";
+
+    #[test]
+    fn test_add_header() {
+        let mut editor = MessageEditor::new(MESSAGE).unwrap();
+        editor.add_header(("X-Archived", "yes")).unwrap();
+
+        let expected = MESSAGE.replacen("\r\n\r\n", "\r\nX-Archived: yes\r\n\r\n", 1);
+        assert_eq!(editor.as_bytes(), expected.as_bytes());
+    }
+
+    #[test]
+    fn test_add_header_keeps_line_endings() {
+        let mut editor = MessageEditor::new("Subject: Hi\n\nBody\n").unwrap();
+        editor.add_header(("X-Archived", "yes")).unwrap();
+        assert_eq!(editor.as_bytes(), b"Subject: Hi\nX-Archived: yes\n\nBody\n");
+    }
+
+    #[test]
+    fn test_add_header_without_body() {
+        let mut editor = MessageEditor::new("Subject: Hi\r\n").unwrap();
+        editor.add_header(("X-A", "b")).unwrap();
+        assert_eq!(editor.as_bytes(), b"Subject: Hi\r\nX-A: b\r\n");
+
+        let mut editor = MessageEditor::new("Subject: Hi").unwrap();
+        editor.add_header(("X-A", "b")).unwrap();
+        assert_eq!(editor.as_bytes(), b"Subject: Hi\r\nX-A: b\r\n");
+    }
+
+    #[test]
+    fn test_remove_header() {
+        let mut editor = MessageEditor::new(MESSAGE).unwrap();
+        assert_eq!(editor.remove_header("x-folded").unwrap(), 1);
+        assert_eq!(editor.remove_header("X-Missing").unwrap(), 0);
+
+        let expected = MESSAGE.replace("X-Folded: first\r\n\tsecond\r\n", "");
+        assert_eq!(editor.as_bytes(), expected.as_bytes());
+    }
+
+    #[test]
+    fn test_replace_attachment() {
+        let mut editor = MessageEditor::new(MESSAGE).unwrap();
+        editor
+            .replace_attachment("a.txt", b"new", &mime::TEXT_PLAIN)
+            .unwrap();
+
+        let result = String::from_utf8(editor.into_bytes()).unwrap();
+        let start = MESSAGE.find("Content-Disposition").unwrap();
+        let end = MESSAGE.find("--foo--").unwrap();
+        assert_eq!(&result[..start], &MESSAGE[..start]);
+        assert!(result.ends_with("\r\n\r\nbmV3\r\n--foo--\r\n"));
+        assert_eq!(
+            &result[result.len() - (MESSAGE.len() - end)..],
+            &MESSAGE[end..]
+        );
+        assert!(!result.contains("b2xk"));
+    }
+
+    #[test]
+    fn test_replace_missing_attachment() {
+        let mut editor = MessageEditor::new(MESSAGE).unwrap();
+        assert!(matches!(
+            editor.replace_attachment("b.txt", b"new", &mime::TEXT_PLAIN),
+            Err(Error::AttachmentNotFound(filename)) if filename == "b.txt"
+        ));
+        assert_eq!(editor.as_bytes(), MESSAGE.as_bytes());
+    }
+}
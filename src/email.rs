@@ -1,12 +1,117 @@
 //! General types for Email messages.
 
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
 #[cfg(feature = "serde")]
 use serde_crate::{Deserialize, Serialize};
 
+use crate::{punycode_domain, FromHeader};
 pub use crate::{Address, Header, Mailbox, MimeMessage, MimeMultipartType};
 
-/// Represents a message id
-pub type MessageId = String;
+/// A message id (RFC 5322, section 3.6.4), as used by the `Message-ID`,
+/// `In-Reply-To` and `References` headers.
+///
+/// Parsing accepts the id with or without angle brackets, displaying adds
+/// them.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(crate = "serde_crate")
+)]
+pub struct MessageId(String);
+
+/// Error values for parsing a `MessageId`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum MessageIdError {
+    /// The id is empty
+    #[error("empty message id")]
+    Empty,
+    /// The id lacks the `@` separating its left and right part, or one of
+    /// them is empty
+    #[error("message id must be of the form left@right")]
+    MissingAt,
+    /// The id contains whitespace, a control character or an angle bracket
+    #[error("invalid character {0:?} in message id")]
+    InvalidCharacter(char),
+}
+
+impl MessageId {
+    /// Creates the id `left@right`
+    pub fn new(left: &str, right: &str) -> Result<MessageId, MessageIdError> {
+        format!("{}@{}", left, right).parse()
+    }
+
+    /// The id without angle brackets
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for MessageId {
+    type Err = MessageIdError;
+
+    fn from_str(s: &str) -> Result<MessageId, MessageIdError> {
+        let s = s.trim();
+        let id = s
+            .strip_prefix('<')
+            .and_then(|id| id.strip_suffix('>'))
+            .unwrap_or(s);
+        if id.is_empty() {
+            return Err(MessageIdError::Empty);
+        }
+        if let Some(c) = id
+            .chars()
+            .find(|&c| c.is_whitespace() || c.is_control() || c == '<' || c == '>')
+        {
+            return Err(MessageIdError::InvalidCharacter(c));
+        }
+        match id.rfind('@') {
+            Some(at) if at > 0 && at + 1 < id.len() => Ok(MessageId(id.to_string())),
+            _ => Err(MessageIdError::MissingAt),
+        }
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "<{}>", self.0)
+    }
+}
+
+impl FromHeader for MessageId {
+    type Error = MessageIdError;
+
+    fn from_header(value: &str) -> Result<MessageId, MessageIdError> {
+        value.parse()
+    }
+}
+
+/// Parses the ids of `In-Reply-To` or `References`, separated by
+/// whitespace, commas or nothing at all
+impl FromHeader for Vec<MessageId> {
+    type Error = MessageIdError;
+
+    fn from_header(value: &str) -> Result<Vec<MessageId>, MessageIdError> {
+        value
+            .replace('>', "> ")
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|id| !id.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+}
+
+/// Leading bytes of a queue blob, followed by the format version
+const QUEUE_BLOB_MAGIC: &[u8] = b"MSGQ";
+/// The format version written by `Email::to_queue_blob`
+const QUEUE_BLOB_VERSION: u8 = 1;
+
+/// Version of queue blobs whose envelope has DSN parameters
+const QUEUE_BLOB_DSN_VERSION: u8 = 2;
 
 /// Simple email representation
 #[derive(PartialEq, Eq, Clone, Debug)]
@@ -34,6 +139,353 @@ impl Email {
     pub fn message_to_string(self) -> Result<String, std::string::FromUtf8Error> {
         std::string::String::from_utf8(self.message)
     }
+
+    /// The raw message, without consuming the email
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.message
+    }
+
+    /// Writes the raw message to `writer`, e.g. to hand the same email to
+    /// several transports
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.message)
+    }
+
+    /// Builds a `mailto:` URL with the recipients, subject and plain text
+    /// body of this email, see `mailto::parse` for the reverse.
+    pub fn to_mailto_url(&self) -> Result<String, crate::Error> {
+        crate::mailto::to_url(self)
+    }
+
+    /// The message with generated values like dates, message ids and
+    /// boundaries replaced by placeholders, e.g. for snapshot tests
+    pub fn to_normalized_string(&self, normalizer: &crate::Normalizer) -> String {
+        normalizer.normalize(&String::from_utf8_lossy(&self.message))
+    }
+
+    /// Checks the message for common violations of RFC 5322 and RFC 2045,
+    /// like duplicate headers, long lines or bare line feeds
+    pub fn validate(&self) -> Vec<crate::ValidationIssue> {
+        crate::validate::validate(&self.message)
+    }
+
+    /// Splits this email into copies with the same content, each addressed
+    /// to at most `max_rcpt_per_message` envelope recipients.
+    ///
+    /// Groups are expanded into their mailboxes first. Fails with
+    /// `Error::ZeroRecipientLimit` if `max_rcpt_per_message` is 0.
+    pub fn split_recipients(
+        &self,
+        max_rcpt_per_message: usize,
+    ) -> Result<Vec<Email>, crate::Error> {
+        if max_rcpt_per_message == 0 {
+            return Err(crate::Error::ZeroRecipientLimit);
+        }
+        Ok(self
+            .envelope
+            .recipients()
+            .chunks(max_rcpt_per_message)
+            .map(|batch| self.with_recipients(batch.to_vec()))
+            .collect())
+    }
+
+    /// Splits this email into copies with the same content, one per
+    /// recipient domain, each addressed to at most `max_rcpt_per_message`
+    /// envelope recipients.
+    ///
+    /// Domains are grouped as by `Envelope::recipients_by_domain`, and
+    /// batches are ordered by domain. Fails with `Error::ZeroRecipientLimit`
+    /// if `max_rcpt_per_message` is 0.
+    pub fn split_recipients_by_domain(
+        &self,
+        max_rcpt_per_message: usize,
+    ) -> Result<Vec<Email>, crate::Error> {
+        if max_rcpt_per_message == 0 {
+            return Err(crate::Error::ZeroRecipientLimit);
+        }
+        Ok(self
+            .envelope
+            .recipients_by_domain()
+            .values()
+            .flat_map(|recipients| recipients.chunks(max_rcpt_per_message))
+            .map(|batch| self.with_recipients(batch.iter().map(|&m| m.clone()).collect()))
+            .collect())
+    }
+
+    /// Serializes the email with its envelope and message id into a single
+    /// buffer, e.g. to persist it in a send queue, which is read back with
+    /// `from_queue_blob`.
+    ///
+    /// The format is versioned: the magic bytes `MSGQ` and a version byte,
+    /// followed by the envelope, the message id and the message, each
+    /// prefixed by its length. Envelopes without DSN parameters are written
+    /// as version 1, readable by older releases.
+    pub fn to_queue_blob(&self) -> Vec<u8> {
+        let envelope = &self.envelope;
+        let has_dsn =
+            !envelope.notify.is_empty() || envelope.ret.is_some() || envelope.envid.is_some();
+        let mut blob = Vec::with_capacity(self.message.len() + 256);
+        blob.extend_from_slice(QUEUE_BLOB_MAGIC);
+        blob.push(if has_dsn {
+            QUEUE_BLOB_DSN_VERSION
+        } else {
+            QUEUE_BLOB_VERSION
+        });
+
+        match self.envelope.reverse_path {
+            Some(ref address) => {
+                blob.push(1);
+                write_address(&mut blob, address);
+            }
+            None => blob.push(0),
+        }
+        write_len(&mut blob, self.envelope.forward_path.len());
+        for address in self.envelope.forward_path.iter() {
+            write_address(&mut blob, address);
+        }
+        if has_dsn {
+            write_len(&mut blob, envelope.notify.len());
+            for (recipient, notify) in envelope.notify.iter() {
+                write_bytes(&mut blob, recipient.as_bytes());
+                blob.push(notify.to_flags());
+            }
+            blob.push(match envelope.ret {
+                None => 0,
+                Some(DsnReturn::Full) => 1,
+                Some(DsnReturn::Headers) => 2,
+            });
+            match envelope.envid {
+                Some(ref envid) => {
+                    blob.push(1);
+                    write_bytes(&mut blob, envid.as_bytes());
+                }
+                None => blob.push(0),
+            }
+        }
+        write_bytes(&mut blob, self.message_id.as_bytes());
+        write_bytes(&mut blob, &self.message);
+        blob
+    }
+
+    /// Reads an email written by `to_queue_blob`
+    pub fn from_queue_blob(blob: &[u8]) -> Result<Email, QueueBlobError> {
+        if blob.len() < QUEUE_BLOB_MAGIC.len() + 1 || !blob.starts_with(QUEUE_BLOB_MAGIC) {
+            return Err(QueueBlobError::UnknownFormat);
+        }
+        let has_dsn = match blob[QUEUE_BLOB_MAGIC.len()] {
+            QUEUE_BLOB_VERSION => false,
+            QUEUE_BLOB_DSN_VERSION => true,
+            _ => return Err(QueueBlobError::UnknownFormat),
+        };
+        let mut reader = BlobReader {
+            blob: &blob[QUEUE_BLOB_MAGIC.len() + 1..],
+        };
+
+        let reverse_path = match reader.byte()? {
+            0 => None,
+            1 => Some(reader.address()?),
+            _ => return Err(QueueBlobError::Malformed),
+        };
+        let count = reader.len()?;
+        let forward_path = (0..count)
+            .map(|_| reader.address())
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut envelope = Envelope::new(reverse_path, forward_path)?;
+        if has_dsn {
+            let count = reader.len()?;
+            for _ in 0..count {
+                let recipient = reader.string()?;
+                let notify =
+                    DsnNotify::from_flags(reader.byte()?).ok_or(QueueBlobError::Malformed)?;
+                envelope.notify.push((recipient, notify));
+            }
+            envelope.ret = match reader.byte()? {
+                0 => None,
+                1 => Some(DsnReturn::Full),
+                2 => Some(DsnReturn::Headers),
+                _ => return Err(QueueBlobError::Malformed),
+            };
+            envelope.envid = match reader.byte()? {
+                0 => None,
+                1 => Some(reader.string()?),
+                _ => return Err(QueueBlobError::Malformed),
+            };
+        }
+        let message_id = reader.string()?;
+        let message = reader.bytes()?.to_vec();
+        if !reader.blob.is_empty() {
+            return Err(QueueBlobError::Malformed);
+        }
+
+        Ok(Email {
+            message,
+            envelope,
+            message_id,
+        })
+    }
+
+    fn with_recipients(&self, to: Vec<Mailbox>) -> Email {
+        let notify = self
+            .envelope
+            .notify
+            .iter()
+            .filter(|(recipient, _)| {
+                to.iter()
+                    .any(|mailbox| mailbox.address.eq_ignore_ascii_case(recipient))
+            })
+            .cloned()
+            .collect();
+        Email {
+            message: self.message.clone(),
+            envelope: Envelope {
+                forward_path: to.into_iter().map(Address::Mailbox).collect(),
+                reverse_path: self.envelope.reverse_path.clone(),
+                notify,
+                ret: self.envelope.ret,
+                envid: self.envelope.envid.clone(),
+            },
+            message_id: self.message_id.clone(),
+        }
+    }
+}
+
+/// A concise summary for log lines, e.g.
+/// `id=<...> from=<joe@example.org> rcpts=2 size=1843`
+impl fmt::Display for Email {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "id={} {} size={}",
+            self.message_id,
+            self.envelope,
+            self.message.len()
+        )
+    }
+}
+
+/// Error values for reading a queue blob with `Email::from_queue_blob`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum QueueBlobError {
+    /// Not a queue blob, or one written by an incompatible version
+    #[error("unknown queue blob format")]
+    UnknownFormat,
+    /// The blob ends within a field
+    #[error("truncated queue blob")]
+    Truncated,
+    /// A field has an invalid value, or there is trailing data
+    #[error("malformed queue blob")]
+    Malformed,
+    /// The envelope is invalid
+    #[error("invalid envelope: {0}")]
+    Envelope(#[from] EnvelopeError),
+}
+
+fn write_len(blob: &mut Vec<u8>, len: usize) {
+    blob.extend_from_slice(&(len as u32).to_be_bytes());
+}
+
+fn write_bytes(blob: &mut Vec<u8>, bytes: &[u8]) {
+    write_len(blob, bytes.len());
+    blob.extend_from_slice(bytes);
+}
+
+fn write_mailbox(blob: &mut Vec<u8>, mailbox: &Mailbox) {
+    match mailbox.name {
+        Some(ref name) => {
+            blob.push(1);
+            write_bytes(blob, name.as_bytes());
+        }
+        None => blob.push(0),
+    }
+    write_bytes(blob, mailbox.address.as_bytes());
+}
+
+fn write_address(blob: &mut Vec<u8>, address: &Address) {
+    match address {
+        Address::Mailbox(mailbox) => {
+            blob.push(0);
+            write_mailbox(blob, mailbox);
+        }
+        Address::Group(name, mailboxes) => {
+            blob.push(1);
+            write_bytes(blob, name.as_bytes());
+            write_len(blob, mailboxes.len());
+            for mailbox in mailboxes {
+                write_mailbox(blob, mailbox);
+            }
+        }
+    }
+}
+
+/// Reads the fields of a queue blob one after another
+struct BlobReader<'a> {
+    blob: &'a [u8],
+}
+
+impl<'a> BlobReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], QueueBlobError> {
+        if self.blob.len() < len {
+            return Err(QueueBlobError::Truncated);
+        }
+        let (taken, rest) = self.blob.split_at(len);
+        self.blob = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, QueueBlobError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn len(&mut self) -> Result<usize, QueueBlobError> {
+        let mut len = [0; 4];
+        len.copy_from_slice(self.take(4)?);
+        Ok(u32::from_be_bytes(len) as usize)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], QueueBlobError> {
+        let len = self.len()?;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String, QueueBlobError> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| QueueBlobError::Malformed)
+    }
+
+    fn mailbox(&mut self) -> Result<Mailbox, QueueBlobError> {
+        let name = match self.byte()? {
+            0 => None,
+            1 => Some(self.string()?),
+            _ => return Err(QueueBlobError::Malformed),
+        };
+        Ok(Mailbox {
+            name,
+            address: self.string()?,
+        })
+    }
+
+    fn address(&mut self) -> Result<Address, QueueBlobError> {
+        match self.byte()? {
+            0 => Ok(Address::Mailbox(self.mailbox()?)),
+            1 => {
+                let name = self.string()?;
+                let count = self.len()?;
+                let mailboxes = (0..count)
+                    .map(|_| self.mailbox())
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Address::Group(name, mailboxes))
+            }
+            _ => Err(QueueBlobError::Malformed),
+        }
+    }
+}
+
+/// The lowercased domain part of an address, or an empty string if there
+/// is none.
+pub(crate) fn domain_of(address: &str) -> String {
+    match address.rfind('@') {
+        Some(at) => address[at + 1..].to_lowercase(),
+        None => String::new(),
+    }
 }
 
 /// Simple email envelope representation
@@ -52,10 +504,19 @@ pub struct Envelope {
     forward_path: Vec<Address>,
     /// The envelope sender address
     reverse_path: Option<Address>,
+    /// The DSN conditions of the recipients which have them
+    #[cfg_attr(feature = "serde", serde(default))]
+    notify: Vec<(String, DsnNotify)>,
+    /// What a DSN returns of the message
+    #[cfg_attr(feature = "serde", serde(default))]
+    ret: Option<DsnReturn>,
+    /// The envelope identifier returned in DSNs
+    #[cfg_attr(feature = "serde", serde(default))]
+    envid: Option<String>,
 }
 
 /// Error values for `Envelope` construction.
-#[derive(Copy, Clone, Debug, thiserror::Error)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, thiserror::Error)]
 pub enum EnvelopeError {
     /// Missing to in the envelope.
     #[error("missing destination address")]
@@ -63,6 +524,97 @@ pub enum EnvelopeError {
     /// Missing from in the envelope.
     #[error("missing from address")]
     MissingFrom,
+    /// The envelope identifier is empty, too long or not printable ASCII.
+    #[error("invalid envelope identifier")]
+    InvalidEnvid,
+}
+
+/// The conditions under which a recipient's server sends a delivery status
+/// notification, the `NOTIFY` parameter of `RCPT TO` (RFC 3461, section
+/// 4.1). `DsnNotify::default()` is `NEVER`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(crate = "serde_crate")
+)]
+pub struct DsnNotify {
+    /// Notify on successful delivery
+    pub success: bool,
+    /// Notify on failed delivery
+    pub failure: bool,
+    /// Notify on delayed delivery
+    pub delay: bool,
+}
+
+impl DsnNotify {
+    /// Only notify on failed delivery
+    pub fn failure() -> DsnNotify {
+        DsnNotify {
+            failure: true,
+            ..DsnNotify::default()
+        }
+    }
+
+    fn to_flags(self) -> u8 {
+        self.success as u8 | (self.failure as u8) << 1 | (self.delay as u8) << 2
+    }
+
+    fn from_flags(flags: u8) -> Option<DsnNotify> {
+        if flags > 0b111 {
+            return None;
+        }
+        Some(DsnNotify {
+            success: flags & 1 != 0,
+            failure: flags & 2 != 0,
+            delay: flags & 4 != 0,
+        })
+    }
+}
+
+/// The parameter value, e.g. `SUCCESS,FAILURE` or `NEVER`
+impl fmt::Display for DsnNotify {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let conditions = [
+            (self.success, "SUCCESS"),
+            (self.failure, "FAILURE"),
+            (self.delay, "DELAY"),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>();
+        if conditions.is_empty() {
+            fmt.write_str("NEVER")
+        } else {
+            fmt.write_str(&conditions.join(","))
+        }
+    }
+}
+
+/// What a delivery status notification includes of the message, the `RET`
+/// parameter of `MAIL FROM` (RFC 3461, section 4.3)
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(crate = "serde_crate")
+)]
+pub enum DsnReturn {
+    /// The full message
+    Full,
+    /// Only the headers
+    Headers,
+}
+
+/// The parameter value, `FULL` or `HDRS`
+impl fmt::Display for DsnReturn {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(match self {
+            DsnReturn::Full => "FULL",
+            DsnReturn::Headers => "HDRS",
+        })
+    }
 }
 
 impl Envelope {
@@ -74,6 +626,9 @@ impl Envelope {
         Ok(Envelope {
             forward_path: to,
             reverse_path: from,
+            notify: vec![],
+            ret: None,
+            envid: None,
         })
     }
 
@@ -86,4 +641,386 @@ impl Envelope {
     pub fn from(&self) -> Option<&Address> {
         self.reverse_path.as_ref()
     }
+
+    /// Whether an address of the envelope is internationalized, so the
+    /// message has to be sent with the SMTPUTF8 extension (RFC 6531)
+    pub fn requires_smtputf8(&self) -> bool {
+        self.reverse_path
+            .iter()
+            .chain(self.forward_path.iter())
+            .any(|address| match address {
+                Address::Mailbox(mailbox) => !mailbox.address.is_ascii(),
+                Address::Group(_, mailboxes) => {
+                    mailboxes.iter().any(|mailbox| !mailbox.address.is_ascii())
+                }
+            })
+    }
+
+    /// The recipients grouped by domain, e.g. for routing each group to
+    /// its own smart host.
+    ///
+    /// The domains are lowercased and internationalized ones converted to
+    /// A-labels, so `Bücher.example` and `xn--bcher-kva.example` end up in
+    /// the same group. Mailboxes of groups are included, and recipients
+    /// without a domain are grouped under the empty string.
+    pub fn recipients_by_domain(&self) -> BTreeMap<String, Vec<&Mailbox>> {
+        let mut domains = BTreeMap::<String, Vec<&Mailbox>>::new();
+        let mailboxes = self.forward_path.iter().flat_map(|address| match address {
+            Address::Mailbox(mailbox) => std::slice::from_ref(mailbox),
+            Address::Group(_, mailboxes) => &mailboxes[..],
+        });
+        for mailbox in mailboxes {
+            domains
+                .entry(punycode_domain(&domain_of(&mailbox.address)))
+                .or_default()
+                .push(mailbox);
+        }
+        domains
+    }
+
+    /// Sets the DSN conditions of `recipient`, compared ignoring case
+    pub fn set_notify(&mut self, recipient: &str, notify: DsnNotify) {
+        match self
+            .notify
+            .iter_mut()
+            .find(|(address, _)| address.eq_ignore_ascii_case(recipient))
+        {
+            Some(entry) => entry.1 = notify,
+            None => self.notify.push((recipient.to_string(), notify)),
+        }
+    }
+
+    /// The DSN conditions of `recipient`, `None` to leave them to the
+    /// server
+    pub fn notify(&self, recipient: &str) -> Option<DsnNotify> {
+        self.notify
+            .iter()
+            .find(|(address, _)| address.eq_ignore_ascii_case(recipient))
+            .map(|(_, notify)| *notify)
+    }
+
+    /// Sets what DSNs return of the message
+    pub fn set_ret(&mut self, ret: DsnReturn) {
+        self.ret = Some(ret);
+    }
+
+    /// What DSNs return of the message, if requested
+    pub fn ret(&self) -> Option<DsnReturn> {
+        self.ret
+    }
+
+    /// Sets the envelope identifier returned in DSNs, which has to be
+    /// printable ASCII of up to 100 characters. Transports xtext encode it
+    /// for the `ENVID` parameter.
+    pub fn set_envid<S: Into<String>>(&mut self, envid: S) -> Result<(), EnvelopeError> {
+        let envid = envid.into();
+        if envid.is_empty() || envid.len() > 100 || !envid.bytes().all(|b| b.is_ascii_graphic()) {
+            return Err(EnvelopeError::InvalidEnvid);
+        }
+        self.envid = Some(envid);
+        Ok(())
+    }
+
+    /// The envelope identifier returned in DSNs, if set
+    pub fn envid(&self) -> Option<&str> {
+        self.envid.as_deref()
+    }
+
+    /// Destination mailboxes, with groups expanded
+    fn recipients(&self) -> Vec<Mailbox> {
+        self.forward_path
+            .iter()
+            .flat_map(|address| match address {
+                Address::Mailbox(mailbox) => vec![mailbox.clone()],
+                Address::Group(_, mailboxes) => mailboxes.clone(),
+            })
+            .collect()
+    }
+}
+
+/// A concise summary for log lines, e.g. `from=<joe@example.org> rcpts=2`
+///
+/// Groups are expanded when counting recipients, and a missing sender is
+/// shown as the null reverse path `<>`.
+impl fmt::Display for Envelope {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let from = match self.reverse_path {
+            Some(Address::Mailbox(ref mailbox)) => mailbox.address.as_str(),
+            Some(Address::Group(_, ref mailboxes)) => mailboxes
+                .first()
+                .map(|mailbox| mailbox.address.as_str())
+                .unwrap_or(""),
+            None => "",
+        };
+        write!(fmt, "from=<{}> rcpts={}", from, self.recipients().len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email_to(recipients: &[&str]) -> Email {
+        let mut builder = Email::builder().from("sender@example.org").body("Hello");
+        for recipient in recipients {
+            builder = builder.to(*recipient);
+        }
+        builder.build().unwrap()
+    }
+
+    fn recipients(email: &Email) -> Vec<String> {
+        email
+            .envelope
+            .to()
+            .iter()
+            .map(|address| match address {
+                Address::Mailbox(mailbox) => mailbox.address.clone(),
+                Address::Group(..) => panic!("unexpected group"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_split_recipients() {
+        let email = email_to(&["a@x.org", "b@y.org", "c@x.org"]);
+        let batches = email.split_recipients(2).unwrap();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(recipients(&batches[0]), vec!["a@x.org", "b@y.org"]);
+        assert_eq!(recipients(&batches[1]), vec!["c@x.org"]);
+        for batch in batches.iter() {
+            assert_eq!(batch.message, email.message);
+            assert_eq!(batch.message_id, email.message_id);
+            assert_eq!(batch.envelope.from(), email.envelope.from());
+        }
+    }
+
+    #[test]
+    fn test_split_recipients_expands_groups() {
+        let envelope = Envelope::new(
+            None,
+            vec![Address::new_group(
+                "team".to_string(),
+                vec![
+                    Mailbox::new("a@x.org".to_string()),
+                    Mailbox::new("b@x.org".to_string()),
+                ],
+            )],
+        )
+        .unwrap();
+        let email = email_to(&["a@x.org"]);
+        let email = Email { envelope, ..email };
+        let batches = email.split_recipients(1).unwrap();
+        assert_eq!(recipients(&batches[0]), vec!["a@x.org"]);
+        assert_eq!(recipients(&batches[1]), vec!["b@x.org"]);
+    }
+
+    #[test]
+    fn test_split_recipients_by_domain() {
+        let email = email_to(&["a@y.org", "b@X.org", "c@x.org", "d@x.org"]);
+        let batches = email.split_recipients_by_domain(2).unwrap();
+        assert_eq!(
+            batches.iter().map(recipients).collect::<Vec<_>>(),
+            vec![
+                vec!["b@X.org".to_string(), "c@x.org".to_string()],
+                vec!["d@x.org".to_string()],
+                vec!["a@y.org".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_recipients_zero_limit() {
+        let email = email_to(&["a@x.org"]);
+        assert!(matches!(
+            email.split_recipients(0),
+            Err(crate::Error::ZeroRecipientLimit)
+        ));
+        assert!(matches!(
+            email.split_recipients_by_domain(0),
+            Err(crate::Error::ZeroRecipientLimit)
+        ));
+    }
+
+    #[test]
+    fn test_recipients_by_domain() {
+        let envelope = Envelope::new(
+            None,
+            vec![
+                Address::new_mailbox("a@Bücher.example".to_string()),
+                Address::new_group(
+                    "team".to_string(),
+                    vec![
+                        Mailbox::new("b@xn--bcher-kva.example".to_string()),
+                        Mailbox::new("c@X.org".to_string()),
+                    ],
+                ),
+                Address::new_mailbox("local".to_string()),
+            ],
+        )
+        .unwrap();
+        let domains = envelope.recipients_by_domain();
+        assert_eq!(
+            domains.keys().collect::<Vec<_>>(),
+            vec!["", "x.org", "xn--bcher-kva.example"]
+        );
+        assert_eq!(
+            domains["xn--bcher-kva.example"]
+                .iter()
+                .map(|mailbox| mailbox.address.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a@Bücher.example", "b@xn--bcher-kva.example"]
+        );
+    }
+
+    #[test]
+    fn test_message_id() {
+        let id: MessageId = " <abc.123@example.org> ".parse().unwrap();
+        assert_eq!(id.as_str(), "abc.123@example.org");
+        assert_eq!(id.to_string(), "<abc.123@example.org>");
+        assert_eq!("abc.123@example.org".parse(), Ok(id));
+        assert_eq!(
+            MessageId::new("abc", "example.org").unwrap().to_string(),
+            "<abc@example.org>"
+        );
+
+        assert_eq!("<>".parse::<MessageId>(), Err(MessageIdError::Empty));
+        assert_eq!(
+            "original".parse::<MessageId>(),
+            Err(MessageIdError::MissingAt)
+        );
+        assert_eq!(
+            "@example.org".parse::<MessageId>(),
+            Err(MessageIdError::MissingAt)
+        );
+        assert_eq!(
+            "a b@example.org".parse::<MessageId>(),
+            Err(MessageIdError::InvalidCharacter(' '))
+        );
+        assert_eq!(
+            "<<a@example.org>>".parse::<MessageId>(),
+            Err(MessageIdError::InvalidCharacter('<'))
+        );
+    }
+
+    #[test]
+    fn test_queue_blob() {
+        let email = email_to(&["a@x.org"]);
+        let group = Address::new_group(
+            "team".to_string(),
+            vec![Mailbox::new_with_name(
+                "Jörg".to_string(),
+                "b@x.org".to_string(),
+            )],
+        );
+        let envelope = Envelope::new(
+            Some(Address::new("bounces@example.org").unwrap()),
+            vec![Address::new("a@x.org").unwrap(), group],
+        )
+        .unwrap();
+        let email = Email { envelope, ..email };
+
+        let blob = email.to_queue_blob();
+        assert!(blob.starts_with(b"MSGQ\x01"));
+        assert_eq!(Email::from_queue_blob(&blob).unwrap(), email);
+
+        let mut dsn = email.clone();
+        dsn.envelope.set_notify("a@x.org", DsnNotify::failure());
+        dsn.envelope.set_ret(DsnReturn::Headers);
+        dsn.envelope.set_envid("QQ314159").unwrap();
+        let dsn_blob = dsn.to_queue_blob();
+        assert!(dsn_blob.starts_with(b"MSGQ\x02"));
+        assert_eq!(Email::from_queue_blob(&dsn_blob).unwrap(), dsn);
+
+        assert_eq!(
+            Email::from_queue_blob(&blob[..blob.len() - 1]),
+            Err(QueueBlobError::Truncated)
+        );
+        assert_eq!(
+            Email::from_queue_blob(b"MSGQ\x03"),
+            Err(QueueBlobError::UnknownFormat)
+        );
+        let mut trailing = blob;
+        trailing.push(0);
+        assert_eq!(
+            Email::from_queue_blob(&trailing),
+            Err(QueueBlobError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_write_to() {
+        let email = email_to(&["a@x.org"]);
+        let mut written = vec![];
+        email.write_to(&mut written).unwrap();
+        assert_eq!(written, email.as_bytes());
+        assert_eq!(
+            email.as_bytes(),
+            email.clone().message_to_string().unwrap().as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let email = email_to(&["a@x.org", "b@y.org"]);
+        assert_eq!(
+            email.to_string(),
+            format!(
+                "id={} from=<sender@example.org> rcpts=2 size={}",
+                email.message_id,
+                email.message.len()
+            )
+        );
+
+        let envelope = Envelope::new(None, vec![Address::new("a@x.org").unwrap()]).unwrap();
+        assert_eq!(envelope.to_string(), "from=<> rcpts=1");
+    }
+
+    #[test]
+    fn test_dsn_parameters() {
+        let mut envelope = Envelope::new(
+            Some(Address::new("bounces@example.org").unwrap()),
+            vec![
+                Address::new("a@x.org").unwrap(),
+                Address::new("b@y.org").unwrap(),
+            ],
+        )
+        .unwrap();
+        envelope.set_notify("A@x.org", DsnNotify::default());
+        envelope.set_notify(
+            "a@x.org",
+            DsnNotify {
+                success: true,
+                failure: true,
+                delay: false,
+            },
+        );
+        assert_eq!(
+            envelope.notify("a@X.org").unwrap().to_string(),
+            "SUCCESS,FAILURE"
+        );
+        assert_eq!(envelope.notify("b@y.org"), None);
+        assert_eq!(DsnNotify::default().to_string(), "NEVER");
+        assert_eq!(
+            envelope.set_envid("has space"),
+            Err(EnvelopeError::InvalidEnvid)
+        );
+        assert_eq!(envelope.envid(), None);
+
+        envelope.set_ret(DsnReturn::Full);
+        let email = Email {
+            envelope,
+            ..email_to(&["a@x.org"])
+        };
+        let split = email.split_recipients(1).unwrap();
+        assert_eq!(
+            split[0].envelope.notify("a@x.org"),
+            Some(DsnNotify {
+                success: true,
+                failure: true,
+                delay: false
+            })
+        );
+        assert_eq!(split[1].envelope.notify("a@x.org"), None);
+        assert_eq!(split[1].envelope.ret().unwrap().to_string(), "FULL");
+    }
 }
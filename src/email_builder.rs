@@ -1,19 +1,59 @@
+use std::borrow::Cow;
 use std::ffi::OsStr;
-use std::path::Path;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::{fs, io};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{fmt, fs, io};
+
+#[cfg(feature = "async")]
+use futures_util::io::AsyncRead;
 
 use mime::Mime;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
 use time::OffsetDateTime;
 use uuid::Uuid;
 
+use crate::body::{self, TextKind};
+use crate::calendar::{calendar_part, CalendarMethod};
+use crate::date::{format_date, DateHeader};
+use crate::dmarc::DmarcAlignment;
+use crate::draft::{Draft, DraftPart};
 use crate::email::{Email, Envelope, EnvelopeError, MessageId};
-use crate::{Address, Header, Mailbox, MimeMessage, MimeMultipartType};
-
-const RFC822Z_TIME_FORMAT: &str = "%a, %d %b %Y %T %z";
+use crate::hash::ContentHash;
+use crate::header::encode_header_value;
+use crate::identity::Identity;
+use crate::list;
+use crate::markup::{self, render_markdown, MarkdownRenderer};
+use crate::message::reseed_boundaries;
+#[cfg(feature = "crypto")]
+use crate::pgp;
+use crate::protected;
+use crate::quote::SIGNATURE_DELIMITER;
+#[cfg(feature = "auto-text")]
+use crate::render::{render_text, RenderOptions};
+use crate::reply::{self, ForwardMode, ReplyMode};
+use crate::retention::Sensitivity;
+use crate::rfc5322::{is_field_name, is_safely_folded, SerializationConfig, MIME_LINE_LENGTH};
+#[cfg(feature = "crypto")]
+use crate::smime::{self, SmimeEncryptor, SmimeSigner};
+use crate::smtputf8;
+#[cfg(feature = "storage")]
+use crate::store::AttachmentStore;
+use crate::template::BodyTemplate;
+#[cfg(feature = "storage")]
+use crate::upload::ChunkedUpload;
+use crate::validate::ValidationIssue;
+use crate::{
+    Address, ContentDisposition, Header, HeaderMap, Mailbox, MimeMessage, MimeMultipartType,
+};
 
 lazy_static::lazy_static! {
-    static ref LINE_BREAKS_RE: regex::Regex = regex::Regex::new(r"(\r\n|\r|\n)").unwrap();
+    pub(crate) static ref LINE_BREAKS_RE: regex::Regex = regex::Regex::new(r"(\r\n|\r|\n)").unwrap();
 }
 
 /// Builds a `MimeMessage` structure
@@ -21,6 +61,45 @@ lazy_static::lazy_static! {
 pub struct PartBuilder {
     /// Message
     message: MimeMessage,
+    encoding_policy: EncodingPolicy,
+}
+
+/// How the bodies of parts without a `Content-Transfer-Encoding` header are
+/// encoded when building
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(crate = "serde_crate")
+)]
+pub enum EncodingPolicy {
+    /// Bodies are emitted as they are, which is the default
+    #[default]
+    Raw,
+    /// Bodies are left as 7bit if they are ASCII with lines of up to 998
+    /// characters, and quoted-printable or base64 encoded otherwise
+    Auto,
+}
+
+/// How `EmailBuilder` fills in the `Sender` header, which names the mailbox
+/// actually sending the message (RFC 5322, section 3.6.2)
+///
+/// With either policy, a `Sender` equal to a single `From` mailbox is
+/// omitted, as it adds nothing.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(crate = "serde_crate")
+)]
+pub enum SenderPolicy {
+    /// When there are several `From` addresses but no `Sender`, the first
+    /// `From` mailbox becomes the `Sender`, which is the default
+    #[default]
+    FirstFrom,
+    /// When there are several `From` addresses but no `Sender`, building
+    /// fails with `Error::MissingSender`
+    Explicit,
 }
 
 impl Default for PartBuilder {
@@ -30,22 +109,415 @@ impl Default for PartBuilder {
 }
 
 /// An enum of all error kinds.
+///
+/// Errors name the value they were caused by, like the address which
+/// failed to parse or the file which could not be read.
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum Error {
     /// Envelope error
-    #[error("Envelope")]
+    #[error("Invalid envelope: {0}")]
     Envelope(#[from] EnvelopeError),
-    /// Envelope error
-    #[error("Address")]
-    Address(#[from] mailparse::MailParseError),
-    /// Unparseable filename for attachment
-    #[error("Cannot parse filename")]
-    CannotParseFilename,
+    /// An address or address list failed to parse
+    #[error("Invalid address {value:?}: {source}")]
+    Address {
+        /// The value which failed to parse
+        value: String,
+        /// The parser error
+        #[source]
+        source: mailparse::MailParseError,
+    },
+    /// The filename of an attachment can't be taken from its path
+    #[error("Cannot take attachment filename from {0:?}")]
+    CannotParseFilename(PathBuf),
+    /// The raw message could not be parsed
+    #[error("Cannot parse message: {0}")]
+    Parse(#[source] mailparse::MailParseError),
+    /// The raw message is not UTF-8, e.g. an 8bit body in a legacy charset
+    #[error("Message is not valid UTF-8: {0}")]
+    InvalidUtf8(#[source] std::str::Utf8Error),
+    /// No attachment with the given filename
+    #[error("Attachment {0:?} not found")]
+    AttachmentNotFound(String),
+    /// A referenced attachment payload is missing from the store
+    #[error("Missing attachment payload {0}")]
+    MissingPayload(ContentHash),
+    /// No part at the given path of child indices
+    #[error("Part not found at {0:?}")]
+    PartNotFound(Vec<usize>),
+    /// No variant with the given key
+    #[error("Unknown variant {0:?}")]
+    UnknownVariant(String),
+    /// The From domain fails DMARC alignment
+    #[error("From address {0:?} not aligned for DMARC")]
+    DmarcMisaligned(String),
+    /// A signing or encryption callback failed
+    #[error("Crypto error: {0}")]
+    Crypto(String),
+    /// An HTML transform failed
+    #[error("HTML transform failed: {0}")]
+    Transform(String),
+    /// A body template failed to render
+    #[error("Template failed to render: {0}")]
+    Template(String),
+    /// There are several From addresses but no Sender
+    #[error("Missing sender for several From addresses")]
+    MissingSender,
+    /// An attachment hook rejected the attachment with the given filename
+    #[error("Attachment {0:?} rejected")]
+    AttachmentRejected(String),
+    /// An envelope address is invalid, or needs SMTPUTF8 which is not
+    /// enabled
+    #[error("Invalid address {0:?}")]
+    InvalidAddress(String),
+    /// A URL is malformed, or has the wrong scheme
+    #[error("Invalid URL {0:?}")]
+    InvalidUrl(String),
+    /// A `List-Id` is not of the form `label.domain`
+    #[error("Invalid list id {0:?}")]
+    InvalidListId(String),
+    /// A header name isn't made of printable ASCII without colons, or the
+    /// value of a header contains control characters
+    #[error("Invalid header {0:?}")]
+    InvalidHeader(String),
+    /// A header contains a line break which would start another header or
+    /// the body, e.g. from untrusted input, with the name of the header
+    #[error("Header injection in {0:?}")]
+    HeaderInjection(String),
+    /// A limit of recipients per message is zero
+    #[error("Recipient limit must be at least 1")]
+    ZeroRecipientLimit,
+    /// A chunk index is not below the total number of chunks
+    #[cfg(feature = "storage")]
+    #[error("Chunk index {0} out of range")]
+    ChunkOutOfRange(usize),
+    /// An upload is missing the chunks with the given indices
+    #[cfg(feature = "storage")]
+    #[error("Upload incomplete, missing chunks {0:?}")]
+    IncompleteUpload(Vec<usize>),
+    /// A file could not be read or written
+    #[error("Cannot access {path:?}: {source}")]
+    File {
+        /// The path of the file
+        path: PathBuf,
+        /// The underlying error
+        #[source]
+        source: io::Error,
+    },
+    /// Zip archive error
+    #[cfg(feature = "zip")]
+    #[error("Zip error: {0}")]
+    Zip(#[from] zip::result::ZipError),
     /// IO error
-    #[error("IO error")]
+    #[error("IO error: {0}")]
     Io(#[from] io::Error),
 }
 
+impl Error {
+    /// Maps a parser error for the address list `value`
+    pub(crate) fn address(value: &str) -> impl FnOnce(mailparse::MailParseError) -> Error + '_ {
+        move |source| Error::Address {
+            value: value.to_string(),
+            source,
+        }
+    }
+
+    /// Maps an error accessing the file at `path`
+    pub(crate) fn file(path: &Path) -> impl FnOnce(io::Error) -> Error + '_ {
+        move |source| Error::File {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+/// Describes an attachment passed to an `AttachmentHook`
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct AttachmentMeta {
+    /// The filename of the attachment
+    pub filename: String,
+    /// The declared content type of the attachment
+    pub content_type: Mime,
+}
+
+/// Preprocesses the content of an attachment before it gets encoded,
+/// e.g. to downscale images or to virus-scan the content.
+pub trait AttachmentHook: Send + Sync {
+    /// Returns the content to attach, or an error rejecting the attachment
+    fn process(&self, meta: &AttachmentMeta, body: Vec<u8>) -> Result<Vec<u8>, Error>;
+}
+
+impl<F> AttachmentHook for F
+where
+    F: Fn(&AttachmentMeta, Vec<u8>) -> Result<Vec<u8>, Error> + Send + Sync,
+{
+    fn process(&self, meta: &AttachmentMeta, body: Vec<u8>) -> Result<Vec<u8>, Error> {
+        self(meta, body)
+    }
+}
+
+/// Hook applied to every email built by an `EmailBuilder` it is registered
+/// on, after all headers have been generated and right before the message
+/// is serialized.
+///
+/// This is the place for cross-cutting concerns like DKIM signing, footers,
+/// header stamping or metrics.
+pub trait BuildHook: Send + Sync {
+    /// Inspects or modifies the finished message and its envelope
+    fn before_finalize(
+        &self,
+        message: &mut MimeMessage,
+        envelope: &mut Envelope,
+    ) -> Result<(), Error>;
+}
+
+impl<F> BuildHook for F
+where
+    F: Fn(&mut MimeMessage, &mut Envelope) -> Result<(), Error> + Send + Sync,
+{
+    fn before_finalize(
+        &self,
+        message: &mut MimeMessage,
+        envelope: &mut Envelope,
+    ) -> Result<(), Error> {
+        self(message, envelope)
+    }
+}
+
+/// Measurements of a single build, passed to `BuildObserver`s
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct BuildStats {
+    /// The time from the start of the build until the message was
+    /// serialized
+    pub duration: Duration,
+    /// The time spent serializing the finished message, part of
+    /// `duration`
+    pub serialize_duration: Duration,
+    /// The size of the serialized message in bytes
+    pub size: usize,
+    /// The number of MIME parts, the message itself included
+    pub parts: usize,
+}
+
+/// Observes the builds of an `EmailBuilder` it is registered on, e.g. to
+/// feed metrics of high-volume senders.
+///
+/// Observers are only called for successful builds.
+pub trait BuildObserver: Send + Sync {
+    /// Receives the measurements of a finished build
+    fn on_build(&self, stats: &BuildStats);
+}
+
+impl<F> BuildObserver for F
+where
+    F: Fn(&BuildStats) + Send + Sync,
+{
+    fn on_build(&self, stats: &BuildStats) {
+        self(stats)
+    }
+}
+
+/// A source of randomness for the multipart boundaries, the generated
+/// Message-ID and inline `Content-ID`s, e.g. a specific CSPRNG or a
+/// deterministic generator for fuzzing, see `EmailBuilder::random_source`.
+pub trait RandomSource: Send + Sync {
+    /// Fills `dest` with random bytes
+    fn fill_bytes(&self, dest: &mut [u8]);
+
+    /// The seed this source continues from, kept in drafts. Sources which
+    /// can't be restored from a seed return `None`.
+    fn seed(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Sources are compared by identity
+impl PartialEq for dyn RandomSource {
+    fn eq(&self, other: &dyn RandomSource) -> bool {
+        let (this, other): (*const dyn RandomSource, *const dyn RandomSource) = (self, other);
+        std::ptr::eq(this as *const u8, other as *const u8)
+    }
+}
+
+impl Eq for dyn RandomSource {}
+
+impl fmt::Debug for dyn RandomSource {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "RandomSource")
+    }
+}
+
+/// Any `rand` generator behind a mutex, e.g. `Mutex::new(OsRng)`
+impl<R: RngCore + Send> RandomSource for std::sync::Mutex<R> {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        self.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .fill_bytes(dest)
+    }
+}
+
+/// The source of `EmailBuilder::with_rng`, a generator seeded with the
+/// current seed, which is advanced on every draw
+struct SeededSource(std::sync::Mutex<u64>);
+
+impl RandomSource for SeededSource {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        let mut seed = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut rng = StdRng::seed_from_u64(*seed);
+        rng.fill_bytes(dest);
+        *seed = rng.gen();
+    }
+
+    fn seed(&self) -> Option<u64> {
+        Some(
+            *self
+                .0
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+        )
+    }
+}
+
+/// Adapts a `RandomSource` to the `rand` traits
+struct SourceRng<'a>(&'a dyn RandomSource);
+
+impl RngCore for SourceRng<'_> {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0; 4];
+        self.0.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0; 8];
+        self.0.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.0.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Transforms the content of HTML parts when building, e.g. to inline CSS
+/// as many webmail clients strip `<style>` blocks.
+pub trait HtmlTransform: Send + Sync {
+    /// Returns the transformed HTML document
+    fn transform(&self, html: &str) -> Result<String, Error>;
+}
+
+impl<F> HtmlTransform for F
+where
+    F: Fn(&str) -> Result<String, Error> + Send + Sync,
+{
+    fn transform(&self, html: &str) -> Result<String, Error> {
+        self(html)
+    }
+}
+
+/// A complete message which can be embedded in another one by
+/// `EmailBuilder::attach_message`
+pub trait EmbeddableMessage {
+    /// The serialized message, with CRLF line endings
+    fn to_rfc822(&self) -> Cow<'_, str>;
+}
+
+impl EmbeddableMessage for Email {
+    fn to_rfc822(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.message)
+    }
+}
+
+impl EmbeddableMessage for MimeMessage {
+    fn to_rfc822(&self) -> Cow<'_, str> {
+        Cow::Owned(self.as_string())
+    }
+}
+
+/// Hooks registered on an `EmailBuilder`, compared by identity
+struct Hooks<H: ?Sized>(Vec<Arc<H>>);
+
+impl<H: ?Sized> Default for Hooks<H> {
+    fn default() -> Self {
+        Hooks(vec![])
+    }
+}
+
+impl<H: ?Sized> Clone for Hooks<H> {
+    fn clone(&self) -> Self {
+        Hooks(self.0.clone())
+    }
+}
+
+impl<H: ?Sized> PartialEq for Hooks<H> {
+    fn eq(&self, other: &Hooks<H>) -> bool {
+        self.0.len() == other.0.len()
+            && self
+                .0
+                .iter()
+                .zip(other.0.iter())
+                .all(|(a, b)| Arc::ptr_eq(a, b))
+    }
+}
+
+impl<H: ?Sized> Eq for Hooks<H> {}
+
+impl Hooks<dyn BuildObserver> {
+    /// Reports a build which started at `started` and whose serialization
+    /// started at `serializing`
+    fn observe(&self, message: &MimeMessage, started: Instant, serializing: Instant, size: usize) {
+        if self.0.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        let stats = BuildStats {
+            duration: now - started,
+            serialize_duration: now - serializing,
+            size,
+            parts: count_parts(message),
+        };
+        for observer in self.0.iter() {
+            observer.on_build(&stats);
+        }
+    }
+}
+
+fn count_parts(part: &MimeMessage) -> usize {
+    1 + part.children.iter().map(count_parts).sum::<usize>()
+}
+
+/// Counts the bytes written through it
+struct CountingWriter<W> {
+    inner: W,
+    count: usize,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<H: ?Sized> fmt::Debug for Hooks<H> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Hooks({})", self.0.len())
+    }
+}
+
 /// Builds an `Email` structure
 #[derive(PartialEq, Eq, Clone, Debug, Default)]
 pub struct EmailBuilder {
@@ -61,18 +533,63 @@ pub struct EmailBuilder {
     bcc: Vec<Address>,
     /// The Reply-To addresses for the mail header
     reply_to: Vec<Address>,
+    /// The Disposition-Notification-To addresses for the mail header
+    read_receipt_to: Vec<Address>,
+    /// The Return-Receipt-To addresses for the mail header
+    return_receipt_to: Vec<Address>,
     /// The In-Reply-To ids for the mail header
     in_reply_to: Vec<MessageId>,
     /// The References ids for the mail header
     references: Vec<MessageId>,
     /// The sender address for the mail header
     sender: Option<Mailbox>,
+    /// How the Sender is picked when there are several From addresses
+    sender_policy: SenderPolicy,
     /// The envelope
     envelope: Option<Envelope>,
+    /// The envelope sender, if it differs from the Sender/From address
+    bounce_address: Option<Mailbox>,
     /// Date issued
     date_issued: bool,
+    /// Build as an unsent draft
+    unsent: bool,
+    /// Drop the From and Sender addresses from To and Cc, for replies
+    skip_own_addresses: bool,
     /// Message-ID
-    message_id: Option<String>,
+    message_id: Option<MessageId>,
+    /// Hooks applied to attachment content, in order
+    attachment_hooks: Hooks<dyn AttachmentHook>,
+    /// Hooks applied to the finished message, in order
+    build_hooks: Hooks<dyn BuildHook>,
+    /// Observers of finished builds
+    build_observers: Hooks<dyn BuildObserver>,
+    /// Transforms applied to the HTML parts, in order
+    html_transforms: Hooks<dyn HtmlTransform>,
+    /// Attachments larger than this many bytes get zipped
+    #[cfg(feature = "zip")]
+    zip_threshold: Option<usize>,
+    /// Text and HTML signature appended to the body parts
+    signature: Option<(String, String)>,
+    /// Text and HTML footer appended to the body parts
+    footer: Option<(String, String)>,
+    /// Width to which plain text parts are wrapped
+    text_width: Option<usize>,
+    /// Move `data:` image URIs of HTML parts into inline parts
+    inline_data_uris: bool,
+    /// Emit non-ASCII header values without encoding them
+    raw_headers: bool,
+    /// Protect the Subject when signing or encrypting
+    protect_subject: bool,
+    /// Accept UTF-8 envelope addresses
+    smtputf8: bool,
+    /// Convert address domains to A-labels
+    punycode_domains: bool,
+    /// Require the From domain to pass DMARC alignment
+    dmarc_alignment: Option<DmarcAlignment>,
+    /// Source of the boundaries, the Message-ID and inline Content-IDs
+    random_source: Option<Arc<dyn RandomSource>>,
+    /// How the built message is serialized
+    serialization_config: SerializationConfig,
 }
 
 impl PartBuilder {
@@ -80,6 +597,7 @@ impl PartBuilder {
     pub fn new() -> PartBuilder {
         PartBuilder {
             message: MimeMessage::new_blank_message(),
+            encoding_policy: EncodingPolicy::Raw,
         }
     }
 
@@ -126,13 +644,113 @@ impl PartBuilder {
         self
     }
 
+    /// Makes this a multipart/alternative part holding the `text/markdown`
+    /// source and its HTML rendered by `render_markdown`
+    pub fn markdown<S: AsRef<str>>(self, body: S) -> PartBuilder {
+        self.markdown_with(body, &render_markdown)
+    }
+
+    /// Like `markdown`, rendering the HTML with a custom `renderer`
+    pub fn markdown_with<S: AsRef<str>>(
+        self,
+        body: S,
+        renderer: &dyn MarkdownRenderer,
+    ) -> PartBuilder {
+        markup::markdown_alternative(self, body.as_ref(), renderer)
+    }
+
+    /// Sets how bodies without a `Content-Transfer-Encoding` get encoded,
+    /// for this part and all of its children
+    pub fn encoding_policy(mut self, policy: EncodingPolicy) -> PartBuilder {
+        self.encoding_policy = policy;
+        self
+    }
+
     /// Gets built `MimeMessage`
     pub fn build(mut self) -> MimeMessage {
+        if self.encoding_policy == EncodingPolicy::Auto {
+            body::apply_transfer_encoding(&mut self.message);
+        }
         self.message.update_headers();
         self.message
     }
 }
 
+/// Builds a plain text part
+pub(crate) fn text_part<S: AsRef<str>>(body: S) -> MimeMessage {
+    PartBuilder::new()
+        .body(body)
+        .header(("Content-Type", mime::TEXT_PLAIN_UTF_8.to_string()))
+        .build()
+}
+
+/// Builds an HTML part
+pub(crate) fn html_part<S: AsRef<str>>(body: S) -> MimeMessage {
+    PartBuilder::new()
+        .body(body)
+        .header(("Content-Type", mime::TEXT_HTML_UTF_8.to_string()))
+        .build()
+}
+
+/// Builds a multipart/alternative part of a text and an HTML part
+pub(crate) fn alternative_part<S: AsRef<str>, T: AsRef<str>>(
+    body_html: S,
+    body_text: T,
+) -> MimeMessage {
+    PartBuilder::new()
+        .message_type(MimeMultipartType::Alternative)
+        .child(text_part(body_text))
+        .child(html_part(body_html))
+        .build()
+}
+
+/// Builds an attachment part holding `files` zipped into `archive_name`
+#[cfg(feature = "zip")]
+fn zip_part(files: &[(&str, &[u8])], archive_name: &str) -> Result<MimeMessage, Error> {
+    let archive = crate::compress::zip_files(files)?;
+    let content_type: Mime = "application/zip".parse().unwrap();
+    Ok(attachment_part(&archive, archive_name, &content_type))
+}
+
+/// Formats message ids for the `In-Reply-To` and `References` headers
+fn join_ids(ids: &[MessageId]) -> String {
+    ids.iter()
+        .map(MessageId::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A random UUID for the Message-ID or a `Content-ID`, drawn from `source`
+/// if there is one
+fn generate_uuid(source: Option<&dyn RandomSource>) -> Uuid {
+    match source {
+        Some(source) => uuid::Builder::from_bytes(SourceRng(source).gen())
+            .set_variant(uuid::Variant::RFC4122)
+            .set_version(uuid::Version::Random)
+            .build(),
+        None => Uuid::new_v4(),
+    }
+}
+
+/// Builds a base64 encoded attachment part
+pub(crate) fn attachment_part(body: &[u8], filename: &str, content_type: &Mime) -> MimeMessage {
+    let mut part = PartBuilder::new()
+        .header(
+            Header::new_with_value(
+                "Content-Disposition".to_string(),
+                ContentDisposition::attachment(filename),
+            )
+            .unwrap(),
+        )
+        .header(("Content-Type", content_type.to_string()))
+        .header(("Content-Transfer-Encoding", "base64"))
+        .build();
+    // Base64 has no line breaks to normalize, so the encoded content is
+    // moved into the part instead of copied by `PartBuilder::body`
+    part.body = base64::encode(body);
+    part
+}
+
 impl EmailBuilder {
     /// Creates a new empty email
     pub fn new() -> EmailBuilder {
@@ -143,12 +761,35 @@ impl EmailBuilder {
             cc: vec![],
             bcc: vec![],
             reply_to: vec![],
+            read_receipt_to: vec![],
+            return_receipt_to: vec![],
             in_reply_to: vec![],
             references: vec![],
             sender: None,
+            sender_policy: SenderPolicy::default(),
             envelope: None,
+            bounce_address: None,
             date_issued: false,
+            unsent: false,
+            skip_own_addresses: false,
             message_id: None,
+            attachment_hooks: Hooks::default(),
+            build_hooks: Hooks::default(),
+            build_observers: Hooks::default(),
+            html_transforms: Hooks::default(),
+            #[cfg(feature = "zip")]
+            zip_threshold: None,
+            signature: None,
+            footer: None,
+            text_width: None,
+            inline_data_uris: false,
+            raw_headers: false,
+            protect_subject: false,
+            smtputf8: false,
+            punycode_domains: false,
+            dmarc_alignment: None,
+            random_source: None,
+            serialization_config: SerializationConfig::default(),
         }
     }
 
@@ -189,6 +830,21 @@ impl EmailBuilder {
         self
     }
 
+    /// Adds an address to the `To` header and stores its mailboxes as
+    /// recipients. Unlike `to`, this accepts groups, e.g. an empty
+    /// `undisclosed-recipients` group when all recipients are Bcc.
+    pub fn to_address(mut self, address: Address) -> EmailBuilder {
+        self.to.push(address);
+        self
+    }
+
+    /// Adds addresses to the `To` header and stores them as recipients,
+    /// e.g. the result of `Address::parse_list`
+    pub fn to_addresses(mut self, addresses: Vec<Address>) -> EmailBuilder {
+        self.to.extend(addresses);
+        self
+    }
+
     /// Adds a `Cc` header and stores the recipient address
     pub fn cc<A: Into<Mailbox>>(mut self, address: A) -> EmailBuilder {
         let mailbox = address.into();
@@ -196,6 +852,13 @@ impl EmailBuilder {
         self
     }
 
+    /// Adds an address, which may be a group, to the `Cc` header and stores
+    /// its mailboxes as recipients
+    pub fn cc_address(mut self, address: Address) -> EmailBuilder {
+        self.cc.push(address);
+        self
+    }
+
     /// Adds a `Bcc` header and stores the recipient address
     pub fn bcc<A: Into<Mailbox>>(mut self, address: A) -> EmailBuilder {
         let mailbox = address.into();
@@ -203,6 +866,13 @@ impl EmailBuilder {
         self
     }
 
+    /// Adds an address, which may be a group, to the `Bcc` header and
+    /// stores its mailboxes as recipients
+    pub fn bcc_address(mut self, address: Address) -> EmailBuilder {
+        self.bcc.push(address);
+        self
+    }
+
     /// Adds a `Reply-To` header
     pub fn reply_to<A: Into<Mailbox>>(mut self, address: A) -> EmailBuilder {
         let mailbox = address.into();
@@ -210,6 +880,40 @@ impl EmailBuilder {
         self
     }
 
+    /// Requests a read receipt (RFC 8098) to be sent to `address`, with the
+    /// `Disposition-Notification-To` header
+    pub fn read_receipt_to<A: Into<Mailbox>>(mut self, address: A) -> EmailBuilder {
+        self.read_receipt_to.push(Address::Mailbox(address.into()));
+        self
+    }
+
+    /// Requests a delivery receipt to be sent to `address`, with the
+    /// non-standard `Return-Receipt-To` header some servers still honor
+    pub fn return_receipt_to<A: Into<Mailbox>>(mut self, address: A) -> EmailBuilder {
+        self.return_receipt_to
+            .push(Address::Mailbox(address.into()));
+        self
+    }
+
+    /// Applies a sender identity: the `From` and `Reply-To` addresses, the
+    /// `Organization` header, the signature and any additional headers.
+    pub fn identity(mut self, identity: &Identity) -> EmailBuilder {
+        self = self.from(identity.from.clone());
+        for address in identity.reply_to.iter() {
+            self = self.reply_to(address.clone());
+        }
+        if let Some(ref organization) = identity.organization {
+            self = self.header(("Organization", organization.as_str()));
+        }
+        if let Some((ref text, ref html)) = identity.signature {
+            self = self.signature(text.as_str(), html.as_str());
+        }
+        for (name, value) in identity.headers.iter() {
+            self = self.header((name.as_str(), value.as_str()));
+        }
+        self
+    }
+
     /// Adds a `In-Reply-To` header
     pub fn in_reply_to(mut self, message_id: MessageId) -> EmailBuilder {
         self.in_reply_to.push(message_id);
@@ -222,6 +926,102 @@ impl EmailBuilder {
         self
     }
 
+    /// Creates a builder for a reply to `original`, with the subject,
+    /// recipients and threading headers filled in.
+    ///
+    /// The reply goes to the `Reply-To` addresses of the original, or its
+    /// `From` addresses if it has none. With `ReplyMode::All`, its `To` and
+    /// `Cc` addresses are copied into `Cc`. The `From` and `Sender`
+    /// addresses of the reply are left out of both when it is built.
+    pub fn reply_to_message(
+        original: &MimeMessage,
+        mode: ReplyMode,
+    ) -> Result<EmailBuilder, Error> {
+        let (to, cc) = reply::reply_recipients(original, mode)?;
+        let (message_id, references) = reply::reply_thread(original);
+
+        let mut builder = EmailBuilder::new()
+            .subject(reply::reply_subject(original))
+            .to_addresses(to);
+        for address in cc {
+            builder = builder.cc_address(address);
+        }
+        builder.in_reply_to.extend(message_id);
+        builder.references = references;
+        builder.skip_own_addresses = true;
+        Ok(builder)
+    }
+
+    /// Creates a builder forwarding `original`, with its subject prefixed
+    /// by `Fwd:`, either quoted inline below an attribution line or
+    /// attached as a whole
+    pub fn forward(original: &MimeMessage, mode: ForwardMode) -> EmailBuilder {
+        reply::forward(original, mode)
+    }
+
+    /// Parses the raw message `raw` back into a builder, e.g. to edit a
+    /// saved draft, with the recipients, the threading headers and the
+    /// Message-ID filled in.
+    ///
+    /// Other headers and the parts are kept as they are, still encoded, with
+    /// the line endings converted to CRLF. The
+    /// `Date` is dropped, so the edited message gets dated when it is built.
+    /// Fails if the message isn't valid UTF-8, as 8bit content in a legacy
+    /// charset can't be kept, or if it or one of its address headers can't
+    /// be parsed.
+    pub fn from_message(raw: &[u8]) -> Result<EmailBuilder, Error> {
+        let raw = std::str::from_utf8(raw).map_err(Error::InvalidUtf8)?;
+        // Saved drafts often end lines in LF, which kept headers can't be
+        // folded with
+        let mut message = MimeMessage::parse(&LINE_BREAKS_RE.replace_all(raw, "\r\n"))?;
+        let mut builder = EmailBuilder::new();
+        let mut headers = HeaderMap::new();
+        for header in message.headers.iter() {
+            let addresses = || {
+                header
+                    .parse_value::<Vec<Address>>()
+                    .map_err(Error::address(header.get_raw_value()))
+            };
+            match &header.name.to_ascii_lowercase()[..] {
+                "to" => builder.to.extend(addresses()?),
+                "from" => builder.from.extend(addresses()?),
+                "cc" => builder.cc.extend(addresses()?),
+                "bcc" => builder.bcc.extend(addresses()?),
+                "reply-to" => builder.reply_to.extend(addresses()?),
+                "disposition-notification-to" => builder.read_receipt_to.extend(addresses()?),
+                "return-receipt-to" => builder.return_receipt_to.extend(addresses()?),
+                "sender" => {
+                    builder.sender = addresses()?.into_iter().find_map(|address| match address {
+                        Address::Mailbox(mailbox) => Some(mailbox),
+                        Address::Group(..) => None,
+                    })
+                }
+                // Ids which don't parse are kept as they are
+                "in-reply-to" => match header.parse_value::<Vec<MessageId>>() {
+                    Ok(ids) => builder.in_reply_to.extend(ids),
+                    Err(_) => headers.insert(header.clone()),
+                },
+                "references" => match header.parse_value::<Vec<MessageId>>() {
+                    Ok(ids) => builder.references.extend(ids),
+                    Err(_) => headers.insert(header.clone()),
+                },
+                // A Message-ID which doesn't parse gets replaced
+                "message-id" => {
+                    if let Ok(id) = header.parse_value::<MessageId>() {
+                        builder.message_id = Some(id);
+                        headers.insert(header.clone());
+                    }
+                }
+                "x-unsent" => builder.unsent = true,
+                "date" | "mime-version" => {}
+                _ => headers.insert(header.clone()),
+            }
+        }
+        message.headers = headers;
+        builder.message.message = message;
+        Ok(builder)
+    }
+
     /// Adds a `Sender` header
     pub fn sender<A: Into<Mailbox>>(mut self, address: A) -> EmailBuilder {
         let mailbox = address.into();
@@ -229,21 +1029,199 @@ impl EmailBuilder {
         self
     }
 
+    /// Sends the message from `sender` on behalf of `from`, e.g. an
+    /// assistant writing for their manager: `from` becomes the only `From`
+    /// address and `sender` the `Sender`.
+    pub fn on_behalf_of<F: Into<Mailbox>, S: Into<Mailbox>>(
+        mut self,
+        from: F,
+        sender: S,
+    ) -> EmailBuilder {
+        self.from = vec![Address::Mailbox(from.into())];
+        self.sender = Some(sender.into());
+        self
+    }
+
+    /// Sets how the `Sender` is picked when there are several `From`
+    /// addresses
+    pub fn sender_policy(mut self, policy: SenderPolicy) -> EmailBuilder {
+        self.sender_policy = policy;
+        self
+    }
+
     /// Adds a `Subject` header
     pub fn subject<S: Into<String>>(mut self, subject: S) -> EmailBuilder {
         self.message = self.message.header(("Subject".to_string(), subject.into()));
         self
     }
 
+    /// Sets whether non-ASCII values of the `Subject` and the other headers
+    /// added to the message get encoded as RFC 2047 encoded-words when
+    /// building, which is the default.
+    ///
+    /// Only disable this for messages sent with SMTPUTF8.
+    pub fn encode_headers(mut self, enabled: bool) -> EmailBuilder {
+        self.raw_headers = !enabled;
+        self
+    }
+
+    /// Draws the multipart boundaries, the generated Message-ID and the
+    /// `Content-ID`s of `inline_data_uris` from a random number generator
+    /// seeded with `seed`, so building the same message gives the same
+    /// output, e.g. for golden-file tests.
+    ///
+    /// Set it before signing or encrypting, and set the `Date` with `date`.
+    /// This is a `random_source` which is kept in drafts, and replaces any
+    /// other source; clones of the builder share it.
+    pub fn with_rng(self, seed: u64) -> EmailBuilder {
+        self.random_source(SeededSource(std::sync::Mutex::new(seed)))
+    }
+
+    /// Draws the multipart boundaries, the generated Message-ID and the
+    /// inline `Content-ID`s from `source` instead of the thread's generator.
+    /// Setting another source, or calling `with_rng`, replaces it.
+    ///
+    /// The source is only kept in drafts if it has a `RandomSource::seed`.
+    pub fn random_source<R: RandomSource + 'static>(mut self, source: R) -> EmailBuilder {
+        self.random_source = Some(Arc::new(source));
+        self
+    }
+
+    /// Sets how the built message is serialized, e.g. folding headers at
+    /// another length or ending lines in LF for local delivery.
+    ///
+    /// The configuration isn't kept in drafts.
+    pub fn serialization_config(mut self, config: SerializationConfig) -> EmailBuilder {
+        self.serialization_config = config;
+        self
+    }
+
+    /// Sets whether envelope addresses may have UTF-8 local parts and
+    /// domains, which requires sending with SMTPUTF8 (RFC 6531), as
+    /// reported by `Envelope::requires_smtputf8`.
+    ///
+    /// Without it, building fails with `Error::InvalidAddress` for such
+    /// addresses.
+    pub fn smtputf8(mut self, enabled: bool) -> EmailBuilder {
+        self.smtputf8 = enabled;
+        self
+    }
+
+    /// Sets whether internationalized domains of the addresses get
+    /// converted to their ASCII form, e.g. `bücher.example` to
+    /// `xn--bcher-kva.example`, so only UTF-8 local parts need SMTPUTF8.
+    ///
+    /// An envelope set with `envelope` is left alone.
+    pub fn punycode_domains(mut self, enabled: bool) -> EmailBuilder {
+        self.punycode_domains = enabled;
+        self
+    }
+
     /// Adds a `Date` header with the given date.
     pub fn date(mut self, date: &OffsetDateTime) -> EmailBuilder {
+        self.message = self.message.header(("Date", format_date(date)));
+        self.date_issued = true;
+        self
+    }
+
+    /// Adds a `Date` header with the current time in UTC, instead of the
+    /// local time used by default.
+    pub fn date_now_utc(mut self) -> EmailBuilder {
         self.message = self
             .message
-            .header(("Date", date.format(RFC822Z_TIME_FORMAT)));
+            .header(Header::new_with_value("Date".into(), DateHeader::now_utc()).unwrap());
         self.date_issued = true;
         self
     }
 
+    /// Adds an `Expires` header, after which the message may be deleted
+    pub fn expires(mut self, date: &OffsetDateTime) -> EmailBuilder {
+        self.message = self.message.replace_header(("Expires", format_date(date)));
+        self
+    }
+
+    /// Adds a `Sensitivity` header
+    pub fn sensitivity(mut self, sensitivity: Sensitivity) -> EmailBuilder {
+        self.message = self
+            .message
+            .replace_header(("Sensitivity", sensitivity.as_str()));
+        self
+    }
+
+    /// Adds a `List-Unsubscribe` header (RFC 2369) with a `mailto:` URL, an
+    /// HTTP(S) URL, or both.
+    ///
+    /// Fails with `Error::InvalidUrl` if a URL has the wrong scheme or
+    /// characters which can't appear in the header, or if both are missing.
+    pub fn list_unsubscribe(
+        self,
+        mailto: Option<&str>,
+        http_url: Option<&str>,
+    ) -> Result<EmailBuilder, Error> {
+        let value = list::unsubscribe_value(mailto, http_url)?;
+        Ok(self.replace_header(("List-Unsubscribe", value)))
+    }
+
+    /// Adds a `List-Unsubscribe` header with the HTTPS URL `https_url`,
+    /// which supports one-click unsubscription by a `POST` request
+    /// (RFC 8058), and the `List-Unsubscribe-Post` header announcing it.
+    ///
+    /// The message has to be DKIM signed covering both headers for mailbox
+    /// providers to offer the one-click button.
+    pub fn list_unsubscribe_one_click(self, https_url: &str) -> Result<EmailBuilder, Error> {
+        let value = list::one_click_value(https_url)?;
+        Ok(self
+            .replace_header(("List-Unsubscribe", value))
+            .replace_header(("List-Unsubscribe-Post", list::ONE_CLICK_POST)))
+    }
+
+    /// Adds a custom header, e.g. `X-Campaign-Id`. Private headers should
+    /// start with `X-`, though any name of printable ASCII characters
+    /// without colons is accepted.
+    ///
+    /// Fails with `Error::HeaderInjection` if the value contains line
+    /// breaks, and with `Error::InvalidHeader` if the name is invalid or the
+    /// value contains other control characters. Non-ASCII values get
+    /// encoded unless `encode_headers` is disabled.
+    pub fn x_header<S: Into<String>, T: Into<String>>(
+        self,
+        name: S,
+        value: T,
+    ) -> Result<EmailBuilder, Error> {
+        let name = name.into();
+        let value = value.into();
+        if value.contains(&['\r', '\n'][..]) {
+            return Err(Error::HeaderInjection(name));
+        }
+        if !is_field_name(&name) || value.chars().any(|c| c.is_control() && c != '\t') {
+            return Err(Error::InvalidHeader(name));
+        }
+        Ok(self.header((name, value)))
+    }
+
+    /// Adds a `List-Id` header (RFC 2919), e.g. `Rust Users
+    /// <users.lists.example.org>` for the description `Rust Users` and the
+    /// id `users.lists.example.org`
+    pub fn list_id(self, description: Option<&str>, id: &str) -> Result<EmailBuilder, Error> {
+        let value = list::list_id_value(description, id)?;
+        Ok(self.replace_header(("List-Id", value)))
+    }
+
+    /// Adds `Precedence: bulk`, which keeps well-behaved autoresponders
+    /// from replying
+    pub fn precedence_bulk(self) -> EmailBuilder {
+        self.replace_header(("Precedence", "bulk"))
+    }
+
+    /// Sets how bodies without a `Content-Transfer-Encoding` get encoded.
+    ///
+    /// With `EncodingPolicy::Auto`, non-ASCII text is quoted-printable
+    /// encoded instead of being sent as raw 8-bit, which some relays mangle.
+    pub fn encoding_policy(mut self, policy: EncodingPolicy) -> EmailBuilder {
+        self.message = self.message.encoding_policy(policy);
+        self
+    }
+
     /// Adds an attachment to the email from a file
     ///
     /// If not specified, the filename will be extracted from the file path.
@@ -254,65 +1232,311 @@ impl EmailBuilder {
         content_type: &Mime,
     ) -> Result<EmailBuilder, Error> {
         self.attachment(
-            fs::read(path)?.as_slice(),
+            fs::read(path).map_err(Error::file(path))?.as_slice(),
             filename.unwrap_or(
                 path.file_name()
                     .and_then(OsStr::to_str)
-                    .ok_or(Error::CannotParseFilename)?,
+                    .ok_or_else(|| Error::CannotParseFilename(path.to_path_buf()))?,
             ),
             content_type,
         )
     }
 
     /// Adds an attachment to the email from a vector of bytes.
+    ///
+    /// If `zip_attachments_over` was set and the attachment is larger than
+    /// the threshold, it is zipped into `<filename>.zip` instead.
     pub fn attachment(
         self,
         body: &[u8],
         filename: &str,
         content_type: &Mime,
     ) -> Result<EmailBuilder, Error> {
-        let encoded_body = base64::encode(&body);
-        let content = PartBuilder::new()
-            .body(encoded_body)
-            .header((
-                "Content-Disposition",
-                format!("attachment; filename=\"{}\"", filename),
-            ))
-            .header(("Content-Type", content_type.to_string()))
-            .header(("Content-Transfer-Encoding", "base64"))
-            .build();
+        let content = self.attachment_content(body, filename, content_type)?;
 
         Ok(self.message_type(MimeMultipartType::Mixed).child(content))
     }
 
-    /// Set the message type
-    pub fn message_type(mut self, message_type: MimeMultipartType) -> EmailBuilder {
-        self.message = self.message.message_type(message_type);
-        self
-    }
-
-    /// Adds a child
-    pub fn child(mut self, child: MimeMessage) -> EmailBuilder {
-        self.message = self.message.child(child);
-        self
+    /// Attaches a complete message as a `message/rfc822` part, e.g. to
+    /// forward it with its original structure.
+    ///
+    /// The message is embedded as is, since RFC 2046 doesn't allow encoding
+    /// `message/rfc822` parts other than 7bit or 8bit.
+    pub fn attach_message<M: EmbeddableMessage + ?Sized>(self, inner: &M) -> EmailBuilder {
+        let inner = inner.to_rfc822();
+        let encoding = if inner.is_ascii() { "7bit" } else { "8bit" };
+        self.message_type(MimeMultipartType::Mixed).child(
+            PartBuilder::new()
+                .header(("Content-Type", "message/rfc822"))
+                .header(("Content-Disposition", "attachment"))
+                .header(("Content-Transfer-Encoding", encoding))
+                .body(inner)
+                .build(),
+        )
     }
 
-    /// Sets the email body to plain text content
-    pub fn text<S: AsRef<str>>(self, body: S) -> EmailBuilder {
-        let text = PartBuilder::new()
-            .body(body)
-            .header(("Content-Type", mime::TEXT_PLAIN_UTF_8.to_string()))
-            .build();
-        self.child(text)
+    /// Adds an attachment to the email, reading its content from `reader`.
+    ///
+    /// The content is base64 encoded chunk by chunk as it is read, so it is
+    /// never held in memory unencoded. Attachment hooks and the zip
+    /// threshold don't apply.
+    pub fn attachment_from_reader<R: Read>(
+        self,
+        reader: R,
+        filename: &str,
+        content_type: &Mime,
+    ) -> Result<EmailBuilder, Error> {
+        let mut content = attachment_part(&[], filename, content_type);
+        content.body = body::encode_base64_reader(reader)?;
+
+        Ok(self.message_type(MimeMultipartType::Mixed).child(content))
+    }
+
+    /// Like `attachment_from_reader`, but reads from an `AsyncRead`
+    #[cfg(feature = "async")]
+    pub async fn attachment_from_async_reader<R: AsyncRead + Unpin>(
+        self,
+        reader: R,
+        filename: &str,
+        content_type: &Mime,
+    ) -> Result<EmailBuilder, Error> {
+        let mut content = attachment_part(&[], filename, content_type);
+        content.body = body::encode_base64_async_reader(reader).await?;
+
+        Ok(self.message_type(MimeMultipartType::Mixed).child(content))
+    }
+
+    /// Adds an attachment to the email which references a file by its path.
+    ///
+    /// Unlike `attachment_from_file`, the file is only read and encoded when
+    /// building, so drafts holding the attachment stay small. All attachment
+    /// hooks and the zip threshold apply at that point.
+    pub fn attachment_by_path(
+        self,
+        path: &Path,
+        filename: Option<&str>,
+        content_type: &Mime,
+    ) -> Result<EmailBuilder, Error> {
+        let filename = match filename {
+            Some(filename) => filename,
+            None => path
+                .file_name()
+                .and_then(OsStr::to_str)
+                .ok_or_else(|| Error::CannotParseFilename(path.to_path_buf()))?,
+        };
+        let mut content = attachment_part(&[], filename, content_type);
+        content.body_path = Some(path.to_path_buf());
+
+        Ok(self.message_type(MimeMultipartType::Mixed).child(content))
+    }
+
+    /// Assembles a completed `ChunkedUpload` and attaches the file like
+    /// `attachment_by_path`, so it is only read when building.
+    ///
+    /// Fails with `Error::IncompleteUpload` if chunks are missing; the
+    /// upload is consumed either way. The assembled file is kept for
+    /// building; take its `ChunkedUpload::assembled_path` beforehand to
+    /// remove it after sending.
+    #[cfg(feature = "storage")]
+    pub fn chunked_attachment(self, mut upload: ChunkedUpload) -> Result<EmailBuilder, Error> {
+        let path = upload.assemble()?;
+        self.attachment_by_path(&path, Some(&upload.filename), &upload.content_type)
+    }
+
+    /// Builds the part for an attachment, zipping it if it exceeds the zip
+    /// threshold
+    fn attachment_content(
+        &self,
+        body: &[u8],
+        filename: &str,
+        content_type: &Mime,
+    ) -> Result<MimeMessage, Error> {
+        let body = self.preprocess_attachment(body, filename, content_type)?;
+
+        #[cfg(feature = "zip")]
+        {
+            if matches!(self.zip_threshold, Some(threshold) if body.len() > threshold) {
+                return zip_part(&[(filename, &body)], &format!("{}.zip", filename));
+            }
+        }
+
+        Ok(attachment_part(&body, filename, content_type))
+    }
+
+    /// Converts the domains of the addresses to A-labels
+    fn punycode_addresses(&mut self) {
+        fn convert(mailbox: &mut Mailbox) {
+            mailbox.address = smtputf8::punycode_address(&mailbox.address);
+        }
+        for address in self
+            .to
+            .iter_mut()
+            .chain(self.from.iter_mut())
+            .chain(self.cc.iter_mut())
+            .chain(self.bcc.iter_mut())
+            .chain(self.reply_to.iter_mut())
+            .chain(self.read_receipt_to.iter_mut())
+            .chain(self.return_receipt_to.iter_mut())
+        {
+            match address {
+                Address::Mailbox(mailbox) => convert(mailbox),
+                Address::Group(_, mailboxes) => mailboxes.iter_mut().for_each(convert),
+            }
+        }
+        self.sender.iter_mut().for_each(convert);
+        self.bounce_address.iter_mut().for_each(convert);
+    }
+
+    /// Replaces the attachments referencing a file by path with the loaded
+    /// content
+    fn load_attachment_paths(&self, part: &mut MimeMessage) -> Result<(), Error> {
+        for child in part.children.iter_mut() {
+            self.load_attachment_paths(child)?;
+        }
+        if let Some(path) = part.body_path.take() {
+            let filename = body::header_value(part, "Content-Disposition")
+                .and_then(|value| {
+                    mailparse::parse_content_disposition(&value)
+                        .params
+                        .remove("filename")
+                })
+                .ok_or_else(|| Error::CannotParseFilename(path.clone()))?;
+            let content_type = body::header_value(part, "Content-Type")
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+            let content = fs::read(&path).map_err(Error::file(&path))?;
+            *part = self.attachment_content(&content, &filename, &content_type)?;
+        }
+        Ok(())
+    }
+
+    /// Registers a hook which is applied to the content of every attachment
+    /// added afterwards, before it is encoded.
+    ///
+    /// Hooks run in the order they were registered. Attachments referencing
+    /// an `AttachmentStore` are not passed through hooks.
+    pub fn attachment_hook<H: AttachmentHook + 'static>(mut self, hook: H) -> EmailBuilder {
+        self.attachment_hooks.0.push(Arc::new(hook));
+        self
+    }
+
+    fn preprocess_attachment<'a>(
+        &self,
+        body: &'a [u8],
+        filename: &str,
+        content_type: &Mime,
+    ) -> Result<Cow<'a, [u8]>, Error> {
+        if self.attachment_hooks.0.is_empty() {
+            return Ok(Cow::Borrowed(body));
+        }
+
+        let meta = AttachmentMeta {
+            filename: filename.to_string(),
+            content_type: content_type.clone(),
+        };
+        let mut body = body.to_vec();
+        for hook in self.attachment_hooks.0.iter() {
+            body = hook.process(&meta, body)?;
+        }
+        Ok(Cow::Owned(body))
+    }
+
+    /// Zips `files`, given as name and content pairs, and attaches the
+    /// archive as `archive_name`.
+    ///
+    /// Attachment hooks see each file as `application/octet-stream`.
+    #[cfg(feature = "zip")]
+    pub fn attachment_zipped(
+        self,
+        files: &[(&str, &[u8])],
+        archive_name: &str,
+    ) -> Result<EmailBuilder, Error> {
+        let files = files
+            .iter()
+            .map(|(name, content)| {
+                self.preprocess_attachment(content, name, &mime::APPLICATION_OCTET_STREAM)
+                    .map(|content| (*name, content))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let files = files
+            .iter()
+            .map(|(name, content)| (*name, content.as_ref()))
+            .collect::<Vec<_>>();
+
+        let content = zip_part(&files, archive_name)?;
+
+        Ok(self.message_type(MimeMultipartType::Mixed).child(content))
+    }
+
+    /// Zips every attachment added afterwards which is larger than
+    /// `threshold` bytes.
+    #[cfg(feature = "zip")]
+    pub fn zip_attachments_over(mut self, threshold: usize) -> EmailBuilder {
+        self.zip_threshold = Some(threshold);
+        self
+    }
+
+    /// Adds an attachment whose payload is held in an `AttachmentStore`.
+    ///
+    /// The payload is only pulled from the store when building with
+    /// `build_with_store`.
+    #[cfg(feature = "storage")]
+    pub fn attachment_from_store(
+        self,
+        hash: ContentHash,
+        filename: &str,
+        content_type: &Mime,
+    ) -> EmailBuilder {
+        let mut content = attachment_part(&[], filename, content_type);
+        content.body_ref = Some(hash);
+
+        self.message_type(MimeMultipartType::Mixed).child(content)
+    }
+
+    /// Set the message type
+    pub fn message_type(mut self, message_type: MimeMultipartType) -> EmailBuilder {
+        self.message = self.message.message_type(message_type);
+        self
+    }
+
+    /// Adds a child
+    pub fn child(mut self, child: MimeMessage) -> EmailBuilder {
+        self.message = self.message.child(child);
+        self
+    }
+
+    /// Adds a child in front of all existing children
+    pub(crate) fn prepend_child(mut self, child: MimeMessage) -> EmailBuilder {
+        self.message.message.children.insert(0, child);
+        self
+    }
+
+    /// Sets the email body to plain text content
+    pub fn text<S: AsRef<str>>(self, body: S) -> EmailBuilder {
+        self.child(text_part(body))
     }
 
     /// Sets the email body to HTML content
     pub fn html<S: AsRef<str>>(self, body: S) -> EmailBuilder {
-        let html = PartBuilder::new()
-            .body(body)
-            .header(("Content-Type", mime::TEXT_HTML_UTF_8.to_string()))
-            .build();
-        self.child(html)
+        self.child(html_part(body))
+    }
+
+    /// Sets the email body to plain text rendered by `template`
+    pub fn text_template<C: ?Sized>(
+        self,
+        template: &dyn BodyTemplate<C>,
+        context: &C,
+    ) -> Result<EmailBuilder, Error> {
+        Ok(self.text(template.render(context)?))
+    }
+
+    /// Sets the email body to HTML content rendered by `template`
+    pub fn html_template<C: ?Sized>(
+        self,
+        template: &dyn BodyTemplate<C>,
+        context: &C,
+    ) -> Result<EmailBuilder, Error> {
+        Ok(self.html(template.render(context)?))
     }
 
     /// Sets the email content
@@ -321,29 +1545,121 @@ impl EmailBuilder {
         body_html: S,
         body_text: T,
     ) -> EmailBuilder {
-        let text = PartBuilder::new()
-            .body(body_text)
-            .header(("Content-Type", mime::TEXT_PLAIN_UTF_8.to_string()))
-            .build();
+        self.message_type(MimeMultipartType::Mixed)
+            .child(alternative_part(body_html, body_text))
+    }
 
-        let html = PartBuilder::new()
-            .body(body_html)
-            .header(("Content-Type", mime::TEXT_HTML_UTF_8.to_string()))
-            .build();
+    /// Sets the email body to HTML content, with a plain text alternative
+    /// rendered from it by `render_text` with the default `RenderOptions`
+    #[cfg(feature = "auto-text")]
+    pub fn html_with_auto_text<S: AsRef<str>>(self, body_html: S) -> EmailBuilder {
+        self.html_with_auto_text_options(body_html, &RenderOptions::new())
+    }
+
+    /// Like `html_with_auto_text`, rendering the text alternative with
+    /// `options`
+    #[cfg(feature = "auto-text")]
+    pub fn html_with_auto_text_options<S: AsRef<str>>(
+        self,
+        body_html: S,
+        options: &RenderOptions,
+    ) -> EmailBuilder {
+        let body_text = render_text(body_html.as_ref(), options);
+        self.alternative(body_html, body_text)
+    }
 
-        let alternate = PartBuilder::new()
-            .message_type(MimeMultipartType::Alternative)
-            .child(text)
-            .child(html);
+    /// Adds a calendar object, e.g. a meeting invitation with the `Request`
+    /// method, in the structure Outlook and Gmail expect: a `text/calendar`
+    /// part alongside the text and HTML bodies of the `alternative` part,
+    /// and an `invite.ics` attachment for other clients.
+    ///
+    /// The `METHOD` property of `ics_body` has to match `method`.
+    pub fn calendar_invite<S: AsRef<str>>(
+        mut self,
+        ics_body: S,
+        method: CalendarMethod,
+    ) -> EmailBuilder {
+        let calendar = calendar_part(ics_body.as_ref(), method);
+        let alternative = self
+            .message
+            .message
+            .children
+            .iter_mut()
+            .find(|child| child.message_type == Some(MimeMultipartType::Alternative));
+        match alternative {
+            Some(alternative) => alternative.children.push(calendar),
+            None => {
+                self = self.child(
+                    PartBuilder::new()
+                        .message_type(MimeMultipartType::Alternative)
+                        .child(calendar)
+                        .build(),
+                )
+            }
+        }
 
+        let ics = LINE_BREAKS_RE.replace_all(ics_body.as_ref(), "\r\n");
+        let content_type: Mime = "application/ics".parse().unwrap();
         self.message_type(MimeMultipartType::Mixed)
-            .child(alternate.build())
+            .child(attachment_part(ics.as_bytes(), "invite.ics", &content_type))
+    }
+
+    /// Word-wraps the plain text parts to `width` characters when building.
+    ///
+    /// The width is capped at 78 characters. Quoted lines keep their `>`
+    /// prefix and words such as URLs are never broken.
+    pub fn wrap_text(mut self, width: usize) -> EmailBuilder {
+        self.text_width = Some(width.min(MIME_LINE_LENGTH));
+        self
+    }
+
+    /// Sets a signature, which is appended to every text part after the
+    /// standard `-- ` delimiter line, and inserted as a delimited block
+    /// before `</body>` in every HTML part when building.
+    ///
+    /// The signature goes before the footer, if any.
+    pub fn signature<S: Into<String>, T: Into<String>>(mut self, text: S, html: T) -> EmailBuilder {
+        self.signature = Some((text.into(), html.into()));
+        self
+    }
+
+    /// Sets a footer, e.g. legal or unsubscribe information, which is
+    /// appended to every text part and inserted before `</body>` in every
+    /// HTML part when building.
+    ///
+    /// If the message has no body parts yet, a multipart/alternative part
+    /// containing only the footer is added.
+    pub fn footer<S: Into<String>, T: Into<String>>(mut self, text: S, html: T) -> EmailBuilder {
+        self.footer = Some((text.into(), html.into()));
+        self
     }
 
     /// Sets the `Message-ID` header
-    pub fn message_id<S: Clone + Into<String>>(mut self, id: S) -> EmailBuilder {
-        self.message = self.message.header(("Message-ID", id.clone()));
-        self.message_id = Some(id.into());
+    pub fn message_id(mut self, id: MessageId) -> EmailBuilder {
+        self.message = self.message.header(("Message-ID", id.to_string()));
+        self.message_id = Some(id);
+        self
+    }
+
+    /// Builds the message as an unsent draft, which mail clients like
+    /// Outlook open for editing rather than as a received message, e.g. to
+    /// hand a pre-filled message to the user's client as an `.eml` file.
+    ///
+    /// The message gets an `X-Unsent: 1` header, and no `Date` or
+    /// `Message-ID` unless set explicitly, as the client adds them when
+    /// sending. The `message_id` of the `Email` is then empty.
+    pub fn unsent(mut self) -> EmailBuilder {
+        self.unsent = true;
+        self
+    }
+
+    /// Sets the envelope sender (the `MAIL FROM` address, which becomes
+    /// the `Return-Path` on delivery) independently of the `From` header,
+    /// e.g. to a domain processing bounces.
+    ///
+    /// Has no effect if the envelope is set explicitly.
+    pub fn bounce_address<A: Into<Mailbox>>(mut self, address: A) -> EmailBuilder {
+        self.bounce_address = Some(address.into());
         self
     }
 
@@ -355,32 +1671,404 @@ impl EmailBuilder {
         self
     }
 
+    /// Makes building fail with `Error::DmarcMisaligned` if the From domain
+    /// won't pass DMARC alignment with the DKIM signing domain or the
+    /// envelope sender of `alignment`.
+    pub fn dmarc_alignment(mut self, alignment: DmarcAlignment) -> EmailBuilder {
+        self.dmarc_alignment = Some(alignment);
+        self
+    }
+
+    /// Registers a hook which is applied to the finished message and
+    /// envelope when building.
+    ///
+    /// Hooks run in the order they were registered.
+    pub fn build_hook<H: BuildHook + 'static>(mut self, hook: H) -> EmailBuilder {
+        self.build_hooks.0.push(Arc::new(hook));
+        self
+    }
+
+    /// Registers an observer which receives the duration, size and part
+    /// count of every successful build
+    pub fn build_observer<O: BuildObserver + 'static>(mut self, observer: O) -> EmailBuilder {
+        self.build_observers.0.push(Arc::new(observer));
+        self
+    }
+
+    /// Turns the message into a PGP/MIME `multipart/signed` structure with
+    /// the detached `signature`, binary or ASCII armored.
+    ///
+    /// The signature has to be computed over the output of `build_body`
+    /// right before this call. The headers set so far stay in the signed
+    /// content, and the ones not starting with `Content-` are copied to the
+    /// outer structure as well.
+    #[cfg(feature = "crypto")]
+    pub fn pgp_signed(self, signature: &[u8]) -> EmailBuilder {
+        self.wrap_message(false, |content| pgp::signed(content, signature))
+    }
+
+    /// Replaces the message with a PGP/MIME `multipart/encrypted` structure
+    /// holding `ciphertext`, binary or ASCII armored.
+    ///
+    /// The ciphertext has to be the encrypted output of `build_body` right
+    /// before this call. The headers set so far which don't start with
+    /// `Content-` are kept on the outer structure.
+    #[cfg(feature = "crypto")]
+    pub fn pgp_encrypted(self, ciphertext: &[u8]) -> EmailBuilder {
+        self.wrap_message(true, |_| pgp::encrypted(ciphertext))
+    }
+
+    /// Moves the real `Subject` into the signed or encrypted content, which
+    /// is marked with `protected-headers="v1"`. Encrypted messages get
+    /// `...` as their outer Subject.
+    ///
+    /// With PGP/MIME, this has to be set before calling `build_body`.
+    pub fn protect_subject(mut self) -> EmailBuilder {
+        self.protect_subject = true;
+        self
+    }
+
+    /// Replaces the message with the result of `wrap`, keeping copies of
+    /// its transport headers
+    #[cfg(feature = "crypto")]
+    fn wrap_message<F: FnOnce(MimeMessage) -> MimeMessage>(
+        mut self,
+        encrypted: bool,
+        wrap: F,
+    ) -> EmailBuilder {
+        let content = self.build_content();
+        let transport_headers = content
+            .headers
+            .iter()
+            .filter(|header| !smime::is_content_header(header))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut message = wrap(content);
+        for header in transport_headers {
+            message.headers.insert(header);
+        }
+        if self.protect_subject && encrypted {
+            protected::obscure_subject(&mut message.headers);
+        }
+        // The outer structure isn't protected content itself
+        self.protect_subject = false;
+        // The content is encoded already, and must not change once wrapped
+        self.message = PartBuilder {
+            message,
+            encoding_policy: EncodingPolicy::Raw,
+        };
+        self
+    }
+
+    /// Signs the message with S/MIME when building, keeping the transport
+    /// headers outside of the `multipart/signed` structure.
+    ///
+    /// Signing runs as a build hook, so hooks which modify the content
+    /// have to be registered before, and hooks like DKIM signing after.
+    #[cfg(feature = "crypto")]
+    pub fn smime_sign<S: SmimeSigner + Send + Sync + 'static>(self, signer: S) -> EmailBuilder {
+        self.build_hook(move |message: &mut MimeMessage, _: &mut Envelope| {
+            let content = std::mem::replace(message, MimeMessage::new_blank_message());
+            *message = smime::sign_message(content, &signer)?;
+            Ok(())
+        })
+    }
+
+    /// Encrypts the message with S/MIME when building, keeping the
+    /// transport headers outside of the `application/pkcs7-mime` part.
+    ///
+    /// Encryption runs as a build hook, like `smime_sign`.
+    #[cfg(feature = "crypto")]
+    pub fn smime_encrypt<E: SmimeEncryptor + Send + Sync + 'static>(
+        self,
+        encryptor: E,
+    ) -> EmailBuilder {
+        self.build_hook(move |message: &mut MimeMessage, _: &mut Envelope| {
+            let content = std::mem::replace(message, MimeMessage::new_blank_message());
+            *message = smime::encrypt_message(content, &encryptor)?;
+            Ok(())
+        })
+    }
+
+    /// Registers a transform which is applied to every HTML part when
+    /// building, after the footer has been added.
+    ///
+    /// Transforms run in the order they were registered.
+    pub fn html_transform<T: HtmlTransform + 'static>(mut self, transform: T) -> EmailBuilder {
+        self.html_transforms.0.push(Arc::new(transform));
+        self
+    }
+
+    /// Converts the base64 `data:` image URIs of HTML parts into inline
+    /// parts referenced by `cid:` URLs when building.
+    ///
+    /// Each affected HTML part gets wrapped into a multipart/related part
+    /// together with its images.
+    pub fn inline_data_uris(mut self) -> EmailBuilder {
+        self.inline_data_uris = true;
+        self
+    }
+
+    /// Takes a snapshot of the builder state, which can be persisted and
+    /// restored with `from_draft`.
+    ///
+    /// Hooks, HTML transforms and the DMARC alignment check are not
+    /// included.
+    pub fn to_draft(&self) -> Draft {
+        Draft {
+            message: DraftPart::from(&self.message.message),
+            to: self.to.clone(),
+            from: self.from.clone(),
+            cc: self.cc.clone(),
+            bcc: self.bcc.clone(),
+            reply_to: self.reply_to.clone(),
+            read_receipt_to: self.read_receipt_to.clone(),
+            return_receipt_to: self.return_receipt_to.clone(),
+            in_reply_to: self.in_reply_to.clone(),
+            references: self.references.clone(),
+            sender: self.sender.clone(),
+            sender_policy: self.sender_policy,
+            envelope: self.envelope.clone(),
+            bounce_address: self.bounce_address.clone(),
+            date_issued: self.date_issued,
+            unsent: self.unsent,
+            skip_own_addresses: self.skip_own_addresses,
+            message_id: self.message_id.clone(),
+            signature: self.signature.clone(),
+            footer: self.footer.clone(),
+            text_width: self.text_width,
+            inline_data_uris: self.inline_data_uris,
+            encoding_policy: self.message.encoding_policy,
+            raw_headers: self.raw_headers,
+            protect_subject: self.protect_subject,
+            seed: self.random_source.as_ref().and_then(|source| source.seed()),
+            smtputf8: self.smtputf8,
+            punycode_domains: self.punycode_domains,
+            #[cfg(feature = "zip")]
+            zip_threshold: self.zip_threshold,
+        }
+    }
+
+    /// Restores a builder from a snapshot taken by `to_draft`
+    pub fn from_draft(draft: Draft) -> EmailBuilder {
+        EmailBuilder {
+            message: PartBuilder {
+                message: draft.message.into(),
+                encoding_policy: draft.encoding_policy,
+            },
+            to: draft.to,
+            from: draft.from,
+            cc: draft.cc,
+            bcc: draft.bcc,
+            reply_to: draft.reply_to,
+            read_receipt_to: draft.read_receipt_to,
+            return_receipt_to: draft.return_receipt_to,
+            in_reply_to: draft.in_reply_to,
+            references: draft.references,
+            sender: draft.sender,
+            sender_policy: draft.sender_policy,
+            envelope: draft.envelope,
+            bounce_address: draft.bounce_address,
+            date_issued: draft.date_issued,
+            unsent: draft.unsent,
+            skip_own_addresses: draft.skip_own_addresses,
+            message_id: draft.message_id,
+            signature: draft.signature,
+            footer: draft.footer,
+            text_width: draft.text_width,
+            inline_data_uris: draft.inline_data_uris,
+            raw_headers: draft.raw_headers,
+            protect_subject: draft.protect_subject,
+            random_source: draft.seed.map(|seed| {
+                let source: Arc<dyn RandomSource> =
+                    Arc::new(SeededSource(std::sync::Mutex::new(seed)));
+                source
+            }),
+            smtputf8: draft.smtputf8,
+            punycode_domains: draft.punycode_domains,
+            #[cfg(feature = "zip")]
+            zip_threshold: draft.zip_threshold,
+            ..EmailBuilder::new()
+        }
+    }
+
     /// Only builds the body, this can be used to encrypt or sign
     /// using S/MIME
-    pub fn build_body(self) -> Result<Vec<u8>, Error> {
-        Ok(self.message.build().as_string().into_bytes())
+    pub fn build_body(mut self) -> Result<Vec<u8>, Error> {
+        Ok(self.build_content().as_string().into_bytes())
+    }
+
+    /// Builds the current message, marked as protected content if the
+    /// Subject is to be protected
+    fn build_content(&mut self) -> MimeMessage {
+        let mut content = std::mem::take(&mut self.message).build();
+        if self.protect_subject {
+            protected::mark_protected(&mut content);
+        }
+        if let Some(source) = self.random_source.as_deref() {
+            reseed_boundaries(&mut content, &mut SourceRng(source));
+        }
+        content
+    }
+
+    /// Builds a copy of the Email and checks it for common violations of
+    /// RFC 5322 and RFC 2045, see `Email::validate`
+    pub fn validate(&self) -> Result<Vec<ValidationIssue>, Error> {
+        Ok(self.clone().build()?.validate())
     }
 
     /// Builds the Email
-    pub fn build(mut self) -> Result<Email, Error> {
+    pub fn build(self) -> Result<Email, Error> {
+        let started = Instant::now();
+        let observers = self.build_observers.clone();
+        let config = self.serialization_config;
+        let (message, envelope, message_id) = self.finalize()?;
+        if let Some(hash) = message.unresolved_body_ref(None) {
+            return Err(Error::MissingPayload(hash));
+        }
+
+        let serializing = Instant::now();
+        let bytes = message.as_string_with_config(&config).into_bytes();
+        observers.observe(&message, started, serializing, bytes.len());
+        Ok(Email {
+            message: bytes,
+            envelope,
+            message_id,
+        })
+    }
+
+    /// Builds the Email and writes the message into `writer` part by part,
+    /// instead of collecting it in memory first.
+    ///
+    /// Returns the envelope and the message id.
+    pub fn build_to<W: Write>(self, writer: W) -> Result<(Envelope, String), Error> {
+        let started = Instant::now();
+        let observers = self.build_observers.clone();
+        let config = self.serialization_config;
+        let (message, envelope, message_id) = self.finalize()?;
+        if let Some(hash) = message.unresolved_body_ref(None) {
+            return Err(Error::MissingPayload(hash));
+        }
+        let serializing = Instant::now();
+        let mut writer = CountingWriter {
+            inner: writer,
+            count: 0,
+        };
+        message.serialize(&mut writer, true, None, &config)?;
+        observers.observe(&message, started, serializing, writer.count);
+
+        Ok((envelope, message_id))
+    }
+
+    /// Builds the Email, pulling referenced attachment payloads from `store`
+    #[cfg(feature = "storage")]
+    pub fn build_with_store(self, store: &dyn AttachmentStore) -> Result<Email, Error> {
+        let started = Instant::now();
+        let observers = self.build_observers.clone();
+        let config = self.serialization_config;
+        let (message, envelope, message_id) = self.finalize()?;
+        if let Some(hash) = message.unresolved_body_ref(Some(store)) {
+            return Err(Error::MissingPayload(hash));
+        }
+
+        let serializing = Instant::now();
+        let bytes = message
+            .serialize_to_string_with(true, Some(store), &config)
+            .into_bytes();
+        observers.observe(&message, started, serializing, bytes.len());
+        Ok(Email {
+            message: bytes,
+            envelope,
+            message_id,
+        })
+    }
+
+    /// Sets the `Date` and `Message-ID` which would be generated when
+    /// building, so clones of the builder build copies of the same message
+    pub(crate) fn pin_generated_headers(mut self) -> EmailBuilder {
+        if self.unsent {
+            return self;
+        }
+        if !self.date_issued {
+            self.message = self
+                .message
+                .header(Header::new_with_value("Date".into(), DateHeader::now_local()).unwrap());
+            self.date_issued = true;
+        }
+        if self.message_id.is_none() {
+            let id = format!(
+                "{}.lettre@localhost",
+                generate_uuid(self.random_source.as_deref())
+            );
+            self = self.message_id(id.parse().unwrap());
+        }
+        self
+    }
+
+    /// The `Bcc` header listing the blind copy recipients, if there are any
+    pub(crate) fn bcc_header(&self) -> Option<Header> {
+        if self.bcc.is_empty() {
+            return None;
+        }
+        Some(Header::new_with_value("Bcc".into(), self.bcc.clone()).unwrap())
+    }
+
+    /// Adds the generated headers and computes the envelope
+    fn finalize(mut self) -> Result<(MimeMessage, Envelope, String), Error> {
+        let mut message =
+            std::mem::replace(&mut self.message.message, MimeMessage::new_blank_message());
+        self.load_attachment_paths(&mut message)?;
+        self.message.message = message;
+
+        if !self.raw_headers {
+            let mut headers = HeaderMap::new();
+            for header in self.message.message.headers.iter() {
+                let value = encode_header_value(header.get_raw_value());
+                headers.insert(Header::new(header.name.clone(), value.into_owned()));
+            }
+            self.message.message.headers = headers;
+        }
+
+        if self.punycode_domains {
+            self.punycode_addresses();
+        }
+
         // If there are multiple addresses in "From", the "Sender" is required.
         if self.from.len() >= 2 && self.sender.is_none() {
-            // So, we must find something to put as Sender.
-            for possible_sender in &self.from {
-                // Only a mailbox can be used as sender, not Address::Group.
-                if let Address::Mailbox(ref mbx) = *possible_sender {
-                    self.sender = Some(mbx.clone());
-                    break;
-                }
+            if self.sender_policy == SenderPolicy::Explicit {
+                return Err(Error::MissingSender);
             }
-            // Address::Group is not yet supported, so the line below will never panic.
-            // If groups are supported one day, add another Error for this case
-            //  and return it here, if sender_header is still None at this point.
-            assert!(self.sender.is_some());
+            // Only a mailbox can be used as sender, not Address::Group.
+            self.sender = self.from.iter().find_map(|address| match address {
+                Address::Mailbox(mailbox) => Some(mailbox.clone()),
+                Address::Group(..) => None,
+            });
+            if self.sender.is_none() {
+                return Err(Error::MissingSender);
+            }
+        }
+        if self.skip_own_addresses {
+            let own = self
+                .from
+                .iter()
+                .flat_map(|address| match address {
+                    Address::Mailbox(mailbox) => std::slice::from_ref(mailbox),
+                    Address::Group(_, mailboxes) => &mailboxes[..],
+                })
+                .chain(self.sender.iter())
+                .collect::<Vec<_>>();
+            reply::drop_own_addresses(&mut self.to, &mut self.cc, &own);
         }
-        // Add the sender header, if any.
+        // Add the sender header, unless it is the only From mailbox.
         if let Some(ref v) = self.sender {
-            self.message = self.message.header(("Sender", v.to_string()));
+            let is_from = match &self.from[..] {
+                [Address::Mailbox(from)] => from.address.eq_ignore_ascii_case(&v.address),
+                _ => false,
+            };
+            if !is_from {
+                self.message = self.message.header(("Sender", v.to_string()));
+            }
         }
         // Calculate the envelope
         let envelope = match self.envelope {
@@ -391,17 +2079,23 @@ impl EmailBuilder {
                 // add all receivers in to_header and cc_header
                 for receiver in self.to.iter().chain(self.cc.iter()).chain(self.bcc.iter()) {
                     match *receiver {
-                        Address::Mailbox(ref m) => to.push(Address::from_str(&m.address)?),
+                        Address::Mailbox(ref m) => to.push(
+                            Address::from_str(&m.address).map_err(Error::address(&m.address))?,
+                        ),
                         Address::Group(_, ref ms) => {
                             for m in ms.iter() {
-                                to.push(Address::from_str(&m.address.clone())?);
+                                to.push(
+                                    Address::from_str(&m.address)
+                                        .map_err(Error::address(&m.address))?,
+                                );
                             }
                         }
                     }
                 }
-                let from = Some(Address::from_str(&match self.sender {
-                    Some(x) => Ok(x.address), // if we have a sender_header, use it
-                    None => {
+                let reverse_path = match (self.bounce_address, self.sender) {
+                    (Some(x), _) => Ok(x.address),    // bounces go to a dedicated address
+                    (None, Some(x)) => Ok(x.address), // if we have a sender_header, use it
+                    (None, None) => {
                         // use a from header
                         debug_assert!(self.from.len() <= 1); // else we'd have sender_header
                         match self.from.first() {
@@ -419,10 +2113,35 @@ impl EmailBuilder {
                             None => Err(Error::Envelope(EnvelopeError::MissingFrom)), // empty envelope sender
                         }
                     }
-                }?)?);
+                }?;
+                let from =
+                    Some(Address::from_str(&reverse_path).map_err(Error::address(&reverse_path))?);
                 Envelope::new(from, to)?
             }
         };
+        for address in envelope.from().into_iter().chain(envelope.to()) {
+            if let Address::Mailbox(mailbox) = address {
+                if !smtputf8::is_valid_address(&mailbox.address, self.smtputf8) {
+                    return Err(Error::InvalidAddress(mailbox.address.clone()));
+                }
+            }
+        }
+        if let Some(ref alignment) = self.dmarc_alignment {
+            let from = match self.from.first() {
+                Some(Address::Mailbox(mailbox)) => Some(&mailbox.address),
+                Some(Address::Group(_, mailboxes)) => mailboxes.first().map(|m| &m.address),
+                None => None,
+            };
+            let envelope_sender = match envelope.from() {
+                Some(Address::Mailbox(mailbox)) => Some(mailbox.address.as_str()),
+                _ => None,
+            };
+            if let Some(from) = from {
+                if !alignment.is_aligned(from, envelope_sender) {
+                    return Err(Error::DmarcMisaligned(from.clone()));
+                }
+            }
+        }
         // Add the collected addresses as mailbox-list all at once.
         // The unwraps are fine because the conversions for Vec<Address> never errs.
         if !self.to.is_empty() {
@@ -435,7 +2154,7 @@ impl EmailBuilder {
                 .message
                 .header(Header::new_with_value("From".into(), self.from).unwrap());
         } else if let Some(from) = envelope.from() {
-            let from = vec![Address::new_mailbox(from.to_string())];
+            let from = vec![from.clone()];
             self.message = self
                 .message
                 .header(Header::new_with_value("From".into(), from).unwrap());
@@ -452,30 +2171,44 @@ impl EmailBuilder {
                 .message
                 .header(Header::new_with_value("Reply-To".into(), self.reply_to).unwrap());
         }
-        if !self.in_reply_to.is_empty() {
+        if !self.read_receipt_to.is_empty() {
             self.message = self.message.header(
-                Header::new_with_value("In-Reply-To".into(), self.in_reply_to.join(" ")).unwrap(),
+                Header::new_with_value("Disposition-Notification-To".into(), self.read_receipt_to)
+                    .unwrap(),
             );
         }
-        if !self.references.is_empty() {
+        if !self.return_receipt_to.is_empty() {
             self.message = self.message.header(
-                Header::new_with_value("References".into(), self.references.join(" ")).unwrap(),
+                Header::new_with_value("Return-Receipt-To".into(), self.return_receipt_to).unwrap(),
             );
         }
-
-        if !self.date_issued {
-            self.message = self.message.header((
-                "Date",
-                OffsetDateTime::now_local().format(RFC822Z_TIME_FORMAT),
-            ));
+        if !self.in_reply_to.is_empty() {
+            self.message = self.message.header(
+                Header::new_with_value("In-Reply-To".into(), join_ids(&self.in_reply_to)).unwrap(),
+            );
+        }
+        if !self.references.is_empty() {
+            self.message = self.message.header(
+                Header::new_with_value("References".into(), join_ids(&self.references)).unwrap(),
+            );
+        }
+
+        if !self.date_issued && !self.unsent {
+            self.message = self
+                .message
+                .header(Header::new_with_value("Date".into(), DateHeader::now_local()).unwrap());
         }
 
         self.message = self.message.header(("MIME-Version", "1.0"));
+        if self.unsent {
+            self.message = self.message.header(("X-Unsent", "1"));
+        }
 
         let message_id = match self.message_id {
-            Some(id) => id,
+            Some(id) => id.to_string(),
+            None if self.unsent => String::new(),
             None => {
-                let message_id = Uuid::new_v4();
+                let message_id = generate_uuid(self.random_source.as_deref());
                 self.message = self
                     .message
                     .header(("Message-ID", format!("<{}.lettre@localhost>", message_id)));
@@ -483,17 +2216,105 @@ impl EmailBuilder {
             }
         };
 
-        Ok(Email {
-            message: self.message.build().as_string().into_bytes(),
-            envelope,
-            message_id,
+        if let Some(width) = self.text_width {
+            body::map_text_bodies(&mut self.message.message, &mut |kind, content| match kind {
+                TextKind::Plain => body::wrap_text(&content, width),
+                TextKind::Html => content,
+            });
+        }
+        if let Some((ref text, ref html)) = self.signature {
+            append_to_bodies(
+                &mut self.message.message,
+                &format!("{}{}", SIGNATURE_DELIMITER, text),
+                &format!("<div class=\"signature\">-- <br>\r\n{}</div>", html),
+            );
+        }
+        if let Some((ref text, ref html)) = self.footer {
+            append_to_bodies(&mut self.message.message, text, html);
+        }
+
+        if !self.html_transforms.0.is_empty() {
+            let transforms = &self.html_transforms.0;
+            body::try_map_text_bodies(&mut self.message.message, &mut |kind, content| {
+                if kind != TextKind::Html {
+                    return Ok(content);
+                }
+                transforms
+                    .iter()
+                    .try_fold(content, |html, transform| transform.transform(&html))
+            })?;
+        }
+
+        if self.inline_data_uris {
+            let source = self.random_source.as_deref();
+            body::inline_data_uris(&mut self.message.message, &mut || generate_uuid(source));
+        }
+
+        let mut message = self.message.build();
+        if self.protect_subject {
+            protected::mark_protected(&mut message);
+        }
+        if let Some(source) = self.random_source.as_deref() {
+            reseed_boundaries(&mut message, &mut SourceRng(source));
+        }
+        let mut envelope = envelope;
+        for hook in self.build_hooks.0.iter() {
+            hook.before_finalize(&mut message, &mut envelope)?;
+        }
+        check_header_injection(&message)?;
+
+        Ok((message, envelope, message_id))
+    }
+}
+
+/// Fails with `Error::HeaderInjection` if a header of `part` or of its
+/// children has a line break which doesn't fold it.
+fn check_header_injection(part: &MimeMessage) -> Result<(), Error> {
+    for header in part.headers.iter() {
+        if header.name.contains(&['\r', '\n'][..]) || !is_safely_folded(header.get_raw_value()) {
+            return Err(Error::HeaderInjection(header.name.clone()));
+        }
+    }
+    part.children.iter().try_for_each(check_header_injection)
+}
+
+/// Appends `text` and `html` to the body parts of `message`, adding them if
+/// there are none.
+fn append_to_bodies(message: &mut MimeMessage, text: &str, html: &str) {
+    let text = LINE_BREAKS_RE.replace_all(text, "\r\n");
+    let html = LINE_BREAKS_RE.replace_all(html, "\r\n");
+
+    let is_empty = message.children.is_empty() && message.body.is_empty();
+    let visited = if is_empty {
+        vec![]
+    } else {
+        body::map_text_bodies(message, &mut |kind, mut content| match kind {
+            TextKind::Plain => {
+                if !content.is_empty() && !content.ends_with("\r\n") {
+                    content.push_str("\r\n");
+                }
+                content.push_str(&text);
+                content
+            }
+            TextKind::Html => body::insert_before_body_end(&content, &html),
         })
+    };
+
+    if visited.is_empty() {
+        message.children.insert(0, alternative_part(&html, &text));
     }
 }
 
 #[cfg(test)]
+#[allow(
+    deprecated,
+    clippy::bool_assert_comparison,
+    clippy::unnecessary_to_owned
+)]
 mod test {
     use super::*;
+    use crate::body::header_value;
+    use crate::date::RFC822Z_TIME_FORMAT;
 
     use time::OffsetDateTime;
 
@@ -536,7 +2357,7 @@ mod test {
             .cc(("cc@localhost", "Alias"))
             .bcc("bcc@localhost")
             .reply_to("reply@localhost")
-            .in_reply_to("original".to_string())
+            .in_reply_to("original@localhost".parse().unwrap())
             .sender("sender@localhost")
             .body("Hello World!")
             .date(&date_now)
@@ -552,7 +2373,7 @@ mod test {
                 "Date: {}\r\nSubject: Hello\r\nX-test: value\r\nSender: \
                  <sender@localhost>\r\nTo: <user@localhost>\r\nFrom: \
                  <user@localhost>\r\nCc: Alias <cc@localhost>\r\n\
-                 Reply-To: <reply@localhost>\r\nIn-Reply-To: original\r\n\
+                 Reply-To: <reply@localhost>\r\nIn-Reply-To: <original@localhost>\r\n\
                  MIME-Version: 1.0\r\nMessage-ID: \
                  <{}.lettre@localhost>\r\n\r\nHello World!\r\n",
                 date_now.format(RFC822Z_TIME_FORMAT),
@@ -572,7 +2393,7 @@ mod test {
             .cc(("cc@localhost", "Alias"))
             .bcc("bcc@localhost")
             .reply_to("reply@localhost")
-            .in_reply_to("original".to_string())
+            .in_reply_to("original@localhost".parse().unwrap())
             .sender("sender@localhost")
             .date(&date_now)
             .subject("Hello")
@@ -588,7 +2409,7 @@ mod test {
                 "Date: {}\r\nSubject: Hello\r\nX-test: value\r\nSender: \
                  <sender@localhost>\r\nTo: <user@localhost>\r\nFrom: \
                  <user@localhost>\r\nCc: Alias <cc@localhost>\r\n\
-                 Reply-To: <reply@localhost>\r\nIn-Reply-To: original\r\n\
+                 Reply-To: <reply@localhost>\r\nIn-Reply-To: <original@localhost>\r\n\
                  MIME-Version: 1.0\r\nMessage-ID: \
                  <{}.lettre@localhost>\r\n\
                  \r\n\
@@ -614,13 +2435,13 @@ mod test {
             .cc(("cc@localhost", "Alias"))
             .bcc("bcc@localhost")
             .reply_to("reply@localhost")
-            .in_reply_to("original".to_string())
+            .in_reply_to("original@localhost".parse().unwrap())
             .sender("sender@localhost")
             .body("Hello World!")
             .date(&date_now)
             .subject("Hello")
             .header(("X-test", "value"))
-            .message_id("my-shiny-id")
+            .message_id("my-shiny-id@localhost".parse().unwrap())
             .build()
             .unwrap();
 
@@ -628,9 +2449,9 @@ mod test {
             email.message_to_string().unwrap(),
             format!(
                 "Date: {}\r\nSubject: Hello\r\nX-test: value\r\nMessage-ID: \
-                 my-shiny-id\r\nSender: <sender@localhost>\r\nTo: <user@localhost>\r\nFrom: \
+                 <my-shiny-id@localhost>\r\nSender: <sender@localhost>\r\nTo: <user@localhost>\r\nFrom: \
                  <user@localhost>\r\nCc: Alias <cc@localhost>\r\nReply-To: \
-                 <reply@localhost>\r\nIn-Reply-To: original\r\nMIME-Version: 1.0\r\n\r\nHello \
+                 <reply@localhost>\r\nIn-Reply-To: <original@localhost>\r\nMIME-Version: 1.0\r\n\r\nHello \
                  World!\r\n",
                 date_now.format(RFC822Z_TIME_FORMAT)
             )
@@ -705,8 +2526,8 @@ mod test {
             .unwrap();
 
         assert_eq!(
-            email.envelope.from().unwrap().to_string(),
-            "sender@localhost".to_string()
+            email.envelope.from().unwrap(),
+            &Address::new("sender@localhost").unwrap()
         );
         assert_eq!(
             email.envelope.to(),
@@ -718,4 +2539,1201 @@ mod test {
             .as_slice()
         );
     }
+
+    #[test]
+    #[cfg(feature = "storage")]
+    fn test_attachment_from_store() {
+        let mut store = crate::MemoryAttachmentStore::new();
+        let hash = store.insert(b"report");
+
+        let builder = EmailBuilder::new()
+            .to("user@localhost")
+            .from("user@localhost")
+            .attachment_from_store(hash, "report.txt", &mime::TEXT_PLAIN);
+
+        assert!(matches!(
+            builder.clone().build(),
+            Err(Error::MissingPayload(missing)) if missing == hash
+        ));
+        assert!(builder
+            .clone()
+            .build_with_store(&crate::MemoryAttachmentStore::new())
+            .is_err());
+
+        let email = builder.build_with_store(&store).unwrap();
+        let message = email.message_to_string().unwrap();
+        assert!(message.contains("filename=\"report.txt\"\r\n"));
+        assert!(message.contains("\r\n\r\ncmVwb3J0\r\n"));
+    }
+
+    #[test]
+    fn test_attachment_hook() {
+        fn upper(meta: &AttachmentMeta, body: Vec<u8>) -> Result<Vec<u8>, Error> {
+            assert_eq!(meta.filename, "a.txt");
+            assert_eq!(meta.content_type, mime::TEXT_PLAIN);
+            Ok(body.to_ascii_uppercase())
+        }
+        fn reject(meta: &AttachmentMeta, _: Vec<u8>) -> Result<Vec<u8>, Error> {
+            Err(Error::AttachmentRejected(meta.filename.clone()))
+        }
+
+        let builder = EmailBuilder::new()
+            .to("user@localhost")
+            .from("user@localhost")
+            .attachment_hook(upper);
+        let email = builder
+            .clone()
+            .attachment(b"abc", "a.txt", &mime::TEXT_PLAIN)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(email
+            .message_to_string()
+            .unwrap()
+            .contains(&base64::encode(b"ABC")));
+
+        assert!(matches!(
+            builder
+                .attachment_hook(reject)
+                .attachment(b"abc", "a.txt", &mime::TEXT_PLAIN),
+            Err(Error::AttachmentRejected(name)) if name == "a.txt"
+        ));
+
+        // Hooks can keep state, e.g. the client of a virus scanner
+        struct Scanner {
+            signature: Vec<u8>,
+        }
+
+        impl AttachmentHook for Scanner {
+            fn process(&self, meta: &AttachmentMeta, body: Vec<u8>) -> Result<Vec<u8>, Error> {
+                if body
+                    .windows(self.signature.len())
+                    .any(|w| w == &self.signature[..])
+                {
+                    return Err(Error::AttachmentRejected(meta.filename.clone()));
+                }
+                Ok(body)
+            }
+        }
+
+        let builder = EmailBuilder::new().attachment_hook(Scanner {
+            signature: b"EICAR".to_vec(),
+        });
+        assert!(builder
+            .clone()
+            .attachment(b"clean", "a.txt", &mime::TEXT_PLAIN)
+            .is_ok());
+        assert!(matches!(
+            builder.attachment(b"xEICARx", "b.txt", &mime::TEXT_PLAIN),
+            Err(Error::AttachmentRejected(name)) if name == "b.txt"
+        ));
+    }
+
+    #[test]
+    fn test_build_hook() {
+        struct Stamp;
+
+        impl BuildHook for Stamp {
+            fn before_finalize(
+                &self,
+                message: &mut MimeMessage,
+                _: &mut Envelope,
+            ) -> Result<(), Error> {
+                message.headers.insert(Header::new(
+                    "X-Stamp".to_string(),
+                    message.headers.len().to_string(),
+                ));
+                Ok(())
+            }
+        }
+
+        let email = EmailBuilder::new()
+            .to("user@localhost")
+            .from("user@localhost")
+            .build_hook(Stamp)
+            .build_hook(|_: &mut MimeMessage, envelope: &mut Envelope| {
+                *envelope = Envelope::new(
+                    envelope.from().cloned(),
+                    vec![Address::new("archive@localhost")
+                        .map_err(Error::address("archive@localhost"))?],
+                )?;
+                Ok(())
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            email.envelope.to(),
+            &[Address::new("archive@localhost").unwrap()]
+        );
+        // The hook sees all generated headers.
+        let message = email.message_to_string().unwrap();
+        assert!(message.contains("Message-ID: <"));
+        assert!(message.contains("X-Stamp: 5\r\n"));
+
+        let failing = EmailBuilder::new()
+            .to("user@localhost")
+            .from("user@localhost")
+            .build_hook(|_: &mut MimeMessage, _: &mut Envelope| {
+                Err(Error::Crypto("no key".to_string()))
+            })
+            .build();
+        assert!(matches!(failing, Err(Error::Crypto(_))));
+    }
+
+    #[test]
+    fn test_footer() {
+        let email = EmailBuilder::new()
+            .to("user@localhost")
+            .from("user@localhost")
+            .alternative("<html><body><p>Hello</p></body></html>", "Hello")
+            .footer("Unsubscribe: https://example.org/u", "<p>Unsubscribe</p>")
+            .build()
+            .unwrap();
+        let message = email.message_to_string().unwrap();
+        assert!(message.contains("\r\n\r\nHello\r\nUnsubscribe: https://example.org/u\r\n"));
+        assert!(message.contains("<p>Hello</p><p>Unsubscribe</p></body></html>"));
+
+        // Encoded parts stay encoded.
+        let encoded = PartBuilder::new()
+            .header(("Content-Type", "text/plain; charset=utf-8"))
+            .header(("Content-Transfer-Encoding", "base64"))
+            .body(base64::encode("Hello"))
+            .build();
+        let email = EmailBuilder::new()
+            .to("user@localhost")
+            .from("user@localhost")
+            .child(encoded)
+            .footer("Footer", "<p>Footer</p>")
+            .build()
+            .unwrap();
+        let message = email.message_to_string().unwrap();
+        assert!(message.contains(&base64::encode("Hello\r\nFooter")));
+        assert!(!message.contains("<p>Footer</p>"));
+    }
+
+    #[test]
+    fn test_footer_creates_body() {
+        let email = EmailBuilder::new()
+            .to("user@localhost")
+            .from("user@localhost")
+            .attachment(b"data", "data.bin", &mime::APPLICATION_OCTET_STREAM)
+            .unwrap()
+            .footer("Footer", "<p>Footer</p>")
+            .build()
+            .unwrap();
+        let message = email.message_to_string().unwrap();
+        assert!(message.contains("multipart/alternative"));
+        assert!(message.find("<p>Footer</p>").unwrap() < message.find("ZGF0YQ==").unwrap());
+    }
+
+    #[test]
+    fn test_wrap_text() {
+        let email = EmailBuilder::new()
+            .to("user@localhost")
+            .from("user@localhost")
+            .alternative(
+                "<p>This line is long but HTML is left alone</p>",
+                "This line is long\n> and so is this quote",
+            )
+            .wrap_text(12)
+            .build()
+            .unwrap();
+        let message = email.message_to_string().unwrap();
+        assert!(message.contains("\r\n\r\nThis line is\r\nlong\r\n> and so is\r\n> this quote\r\n"));
+        assert!(message.contains("<p>This line is long but HTML is left alone</p>"));
+    }
+
+    #[test]
+    fn test_html_transform() {
+        let inline = |html: &str| -> Result<String, Error> {
+            Ok(html
+                .replace("<style>p { color: red }</style>", "")
+                .replace("<p>", "<p style=\"color: red\">"))
+        };
+        let email = EmailBuilder::new()
+            .to("user@localhost")
+            .from("user@localhost")
+            .alternative(
+                "<html><head><style>p { color: red }</style></head><body><p>Hi</p></body></html>",
+                "<p>Hi</p>",
+            )
+            .footer("", "<p>Footer</p>")
+            .html_transform(inline)
+            .build()
+            .unwrap();
+        let message = email.message_to_string().unwrap();
+        assert!(message.contains(
+            "<head></head><body><p style=\"color: red\">Hi</p><p style=\"color: red\">Footer</p>"
+        ));
+        // Text parts are left alone.
+        assert!(message.contains("\r\n\r\n<p>Hi</p>\r\n"));
+
+        let failing = EmailBuilder::new()
+            .to("user@localhost")
+            .from("user@localhost")
+            .html("<p>Hi</p>")
+            .html_transform(|_: &str| -> Result<String, Error> {
+                Err(Error::Transform("bad CSS".to_string()))
+            })
+            .build();
+        assert!(matches!(failing, Err(Error::Transform(_))));
+    }
+
+    #[test]
+    fn test_inline_data_uris() {
+        let email = EmailBuilder::new()
+            .to("user@localhost")
+            .from("user@localhost")
+            .alternative("<img src=\"data:image/png;base64,iVBORw0K\">", "Image")
+            .inline_data_uris()
+            .build()
+            .unwrap();
+        let message = email.message_to_string().unwrap();
+        assert!(!message.contains("data:image"));
+        assert!(message.contains("Content-Type: multipart/related;"));
+        assert!(message.contains("<img src=\"cid:"));
+        assert!(message.contains("Content-Type: image/png\r\n"));
+        assert!(message.contains("\r\n\r\niVBORw0K\r\n"));
+    }
+
+    #[test]
+    fn test_signature() {
+        let email = EmailBuilder::new()
+            .to("user@localhost")
+            .from("user@localhost")
+            .alternative("<p>Hello</p>", "Hello")
+            .footer("Footer", "<p>Footer</p>")
+            .signature("Joe\nExample Inc.", "<b>Joe</b>")
+            .build()
+            .unwrap();
+        let message = email.message_to_string().unwrap();
+        assert!(message.contains("\r\n\r\nHello\r\n-- \r\nJoe\r\nExample Inc.\r\nFooter\r\n"));
+        assert!(message.contains(
+            "<p>Hello</p><div class=\"signature\">-- <br>\r\n<b>Joe</b></div><p>Footer</p>\r\n"
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "storage")]
+    fn test_draft_roundtrip() {
+        let builder = EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .subject("Draft")
+            .message_id("draft@example.org".parse().unwrap())
+            .date(&OffsetDateTime::now_utc())
+            .alternative("<p>Hello</p>", "Hello")
+            .attachment_from_store(ContentHash::of(b"data"), "data.bin", &mime::TEXT_PLAIN)
+            .footer("Bye", "<p>Bye</p>");
+        let draft = builder.to_draft();
+        assert_eq!(
+            draft.to,
+            vec![Address::new_mailbox("jane@example.org".to_string())]
+        );
+        assert_eq!(draft.message.children.len(), 2);
+
+        let restored = EmailBuilder::from_draft(draft.clone());
+        assert_eq!(restored.to_draft(), draft);
+
+        let mut store = crate::store::MemoryAttachmentStore::default();
+        store.insert(b"data");
+        let expected = builder.build_with_store(&store).unwrap();
+        let email = restored.build_with_store(&store).unwrap();
+        assert_eq!(email.message_to_string(), expected.message_to_string());
+    }
+
+    #[test]
+    fn test_attachment_by_path() {
+        let path = std::env::temp_dir().join(format!("{}.txt", Uuid::new_v4()));
+        let builder = EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .body("See attachment")
+            .attachment_by_path(&path, Some("hello.txt"), &mime::TEXT_PLAIN)
+            .unwrap();
+        let draft = builder.to_draft();
+        assert_eq!(draft.message.children[0].body, "");
+        assert_eq!(draft.message.children[0].body_path.as_ref(), Some(&path));
+
+        // The file is only read when building
+        fs::write(&path, b"Hello").unwrap();
+        let email = EmailBuilder::from_draft(draft).build();
+        fs::remove_file(&path).unwrap();
+        let email = email.unwrap().message_to_string().unwrap();
+        assert!(email.contains("attachment; filename=\"hello.txt\""));
+        assert!(email.contains("\r\n\r\nSGVsbG8=\r\n"));
+    }
+
+    #[test]
+    fn test_attachment_from_reader() {
+        // Spans several chunks, the last one needing padding
+        let data = (0..100_000).map(|i| i as u8).collect::<Vec<_>>();
+        let builder = EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .message_id("reader@example.org".parse().unwrap())
+            .date(&OffsetDateTime::now_utc())
+            .attachment_from_reader(&data[..], "data.bin", &mime::APPLICATION_OCTET_STREAM)
+            .unwrap();
+
+        let mut written = vec![];
+        let (envelope, message_id) = builder.clone().build_to(&mut written).unwrap();
+        let email = builder.build().unwrap();
+        assert_eq!(envelope, email.envelope);
+        assert_eq!(message_id, email.message_id);
+        assert_eq!(written, email.message);
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.contains(&format!("\r\n\r\n{}\r\n", base64::encode(&data))));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_attachment_from_async_reader() {
+        let reader = futures_util::io::Cursor::new(b"Hello".to_vec());
+        let builder = futures_executor::block_on(EmailBuilder::new().attachment_from_async_reader(
+            reader,
+            "hello.txt",
+            &mime::TEXT_PLAIN,
+        ))
+        .unwrap();
+        let body = builder.build_body().unwrap();
+        assert!(String::from_utf8(body)
+            .unwrap()
+            .contains("\r\n\r\nSGVsbG8=\r\n"));
+    }
+
+    #[test]
+    fn test_identity() {
+        let identity = Identity::new(("joe@example.org", "Joe"))
+            .reply_to("support@example.org")
+            .organization("Example Inc.")
+            .signature("Joe", "<b>Joe</b>")
+            .header("X-Mailer", "Example Mail");
+        let email = EmailBuilder::new()
+            .identity(&identity)
+            .to("jane@example.org")
+            .text("Hello")
+            .build()
+            .unwrap();
+        let email = email.message_to_string().unwrap();
+
+        assert!(email.contains("From: Joe <joe@example.org>\r\n"));
+        assert!(email.contains("Reply-To: <support@example.org>\r\n"));
+        assert!(email.contains("Organization: Example Inc.\r\n"));
+        assert!(email.contains("X-Mailer: Example Mail\r\n"));
+        assert!(email.contains("Hello\r\n-- \r\nJoe\r\n"));
+    }
+
+    #[test]
+    fn test_encode_headers() {
+        let builder = EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .subject("Grüße")
+            .header(("X-Note", "café"))
+            .body("Hello");
+
+        let email = builder.clone().build().unwrap();
+        let email = email.message_to_string().unwrap();
+        assert!(email.contains("Subject: =?utf-8?q?Gr=C3=BC=C3=9Fe?=\r\n"));
+        assert!(email.contains("X-Note: =?utf-8?q?caf=C3=A9?=\r\n"));
+
+        let email = builder.encode_headers(false).build().unwrap();
+        let email = email.message_to_string().unwrap();
+        assert!(email.contains("Subject: Grüße\r\n"));
+    }
+
+    #[test]
+    fn test_dmarc_alignment() {
+        let builder = EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .body("Hello");
+
+        let alignment = DmarcAlignment::new().dkim_domain("mail.example.org");
+        assert!(builder.clone().dmarc_alignment(alignment).build().is_ok());
+
+        let alignment = DmarcAlignment::new().dkim_domain("esp.example.net");
+        let envelope = Envelope::new(
+            Some(Address::new_mailbox("bounces@esp.example.net".to_string())),
+            vec![Address::new_mailbox("jane@example.org".to_string())],
+        )
+        .unwrap();
+        match builder
+            .envelope(envelope)
+            .dmarc_alignment(alignment)
+            .build()
+        {
+            Err(Error::DmarcMisaligned(from)) => assert_eq!(from, "joe@example.org"),
+            result => panic!("expected misalignment, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_bounce_address() {
+        let email = EmailBuilder::new()
+            .from("joe@example.org")
+            .sender("news@example.org")
+            .to("jane@example.org")
+            .bounce_address("bounces+jane=example.org@bounces.example.org")
+            .body("Hello")
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            email.envelope.from(),
+            Some(&Address::new_mailbox(
+                "bounces+jane=example.org@bounces.example.org".to_string()
+            ))
+        );
+        let email = email.message_to_string().unwrap();
+        assert!(email.contains("From: <joe@example.org>\r\n"));
+        assert!(email.contains("Sender: <news@example.org>\r\n"));
+        assert!(!email.contains("bounces"));
+    }
+
+    #[test]
+    #[cfg(feature = "crypto")]
+    fn test_pgp_signed() {
+        let builder = EmailBuilder::new()
+            .subject("Signed")
+            .header(("Content-Type", "text/plain"))
+            .body("Hello");
+        let content = builder.clone().build_body().unwrap();
+        let email = builder
+            .pgp_signed(b"-----BEGIN PGP SIGNATURE-----\r\n\r\nAAAA\r\n-----END PGP SIGNATURE-----")
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .build()
+            .unwrap();
+        let email = email.message_to_string().unwrap();
+        let message = MimeMessage::parse(&email).unwrap();
+
+        assert_eq!(message.message_type, Some(MimeMultipartType::Signed));
+        assert_eq!(
+            header_value(&message, "Subject"),
+            Some("Signed".to_string())
+        );
+        assert_eq!(message.children[0].as_string().into_bytes(), content);
+    }
+
+    #[test]
+    #[cfg(feature = "crypto")]
+    fn test_pgp_encrypted() {
+        let email = EmailBuilder::new()
+            .subject("Secret")
+            .text("Hello")
+            .pgp_encrypted(&[0xc1, 0x00])
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .build()
+            .unwrap();
+        let email = email.message_to_string().unwrap();
+
+        assert!(email.contains("Subject: Secret\r\n"));
+        assert!(email.contains("Content-Type: application/pgp-encrypted\r\n"));
+        assert!(email.contains("-----BEGIN PGP MESSAGE-----\r\n"));
+        assert!(!email.contains("Hello"));
+    }
+
+    #[test]
+    fn test_date_now_utc() {
+        let email = EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .date_now_utc()
+            .body("Hello")
+            .build()
+            .unwrap();
+        let email = email.message_to_string().unwrap();
+        let message = MimeMessage::parse(&email).unwrap();
+
+        let dates = message.headers.find("Date").unwrap();
+        assert_eq!(dates.len(), 1);
+        assert!(dates[0].get_value().ends_with(" +0000"));
+    }
+
+    #[test]
+    fn test_read_receipt_to() {
+        let email = EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .read_receipt_to(("joe@example.org", "Joe"))
+            .read_receipt_to("receipts@example.org")
+            .return_receipt_to("joe@example.org")
+            .body("Hello")
+            .build()
+            .unwrap();
+        let email = email.message_to_string().unwrap();
+        assert!(email.contains(
+            "Disposition-Notification-To: Joe <joe@example.org>, <receipts@example.org>\r\n"
+        ));
+        assert!(email.contains("Return-Receipt-To: <joe@example.org>\r\n"));
+    }
+
+    #[test]
+    fn test_calendar_invite() {
+        let ics = "BEGIN:VCALENDAR\nMETHOD:REQUEST\nBEGIN:VEVENT\nSUMMARY:Sync\nEND:VEVENT\nEND:VCALENDAR\n";
+        let email = EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .alternative("<p>Join us</p>", "Join us")
+            .calendar_invite(ics, CalendarMethod::Request)
+            .build()
+            .unwrap();
+        let email = email.message_to_string().unwrap();
+        let message = MimeMessage::parse(&email).unwrap();
+
+        assert_eq!(message.message_type, Some(MimeMultipartType::Mixed));
+        let alternative = &message.children[0];
+        assert_eq!(alternative.children.len(), 3);
+        let calendar = &alternative.children[2];
+        assert_eq!(
+            header_value(calendar, "Content-Type").unwrap(),
+            "text/calendar; charset=utf-8; method=REQUEST"
+        );
+        assert_eq!(calendar.body, ics.replace('\n', "\r\n"));
+
+        let attachment = &message.children[1];
+        assert!(attachment.is_attachment());
+        assert_eq!(
+            body::decode_body(attachment).unwrap(),
+            ics.replace('\n', "\r\n").into_bytes()
+        );
+    }
+
+    #[test]
+    fn test_markdown_part() {
+        let part = PartBuilder::new()
+            .markdown_with("Hello *there*", &|markdown: &str| {
+                format!("<div>{}</div>", markdown)
+            })
+            .build();
+
+        assert_eq!(part.message_type, Some(MimeMultipartType::Alternative));
+        assert_eq!(
+            header_value(&part.children[0], "Content-Type").unwrap(),
+            "text/markdown; charset=utf-8; variant=CommonMark"
+        );
+        assert_eq!(part.children[0].body, "Hello *there*");
+        assert_eq!(part.children[1].body, "<div>Hello *there*</div>");
+
+        let part = PartBuilder::new().markdown("Hello *there*").build();
+        assert_eq!(part.children[1].body, "<p>Hello <em>there</em></p>\r\n");
+    }
+
+    #[test]
+    fn test_expires_and_sensitivity() {
+        let expires = OffsetDateTime::from_unix_timestamp(1_600_000_000);
+        let email = EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .body("Hello")
+            .expires(&expires)
+            .sensitivity(Sensitivity::Private)
+            .sensitivity(Sensitivity::CompanyConfidential)
+            .build()
+            .unwrap();
+        let email = email.message_to_string().unwrap();
+        assert!(email.contains("Expires: Sun, 13 Sep 2020 12:26:40 +0000\r\n"));
+        assert!(email.contains("Sensitivity: Company-Confidential\r\n"));
+        assert!(!email.contains("Private"));
+
+        let message = MimeMessage::parse(&email).unwrap();
+        assert_eq!(message.expires(), Some(expires));
+        assert_eq!(
+            message.sensitivity(),
+            Some(Sensitivity::CompanyConfidential)
+        );
+
+        let message = MimeMessage::parse(
+            "Expiry-Date: Sun, 13 Sep 2020 12:26:40 +0000\r\nSensitivity: personal\r\n\r\nHello",
+        )
+        .unwrap();
+        assert_eq!(message.expires(), Some(expires));
+        assert_eq!(message.sensitivity(), Some(Sensitivity::Personal));
+    }
+
+    #[test]
+    fn test_attach_message() {
+        let inner = EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .subject("Original")
+            .body("Grüße")
+            .build()
+            .unwrap();
+        let email = EmailBuilder::new()
+            .from("jane@example.org")
+            .to("jim@example.org")
+            .text("See below")
+            .attach_message(&inner)
+            .build()
+            .unwrap();
+        let inner = inner.message_to_string().unwrap();
+        let email = email.message_to_string().unwrap();
+        let message = MimeMessage::parse(&email).unwrap();
+
+        assert_eq!(message.message_type, Some(MimeMultipartType::Mixed));
+        let attached = &message.children[1];
+        assert_eq!(
+            header_value(attached, "Content-Type").unwrap(),
+            "message/rfc822"
+        );
+        assert_eq!(
+            header_value(attached, "Content-Transfer-Encoding").unwrap(),
+            "8bit"
+        );
+        assert!(attached.body.starts_with(inner.trim_end()));
+
+        let parsed = MimeMessage::parse(&inner).unwrap();
+        let email = EmailBuilder::new()
+            .from("jane@example.org")
+            .to("jim@example.org")
+            .attach_message(&parsed)
+            .build()
+            .unwrap();
+        assert!(email
+            .message_to_string()
+            .unwrap()
+            .contains("Content-Type: message/rfc822\r\n"));
+    }
+
+    #[test]
+    fn test_encoding_policy_auto() {
+        let email = EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .encoding_policy(EncodingPolicy::Auto)
+            .alternative("<p>Hello</p>", "Hello from Köln, see you soon!")
+            .attachment(b"data", "data.bin", &mime::APPLICATION_OCTET_STREAM)
+            .unwrap()
+            .build()
+            .unwrap();
+        let message = MimeMessage::parse(&email.message_to_string().unwrap()).unwrap();
+
+        let alternative = &message.children[0];
+        let text = &alternative.children[0];
+        assert_eq!(
+            header_value(text, "Content-Transfer-Encoding").unwrap(),
+            "quoted-printable"
+        );
+        assert_eq!(text.body.trim_end(), "Hello from K=C3=B6ln, see you soon!");
+        let html = &alternative.children[1];
+        assert_eq!(
+            header_value(html, "Content-Transfer-Encoding").unwrap(),
+            "7bit"
+        );
+        assert_eq!(
+            body::decode_body(&message.children[1]).unwrap(),
+            b"data".to_vec()
+        );
+
+        let email = EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .body("Grüße")
+            .build()
+            .unwrap();
+        let email = email.message_to_string().unwrap();
+        assert!(!email.contains("Content-Transfer-Encoding"));
+    }
+
+    #[test]
+    #[cfg(feature = "crypto")]
+    fn test_protect_subject() {
+        let reverse = |content: &[u8]| -> Result<Vec<u8>, Error> {
+            Ok(content.iter().rev().cloned().collect())
+        };
+        let email = EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .subject("Secret plans")
+            .text("Hello")
+            .protect_subject()
+            .smime_encrypt(reverse)
+            .build()
+            .unwrap();
+        let message = MimeMessage::parse(&email.message_to_string().unwrap()).unwrap();
+        assert_eq!(header_value(&message, "Subject").unwrap(), "...");
+        assert_eq!(message.protected_subject(), None);
+
+        let mut content = body::decode_body(&message).unwrap();
+        content.reverse();
+        let content = MimeMessage::parse(&String::from_utf8(content).unwrap()).unwrap();
+        assert!(header_value(&content, "Content-Type")
+            .unwrap()
+            .contains("protected-headers=v1"));
+        assert_eq!(header_value(&content, "From").unwrap(), "<joe@example.org>");
+        assert_eq!(
+            content.protected_subject(),
+            Some("Secret plans".to_string())
+        );
+
+        let builder = EmailBuilder::new()
+            .subject("Secret plans")
+            .text("Hello")
+            .protect_subject();
+        let content = builder.clone().build_body().unwrap();
+        let content = MimeMessage::parse(&String::from_utf8(content).unwrap()).unwrap();
+        assert_eq!(
+            content.protected_subject(),
+            Some("Secret plans".to_string())
+        );
+        let email = builder
+            .pgp_encrypted(&[0xc1, 0x00])
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .build()
+            .unwrap();
+        let email = email.message_to_string().unwrap();
+        assert!(email.contains("Subject: ...\r\n"));
+        assert!(!email.contains("protected-headers"));
+    }
+
+    #[test]
+    fn test_group_addresses() {
+        let email = EmailBuilder::new()
+            .from("joe@example.org")
+            .to_address(Address::new_group("undisclosed-recipients".into(), vec![]))
+            .cc_address(Address::new_group(
+                "team".into(),
+                vec!["jane@example.org".into(), "jim@example.org".into()],
+            ))
+            .bcc_address(Address::new_mailbox("boss@example.org".into()))
+            .body("Hello")
+            .build()
+            .unwrap();
+        assert_eq!(
+            email.envelope.to(),
+            &[
+                Address::new_mailbox("jane@example.org".into()),
+                Address::new_mailbox("jim@example.org".into()),
+                Address::new_mailbox("boss@example.org".into()),
+            ][..]
+        );
+        let email = email.message_to_string().unwrap();
+        assert!(email.contains("To: undisclosed-recipients: ;\r\n"));
+        assert!(email.contains("Cc: team: <jane@example.org>, <jim@example.org>;\r\n"));
+
+        let result = EmailBuilder::new()
+            .from("joe@example.org")
+            .to_address(Address::new_group("nobody".into(), vec![]))
+            .body("Hello")
+            .build();
+        assert!(matches!(
+            result,
+            Err(Error::Envelope(EnvelopeError::MissingTo))
+        ));
+    }
+
+    #[test]
+    fn test_smtputf8() {
+        let builder = || {
+            EmailBuilder::new()
+                .from("joe@example.org")
+                .to("jörg@bücher.example")
+                .body("Hello")
+        };
+        assert!(matches!(
+            builder().build(),
+            Err(Error::InvalidAddress(address)) if address == "jörg@bücher.example"
+        ));
+
+        let email = builder().smtputf8(true).build().unwrap();
+        assert!(email.envelope.requires_smtputf8());
+
+        let email = builder()
+            .smtputf8(true)
+            .punycode_domains(true)
+            .build()
+            .unwrap();
+        assert_eq!(
+            email.envelope.to(),
+            &[Address::new_mailbox("jörg@xn--bcher-kva.example".into())][..]
+        );
+        assert!(email.envelope.requires_smtputf8());
+
+        let email = EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@bücher.example")
+            .punycode_domains(true)
+            .body("Hello")
+            .build()
+            .unwrap();
+        assert!(!email.envelope.requires_smtputf8());
+        assert!(email
+            .message_to_string()
+            .unwrap()
+            .contains("To: <jane@xn--bcher-kva.example>\r\n"));
+    }
+
+    #[test]
+    fn test_with_rng() {
+        let date = OffsetDateTime::from_unix_timestamp(1_600_000_000);
+        let build = |seed| {
+            EmailBuilder::new()
+                .from("joe@example.org")
+                .to("jane@example.org")
+                .date(&date)
+                .alternative("<img src=\"data:image/gif;base64,R0lGODlh\">", "Hello")
+                .inline_data_uris()
+                .attachment(b"data", "a.bin", &mime::APPLICATION_OCTET_STREAM)
+                .unwrap()
+                .with_rng(seed)
+                .build()
+                .unwrap()
+        };
+        let (a, b) = (build(7), build(7));
+        assert_eq!(a, b);
+        assert_ne!(a.message_id, build(8).message_id);
+
+        let raw = a.message_to_string().unwrap();
+        assert!(raw.contains("Content-ID: <"));
+
+        let message = MimeMessage::parse(&raw).unwrap();
+        assert_eq!(message.children.len(), 2);
+        assert_ne!(message.boundary, message.children[0].boundary);
+    }
+
+    #[test]
+    fn test_sender_policy() {
+        let email = EmailBuilder::new()
+            .to("jane@example.org")
+            .on_behalf_of("boss@example.org", "assistant@example.org")
+            .body("Hello")
+            .build()
+            .unwrap();
+        assert_eq!(
+            email.envelope.from(),
+            Some(&Address::new_mailbox("assistant@example.org".into()))
+        );
+        let message = email.message_to_string().unwrap();
+        assert!(message.contains("Sender: <assistant@example.org>\r\n"));
+        assert!(message.contains("From: <boss@example.org>\r\n"));
+
+        let email = EmailBuilder::new()
+            .to("jane@example.org")
+            .on_behalf_of("joe@example.org", "Joe@example.org")
+            .body("Hello")
+            .build()
+            .unwrap();
+        assert!(!email.message_to_string().unwrap().contains("Sender:"));
+
+        let builder = EmailBuilder::new()
+            .to("jane@example.org")
+            .from("joe@example.org")
+            .from("jim@example.org")
+            .body("Hello");
+        assert!(builder
+            .clone()
+            .build()
+            .unwrap()
+            .message_to_string()
+            .unwrap()
+            .contains("Sender: <joe@example.org>\r\n"));
+        assert!(matches!(
+            builder.sender_policy(SenderPolicy::Explicit).build(),
+            Err(Error::MissingSender)
+        ));
+    }
+
+    #[test]
+    fn test_unsent() {
+        let email = EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .subject("Pre-filled")
+            .body("Hello")
+            .unsent()
+            .build()
+            .unwrap();
+        assert_eq!(email.message_id, "");
+        let message = MimeMessage::parse(&email.message_to_string().unwrap()).unwrap();
+        assert_eq!(
+            message.headers.get("X-Unsent".into()).unwrap().get_value(),
+            "1"
+        );
+        assert!(message.headers.find("Date").is_none());
+        assert!(message.headers.find("Message-ID").is_none());
+    }
+
+    #[test]
+    fn test_build_observer() {
+        let stats = Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = stats.clone();
+        let builder = EmailBuilder::new()
+            .to("user@localhost")
+            .from("user@localhost")
+            .text("Hello")
+            .html("<p>Hello</p>")
+            .build_observer(move |stats: &BuildStats| recorded.lock().unwrap().push(*stats));
+
+        let email = builder.clone().build().unwrap();
+        let mut written = vec![];
+        builder.build_to(&mut written).unwrap();
+
+        let stats = stats.lock().unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].size, email.message.len());
+        assert_eq!(stats[1].size, written.len());
+        assert_eq!(stats[0].parts, 3);
+        assert!(stats[0].serialize_duration <= stats[0].duration);
+    }
+
+    #[test]
+    fn test_error_context() {
+        let path = std::env::temp_dir().join(format!("{}.txt", Uuid::new_v4()));
+        let error = EmailBuilder::new()
+            .attachment_from_file(&path, None, &mime::TEXT_PLAIN)
+            .unwrap_err();
+        assert!(matches!(error, Error::File { path: ref failed, .. } if *failed == path));
+        assert!(error.to_string().contains(path.to_str().unwrap()));
+
+        let error = EmailBuilder::new()
+            .from("user@localhost")
+            .to("<broken")
+            .build()
+            .unwrap_err();
+        assert!(matches!(error, Error::Address { ref value, .. } if value == "<broken"));
+        assert!(error.to_string().starts_with("Invalid address \"<broken\""));
+    }
+
+    #[test]
+    #[cfg(feature = "auto-text")]
+    fn test_html_with_auto_text() {
+        let email = EmailBuilder::new()
+            .from("user@localhost")
+            .to("user@localhost")
+            .html_with_auto_text("<h1>News</h1><p>See <a href=\"https://example.org\">this</a></p>")
+            .build()
+            .unwrap()
+            .message_to_string()
+            .unwrap();
+        let message = MimeMessage::parse(&email).unwrap();
+        let alternative = &message.children[0];
+        assert_eq!(
+            alternative.message_type,
+            Some(MimeMultipartType::Alternative)
+        );
+        assert_eq!(
+            header_value(&alternative.children[0], "Content-Type").unwrap(),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            alternative.children[0].body,
+            render_text(
+                "<h1>News</h1><p>See <a href=\"https://example.org\">this</a></p>",
+                &RenderOptions::new()
+            )
+        );
+    }
+    #[test]
+    fn test_random_source() {
+        let date = OffsetDateTime::from_unix_timestamp(1_600_000_000);
+        let build = |builder: EmailBuilder, seed| {
+            builder
+                .from("joe@example.org")
+                .to("jane@example.org")
+                .date(&date)
+                .alternative("<p>Hello</p>", "Hello")
+                .random_source(std::sync::Mutex::new(StdRng::seed_from_u64(seed)))
+                .build()
+                .unwrap()
+        };
+        let a = build(EmailBuilder::new(), 3);
+        assert_eq!(a, build(EmailBuilder::new().with_rng(7), 3));
+        assert_ne!(a.message_id, build(EmailBuilder::new(), 4).message_id);
+
+        assert_eq!(EmailBuilder::new().with_rng(7).to_draft().seed, Some(7));
+        let source = std::sync::Mutex::new(StdRng::seed_from_u64(3));
+        assert_eq!(
+            EmailBuilder::new().random_source(source).to_draft().seed,
+            None
+        );
+    }
+
+    #[test]
+    fn test_x_header() {
+        let builder = || {
+            EmailBuilder::new()
+                .from("joe@example.org")
+                .to("jane@example.org")
+        };
+        let message = builder()
+            .x_header("X-Campaign-Id", "spring-2020")
+            .unwrap()
+            .x_header("X-Team", "Grüße")
+            .unwrap()
+            .body("Hello")
+            .build()
+            .unwrap()
+            .message_to_string()
+            .unwrap();
+        assert!(message.contains("X-Campaign-Id: spring-2020\r\n"));
+        assert!(message.contains("X-Team: =?utf-8?"));
+
+        for (name, value) in [
+            ("X-Campaign-Id", "spring\u{0}"),
+            ("X-Campaign Id", "spring"),
+            ("X-Campaign:Id", "spring"),
+            ("", "spring"),
+        ]
+        .iter()
+        {
+            assert!(matches!(
+                builder().x_header(*name, *value),
+                Err(Error::InvalidHeader(ref invalid)) if invalid == name
+            ));
+        }
+        assert!(matches!(
+            builder().x_header("X-Campaign-Id", "spring\r\nBcc: victim@example.org"),
+            Err(Error::HeaderInjection(_))
+        ));
+    }
+
+    #[test]
+    fn test_header_injection() {
+        let builder = || EmailBuilder::new().from("joe@example.org").body("Hello");
+        let injected = [
+            builder()
+                .to("jane@example.org")
+                .subject("Hello\r\nBcc: victim@example.org"),
+            builder()
+                .to("jane@example.org")
+                .header(("X-Note", "a\nBcc: victim@example.org")),
+            builder()
+                .to("jane@example.org")
+                .header(("X-Note", "a\r\n \r\n\r\nInjected body")),
+            builder()
+                .to("jane@example.org")
+                .reply_to("joe@example.org>\r\nBcc: <x@example.org"),
+            builder().to("jane@example.org").child(
+                PartBuilder::new()
+                    .header(("Content-Description", "a\rb"))
+                    .body("Part")
+                    .build(),
+            ),
+        ];
+        for builder in injected.iter() {
+            assert!(matches!(
+                builder.clone().build(),
+                Err(Error::HeaderInjection(_))
+            ));
+        }
+
+        // Folded values and encoded filenames are fine
+        let message = builder()
+            .to(("jane@example.org", "Jane\r\nBcc: victim@example.org"))
+            .to("a-rather-long-address-to-fold-the-to-header@example.org")
+            .to("another-rather-long-address@example.org")
+            .attachment(b"Hello", "a\r\nb.txt", &mime::TEXT_PLAIN)
+            .unwrap()
+            .build()
+            .unwrap()
+            .message_to_string()
+            .unwrap();
+        assert!(message.contains(">, \r\n\t<a-rather-long"));
+        assert!(!message.contains("\nBcc"));
+        assert!(message.contains("filename*=utf-8''a%0D%0Ab.txt"));
+    }
+
+    #[test]
+    fn test_from_message() {
+        let original = EmailBuilder::new()
+            .from("joe@example.org")
+            .to(("jane@example.org", "Jane Müller"))
+            .cc("bob@example.org")
+            .reply_to("team@example.org")
+            .in_reply_to("<1234@example.org>".parse().unwrap())
+            .subject("Grüße")
+            .header(("X-Priority", "1"))
+            .alternative("<p>Hello</p>", "Hello")
+            .attachment(b"Notes", "notes.txt", &mime::TEXT_PLAIN)
+            .unwrap()
+            .unsent()
+            .message_id("<draft@example.org>".parse().unwrap())
+            .build()
+            .unwrap();
+
+        let builder = EmailBuilder::from_message(&original.message).unwrap();
+        assert_eq!(
+            builder.to,
+            vec![Address::new_mailbox_with_name(
+                "Jane Müller".into(),
+                "jane@example.org".into()
+            )]
+        );
+        assert_eq!(
+            builder.from,
+            vec![Address::new_mailbox("joe@example.org".into())]
+        );
+        assert_eq!(builder.cc.len(), 1);
+        assert_eq!(builder.reply_to.len(), 1);
+        assert_eq!(builder.in_reply_to.len(), 1);
+        assert!(builder.unsent);
+        assert_eq!(
+            builder.get_header("Subject".into()).unwrap().get_value(),
+            "Grüße"
+        );
+
+        let edited = builder.to("carol@example.org").build().unwrap();
+        assert_eq!(edited.message_id, original.message_id);
+        // Unsent drafts have no Date
+        assert_eq!(edited.validate(), vec![ValidationIssue::MissingDate]);
+        let edited = MimeMessage::parse(&edited.message_to_string().unwrap()).unwrap();
+        let original = MimeMessage::parse(&original.message_to_string().unwrap()).unwrap();
+        let parts = |message: &MimeMessage| {
+            message
+                .children
+                .iter()
+                .map(MimeMessage::as_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(parts(&edited), parts(&original));
+        assert_eq!(
+            edited.headers.get("To".into()).unwrap().get_value(),
+            "Jane Müller <jane@example.org>, <carol@example.org>"
+        );
+        for name in [
+            "Cc",
+            "Reply-To",
+            "In-Reply-To",
+            "Subject",
+            "X-Priority",
+            "X-Unsent",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                edited.headers.get(name.to_string()),
+                original.headers.get(name.to_string())
+            );
+        }
+
+        assert!(matches!(
+            EmailBuilder::from_message(b"To: <jane@\r\n\r\nHello"),
+            Err(Error::Address { .. })
+        ));
+        assert!(matches!(
+            EmailBuilder::from_message(
+                b"To: jane@example.org\r\n\
+                  Content-Type: text/plain; charset=iso-8859-1\r\n\
+                  Content-Transfer-Encoding: 8bit\r\n\r\nGr\xfc\xdfe\r\n"
+            ),
+            Err(Error::InvalidUtf8(_))
+        ));
+
+        let edited = EmailBuilder::from_message(
+            b"From: joe@example.org\nTo: jane@example.org\nSubject: A long\n subject\n\nHello\n",
+        )
+        .unwrap()
+        .build()
+        .unwrap()
+        .message_to_string()
+        .unwrap();
+        assert!(edited.contains("Subject: A long\r\n subject\r\n"));
+        assert!(edited.ends_with("\r\n\r\nHello\r\n"));
+    }
 }
@@ -0,0 +1,170 @@
+use std::fmt;
+use std::io::{self, Write};
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+use crate::MimeMessage;
+
+/// SHA-256 hash identifying a payload in an `AttachmentStore`
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(crate = "serde_crate")
+)]
+pub struct ContentHash([u8; 32]);
+
+impl ContentHash {
+    /// Computes the hash of `data`
+    pub fn of(data: &[u8]) -> ContentHash {
+        let mut hash = [0; 32];
+        hash.copy_from_slice(&Sha256::digest(data));
+        ContentHash(hash)
+    }
+
+    /// The raw bytes of the hash
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for ContentHash {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0.iter() {
+            write!(fmt, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Hash algorithm of `MimeMessage::part_digests`
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum DigestAlgorithm {
+    /// SHA-256, as used by most malware databases
+    Sha256,
+    /// SHA-384
+    Sha384,
+    /// SHA-512
+    Sha512,
+}
+
+/// The hash of the decoded content of a leaf part
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct PartDigest {
+    /// The indices of the children leading to the part
+    pub path: Vec<usize>,
+    /// The algorithm the digest was computed with
+    pub algorithm: DigestAlgorithm,
+    /// The digest of the decoded content
+    pub digest: Vec<u8>,
+}
+
+/// The digest in lowercase hex
+impl fmt::Display for PartDigest {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.digest.iter() {
+            write!(fmt, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Feeds everything written into a hasher
+struct DigestWriter<D>(D);
+
+impl<D: Digest> Write for DigestWriter<D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn digest_of<D: Digest>(part: &MimeMessage, hasher: D) -> io::Result<Vec<u8>> {
+    let mut writer = DigestWriter(hasher);
+    part.decode_to(&mut writer)?;
+    Ok(writer.0.finalize().to_vec())
+}
+
+/// Hashes the leaf parts within `part`, whose children are at `path`
+pub(crate) fn part_digests(
+    part: &MimeMessage,
+    algorithm: DigestAlgorithm,
+    path: &mut Vec<usize>,
+    digests: &mut Vec<PartDigest>,
+) {
+    if !part.children.is_empty() {
+        for (i, child) in part.children.iter().enumerate() {
+            path.push(i);
+            part_digests(child, algorithm, path, digests);
+            path.pop();
+        }
+        return;
+    }
+    if part.body_ref.is_some() {
+        return;
+    }
+    let digest = match algorithm {
+        DigestAlgorithm::Sha256 => digest_of(part, Sha256::new()),
+        DigestAlgorithm::Sha384 => digest_of(part, Sha384::new()),
+        DigestAlgorithm::Sha512 => digest_of(part, Sha512::new()),
+    };
+    // Content which fails to decode is hashed as it is, so it still gets
+    // checked.
+    let digest = digest.unwrap_or_else(|_| match algorithm {
+        DigestAlgorithm::Sha256 => Sha256::digest(part.body.as_bytes()).to_vec(),
+        DigestAlgorithm::Sha384 => Sha384::digest(part.body.as_bytes()).to_vec(),
+        DigestAlgorithm::Sha512 => Sha512::digest(part.body.as_bytes()).to_vec(),
+    });
+    digests.push(PartDigest {
+        path: path.clone(),
+        algorithm,
+        digest,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email_builder::{attachment_part, text_part};
+    use crate::{MimeMultipartType, PartBuilder};
+
+    #[test]
+    fn test_content_hash() {
+        assert_eq!(
+            ContentHash::of(b"abc").to_string(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_part_digests() {
+        let message = PartBuilder::new()
+            .message_type(MimeMultipartType::Mixed)
+            .child(text_part("Hello"))
+            .child(attachment_part(
+                b"%PDF-1.4",
+                "report.pdf",
+                &"application/pdf".parse().unwrap(),
+            ))
+            .build();
+
+        let digests = message.part_digests(DigestAlgorithm::Sha256);
+        assert_eq!(digests.len(), 2);
+        assert_eq!(digests[0].path, vec![0]);
+        assert_eq!(digests[1].path, vec![1]);
+        assert_eq!(
+            digests[1].to_string(),
+            ContentHash::of(b"%PDF-1.4").to_string()
+        );
+
+        let digests = text_part("Hello").part_digests(DigestAlgorithm::Sha512);
+        assert_eq!(digests[0].path, Vec::<usize>::new());
+        assert_eq!(digests[0].digest, Sha512::digest(b"Hello").to_vec());
+    }
+}
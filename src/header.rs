@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::fmt;
@@ -5,6 +6,69 @@ use std::ops::Deref;
 use std::slice::Iter as SliceIter;
 use std::sync::Arc;
 
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+use crate::email_builder::Error;
+use crate::rfc5322::Rfc5322Builder;
+
+lazy_static::lazy_static! {
+    static ref ENCODED_WORD_RE: regex::Regex =
+        regex::Regex::new(r"=\?[^?\s]+\?[QqBb]\?[^?\s]*\?=").unwrap();
+    /// Runs of encoded-words, as the whitespace between them is ignored
+    static ref ENCODED_WORDS_RE: regex::Regex = regex::Regex::new(
+        r"=\?[^?\s]+\?[QqBb]\?[^?\s]*\?=(?:[ \t]+=\?[^?\s]+\?[QqBb]\?[^?\s]*\?=)*"
+    ).unwrap();
+    static ref FOLD_RE: regex::Regex = regex::Regex::new(r"\r?\n([ \t])").unwrap();
+}
+
+/// The maximum length of an encoded-word (RFC 2047, section 2)
+const MAX_ENCODED_WORD_LENGTH: usize = 75;
+
+/// Encodes `value` into RFC 2047 encoded-words if it contains non-ASCII
+/// characters, choosing Q or B encoding, whichever is shorter.
+///
+/// Long values are split into several words, which can be folded.
+pub fn encode_header_value(value: &str) -> Cow<'_, str> {
+    if value.is_ascii() {
+        return Cow::Borrowed(value);
+    }
+
+    let encode =
+        |s: &str| encoded_words::encode(s, None, encoded_words::EncodingFlag::Shortest, None);
+    let mut words: Vec<String> = vec![];
+    let mut start = 0;
+    for (position, c) in value.char_indices() {
+        let end = position + c.len_utf8();
+        if position > start && encode(&value[start..end]).len() > MAX_ENCODED_WORD_LENGTH {
+            words.push(encode(&value[start..position]));
+            start = position;
+        }
+    }
+    words.push(encode(&value[start..]));
+    Cow::Owned(words.join(" "))
+}
+
+/// Unfolds `value` and decodes the RFC 2047 encoded-words within it.
+///
+/// Encoded-words which fail to decode are left untouched.
+pub fn decode_header_value(value: &str) -> String {
+    ENCODED_WORDS_RE
+        .replace_all(
+            &FOLD_RE.replace_all(value, "$1"),
+            |caps: &regex::Captures| {
+                ENCODED_WORD_RE
+                    .find_iter(&caps[0])
+                    .map(|word| match encoded_words::decode(word.as_str()) {
+                        Ok(result) => result.decoded,
+                        Err(_) => word.as_str().to_string(),
+                    })
+                    .collect::<String>()
+            },
+        )
+        .into_owned()
+}
+
 /// Trait for converting from a Rust type into a Header value.
 pub trait ToHeader {
     type Error;
@@ -37,24 +101,66 @@ impl<T: ToHeader> ToFoldedHeader for T {
     }
 }
 
+/// Trait for converting a Header value into a Rust type, the
+/// counterpart of `ToHeader`.
+pub trait FromHeader: Sized {
+    type Error;
+
+    /// Parses `value`, which is unfolded but may still contain RFC 2047
+    /// encoded-words.
+    fn from_header(value: &str) -> Result<Self, Self::Error>;
+}
+
+/// Encoded-words are decoded with `decode_header_value`
+impl FromHeader for String {
+    type Error = ();
+
+    fn from_header(value: &str) -> Result<String, ()> {
+        Ok(decode_header_value(value))
+    }
+}
+
+macro_rules! from_header_for_uint {
+    ($($ty:ty),*) => {
+        $(
+            impl FromHeader for $ty {
+                type Error = std::num::ParseIntError;
+
+                fn from_header(value: &str) -> Result<$ty, std::num::ParseIntError> {
+                    value.trim().parse()
+                }
+            }
+        )*
+    };
+}
+
+from_header_for_uint!(u8, u16, u32, u64, usize);
+
+/// Non-ASCII values are encoded with `encode_header_value`
 impl ToHeader for String {
     type Error = ();
 
     fn to_header(value: String) -> Result<String, ()> {
-        Ok(value)
+        Ok(encode_header_value(&value).into_owned())
     }
 }
 
+/// Non-ASCII values are encoded with `encode_header_value`
 impl<'a> ToHeader for &'a str {
     type Error = ();
 
     fn to_header(value: &'a str) -> Result<String, ()> {
-        Ok(value.to_string())
+        Ok(encode_header_value(value).into_owned())
     }
 }
 
 /// Represents an RFC 822 Header
 #[derive(PartialEq, Eq, Clone, Debug, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(crate = "serde_crate")
+)]
 pub struct Header {
     /// The name of this header
     pub name: String,
@@ -84,8 +190,22 @@ impl Header {
             .map(|val| Header::new(name.clone(), val))
     }
 
-    /// Get the value represented by this header.
-    pub fn get_value(&self) -> &str {
+    /// Get the value represented by this header, unfolded and with any RFC 2047
+    /// encoded-words decoded.
+    ///
+    /// Encoded-words which fail to decode are left untouched.
+    pub fn get_value(&self) -> String {
+        decode_header_value(&self.value)
+    }
+
+    /// Parses the unfolded value into `T`, e.g. `parse_value::<DateHeader>()`
+    /// for a `Date` header.
+    pub fn parse_value<T: FromHeader>(&self) -> Result<T, T::Error> {
+        T::from_header(&FOLD_RE.replace_all(&self.value, "$1"))
+    }
+
+    /// Get the value of this header exactly as it will be emitted.
+    pub fn get_raw_value(&self) -> &str {
         &self.value
     }
 }
@@ -118,21 +238,70 @@ impl<'s> Iterator for HeaderIter<'s> {
     }
 }
 
+/// Groups of headers in the order `HeaderMap::canonical_order` sorts
+/// them into. Headers within a group keep their order.
+const CANONICAL_ORDER: &[&[&str]] = &[
+    &[
+        "Return-Path",
+        "Received",
+        "Received-SPF",
+        "Authentication-Results",
+    ],
+    &["Date"],
+    &["From"],
+    &["Sender"],
+    &["Reply-To"],
+    &["To"],
+    &["Cc"],
+    &["Bcc"],
+    &["Message-ID"],
+    &["In-Reply-To"],
+    &["References"],
+    &["Subject"],
+];
+
 /// A collection of Headers
+///
+/// Header names are matched ignoring ASCII case, as per RFC 5322, while
+/// each header keeps the casing it was created with.
+///
+/// With the `serde` feature it is serialized as the list of headers, in
+/// order.
 #[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(crate = "serde_crate", from = "Vec<Header>", into = "Vec<Header>")
+)]
 pub struct HeaderMap {
     // We store headers "twice" inside the HeaderMap.
     //
     // The first is as an ordered list of headers,
     // which is used to iterate over.
     ordered_headers: Vec<Arc<Header>>,
-    // The second is as a mapping between header names
+    // The second is as a mapping between lowercased header names
     // and all of the headers with that name.
     //
     // This allows quick retrival of a header by name.
     headers: HashMap<String, Vec<Arc<Header>>>,
 }
 
+impl From<Vec<Header>> for HeaderMap {
+    fn from(headers: Vec<Header>) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for header in headers {
+            map.insert(header);
+        }
+        map
+    }
+}
+
+impl From<HeaderMap> for Vec<Header> {
+    fn from(headers: HeaderMap) -> Vec<Header> {
+        headers.iter().cloned().collect()
+    }
+}
+
 impl HeaderMap {
     pub fn new() -> HeaderMap {
         HeaderMap {
@@ -143,7 +312,7 @@ impl HeaderMap {
 
     /// Adds a header to the collection
     pub fn insert(&mut self, header: Header) {
-        let header_name = header.name.clone();
+        let header_name = header.name.to_ascii_lowercase();
         let rc = Arc::new(header);
         // Add to the ordered list of headers
         self.ordered_headers.push(rc.clone());
@@ -156,21 +325,63 @@ impl HeaderMap {
             Entry::Vacant(entry) => {
                 // There haven't been any headers with this name
                 // as of yet, so make a new list and push it in.
-                let mut header_list = Vec::new();
-                header_list.push(rc);
-                entry.insert(header_list);
+                entry.insert(vec![rc]);
             }
         };
     }
 
+    /// Inserts a header at `index` of the order of the headers, shifting
+    /// those after it, e.g. `insert_at(0, ..)` for a trace header.
+    ///
+    /// Panics if `index` is greater than the number of headers.
+    pub fn insert_at(&mut self, index: usize, header: Header) {
+        let header_name = header.name.to_ascii_lowercase();
+        let rc = Arc::new(header);
+        // Headers of the same name before `index` stay before this one
+        let position = self.ordered_headers[..index]
+            .iter()
+            .filter(|header| header.name.eq_ignore_ascii_case(&header_name))
+            .count();
+        self.ordered_headers.insert(index, rc.clone());
+        self.headers
+            .entry(header_name)
+            .or_default()
+            .insert(position, rc);
+    }
+
+    /// Sorts the headers into the conventional order of RFC 5322, section
+    /// 3.6: trace headers first, then the origination date, originator,
+    /// destination and identification fields and the subject. Other headers
+    /// follow.
+    ///
+    /// The sort is stable, so headers of the same name, like `Received`,
+    /// keep their order.
+    pub fn canonical_order(&mut self) {
+        self.ordered_headers.sort_by_key(|header| {
+            CANONICAL_ORDER
+                .iter()
+                .position(|group| {
+                    group
+                        .iter()
+                        .any(|name| header.name.eq_ignore_ascii_case(name))
+                })
+                .unwrap_or(CANONICAL_ORDER.len())
+        });
+    }
+
+    /// Replaces the headers of the same name by `header`, at the position
+    /// of the first one, or appends it if there are none
     pub fn replace(&mut self, header: Header) {
-        let header_name = header.name.clone();
+        let header_name = header.name.to_ascii_lowercase();
         let rc = Arc::new(header);
         // Remove existing
         let mut i = 0;
         let mut have_inserted = false;
         while i < self.ordered_headers.len() {
-            if self.ordered_headers[i].name == header_name {
+            if self.ordered_headers[i]
+                .name
+                .eq_ignore_ascii_case(&header_name)
+            {
                 if have_inserted {
                     // Just remove the header, as we've already updated
                     self.ordered_headers.remove(i);
@@ -178,26 +389,37 @@ impl HeaderMap {
                     // Update the header in-place
                     self.ordered_headers[i] = rc.clone();
                     have_inserted = true;
+                    i += 1;
                 }
             } else {
                 i += 1;
             }
         }
-        let mut header_list = Vec::new();
-        header_list.push(rc.clone());
+        if !have_inserted {
+            // There was nothing to replace, so append it instead
+            self.ordered_headers.push(rc.clone());
+        }
         // Straight up replace the header in the map
-        self.headers.insert(header_name, header_list);
+        self.headers.insert(header_name, vec![rc]);
+    }
+
+    /// Removes all headers of `name`
+    pub fn remove(&mut self, name: &str) {
+        if self.headers.remove(&name.to_ascii_lowercase()).is_some() {
+            self.ordered_headers
+                .retain(|header| !header.name.eq_ignore_ascii_case(name));
+        }
     }
 
     /// Get an Iterator over the collection of headers.
-    pub fn iter(&self) -> HeaderIter {
+    pub fn iter(&self) -> HeaderIter<'_> {
         HeaderIter::new(self.ordered_headers.iter())
     }
 
     /// Get the last value of the header with `name`
     pub fn get(&self, name: String) -> Option<&Header> {
         self.headers
-            .get(&name)
+            .get(&name.to_ascii_lowercase())
             .map(|headers| headers.last().unwrap())
             .map(|rc| rc.deref())
     }
@@ -214,11 +436,55 @@ impl HeaderMap {
 
     /// Find a list of headers of `name`, `None` if there
     /// are no headers with that name.
+    ///
+    /// The headers are in the order they were inserted, which for a parsed
+    /// message is the order they appear in, e.g. the most recent `Received`
+    /// header first.
     pub fn find(&self, name: &str) -> Option<Vec<&Header>> {
         self.headers
-            .get(name)
+            .get(&name.to_ascii_lowercase())
             .map(|rcs| rcs.iter().map(|rc| rc.deref()).collect())
     }
+
+    /// All headers of `name` in the order they were inserted, like `find`,
+    /// but without collecting them.
+    pub fn get_all(&self, name: &str) -> &[Arc<Header>] {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map_or(&[], |rcs| &rcs[..])
+    }
+
+    /// The header of `name` at `index` of the order they were inserted,
+    /// e.g. `nth("Received", 0)` for the one added by the last hop.
+    pub fn nth(&self, name: &str, index: usize) -> Option<&Header> {
+        self.get_all(name).get(index).map(|rc| rc.deref())
+    }
+
+    /// Serializes the headers into a block of folded `Name: value` lines,
+    /// as they would appear in a message, without the terminating empty
+    /// line.
+    pub fn to_block_string(&self) -> String {
+        let mut builder = Rfc5322Builder::new();
+        for header in self.iter() {
+            builder.emit_folded(&header.to_string()[..]);
+            builder.emit_raw("\r\n");
+        }
+        builder.result().clone()
+    }
+
+    /// Parses a block of headers, e.g. one created by `to_block_string`.
+    ///
+    /// Folded values are unfolded. Parsing stops at the first empty line.
+    pub fn parse_block(block: &str) -> Result<HeaderMap, Error> {
+        let (headers, _) = mailparse::parse_headers(block.as_bytes()).map_err(Error::Parse)?;
+        let mut map = HeaderMap::new();
+        for header in headers.iter() {
+            let value = String::from_utf8_lossy(header.get_value_raw());
+            let value = FOLD_RE.replace_all(&value, "$1");
+            map.insert(Header::new(header.get_key(), value.into_owned()));
+        }
+        Ok(map)
+    }
 }
 
 impl Default for HeaderMap {
@@ -228,6 +494,7 @@ impl Default for HeaderMap {
 }
 
 #[cfg(test)]
+#[allow(clippy::redundant_static_lifetimes)]
 mod tests {
     use super::*;
     use std::collections::HashSet;
@@ -269,9 +536,13 @@ mod tests {
                 result: Some("Test text"),
             },
             HeaderTest {
-                input: "=?ISO-8859-1?Q?Multiple?= =?utf-8?b?ZW5jb2Rpbmdz?=",
+                input: "=?ISO-8859-1?Q?Multiple_?= =?utf-8?b?ZW5jb2Rpbmdz?=",
                 result: Some("Multiple encodings"),
             },
+            HeaderTest {
+                input: "=?ISO-8859-1?Q?Adjacent?= =?utf-8?b?ZW5jb2Rpbmdz?=",
+                result: Some("Adjacentencodings"),
+            },
             HeaderTest {
                 input: "Some things with =?utf-8?b?ZW5jb2Rpbmdz?=, other things without.",
                 result: Some("Some things with encodings, other things without."),
@@ -322,4 +593,171 @@ mod tests {
         // And that there is the right number of them
         assert_eq!(count, expected_headers.len());
     }
+
+    #[test]
+    fn test_header_map_nth() {
+        let headers = HeaderMap::from(make_sample_headers());
+        let values = headers
+            .get_all("Test")
+            .iter()
+            .map(|header| header.get_value())
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec!["Value", "Value 2"]);
+        assert_eq!(headers.nth("Test", 1).unwrap().get_value(), "Value 2");
+        assert_eq!(headers.nth("Test", 2), None);
+        assert!(headers.get_all("Missing").is_empty());
+    }
+
+    #[test]
+    fn test_header_map_case_insensitive() {
+        let mut headers = HeaderMap::new();
+        headers.insert(Header::new("content-type".into(), "text/plain".into()));
+        headers.insert(Header::new("X-Test".into(), "1".into()));
+        headers.insert(Header::new("x-test".into(), "2".into()));
+        assert_eq!(headers.find("X-TEST").unwrap().len(), 2);
+        assert_eq!(headers.get("x-Test".into()).unwrap().get_value(), "2");
+        assert_eq!(headers.nth("X-Test", 1).unwrap().name, "x-test");
+
+        headers.replace(Header::new("Content-Type".into(), "text/html".into()));
+        assert_eq!(headers.len(), 3);
+        assert_eq!(
+            headers.to_block_string(),
+            "Content-Type: text/html\r\nX-Test: 1\r\nx-test: 2\r\n"
+        );
+
+        headers.remove("X-TEST");
+        assert_eq!(headers.len(), 1);
+        assert!(headers.find("x-test").is_none());
+    }
+
+    #[test]
+    fn test_parse_value() {
+        use crate::date::DateHeader;
+        use crate::email::MessageId;
+        use crate::{Address, MimeContentTypeHeader};
+
+        let header = Header::new("Subject".into(), "=?utf-8?q?Gr=C3=BC=C3=9Fe?=".into());
+        assert_eq!(header.parse_value::<String>().unwrap(), "Grüße");
+
+        let header = Header::new("To".into(), "A <a@x.org>,\r\n b@y.org".into());
+        assert_eq!(header.parse_value::<Vec<Address>>().unwrap().len(), 2);
+
+        let header = Header::new("Date".into(), "Sun, 13 Sep 2020 12:26:40 +0000".into());
+        let date = header.parse_value::<DateHeader>().unwrap().date();
+        assert_eq!(date.unix_timestamp(), 1_600_000_000);
+
+        let header = Header::new(
+            "References".into(),
+            "<a@x.org>\r\n <b@y.org><c@z.org>".into(),
+        );
+        let ids = header.parse_value::<Vec<MessageId>>().unwrap();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(ids[2].as_str(), "c@z.org");
+        assert!(header.parse_value::<MessageId>().is_err());
+
+        let header = Header::new("Content-Type".into(), "Text/Plain; charset=utf-8".into());
+        let content_type = header.parse_value::<MimeContentTypeHeader>().unwrap();
+        assert_eq!(content_type.content_type, ("text".into(), "plain".into()));
+        assert_eq!(content_type.params["charset"], "utf-8");
+
+        let header = Header::new("Content-Length".into(), " 42 ".into());
+        assert_eq!(header.parse_value::<u64>().unwrap(), 42);
+        assert!(header.parse_value::<u8>().is_ok());
+        let header = Header::new("Content-Length".into(), "-1".into());
+        assert!(header.parse_value::<u32>().is_err());
+    }
+
+    #[test]
+    fn test_header_map_from_vec() {
+        let headers = HeaderMap::from(make_sample_headers());
+        assert_eq!(headers.len(), SAMPLE_HEADERS.len());
+        assert_eq!(
+            headers.get("Test".to_string()).unwrap().get_value(),
+            "Value 2"
+        );
+        assert_eq!(Vec::from(headers), make_sample_headers());
+    }
+
+    #[test]
+    fn test_header_block_roundtrip() {
+        let mut headers = HeaderMap::new();
+        headers.insert(Header::new("Test".to_string(), "Value".to_string()));
+        headers.insert(Header::new("Test".to_string(), "Value 2".to_string()));
+        headers.insert(Header::new("Test-2".to_string(), "Value 3".to_string()));
+        let long_value = vec!["word"; 30].join(" ");
+        headers.insert(Header::new("Long".to_string(), long_value.clone()));
+
+        let block = headers.to_block_string();
+        assert!(block.starts_with("Test: Value\r\nTest: Value 2\r\nTest-2: Value 3\r\n"));
+        assert!(block.contains("\r\n\tword"));
+        assert!(block.ends_with("word\r\n"));
+
+        let parsed = HeaderMap::parse_block(&block).unwrap();
+        assert_eq!(parsed.len(), 4);
+        assert_eq!(parsed.find("Test").unwrap(), headers.find("Test").unwrap());
+        // Folding turned a space into a tab, which unfolding keeps.
+        let long = parsed.get("Long".to_string()).unwrap().get_value();
+        assert_eq!(long.replace('\t', " "), long_value);
+
+        // Parsing stops at the end of the header block.
+        let parsed = HeaderMap::parse_block("Subject: Hi\r\n\r\nBody: no\r\n").unwrap();
+        assert_eq!(parsed.len(), 1);
+    }
+
+    #[test]
+    fn test_encode_header_value() {
+        assert_eq!(encode_header_value("Hello"), "Hello");
+
+        let header = Header::new_with_value("Subject".to_string(), "Grüße").unwrap();
+        assert_eq!(header.get_raw_value(), "=?utf-8?q?Gr=C3=BC=C3=9Fe?=");
+        assert_eq!(header.get_value(), "Grüße");
+
+        let value = "Ünïcödé ".repeat(10);
+        let header = Header::new_with_value("Subject".to_string(), value.as_str()).unwrap();
+        assert!(header.get_raw_value().split(' ').count() > 1);
+        assert!(header
+            .get_raw_value()
+            .split(' ')
+            .all(|word| word.len() <= MAX_ENCODED_WORD_LENGTH));
+        assert_eq!(header.get_value(), value);
+    }
+    #[test]
+    fn test_header_map_canonical_order() {
+        let mut headers = HeaderMap::new();
+        for (name, value) in [
+            ("MIME-Version", "1.0"),
+            ("Subject", "Hi"),
+            ("To", "jane@example.org"),
+            ("Received", "from b"),
+            ("From", "joe@example.org"),
+            ("Date", "Tue, 01 Jul 2003 10:52:37 +0200"),
+        ]
+        .iter()
+        {
+            headers.insert(Header::new(name.to_string(), value.to_string()));
+        }
+        headers.insert_at(0, Header::new("Received".into(), "from a".into()));
+        headers.insert_at(2, Header::new("X-Mailer".into(), "test".into()));
+        assert_eq!(headers.nth("Received", 0).unwrap().get_value(), "from a");
+
+        headers.canonical_order();
+        let names = headers
+            .iter()
+            .map(|header| header.name.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "Received",
+                "Received",
+                "Date",
+                "From",
+                "To",
+                "Subject",
+                "MIME-Version",
+                "X-Mailer"
+            ]
+        );
+        assert_eq!(headers.iter().next().unwrap().get_value(), "from a");
+    }
 }
@@ -0,0 +1,62 @@
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+use crate::Mailbox;
+
+/// A sender identity, e.g. one of the accounts configured in a mail client,
+/// applied to a message with `EmailBuilder::identity`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(crate = "serde_crate")
+)]
+pub struct Identity {
+    /// The `From` address
+    pub from: Mailbox,
+    /// The `Reply-To` addresses
+    pub reply_to: Vec<Mailbox>,
+    /// The `Organization` header (RFC 2076)
+    pub organization: Option<String>,
+    /// Text and HTML signature
+    pub signature: Option<(String, String)>,
+    /// Additional headers as name and value pairs
+    pub headers: Vec<(String, String)>,
+}
+
+impl Identity {
+    /// Creates an identity sending from `from`
+    pub fn new<A: Into<Mailbox>>(from: A) -> Identity {
+        Identity {
+            from: from.into(),
+            reply_to: vec![],
+            organization: None,
+            signature: None,
+            headers: vec![],
+        }
+    }
+
+    /// Adds a `Reply-To` address
+    pub fn reply_to<A: Into<Mailbox>>(mut self, address: A) -> Identity {
+        self.reply_to.push(address.into());
+        self
+    }
+
+    /// Sets the `Organization` header
+    pub fn organization<S: Into<String>>(mut self, organization: S) -> Identity {
+        self.organization = Some(organization.into());
+        self
+    }
+
+    /// Sets the text and HTML signature
+    pub fn signature<S: Into<String>, T: Into<String>>(mut self, text: S, html: T) -> Identity {
+        self.signature = Some((text.into(), html.into()));
+        self
+    }
+
+    /// Adds a header, e.g. `X-Mailer`
+    pub fn header<S: Into<String>, T: Into<String>>(mut self, name: S, value: T) -> Identity {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
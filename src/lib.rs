@@ -12,18 +12,105 @@
 )]
 
 mod email_builder;
+mod append;
+mod banner;
 mod address;
 mod header;
 mod mimeheader;
 mod message;
+mod editor;
+#[cfg(feature = "storage")]
+mod store;
+mod send_set;
+mod validate;
+mod variant;
+mod matcher;
+mod digest;
+mod draft;
+#[cfg(feature = "crypto")]
+mod dkim;
+mod dmarc;
+mod hash;
+mod identity;
+mod list;
+mod protected;
+mod quote;
+mod reply;
+mod render;
+mod retention;
+mod retry;
+mod rewrite;
+mod smtputf8;
+mod strip;
+mod subject;
+mod template;
+mod trace;
+#[cfg(feature = "storage")]
+mod upload;
+#[cfg(feature = "infer")]
+mod sniff;
+mod body;
+mod calendar;
+mod markup;
+mod messages;
+mod normalize;
+#[cfg(feature = "zip")]
+mod compress;
 mod rfc5322;
 
     
+pub mod date;
+#[cfg(feature = "deliverability")]
+pub mod deliverability;
 pub mod email;
+pub mod mailto;
+#[cfg(feature = "crypto")]
+pub mod pgp;
+pub mod redact;
+#[cfg(feature = "crypto")]
+pub mod smime;
 
 pub use self::message::*;
+pub use self::append::*;
+pub use self::banner::*;
+pub use self::calendar::*;
+pub use self::markup::*;
+pub use self::messages::*;
+pub use self::normalize::*;
 pub use self::mimeheader::*;
 pub use self::email_builder::*;
 pub use self::address::*;
 pub use self::header::*;
+pub use self::editor::*;
+#[cfg(feature = "storage")]
+pub use self::store::*;
+pub use self::send_set::*;
+pub use self::rfc5322::{FoldStyle, LineEnding, SerializationConfig};
+pub use self::validate::ValidationIssue;
+pub use self::variant::*;
+pub use self::matcher::*;
+pub use self::digest::*;
+pub use self::draft::*;
+#[cfg(feature = "crypto")]
+pub use self::dkim::*;
+pub use self::dmarc::*;
+pub use self::hash::*;
+pub use self::identity::*;
+pub use self::list::*;
+pub use self::protected::*;
+pub use self::quote::*;
+pub use self::reply::*;
+pub use self::render::*;
+pub use self::retention::*;
+pub use self::retry::*;
+pub use self::rewrite::*;
+pub use self::smtputf8::*;
+pub use self::strip::*;
+pub use self::subject::*;
+pub use self::template::*;
+pub use self::trace::*;
+#[cfg(feature = "storage")]
+pub use self::upload::*;
+#[cfg(feature = "infer")]
+pub use self::sniff::*;
 
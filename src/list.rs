@@ -0,0 +1,199 @@
+use crate::{Error, Mailbox};
+
+/// The value of `List-Unsubscribe-Post` for one-click unsubscription
+/// (RFC 8058)
+pub(crate) const ONE_CLICK_POST: &str = "List-Unsubscribe=One-Click";
+
+/// The addresses of a message resent by a mailing list, as produced by
+/// `munge_from_for_list`
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct MungedFrom {
+    /// The new `From` address, at the list's domain
+    pub from: Mailbox,
+    /// The `Reply-To` address, pointing back to the original author
+    pub reply_to: Mailbox,
+}
+
+/// Rewrites the sender of a message resent by a mailing list, the usual
+/// workaround for authors whose domain publishes a strict DMARC policy.
+///
+/// The message is sent from the list address as `"Joe via List"`, while
+/// replies still go to the original author. Without display names, the
+/// author's address and the local part of the list address are used.
+pub fn munge_from_for_list(original_from: &Mailbox, list_address: &Mailbox) -> MungedFrom {
+    let author = match original_from.name {
+        Some(ref name) if !name.trim().is_empty() => name.trim().to_string(),
+        _ => original_from.address.replacen('@', " at ", 1),
+    };
+    let list = match list_address.name {
+        Some(ref name) if !name.trim().is_empty() => name.trim(),
+        _ => list_address
+            .address
+            .split('@')
+            .next()
+            .unwrap_or(&list_address.address),
+    };
+
+    MungedFrom {
+        from: Mailbox::new_with_name(
+            format!("{} via {}", author, list),
+            list_address.address.clone(),
+        ),
+        reply_to: original_from.clone(),
+    }
+}
+
+/// Checks that `url` starts with one of `schemes`, ignoring case, and has
+/// no characters which would end the `<url>` in a header
+fn checked_url<'a>(url: &'a str, schemes: &[&str]) -> Result<&'a str, Error> {
+    let url = url.trim();
+    let has_scheme = schemes.iter().any(|scheme| {
+        matches!(url.get(..scheme.len()), Some(prefix) if prefix.eq_ignore_ascii_case(scheme))
+            && url.len() > scheme.len()
+    });
+    let is_clean = url
+        .chars()
+        .all(|c| c.is_ascii_graphic() && !matches!(c, '<' | '>' | ',' | '"'));
+    if has_scheme && is_clean {
+        Ok(url)
+    } else {
+        Err(Error::InvalidUrl(url.to_string()))
+    }
+}
+
+/// The `List-Unsubscribe` value with the `mailto:` URL first, as RFC 2369
+/// asks clients to prefer the first URL they support
+pub(crate) fn unsubscribe_value(
+    mailto: Option<&str>,
+    http_url: Option<&str>,
+) -> Result<String, Error> {
+    let mut urls = vec![];
+    if let Some(mailto) = mailto {
+        urls.push(format!("<{}>", checked_url(mailto, &["mailto:"])?));
+    }
+    if let Some(http_url) = http_url {
+        urls.push(format!(
+            "<{}>",
+            checked_url(http_url, &["https://", "http://"])?
+        ));
+    }
+    if urls.is_empty() {
+        return Err(Error::InvalidUrl(String::new()));
+    }
+    Ok(urls.join(", "))
+}
+
+/// The `List-Unsubscribe` value for one-click unsubscription, which
+/// RFC 8058 requires to be HTTPS
+pub(crate) fn one_click_value(https_url: &str) -> Result<String, Error> {
+    Ok(format!("<{}>", checked_url(https_url, &["https://"])?))
+}
+
+/// The `List-Id` value, with the description quoted if needed
+pub(crate) fn list_id_value(description: Option<&str>, id: &str) -> Result<String, Error> {
+    let is_label = |label: &str| {
+        !label.is_empty()
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+    };
+    let valid = id.len() <= 255 && id.contains('.') && id.split('.').all(is_label);
+    if !valid {
+        return Err(Error::InvalidListId(id.to_string()));
+    }
+    match description.map(str::trim) {
+        Some(description) if !description.is_empty() => {
+            let is_atoms = description
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == ' ' || !c.is_ascii());
+            if is_atoms {
+                Ok(format!("{} <{}>", description, id))
+            } else {
+                let escaped = description.replace('\\', "\\\\").replace('"', "\\\"");
+                Ok(format!("\"{}\" <{}>", escaped, id))
+            }
+        }
+        _ => Ok(format!("<{}>", id)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmailBuilder;
+
+    #[test]
+    fn test_munge_from_for_list() {
+        let list = Mailbox::new_with_name("Rust Users".into(), "users@lists.example.org".into());
+        let munged = munge_from_for_list(&"joe@example.org".into(), &list);
+        assert_eq!(
+            munged.from,
+            Mailbox::new_with_name(
+                "joe at example.org via Rust Users".into(),
+                "users@lists.example.org".into()
+            )
+        );
+        assert_eq!(munged.reply_to, Mailbox::new("joe@example.org".into()));
+
+        let author = Mailbox::new_with_name("Jörg".into(), "joerg@example.org".into());
+        let munged = munge_from_for_list(&author, &"dev@lists.example.org".into());
+        let email = EmailBuilder::new()
+            .from(munged.from)
+            .reply_to(munged.reply_to)
+            .to("dev@lists.example.org")
+            .body("Hello")
+            .build()
+            .unwrap();
+        let email = email.message_to_string().unwrap();
+        assert!(email.contains("From: =?utf-8?q?J=C3=B6rg_via_dev?= <dev@lists.example.org>\r\n"));
+        assert!(email.contains("Reply-To: =?utf-8?q?J=C3=B6rg?= <joerg@example.org>\r\n"));
+    }
+
+    #[test]
+    fn test_list_headers() {
+        let email = EmailBuilder::new()
+            .from("news@example.org")
+            .to("joe@example.org")
+            .body("Hello")
+            .list_unsubscribe(
+                Some("mailto:unsubscribe@example.org?subject=unsubscribe"),
+                Some("https://example.org/unsubscribe/joe"),
+            )
+            .unwrap()
+            .list_id(Some("Example News, weekly"), "news.example.org")
+            .unwrap()
+            .precedence_bulk()
+            .build()
+            .unwrap()
+            .message_to_string()
+            .unwrap();
+        assert!(email.contains(
+            "List-Unsubscribe: <mailto:unsubscribe@example.org?subject=unsubscribe>,\r\n\t<https://example.org/unsubscribe/joe>\r\n"
+        ));
+        assert!(email.contains("List-Id: \"Example News, weekly\" <news.example.org>\r\n"));
+        assert!(email.contains("Precedence: bulk\r\n"));
+
+        let email = EmailBuilder::new()
+            .from("news@example.org")
+            .to("joe@example.org")
+            .body("Hello")
+            .list_unsubscribe_one_click("https://example.org/u/joe")
+            .unwrap()
+            .build()
+            .unwrap()
+            .message_to_string()
+            .unwrap();
+        assert!(email.contains("List-Unsubscribe: <https://example.org/u/joe>\r\n"));
+        assert!(email.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n"));
+
+        assert!(unsubscribe_value(Some("https://example.org"), None).is_err());
+        assert!(unsubscribe_value(None, Some("https://example.org/a>b")).is_err());
+        assert!(unsubscribe_value(None, None).is_err());
+        assert!(one_click_value("http://example.org/u").is_err());
+        assert!(list_id_value(None, "localhost").is_err());
+        assert_eq!(
+            list_id_value(None, "a.example.org").unwrap(),
+            "<a.example.org>"
+        );
+    }
+}
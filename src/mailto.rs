@@ -0,0 +1,189 @@
+//! `mailto:` URLs (RFC 6068).
+//!
+//! `parse` turns a link into a builder with the recipients, subject and
+//! body filled in, `Email::to_mailto_url` goes the other way.
+
+use crate::body::{self, TextKind};
+use crate::email::Email;
+use crate::{Address, EmailBuilder, Error, MimeMessage};
+
+/// Fields of a URL which are filled into the builder. Others, like
+/// `in-reply-to`, are ignored as RFC 6068 suggests for unknown fields.
+const TO: &str = "to";
+const CC: &str = "cc";
+const BCC: &str = "bcc";
+const SUBJECT: &str = "subject";
+const BODY: &str = "body";
+
+/// Parses a `mailto:` URL into a builder with the `To`, `Cc` and `Bcc`
+/// recipients, subject and body of the URL.
+///
+/// Addresses from the path and the `to` field are both added. Line breaks
+/// in the body are kept as they are.
+pub fn parse(url: &str) -> Result<EmailBuilder, Error> {
+    let rest = match url.get(..7) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("mailto:") => &url[7..],
+        _ => return Err(Error::InvalidUrl(url.to_string())),
+    };
+    let (path, query) = match rest.find('?') {
+        Some(position) => (&rest[..position], &rest[position + 1..]),
+        None => (rest, ""),
+    };
+
+    let mut builder = EmailBuilder::new();
+    let path = percent_decode(path).ok_or_else(|| Error::InvalidUrl(url.to_string()))?;
+    if !path.is_empty() {
+        builder = builder.to_addresses(Address::parse_list(&path).map_err(Error::address(&path))?);
+    }
+    for field in query.split('&').filter(|field| !field.is_empty()) {
+        let (name, value) = match field.find('=') {
+            Some(position) => (&field[..position], &field[position + 1..]),
+            None => (field, ""),
+        };
+        let value = percent_decode(value).ok_or_else(|| Error::InvalidUrl(url.to_string()))?;
+        match name.to_ascii_lowercase().as_str() {
+            TO if !value.is_empty() => {
+                builder = builder
+                    .to_addresses(Address::parse_list(&value).map_err(Error::address(&value))?);
+            }
+            CC if !value.is_empty() => {
+                for address in Address::parse_list(&value).map_err(Error::address(&value))? {
+                    builder = builder.cc_address(address);
+                }
+            }
+            BCC if !value.is_empty() => {
+                for address in Address::parse_list(&value).map_err(Error::address(&value))? {
+                    builder = builder.bcc_address(address);
+                }
+            }
+            SUBJECT => builder = builder.subject(value),
+            BODY => builder = builder.body(value),
+            _ => {}
+        }
+    }
+    Ok(builder)
+}
+
+/// Builds the `mailto:` URL of `email`, with its `To` addresses as the
+/// path, and its `Cc` addresses, subject and first plain text body as
+/// fields.
+///
+/// Display names and `Bcc` recipients are left out.
+pub(crate) fn to_url(email: &Email) -> Result<String, Error> {
+    let message = MimeMessage::parse(&String::from_utf8_lossy(&email.message))?;
+    let addresses = |name: &str| -> Result<String, Error> {
+        let mut addresses = vec![];
+        if let Some(header) = message.headers.nth(name, 0) {
+            for address in header
+                .parse_value::<Vec<Address>>()
+                .map_err(Error::address(header.get_raw_value()))?
+            {
+                match address {
+                    Address::Mailbox(mailbox) => addresses.push(mailbox.address),
+                    Address::Group(_, mailboxes) => {
+                        addresses.extend(mailboxes.into_iter().map(|mailbox| mailbox.address))
+                    }
+                }
+            }
+        }
+        Ok(addresses
+            .iter()
+            .map(|address| percent_encode(address, b"@"))
+            .collect::<Vec<_>>()
+            .join(","))
+    };
+
+    let mut url = format!("mailto:{}", addresses("To")?);
+    let mut fields = vec![];
+    let cc = addresses("Cc")?;
+    if !cc.is_empty() {
+        fields.push(format!("{}={}", CC, cc));
+    }
+    if let Some(subject) = body::header_value(&message, "Subject") {
+        fields.push(format!("{}={}", SUBJECT, percent_encode(&subject, b"")));
+    }
+    let text = body::text_bodies(&message)
+        .into_iter()
+        .find(|(kind, _)| *kind == TextKind::Plain);
+    if let Some((_, text)) = text {
+        fields.push(format!("{}={}", BODY, percent_encode(&text, b"")));
+    }
+    if !fields.is_empty() {
+        url.push('?');
+        url.push_str(&fields.join("&"));
+    }
+    Ok(url)
+}
+
+/// Percent-encodes everything but unreserved characters and `extra`
+fn percent_encode(value: &str, extra: &[u8]) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) || extra.contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Decodes `%XX` escapes as UTF-8, `None` if an escape or the result is
+/// invalid
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let email = parse(
+            "mailto:joe@example.org,jane@example.org?cc=bob@example.org\
+             &subject=Caf%C3%A9%20plans&body=Hi%2C%0D%0Asee%20you%20there&x-custom=1",
+        )
+        .unwrap()
+        .from("me@example.org")
+        .build()
+        .unwrap();
+        assert_eq!(email.envelope.to().len(), 3);
+
+        let message = MimeMessage::parse(&email.message_to_string().unwrap()).unwrap();
+        assert_eq!(
+            body::header_value(&message, "To").unwrap(),
+            "<joe@example.org>, <jane@example.org>"
+        );
+        assert_eq!(
+            body::header_value(&message, "Subject").unwrap(),
+            "Café plans"
+        );
+        assert_eq!(message.body, "Hi,\r\nsee you there");
+        assert!(message.headers.get("X-Custom".to_string()).is_none());
+
+        assert!(parse("http://example.org").is_err());
+        assert!(parse("mailto:joe@example.org?subject=%E").is_err());
+    }
+
+    #[test]
+    fn test_to_mailto_url() {
+        let url = "mailto:joe@example.org?cc=bob@example.org\
+                   &subject=Caf%C3%A9%20plans&body=Hi%2C%0D%0Asee%20you%20there";
+        let email = parse(url).unwrap().from("me@example.org").build().unwrap();
+        assert_eq!(email.to_mailto_url().unwrap(), url);
+    }
+}
@@ -0,0 +1,325 @@
+use regex::Regex;
+
+use crate::body::escape_html;
+use crate::{MimeMultipartType, PartBuilder};
+
+lazy_static::lazy_static! {
+    static ref HEADING_RE: Regex = Regex::new(r"^(#{1,6})\s+(.*?)\s*#*\s*$").unwrap();
+    static ref LIST_ITEM_RE: Regex = Regex::new(r"^\s{0,3}(?:([-*+])|([0-9]+)[.)])\s+(.*)$").unwrap();
+    static ref RULE_RE: Regex = Regex::new(r"^\s{0,3}(?:(?:-\s*){3,}|(?:\*\s*){3,}|(?:_\s*){3,})$").unwrap();
+    static ref LINK_RE: Regex = Regex::new(r"\[([^\]]+)\]\(([^)\s]+)\)").unwrap();
+    static ref STRONG_RE: Regex = Regex::new(r"\*\*([^*]+)\*\*|__([^_]+)__").unwrap();
+    static ref EMPHASIS_RE: Regex = Regex::new(r"\*([^*]+)\*|\b_([^_]+)_\b").unwrap();
+    static ref ENRICHED_TOKEN_RE: Regex = Regex::new(r"(?i)<<|<(/?)([a-z0-9-]{1,60})>|\r?\n(?:\r?\n)*").unwrap();
+}
+
+/// Converts Markdown to HTML for `PartBuilder::markdown_with`, e.g. a
+/// wrapper around a full CommonMark implementation.
+pub trait MarkdownRenderer: Send + Sync {
+    /// Returns the HTML for `markdown`
+    fn render(&self, markdown: &str) -> String;
+}
+
+impl<F> MarkdownRenderer for F
+where
+    F: Fn(&str) -> String + Send + Sync,
+{
+    fn render(&self, markdown: &str) -> String {
+        self(markdown)
+    }
+}
+
+/// Builds the multipart/alternative part holding `markdown` and its
+/// rendered HTML
+pub(crate) fn markdown_alternative(
+    builder: PartBuilder,
+    markdown: &str,
+    renderer: &dyn MarkdownRenderer,
+) -> PartBuilder {
+    let html = renderer.render(markdown);
+    builder
+        .message_type(MimeMultipartType::Alternative)
+        .child(
+            PartBuilder::new()
+                .header((
+                    "Content-Type",
+                    "text/markdown; charset=utf-8; variant=CommonMark",
+                ))
+                .body(markdown)
+                .build(),
+        )
+        .child(
+            PartBuilder::new()
+                .header(("Content-Type", mime::TEXT_HTML_UTF_8.to_string()))
+                .body(html)
+                .build(),
+        )
+}
+
+/// Renders the basic subset of Markdown used in messages as HTML:
+/// paragraphs, headings, lists, block quotes, code blocks, rules, and
+/// emphasis, code spans and links within text. Raw HTML is escaped.
+pub fn render_markdown(markdown: &str) -> String {
+    let lines = markdown.lines().collect::<Vec<_>>();
+    let mut html = String::new();
+    render_blocks(&lines, &mut html);
+    html
+}
+
+fn render_blocks(lines: &[&str], html: &mut String) {
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim();
+
+        if trimmed.is_empty() {
+            i += 1;
+        } else if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            let fence = &trimmed[..3];
+            let end = lines[i + 1..]
+                .iter()
+                .position(|line| line.trim().starts_with(fence))
+                .map_or(lines.len(), |end| i + 1 + end);
+            html.push_str("<pre><code>");
+            for line in &lines[i + 1..end] {
+                html.push_str(&escape_html(line));
+                html.push('\n');
+            }
+            html.push_str("</code></pre>\n");
+            i = end + 1;
+        } else if let Some(heading) = HEADING_RE.captures(trimmed) {
+            let level = heading[1].len();
+            html.push_str(&format!(
+                "<h{}>{}</h{}>\n",
+                level,
+                render_inline(&heading[2]),
+                level
+            ));
+            i += 1;
+        } else if RULE_RE.is_match(line) {
+            html.push_str("<hr>\n");
+            i += 1;
+        } else if trimmed.starts_with('>') {
+            let end = block_end(lines, i, |line| line.trim_start().starts_with('>'));
+            let quoted = lines[i..end]
+                .iter()
+                .map(|line| {
+                    let line = line.trim_start().trim_start_matches('>');
+                    line.strip_prefix(' ').unwrap_or(line)
+                })
+                .collect::<Vec<_>>();
+            html.push_str("<blockquote>\n");
+            render_blocks(&quoted, html);
+            html.push_str("</blockquote>\n");
+            i = end;
+        } else if let Some(item) = LIST_ITEM_RE.captures(line) {
+            let ordered = item.get(2).is_some();
+            let tag = if ordered { "ol" } else { "ul" };
+            html.push('<');
+            html.push_str(tag);
+            html.push_str(">\n");
+            while i < lines.len() {
+                let item = match LIST_ITEM_RE.captures(lines[i]) {
+                    Some(item) if item.get(2).is_some() == ordered => item,
+                    _ => break,
+                };
+                let mut text = item[3].to_string();
+                i += 1;
+                // Lazy continuation lines belong to the item
+                while i < lines.len()
+                    && !lines[i].trim().is_empty()
+                    && !LIST_ITEM_RE.is_match(lines[i])
+                {
+                    text.push('\n');
+                    text.push_str(lines[i].trim());
+                    i += 1;
+                }
+                html.push_str(&format!("<li>{}</li>\n", render_inline(&text)));
+            }
+            html.push_str("</");
+            html.push_str(tag);
+            html.push_str(">\n");
+        } else {
+            let end = block_end(lines, i, |line| {
+                !line.trim().is_empty()
+                    && !line.trim_start().starts_with('>')
+                    && !HEADING_RE.is_match(line.trim())
+                    && !LIST_ITEM_RE.is_match(line)
+            });
+            let text = lines[i..end]
+                .iter()
+                .map(|line| line.trim())
+                .collect::<Vec<_>>()
+                .join("\n");
+            html.push_str(&format!("<p>{}</p>\n", render_inline(&text)));
+            i = end.max(i + 1);
+        }
+    }
+}
+
+/// The index of the first line after `start` not belonging to the block
+fn block_end<F: Fn(&str) -> bool>(lines: &[&str], start: usize, belongs: F) -> usize {
+    lines[start + 1..]
+        .iter()
+        .position(|line| !belongs(line))
+        .map_or(lines.len(), |end| start + 1 + end)
+}
+
+fn render_inline(text: &str) -> String {
+    // Code spans are taken literally, so only the text between them is
+    // formatted.
+    let mut html = String::new();
+    for (i, segment) in text.split('`').enumerate() {
+        if i % 2 == 1 {
+            html.push_str("<code>");
+            html.push_str(&escape_html(segment));
+            html.push_str("</code>");
+            continue;
+        }
+        let segment = escape_html(segment);
+        let segment = LINK_RE.replace_all(&segment, "<a href=\"$2\">$1</a>");
+        let segment = STRONG_RE.replace_all(&segment, "<strong>$1$2</strong>");
+        let segment = EMPHASIS_RE.replace_all(&segment, "<em>$1$2</em>");
+        html.push_str(&segment.replace('\n', "<br>\n"));
+    }
+    html
+}
+
+/// Converts `text/enriched` content (RFC 1896) to HTML.
+///
+/// The formatting commands of the RFC are mapped to their HTML
+/// counterparts, unknown commands and `<param>` content are dropped.
+pub fn enriched_to_html(enriched: &str) -> String {
+    let mut html = String::new();
+    let mut position = 0;
+    let mut param_depth = 0usize;
+    let mut nofill_depth = 0usize;
+
+    for token in ENRICHED_TOKEN_RE.captures_iter(enriched) {
+        let whole = token.get(0).unwrap();
+        if param_depth == 0 {
+            html.push_str(&escape_html(&enriched[position..whole.start()]));
+        }
+        position = whole.end();
+
+        let command = match token.get(2) {
+            Some(command) => command.as_str().to_ascii_lowercase(),
+            None => {
+                if param_depth > 0 {
+                    continue;
+                }
+                if whole.as_str() == "<<" {
+                    html.push_str("&lt;");
+                } else if nofill_depth > 0 {
+                    html.push_str(whole.as_str());
+                } else {
+                    // A single line break is a space, any further ones are
+                    // kept.
+                    let breaks = whole.as_str().matches('\n').count();
+                    if breaks == 1 {
+                        html.push(' ');
+                    }
+                    for _ in 1..breaks {
+                        html.push_str("<br>\n");
+                    }
+                }
+                continue;
+            }
+        };
+        let closing = !token[1].is_empty();
+
+        if command == "param" {
+            param_depth = if closing {
+                param_depth.saturating_sub(1)
+            } else {
+                param_depth + 1
+            };
+            continue;
+        }
+        if param_depth > 0 {
+            continue;
+        }
+        if command == "nofill" {
+            nofill_depth = if closing {
+                nofill_depth.saturating_sub(1)
+            } else {
+                nofill_depth + 1
+            };
+        }
+
+        let tag = match &command[..] {
+            "bold" => "b",
+            "italic" => "i",
+            "underline" => "u",
+            "fixed" => "tt",
+            "bigger" => "big",
+            "smaller" => "small",
+            "excerpt" => "blockquote",
+            "nofill" => "pre",
+            "center" => "div style=\"text-align: center\"",
+            "flushleft" => "div style=\"text-align: left\"",
+            "flushright" => "div style=\"text-align: right\"",
+            "flushboth" => "div style=\"text-align: justify\"",
+            _ => continue,
+        };
+        if closing {
+            html.push_str("</");
+            html.push_str(tag.split(' ').next().unwrap());
+        } else {
+            html.push('<');
+            html.push_str(tag);
+        }
+        html.push('>');
+    }
+    if param_depth == 0 {
+        html.push_str(&escape_html(&enriched[position..]));
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_markdown() {
+        let markdown = "# Release *1.0*\n\
+                        \n\
+                        The **new** version is [out](https://example.org/1.0).\n\
+                        Use `a < b` now.\n\
+                        \n\
+                        - One\n\
+                        - Two\n\
+                        \n\
+                        1. First\n\
+                        \n\
+                        > Quoted\n\
+                        \n\
+                        ```\n\
+                        <code>\n\
+                        ```\n\
+                        ---";
+        assert_eq!(
+            render_markdown(markdown),
+            "<h1>Release <em>1.0</em></h1>\n\
+             <p>The <strong>new</strong> version is <a href=\"https://example.org/1.0\">out</a>.<br>\n\
+             Use <code>a &lt; b</code> now.</p>\n\
+             <ul>\n<li>One</li>\n<li>Two</li>\n</ul>\n\
+             <ol>\n<li>First</li>\n</ol>\n\
+             <blockquote>\n<p>Quoted</p>\n</blockquote>\n\
+             <pre><code>&lt;code&gt;\n</code></pre>\n\
+             <hr>\n"
+        );
+    }
+
+    #[test]
+    fn test_enriched_to_html() {
+        let enriched = "<bold>Hello</bold> <<you>,\r\n\
+                        see <color><param>red</param>this</color>.\r\n\r\n\
+                        <nofill>a\r\nb</nofill>";
+        assert_eq!(
+            enriched_to_html(enriched),
+            "<b>Hello</b> &lt;you&gt;, see this.<br>\n<pre>a\r\nb</pre>"
+        );
+    }
+}
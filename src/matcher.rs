@@ -0,0 +1,166 @@
+use std::ops::Not;
+
+use crate::body;
+use crate::MimeMessage;
+
+/// A Sieve-style condition on messages, for filtering.
+///
+/// Header values are decoded before matching and all comparisons ignore
+/// ASCII case, like the `i;ascii-casemap` comparator of Sieve.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Matcher {
+    /// A header with the given name contains the given text
+    HeaderContains(String, String),
+    /// An address in the `From` header belongs to the given domain
+    FromDomain(String),
+    /// The message has an attachment of the given MIME type, which may be a
+    /// wildcard like `image/*`
+    HasAttachmentType(String),
+    /// All of the matchers match
+    All(Vec<Matcher>),
+    /// Any of the matchers matches
+    Any(Vec<Matcher>),
+    /// The matcher doesn't match
+    Not(Box<Matcher>),
+}
+
+impl Matcher {
+    /// Matches if a header called `name` contains `text`
+    pub fn header_contains<S: Into<String>, T: Into<String>>(name: S, text: T) -> Matcher {
+        Matcher::HeaderContains(name.into(), text.into())
+    }
+
+    /// Matches if an address in the `From` header is at `domain`
+    pub fn from_domain<S: Into<String>>(domain: S) -> Matcher {
+        Matcher::FromDomain(domain.into())
+    }
+
+    /// Matches if there is an attachment of type `mime_type`, e.g.
+    /// `application/pdf` or `image/*`
+    pub fn has_attachment_type<S: Into<String>>(mime_type: S) -> Matcher {
+        Matcher::HasAttachmentType(mime_type.into())
+    }
+
+    /// Matches if both this and `other` match
+    pub fn and(self, other: Matcher) -> Matcher {
+        match self {
+            Matcher::All(mut matchers) => {
+                matchers.push(other);
+                Matcher::All(matchers)
+            }
+            _ => Matcher::All(vec![self, other]),
+        }
+    }
+
+    /// Matches if this or `other` matches
+    pub fn or(self, other: Matcher) -> Matcher {
+        match self {
+            Matcher::Any(mut matchers) => {
+                matchers.push(other);
+                Matcher::Any(matchers)
+            }
+            _ => Matcher::Any(vec![self, other]),
+        }
+    }
+
+    /// Evaluates this matcher against `message`
+    pub fn matches(&self, message: &MimeMessage) -> bool {
+        match self {
+            Matcher::HeaderContains(name, text) => {
+                let text = text.to_lowercase();
+                message
+                    .headers
+                    .get_all(name)
+                    .iter()
+                    .any(|header| header.get_value().to_lowercase().contains(&text))
+            }
+            Matcher::FromDomain(domain) => message
+                .headers
+                .get_all("From")
+                .iter()
+                .filter_map(|header| mailparse::addrparse(&header.get_value()).ok())
+                .flat_map(|addresses| addresses.extract_single_info())
+                .any(|info| match info.addr.rfind('@') {
+                    Some(at) => info.addr[at + 1..].eq_ignore_ascii_case(domain),
+                    None => false,
+                }),
+            Matcher::HasAttachmentType(mime_type) => has_attachment_type(message, mime_type),
+            Matcher::All(matchers) => matchers.iter().all(|matcher| matcher.matches(message)),
+            Matcher::Any(matchers) => matchers.iter().any(|matcher| matcher.matches(message)),
+            Matcher::Not(matcher) => !matcher.matches(message),
+        }
+    }
+}
+
+impl Not for Matcher {
+    type Output = Matcher;
+
+    fn not(self) -> Matcher {
+        match self {
+            Matcher::Not(matcher) => *matcher,
+            _ => Matcher::Not(Box::new(self)),
+        }
+    }
+}
+
+fn has_attachment_type(part: &MimeMessage, mime_type: &str) -> bool {
+    if part.is_attachment() {
+        let actual = body::mime_type(part);
+        let matches = match mime_type.strip_suffix("/*") {
+            // The actual type is already lowercased.
+            Some(top_level) => actual.starts_with(&format!("{}/", top_level.to_ascii_lowercase())),
+            None => actual.eq_ignore_ascii_case(mime_type),
+        };
+        if matches {
+            return true;
+        }
+    }
+    part.children
+        .iter()
+        .any(|child| has_attachment_type(child, mime_type))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email_builder::{attachment_part, text_part};
+    use crate::PartBuilder;
+
+    fn message() -> MimeMessage {
+        PartBuilder::new()
+            .header(("From", "=?utf-8?q?J=C3=B6e?= <joe@Example.COM>"))
+            .header(("List-Id", "Announcements <announce.example.com>"))
+            .child(text_part("Hello"))
+            .child(attachment_part(b"%PDF", "a.pdf", &mime::APPLICATION_PDF))
+            .build()
+    }
+
+    #[test]
+    fn test_simple_matchers() {
+        let message = message();
+        assert!(Matcher::header_contains("list-id", "ANNOUNCE").matches(&message));
+        assert!(Matcher::header_contains("From", "Jöe").matches(&message));
+        assert!(!Matcher::header_contains("Subject", "").matches(&message));
+        assert!(Matcher::from_domain("example.com").matches(&message));
+        assert!(!Matcher::from_domain("mail.example.com").matches(&message));
+        assert!(Matcher::has_attachment_type("application/pdf").matches(&message));
+        assert!(Matcher::has_attachment_type("application/*").matches(&message));
+        // Inline text isn't an attachment.
+        assert!(!Matcher::has_attachment_type("text/plain").matches(&message));
+    }
+
+    #[test]
+    fn test_combined_matchers() {
+        let message = message();
+        let matcher = Matcher::from_domain("example.com").and(
+            Matcher::has_attachment_type("image/*")
+                .or(Matcher::header_contains("List-Id", "announce")),
+        );
+        assert!(matcher.matches(&message));
+        assert!(!(!matcher.clone()).matches(&message));
+        assert_eq!(!!matcher.clone(), matcher);
+        assert!(!Matcher::from_domain("example.com")
+            .and(Matcher::has_attachment_type("image/png"))
+            .matches(&message));
+    }
+}
@@ -1,16 +1,48 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
 
+#[cfg(feature = "async")]
+use futures_util::io::{AsyncWrite, AsyncWriteExt};
+use mime::Mime;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+use time::OffsetDateTime;
 
+use crate::banner::{self, BannerPosition};
+use crate::body::{self, TextKind};
+use crate::email_builder::Error;
+use crate::hash::{self, ContentHash, DigestAlgorithm, PartDigest};
 use crate::header::{Header, HeaderMap};
-use crate::mimeheader::{MimeContentType, MimeContentTypeHeader};
-use crate::rfc5322::Rfc5322Builder;
+use crate::mimeheader::{
+    ContentDisposition, DispositionType, MimeContentType, MimeContentTypeHeader,
+};
+use crate::protected;
+use crate::retention::{self, Sensitivity};
+use crate::rfc5322::SerializationConfig;
+#[cfg(feature = "storage")]
+use crate::store::AttachmentStore;
+use crate::strip::{self, RemovedAttachment, StripPolicy};
 
 const BOUNDARY_LENGTH: usize = 30;
 
+/// Stands in for the store the serializer pulls referenced bodies from,
+/// which without the `storage` feature is never given
+#[cfg(not(feature = "storage"))]
+pub(crate) trait AttachmentStore {
+    fn get(&self, hash: &ContentHash) -> Option<&str>;
+}
+
 /// Marks the type of a multipart message
 #[derive(Eq, PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(crate = "serde_crate")
+)]
 pub enum MimeMultipartType {
     /// Entries which are independent.
     ///
@@ -41,6 +73,11 @@ pub enum MimeMultipartType {
     ///
     /// As defined by Section 2.1 of RFC 1847
     Signed,
+    /// Entries which form a compound object, the first of which is the root,
+    /// e.g. an HTML document and the images it references.
+    ///
+    /// As defined by RFC 2387
+    Related,
 }
 
 impl MimeMultipartType {
@@ -53,6 +90,7 @@ impl MimeMultipartType {
             ("multipart", "encrypted") => Some(MimeMultipartType::Encrypted),
             ("multipart", "parallel") => Some(MimeMultipartType::Parallel),
             ("multipart", "signed") => Some(MimeMultipartType::Signed),
+            ("multipart", "related") => Some(MimeMultipartType::Related),
             ("multipart", "mixed") | ("multipart", _) => Some(MimeMultipartType::Mixed),
             _ => None,
         }
@@ -68,13 +106,23 @@ impl MimeMultipartType {
             MimeMultipartType::Encrypted => (multipart, "encrypted".to_string()),
             MimeMultipartType::Parallel => (multipart, "parallel".to_string()),
             MimeMultipartType::Signed => (multipart, "signed".to_string()),
+            MimeMultipartType::Related => (multipart, "related".to_string()),
         }
     }
 }
 
 /// Represents a MIME message
 /// [unstable]
+///
+/// Fields may be added, so messages are made with the constructors like
+/// `MimeMessage::new` rather than struct literals.
 #[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(crate = "serde_crate")
+)]
+#[non_exhaustive]
 pub struct MimeMessage {
     /// The headers for this message
     pub headers: HeaderMap,
@@ -85,11 +133,21 @@ pub struct MimeMessage {
     /// or base64 encoded.
     pub body: String,
 
+    /// Reference to a payload held in an `AttachmentStore`, which replaces
+    /// `body` when serializing with a store.
+    pub body_ref: Option<ContentHash>,
+
+    /// Path of a file holding the payload, which is loaded and encoded into
+    /// `body` when an `EmailBuilder` builds the message.
+    pub body_path: Option<PathBuf>,
+
     /// The MIME multipart message type of this message, or `None` if the message
     /// is not a multipart message.
     pub message_type: Option<MimeMultipartType>,
 
     /// Any additional parameters of the MIME multipart header, not including the boundary.
+    ///
+    /// The values are unquoted, they get quoted as needed when serializing.
     pub message_type_params: Option<HashMap<String, String>>,
 
     /// The sub-messages of this message
@@ -102,8 +160,12 @@ pub struct MimeMessage {
 }
 
 impl MimeMessage {
-    fn random_boundary() -> String {
-        let mut rng = thread_rng();
+    pub(crate) fn random_boundary() -> String {
+        MimeMessage::boundary_from(&mut thread_rng())
+    }
+
+    /// A boundary drawn from `rng`
+    pub(crate) fn boundary_from<R: Rng>(rng: &mut R) -> String {
         std::iter::repeat(())
             .map(|()| rng.sample(Alphanumeric))
             .take(BOUNDARY_LENGTH)
@@ -167,6 +229,8 @@ impl MimeMessage {
         MimeMessage {
             headers: HeaderMap::new(),
             body: "".to_string(),
+            body_ref: None,
+            body_path: None,
             message_type: None,
             message_type_params: None,
             children: Vec::new(),
@@ -203,39 +267,565 @@ impl MimeMessage {
         }
     }
 
-    pub fn as_string(&self) -> String {
-        let mut builder = Rfc5322Builder::new();
+    /// Replaces the content of the part at `path`, the indices of the
+    /// children leading to it, with `content` of `content_type`.
+    ///
+    /// A transfer encoding suitable for the new content is picked, and the
+    /// `Content-Type` and `Content-Transfer-Encoding` headers are replaced
+    /// along with the body. With a `filename`, the `Content-Disposition`
+    /// filename is replaced too, otherwise it is kept like other headers.
+    /// A multipart at `path` becomes a single part.
+    pub fn set_part_content(
+        &mut self,
+        path: &[usize],
+        content: &[u8],
+        content_type: &Mime,
+        filename: Option<&str>,
+    ) -> Result<(), Error> {
+        let mut part = self;
+        for &index in path {
+            part = part
+                .children
+                .get_mut(index)
+                .ok_or_else(|| Error::PartNotFound(path.to_vec()))?;
+        }
+
+        let (encoding, body) = body::encode_content(content, content_type.essence_str());
+        part.headers.replace(Header::new(
+            "Content-Type".to_string(),
+            content_type.to_string(),
+        ));
+        part.headers.replace(Header::new(
+            "Content-Transfer-Encoding".to_string(),
+            encoding.to_string(),
+        ));
+        if let Some(filename) = filename {
+            let disposition = part
+                .headers
+                .nth("Content-Disposition", 0)
+                .and_then(|header| header.parse_value::<ContentDisposition>().ok())
+                .map_or(DispositionType::Attachment, |value| value.disposition);
+            let value = ContentDisposition {
+                disposition,
+                filename: Some(filename.to_string()),
+            };
+            part.headers
+                .replace(Header::new_with_value("Content-Disposition".to_string(), value).unwrap());
+        }
+        part.body = body;
+        part.body_ref = None;
+        part.body_path = None;
+        part.children.clear();
+        part.message_type = None;
+        part.message_type_params = None;
+        Ok(())
+    }
+
+    /// Appends `child`, e.g. a message from another source, as the last
+    /// child of this part.
+    ///
+    /// Multipart boundaries within `child` that equal or overlap one used
+    /// by this part or its children are regenerated, as is the boundary of
+    /// this part when it occurs as a delimiter line within `child`, so the
+    /// result parses back into the same structure.
+    pub fn adopt(&mut self, mut child: MimeMessage) {
+        let mut in_use = vec![];
+        collect_boundaries(self, &mut in_use);
+        resolve_boundaries(&mut child, &mut in_use);
+
+        if self.message_type.is_some() || !self.children.is_empty() {
+            let content = child.as_string();
+            while contains_delimiter(&content, &self.boundary) {
+                renew_boundary(self, &in_use);
+            }
+        }
+        self.children.push(child);
+        self.update_headers();
+    }
+
+    /// Removes the attachments exceeding the size of `policy`, replacing each
+    /// with a text part noting its name, size and location.
+    ///
+    /// Attachments referencing an `AttachmentStore` are left alone.
+    pub fn strip_attachments(&mut self, policy: &StripPolicy) -> Vec<RemovedAttachment> {
+        let mut removed = vec![];
+        strip::strip_attachments(self, policy, &mut removed);
+        removed
+    }
+
+    /// Adds a banner to every inline text and HTML part, e.g. the warning
+    /// gateways put on mail from external senders. Returns the number of
+    /// parts changed.
+    ///
+    /// The HTML banner goes right inside the `<body>`. Changed parts are
+    /// re-encoded as UTF-8, whatever charset they had.
+    pub fn insert_banner(&mut self, text: &str, html: &str, position: BannerPosition) -> usize {
+        banner::insert_banner(self, text, html, position)
+    }
+
+    /// Writes the content of this part to `writer`, undoing the base64 or
+    /// quoted-printable transfer encoding piece by piece, so large
+    /// attachments can go straight to a file or hasher. Returns the number
+    /// of bytes written.
+    pub fn decode_to<W: Write>(&self, writer: W) -> io::Result<u64> {
+        body::decode_body_to(self, writer)
+    }
+
+    /// Hashes the decoded content of every leaf part, e.g. for antivirus
+    /// lookups or deduplication, in the order of the parts. Parts whose
+    /// payload is held in an `AttachmentStore` are skipped.
+    pub fn part_digests(&self, algorithm: DigestAlgorithm) -> Vec<PartDigest> {
+        let mut digests = vec![];
+        hash::part_digests(self, algorithm, &mut vec![], &mut digests);
+        digests
+    }
+
+    /// Returns true if this part is marked as an attachment by its
+    /// `Content-Disposition` header.
+    pub fn is_attachment(&self) -> bool {
+        self.headers.iter().any(|header| {
+            header.name.eq_ignore_ascii_case("Content-Disposition")
+                && header
+                    .get_value()
+                    .trim_start()
+                    .to_ascii_lowercase()
+                    .starts_with("attachment")
+        })
+    }
+
+    /// Returns the remote URLs referenced by the HTML parts of this message,
+    /// e.g. images or stylesheets which clients would load when displaying
+    /// it, in order of appearance and without duplicates.
+    pub fn external_references(&self) -> Vec<String> {
+        let mut urls = vec![];
+        for (kind, content) in body::text_bodies(self) {
+            if kind == TextKind::Html {
+                body::remote_urls(&content, &mut urls);
+            }
+        }
+        urls
+    }
+
+    /// Returns when the message expires, from its `Expires` or
+    /// `Expiry-Date` header.
+    pub fn expires(&self) -> Option<OffsetDateTime> {
+        retention::expires(self)
+    }
+
+    /// Returns the `Sensitivity` of the message, `None` if it's unset or
+    /// unknown.
+    pub fn sensitivity(&self) -> Option<Sensitivity> {
+        retention::sensitivity(self)
+    }
+
+    /// Returns the real subject of a message using protected headers, e.g.
+    /// after decrypting it, from the first part marked with the
+    /// `protected-headers` parameter.
+    ///
+    /// Anyone can add such a part, so the value is only trustworthy once
+    /// the signature covering it has been verified.
+    pub fn protected_subject(&self) -> Option<String> {
+        protected::protected_part(self).and_then(|part| body::header_value(part, "Subject"))
+    }
+
+    /// Returns the value of the header `name` to display. If `verified`,
+    /// i.e. the caller verified the signature covering the protected
+    /// headers, the protected copy is preferred over the outer header,
+    /// which may have been altered in transit or obscured, e.g. the `...`
+    /// Subject of encrypted mail.
+    ///
+    /// Without verification the outer header is returned, as anyone can
+    /// add a part with forged protected headers.
+    pub fn effective_header(&self, name: &str, verified: bool) -> Option<String> {
+        protected::protected_part(self)
+            .filter(|_| verified)
+            .and_then(|part| body::header_value(part, name))
+            .or_else(|| body::header_value(self, name))
+    }
+
+    /// Returns the `Subject` to display, see `effective_header`
+    pub fn effective_subject(&self, verified: bool) -> Option<String> {
+        self.effective_header("Subject", verified)
+    }
+
+    /// Returns the `From` to display, see `effective_header`
+    pub fn effective_from(&self, verified: bool) -> Option<String> {
+        self.effective_header("From", verified)
+    }
+
+    /// Returns the `To` to display, see `effective_header`
+    pub fn effective_to(&self, verified: bool) -> Option<String> {
+        self.effective_header("To", verified)
+    }
+
+    /// Parses a raw RFC 5322 message into a tree of parts.
+    ///
+    /// Headers and bodies are kept as they are, still folded and transfer
+    /// encoded, so serializing a parsed message reproduces the original up
+    /// to the line endings and the multipart epilogues.
+    pub fn parse(s: &str) -> Result<MimeMessage, Error> {
+        let mut message = MimeMessage::new_blank_message();
+
+        let body_start = if starts_with_header(s) {
+            let (headers, body_start) =
+                mailparse::parse_headers(s.as_bytes()).map_err(Error::Parse)?;
+            for header in headers.iter() {
+                let value = String::from_utf8_lossy(header.get_value_raw());
+                message
+                    .headers
+                    .insert(Header::new(header.get_key(), value.into_owned()));
+            }
+            body_start
+        } else {
+            // A part without headers, which starts with the body right away
+            // or with the empty line separating the (missing) headers.
+            s.strip_prefix("\r\n")
+                .or_else(|| s.strip_prefix('\n'))
+                .map_or(0, |rest| s.len() - rest.len())
+        };
+        let body = &s[body_start..];
+
+        let content_type = message
+            .headers
+            .nth("Content-Type", 0)
+            .map(|header| mailparse::parse_content_type(&header.get_value()));
+        let multipart = content_type.and_then(|mut content_type| {
+            let boundary = content_type.params.remove("boundary")?;
+            let mut type_parts = content_type.mimetype.splitn(2, '/');
+            let major = type_parts.next()?.to_string();
+            let minor = type_parts.next()?.to_string();
+            let message_type = MimeMultipartType::from_content_type((major, minor))?;
+            Some((message_type, boundary, content_type.params))
+        });
 
-        for header in self.headers.iter() {
-            builder.emit_folded(&header.to_string()[..]);
-            builder.emit_raw("\r\n");
+        match multipart {
+            Some((message_type, boundary, params)) => {
+                let mut sections = split_multipart(body, &boundary).into_iter();
+                message.body = strip_line_ending(sections.next().unwrap_or("")).to_string();
+                for section in sections {
+                    message.children.push(MimeMessage::parse(section)?);
+                }
+                message.message_type = Some(message_type);
+                message.boundary = boundary;
+                let params = params.into_iter().collect::<HashMap<_, _>>();
+                if !params.is_empty() {
+                    message.message_type_params = Some(params);
+                }
+            }
+            None => message.body = strip_line_ending(body).to_string(),
         }
-        builder.emit_raw("\r\n");
 
-        self.as_string_without_headers_internal(builder)
+        Ok(message)
+    }
+
+    pub fn as_string(&self) -> String {
+        self.serialize_to_string(true, None)
     }
 
     pub fn as_string_without_headers(&self) -> String {
-        let builder = Rfc5322Builder::new();
+        self.serialize_to_string(false, None)
+    }
+
+    /// Serializes the message, pulling referenced bodies from `store`.
+    #[cfg(feature = "storage")]
+    pub fn as_string_with_store(&self, store: &dyn AttachmentStore) -> Result<String, Error> {
+        if let Some(hash) = self.unresolved_body_ref(Some(store)) {
+            return Err(Error::MissingPayload(hash));
+        }
+
+        Ok(self.serialize_to_string(true, Some(store)))
+    }
+
+    /// Serializes the message as `config` says, e.g. with LF line endings
+    pub fn as_string_with_config(&self, config: &SerializationConfig) -> String {
+        self.serialize_to_string_with(true, None, config)
+    }
+
+    fn serialize_to_string(
+        &self,
+        with_headers: bool,
+        store: Option<&dyn AttachmentStore>,
+    ) -> String {
+        self.serialize_to_string_with(with_headers, store, &SerializationConfig::default())
+    }
 
-        self.as_string_without_headers_internal(builder)
+    pub(crate) fn serialize_to_string_with(
+        &self,
+        with_headers: bool,
+        store: Option<&dyn AttachmentStore>,
+        config: &SerializationConfig,
+    ) -> String {
+        // Sized up front, as growing the buffer of a message with large
+        // attachments would briefly need twice its size
+        let mut result = Vec::with_capacity(self.serialized_len_hint(with_headers, config));
+        self.serialize(&mut result, with_headers, store, config)
+            .expect("writing to a Vec never fails");
+        String::from_utf8(result).expect("the message is serialized from strings")
     }
 
-    fn as_string_without_headers_internal(&self, mut builder: Rfc5322Builder) -> String {
-        builder.emit_raw(&format!("{}\r\n", self.body)[..]);
+    /// Finds a body reference in this message tree which `store` cannot
+    /// resolve, if any.
+    pub(crate) fn unresolved_body_ref(
+        &self,
+        store: Option<&dyn AttachmentStore>,
+    ) -> Option<ContentHash> {
+        match self.body_ref {
+            Some(hash) if store.and_then(|store| store.get(&hash)).is_none() => Some(hash),
+            _ => self
+                .children
+                .iter()
+                .find_map(|child| child.unresolved_body_ref(store)),
+        }
+    }
 
+    /// The approximate length of the serialized message, used as the
+    /// capacity of the buffer it is serialized into
+    fn serialized_len_hint(&self, with_headers: bool, config: &SerializationConfig) -> usize {
+        let mut len = self.body.len() + 2;
+        if with_headers {
+            for header in self.headers.iter() {
+                let line = header.name.len() + 2 + header.get_raw_value().len();
+                // Folding turns a space into CRLF and a tab
+                len += line + 2 + 2 * (line / config.max_line_len.max(1));
+            }
+            len += 2;
+        }
         if !self.children.is_empty() {
             for part in self.children.iter() {
-                builder.emit_raw(&format!("--{}\r\n{}\r\n", self.boundary, part.as_string())[..]);
+                len += self.boundary.len() + 6 + part.serialized_len_hint(true, config);
             }
+            len += self.boundary.len() + 6;
+        }
+        len
+    }
+
+    /// Writes the message into `writer` in a single pass, without
+    /// collecting it in a string first, e.g. to stream it as SMTP `DATA`.
+    ///
+    /// Referenced bodies are written as their placeholders.
+    pub fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        self.write_to_with_config(writer, &SerializationConfig::default())
+    }
+
+    /// Like `write_to`, but serializes the message as `config` says
+    pub fn write_to_with_config<W: Write>(
+        &self,
+        mut writer: W,
+        config: &SerializationConfig,
+    ) -> io::Result<()> {
+        self.serialize(&mut writer, true, None, config)
+    }
 
-            builder.emit_raw(&format!("--{}--\r\n", self.boundary)[..]);
+    /// Like `write_to`, but writes into an `AsyncWrite`
+    #[cfg(feature = "async")]
+    pub async fn write_to_async<W: AsyncWrite + Unpin>(&self, writer: W) -> io::Result<()> {
+        self.write_to_async_with_config(writer, &SerializationConfig::default())
+            .await
+    }
+
+    /// Like `write_to_async`, but serializes the message as `config` says
+    #[cfg(feature = "async")]
+    pub async fn write_to_async_with_config<W: AsyncWrite + Unpin>(
+        &self,
+        mut writer: W,
+        config: &SerializationConfig,
+    ) -> io::Result<()> {
+        let mut chunks = vec![];
+        self.chunks(&mut chunks, true, None, config);
+        for chunk in chunks {
+            writer
+                .write_all(&config.convert_line_endings(chunk))
+                .await?;
         }
+        writer.flush().await
+    }
 
-        builder.result().clone()
+    /// Writes the message into `writer` part by part.
+    pub(crate) fn serialize(
+        &self,
+        writer: &mut dyn Write,
+        with_headers: bool,
+        store: Option<&dyn AttachmentStore>,
+        config: &SerializationConfig,
+    ) -> io::Result<()> {
+        let mut chunks = vec![];
+        self.chunks(&mut chunks, with_headers, store, config);
+        for chunk in chunks {
+            writer.write_all(&config.convert_line_endings(chunk))?;
+        }
+        Ok(())
+    }
+
+    /// Collects the serialized message as a sequence of chunks, in which
+    /// the bodies are borrowed rather than copied. Headers are folded as
+    /// `config` says, line endings are left as CRLF.
+    fn chunks<'a>(
+        &'a self,
+        chunks: &mut Vec<Cow<'a, [u8]>>,
+        with_headers: bool,
+        store: Option<&'a dyn AttachmentStore>,
+        config: &SerializationConfig,
+    ) {
+        if with_headers {
+            for header in self.headers.iter() {
+                let mut line = config.fold_line(&header.to_string());
+                line.push_str("\r\n");
+                chunks.push(Cow::Owned(line.into_bytes()));
+            }
+            chunks.push(Cow::Borrowed(b"\r\n"));
+        }
+
+        let body = match (self.body_ref, store) {
+            (Some(hash), Some(store)) => store.get(&hash).unwrap_or(&self.body),
+            _ => &self.body,
+        };
+        chunks.push(Cow::Borrowed(body.as_bytes()));
+        chunks.push(Cow::Borrowed(b"\r\n"));
+
+        if !self.children.is_empty() {
+            let signed = self.message_type == Some(MimeMultipartType::Signed);
+            for (index, part) in self.children.iter().enumerate() {
+                chunks.push(Cow::Owned(format!("--{}\r\n", self.boundary).into_bytes()));
+                // The signed content is emitted as it was signed, only its
+                // line endings get converted
+                let default_config = SerializationConfig::default();
+                let part_config = if signed && index == 0 {
+                    &default_config
+                } else {
+                    config
+                };
+                part.chunks(chunks, true, store, part_config);
+                chunks.push(Cow::Borrowed(b"\r\n"));
+            }
+
+            chunks.push(Cow::Owned(
+                format!("--{}--\r\n", self.boundary).into_bytes(),
+            ));
+        }
+    }
+}
+
+/// Returns true if `s` starts with a header field, rather than with a body.
+fn starts_with_header(s: &str) -> bool {
+    let first_line = s.lines().next().unwrap_or("");
+    match first_line.find(':') {
+        Some(colon) => !first_line[..colon].contains(char::is_whitespace) && colon > 0,
+        None => false,
+    }
+}
+
+/// Gives the multiparts within `part` boundaries drawn from `rng`.
+///
+/// The content of `multipart/signed` parts is left alone, as changing it
+/// would break the signature.
+pub(crate) fn reseed_boundaries<R: Rng>(part: &mut MimeMessage, rng: &mut R) {
+    if is_multipart(part) {
+        part.boundary = MimeMessage::boundary_from(rng);
+        part.update_headers();
+    }
+    if part.message_type != Some(MimeMultipartType::Signed) {
+        for child in part.children.iter_mut() {
+            reseed_boundaries(child, rng);
+        }
+    }
+}
+
+/// Whether `part` is serialized with delimiters
+fn is_multipart(part: &MimeMessage) -> bool {
+    part.message_type.is_some() || !part.children.is_empty()
+}
+
+/// Collects the boundaries of the multiparts within `part`
+fn collect_boundaries(part: &MimeMessage, in_use: &mut Vec<String>) {
+    if is_multipart(part) {
+        in_use.push(part.boundary.clone());
+    }
+    for child in part.children.iter() {
+        collect_boundaries(child, in_use);
+    }
+}
+
+/// Whether the boundaries are the same or one delimiter line starts with
+/// the other, which confuses some parsers
+fn boundaries_overlap(a: &str, b: &str) -> bool {
+    a.starts_with(b) || b.starts_with(a)
+}
+
+/// Gives the multiparts within `part` boundaries not overlapping `in_use`
+fn resolve_boundaries(part: &mut MimeMessage, in_use: &mut Vec<String>) {
+    if is_multipart(part) {
+        if in_use
+            .iter()
+            .any(|boundary| boundaries_overlap(boundary, &part.boundary))
+        {
+            renew_boundary(part, in_use);
+        }
+        in_use.push(part.boundary.clone());
+    }
+    for child in part.children.iter_mut() {
+        resolve_boundaries(child, in_use);
     }
 }
 
+/// Replaces the boundary of `part` by a random one not overlapping `in_use`
+fn renew_boundary(part: &mut MimeMessage, in_use: &[String]) {
+    loop {
+        part.boundary = MimeMessage::random_boundary();
+        if !in_use
+            .iter()
+            .any(|boundary| boundaries_overlap(boundary, &part.boundary))
+        {
+            break;
+        }
+    }
+    part.update_headers();
+}
+
+/// Whether a line of `content` is a delimiter of `boundary`
+fn contains_delimiter(content: &str, boundary: &str) -> bool {
+    let delimiter = format!("--{}", boundary);
+    content.lines().any(|line| line.starts_with(&delimiter[..]))
+}
+
+/// Removes the line ending which separates a body from what follows it.
+fn strip_line_ending(s: &str) -> &str {
+    s.strip_suffix("\r\n")
+        .or_else(|| s.strip_suffix('\n'))
+        .unwrap_or(s)
+}
+
+/// Splits a multipart body into the preamble followed by the parts.
+///
+/// The line ending in front of each delimiter belongs to the delimiter and
+/// is removed, as is everything following the close delimiter.
+fn split_multipart<'a>(body: &'a str, boundary: &str) -> Vec<&'a str> {
+    let delimiter = format!("--{}", boundary);
+    let mut sections = vec![];
+    let mut section_start = 0;
+    let mut line_start = 0;
+    while line_start < body.len() {
+        let line_end = body[line_start..]
+            .find('\n')
+            .map_or(body.len(), |i| line_start + i + 1);
+        let line = body[line_start..line_end].trim_end();
+        if let Some(rest) = line.strip_prefix(&delimiter[..]) {
+            if rest.is_empty() || rest == "--" {
+                sections.push(strip_line_ending(&body[section_start..line_start]));
+                if rest == "--" {
+                    return sections;
+                }
+                section_start = line_end;
+            }
+        }
+        line_start = line_end;
+    }
+    // There was no close delimiter, so the last part runs until the end.
+    if !sections.is_empty() {
+        sections.push(&body[section_start..]);
+    }
+    sections
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -316,6 +906,273 @@ mod tests {
         // This is random, so we can only really check that it's the expected length
         assert_eq!(message.boundary.len(), super::BOUNDARY_LENGTH);
     }
+
+    #[test]
+    fn test_parse_roundtrip() {
+        let email = crate::EmailBuilder::new()
+            .from("joe@example.org")
+            .to("john@example.org")
+            .subject("A subject which is long enough to be folded when it gets serialized, surely")
+            .alternative("<p>Hello</p>", "Hello")
+            .attachment(b"data", "data.bin", &mime::APPLICATION_OCTET_STREAM)
+            .unwrap()
+            .build()
+            .unwrap();
+        let raw = email.message_to_string().unwrap();
+
+        let message = MimeMessage::parse(&raw).unwrap();
+        assert_eq!(message.message_type, Some(MimeMultipartType::Mixed));
+        let config = SerializationConfig::default();
+        assert!(message.serialized_len_hint(true, &config) >= message.as_string().len());
+        let mut written = vec![];
+        message.write_to(&mut written).unwrap();
+        assert_eq!(written, message.as_string().into_bytes());
+        #[cfg(feature = "async")]
+        {
+            let mut written = futures_util::io::Cursor::new(vec![]);
+            futures_executor::block_on(message.write_to_async(&mut written)).unwrap();
+            assert_eq!(written.into_inner(), message.as_string().into_bytes());
+        }
+        assert_eq!(message.children.len(), 2);
+        assert_eq!(
+            message.children[0].message_type,
+            Some(MimeMultipartType::Alternative)
+        );
+        assert_eq!(message.children[0].children[1].body, "<p>Hello</p>");
+        assert_eq!(message.children[1].body, "ZGF0YQ==");
+        assert_eq!(message.as_string(), raw);
+    }
+
+    #[test]
+    fn test_parse() {
+        let message = MimeMessage::parse(
+            "From: joe@example.org\r\n\
+             Content-Type: multipart/signed; protocol=\"application/pgp-signature\";\r\n\
+             \tboundary=foo\r\n\
+             \r\n\
+             Preamble\r\n\
+             --foo\r\n\
+             Hello!\r\n\
+             --foo\r\n\
+             \r\n\
+             Other\r\n\
+             \r\n\
+             --foo--\r\n\
+             Epilogue\r\n",
+        )
+        .unwrap();
+        assert_eq!(message.body, "Preamble");
+        assert_eq!(message.boundary, "foo");
+        assert_eq!(message.message_type, Some(MimeMultipartType::Signed));
+        assert_eq!(
+            message.message_type_params.unwrap()["protocol"],
+            "application/pgp-signature"
+        );
+        assert_eq!(message.children.len(), 2);
+        assert!(message.children[0].headers.is_empty());
+        assert_eq!(message.children[0].body, "Hello!");
+        assert_eq!(message.children[1].body, "Other");
+
+        let message = MimeMessage::parse("Subject: Hi\n\nHello\n").unwrap();
+        assert_eq!(
+            message
+                .headers
+                .get("Subject".to_string())
+                .unwrap()
+                .get_value(),
+            "Hi"
+        );
+        assert_eq!(message.body, "Hello");
+    }
+
+    #[test]
+    fn test_external_references() {
+        let html = crate::PartBuilder::new()
+            .header(("Content-Type", "text/html"))
+            .header(("Content-Transfer-Encoding", "base64"))
+            .body(base64::encode(
+                "<img src=\"https://example.org/pixel.gif\">",
+            ))
+            .build();
+        let text = crate::PartBuilder::new()
+            .header(("Content-Type", "text/plain"))
+            .body("<img src=\"https://example.org/text.gif\">")
+            .build();
+        let message = MimeMessage::new_with_children(
+            String::new(),
+            MimeMultipartType::Alternative,
+            vec![text, html],
+        );
+        assert_eq!(
+            message.external_references(),
+            vec!["https://example.org/pixel.gif"]
+        );
+    }
+
+    #[test]
+    fn test_set_part_content() {
+        let raw = "Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+                   --b\r\ncontent-type: text/plain\r\n\r\nHello\r\n\
+                   --b\r\nContent-Type: application/msword\r\n\
+                   Content-Transfer-Encoding: base64\r\n\
+                   Content-Disposition: attachment; filename=\"a.doc\"\r\n\r\nAAAA\r\n\
+                   --b--\r\n";
+        let mut message = MimeMessage::parse(raw).unwrap();
+
+        let pdf = b"%PDF-1.4\xff\x00";
+        message
+            .set_part_content(
+                &[1],
+                pdf,
+                &"application/pdf".parse().unwrap(),
+                Some("a.pdf"),
+            )
+            .unwrap();
+        message
+            .set_part_content(&[0], "Grüße".as_bytes(), &mime::TEXT_PLAIN_UTF_8, None)
+            .unwrap();
+        assert!(matches!(
+            message.set_part_content(&[2], b"", &mime::TEXT_PLAIN, None),
+            Err(Error::PartNotFound(_))
+        ));
+
+        let message = MimeMessage::parse(&message.as_string()).unwrap();
+        let attachment = &message.children[1];
+        assert_eq!(body::decode_body(attachment).unwrap(), pdf);
+        assert_eq!(
+            body::header_value(attachment, "Content-Type").unwrap(),
+            "application/pdf"
+        );
+        assert!(attachment.is_attachment());
+        assert_eq!(
+            body::header_value(attachment, "Content-Disposition").unwrap(),
+            "attachment; filename=\"a.pdf\""
+        );
+
+        let text = &message.children[0];
+        assert_eq!(text.headers.len(), 2);
+        assert_eq!(
+            body::header_value(text, "Content-Transfer-Encoding").unwrap(),
+            "base64"
+        );
+        assert_eq!(body::decode_body(text).unwrap(), "Grüße".as_bytes());
+    }
+
+    #[test]
+    fn test_decode_to() {
+        use crate::PartBuilder;
+
+        let content = (0..200_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let part = crate::email_builder::attachment_part(
+            &content,
+            "data.bin",
+            &mime::APPLICATION_OCTET_STREAM,
+        );
+        let mut decoded = vec![];
+        assert_eq!(part.decode_to(&mut decoded).unwrap(), content.len() as u64);
+        assert_eq!(decoded, content);
+
+        let part = PartBuilder::new()
+            .header(("Content-Transfer-Encoding", "quoted-printable"))
+            .body("Gr=C3=BC=C3=9Fe, a long =\r\nline\r\nand more  \r\n")
+            .build();
+        let mut decoded = vec![];
+        part.decode_to(&mut decoded).unwrap();
+        assert_eq!(decoded, body::decode_body(&part).unwrap());
+        assert_eq!(decoded, "Grüße, a long line\r\nand more\r\n".as_bytes());
+
+        let part = PartBuilder::new()
+            .header(("Content-Transfer-Encoding", "base64"))
+            .body("not base64!")
+            .build();
+        assert!(part.decode_to(io::sink()).is_err());
+    }
+
+    #[test]
+    fn test_adopt() {
+        let raw = "Content-type: multipart/mixed; boundary=foo\r\n\r\n\
+                   --foo\r\n\
+                   Content-Type: text/plain\r\n\r\n\
+                   Hello\r\n\
+                   --foo--\r\n";
+        let mut digest = MimeMessage::parse(raw).unwrap();
+        digest.adopt(MimeMessage::parse(raw).unwrap());
+        digest.adopt(MimeMessage::new("--foo\r\n--foo--".to_string()));
+        digest.adopt(
+            MimeMessage::parse(&raw.replace("foo", &format!("{}x", digest.boundary))).unwrap(),
+        );
+
+        let child = &digest.children[1];
+        assert_ne!(child.boundary, "foo");
+        assert_eq!(child.headers.len(), 1);
+        assert_ne!(digest.boundary, "foo");
+        assert!(!digest.children[3].boundary.starts_with(&digest.boundary));
+
+        let parsed = MimeMessage::parse(&digest.as_string()).unwrap();
+        assert_eq!(parsed.children.len(), 4);
+        assert_eq!(parsed.children[1].children.len(), 1);
+        assert_eq!(parsed.children[2].body, "--foo\r\n--foo--");
+        assert_eq!(parsed.children[3].children.len(), 1);
+    }
+
+    #[test]
+    fn test_serialization_config() {
+        use crate::{EmailBuilder, FoldStyle, LineEnding};
+
+        let message = EmailBuilder::new()
+            .from("joe@example.org")
+            .to(("alice@example.org", "Alice Example"))
+            .to(("bob@example.org", "Bob Example"))
+            .to(("carol@example.org", "Carol Example"))
+            .subject("Hello")
+            .body("Hello\nWorld")
+            .build()
+            .unwrap()
+            .message_to_string()
+            .unwrap();
+        let message = MimeMessage::parse(&message).unwrap();
+        assert_eq!(
+            message.as_string_with_config(&SerializationConfig::default()),
+            message.as_string()
+        );
+
+        let config = SerializationConfig::new()
+            .max_line_len(40)
+            .fold_style(FoldStyle::Space)
+            .line_ending(LineEnding::Lf);
+        let serialized = message.as_string_with_config(&config);
+        assert!(message.serialized_len_hint(true, &config) >= serialized.len());
+        assert!(!serialized.contains('\r'));
+        assert!(serialized.contains(
+            "To: Alice Example <alice@example.org>,\n Bob Example <bob@example.org>, Carol\n \
+             Example <carol@example.org>\n"
+        ));
+        assert!(serialized.ends_with("\n\nHello\nWorld\n"));
+
+        let mut written = vec![];
+        message.write_to_with_config(&mut written, &config).unwrap();
+        assert_eq!(written, serialized.as_bytes());
+
+        // Signed content keeps the folding it was signed with
+        let content = crate::PartBuilder::new()
+            .header((
+                "Content-Description",
+                "A description long enough to be folded at forty characters",
+            ))
+            .body("Signed")
+            .build();
+        let signed_bytes = content.as_string();
+        let signed = crate::PartBuilder::new()
+            .message_type(MimeMultipartType::Signed)
+            .child(content)
+            .child(crate::PartBuilder::new().body("signature").build())
+            .build();
+        let config = SerializationConfig::new().max_line_len(40);
+        let serialized = signed.as_string_with_config(&config);
+        assert!(serialized.contains(&signed_bytes));
+        let serialized = signed.as_string_with_config(&config.line_ending(LineEnding::Lf));
+        assert!(serialized.contains(&signed_bytes.replace("\r\n", "\n")));
+    }
 }
 
 #[cfg(all(feature = "nightly", test))]
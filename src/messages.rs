@@ -0,0 +1,134 @@
+use std::fmt;
+use std::sync::Arc;
+
+use time::OffsetDateTime;
+
+use crate::date::format_date;
+use crate::strip::format_size;
+
+/// The boilerplate text put into generated messages, e.g. the placeholders
+/// of stripped attachments or the attribution line of quotes.
+///
+/// Every method defaults to the English text, so a catalog for another
+/// locale only overrides what it translates.
+pub trait Messages: Send + Sync {
+    /// Placeholder for an attachment removed by `strip_attachments`, e.g.
+    /// `Attachment removed: report.pdf, 12.0 MB, stored at <url>`
+    fn attachment_removed(
+        &self,
+        filename: Option<&str>,
+        size: usize,
+        location: Option<&str>,
+    ) -> String {
+        let mut text = format!(
+            "Attachment removed: {}, {}",
+            filename.unwrap_or("unnamed"),
+            format_size(size)
+        );
+        if let Some(location) = location {
+            text.push_str(&format!(", stored at <{}>", location));
+        }
+        text
+    }
+
+    /// Stands in for the subject of a message without one in digests
+    fn no_subject(&self) -> String {
+        "(no subject)".to_string()
+    }
+
+    /// Line naming the sender of a message in the text of digests
+    fn digest_from(&self, from: &str) -> String {
+        format!("From: {}", from)
+    }
+
+    /// The attribution line above quotes, see `quote::attribution`
+    fn attribution(&self, date: &OffsetDateTime, name: &str) -> String {
+        format!("On {}, {} wrote:", format_date(date), name)
+    }
+}
+
+/// The built-in English texts
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct English;
+
+impl Messages for English {}
+
+/// A shared catalog, compared by identity
+#[derive(Clone)]
+pub(crate) struct Catalog(pub(crate) Arc<dyn Messages>);
+
+impl Catalog {
+    /// The messages of `catalog`, English if there is none
+    pub(crate) fn or_english(catalog: &Option<Catalog>) -> &dyn Messages {
+        match catalog {
+            Some(catalog) => &*catalog.0,
+            None => &English,
+        }
+    }
+}
+
+impl PartialEq for Catalog {
+    fn eq(&self, other: &Catalog) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Catalog {}
+
+impl fmt::Debug for Catalog {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("Catalog")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email_builder::{attachment_part, text_part};
+    use crate::{Digest, MimeMultipartType, PartBuilder, StripPolicy};
+
+    struct German;
+
+    impl Messages for German {
+        fn attachment_removed(
+            &self,
+            filename: Option<&str>,
+            _size: usize,
+            _location: Option<&str>,
+        ) -> String {
+            format!("Anhang entfernt: {}", filename.unwrap_or("ohne Namen"))
+        }
+
+        fn no_subject(&self) -> String {
+            "(kein Betreff)".to_string()
+        }
+    }
+
+    #[test]
+    fn test_catalog() {
+        let mut message = PartBuilder::new()
+            .message_type(MimeMultipartType::Mixed)
+            .child(text_part("Hallo"))
+            .child(attachment_part(&[0; 64], "bild.png", &mime::IMAGE_PNG))
+            .build();
+        message.strip_attachments(&StripPolicy::new(16).catalog(German));
+        assert_eq!(message.children[1].body, "Anhang entfernt: bild.png");
+
+        let email = Digest::new("Zusammenfassung")
+            .message(
+                PartBuilder::new()
+                    .header(("From", "bot@example.org"))
+                    .body("Hallo")
+                    .build(),
+            )
+            .catalog(German)
+            .into_builder()
+            .from("bot@example.org")
+            .to("joe@example.org")
+            .build()
+            .unwrap()
+            .message_to_string()
+            .unwrap();
+        assert!(email.contains("* (kein Betreff)\r\n  From: bot@example.org\r\n"));
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
+use std::str::FromStr;
 
-use crate::header::ToHeader;
+use crate::header::{FromHeader, ToHeader};
 
 /// Content-Type string, major/minor as the first and second elements
 /// respectively.
@@ -11,7 +12,7 @@ pub type MimeContentType = (String, String);
 pub struct MimeContentTypeHeader {
     /// The content type presented by this header
     pub content_type: MimeContentType,
-    /// Parameters of this header
+    /// Parameters of this header, unquoted
     pub params: HashMap<String, String>,
 }
 
@@ -21,13 +22,186 @@ impl ToHeader for MimeContentTypeHeader {
     fn to_header(value: MimeContentTypeHeader) -> Result<String, ()> {
         let (mime_major, mime_minor) = value.content_type;
         let mut result = format!("{}/{}", mime_major, mime_minor);
-        for (key, val) in value.params.iter() {
-            result = format!("{}; {}={}", result, key, val);
+        let mut params = value.params.into_iter().collect::<Vec<_>>();
+        params.sort();
+        for (key, val) in params.iter() {
+            if val
+                .chars()
+                .all(|c| (' '..='~').contains(&c) && c != '"' && c != '\\')
+            {
+                result.push_str(&format!("; {}={}", key, quote_param(val)));
+            } else {
+                result.push_str(&encode_param(key, val));
+            }
         }
         Ok(result)
     }
 }
 
+/// Parses with the `FromHeader` implementation
+impl FromStr for MimeContentTypeHeader {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<MimeContentTypeHeader, ()> {
+        MimeContentTypeHeader::from_header(value)
+    }
+}
+
+/// Quotes a parameter value unless it is a token (RFC 2045). Values with
+/// `"` or `\` are RFC 2231 encoded instead, which parsers handle better
+/// than quoted pairs.
+fn quote_param(value: &str) -> String {
+    let is_token = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_graphic() && !"()<>@,;:\\\"/[]?=".contains(c));
+    if is_token {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value)
+    }
+}
+
+/// Fails if the value lacks a `type/subtype`. The type, subtype and
+/// parameter names are lowercased.
+impl FromHeader for MimeContentTypeHeader {
+    type Error = ();
+
+    fn from_header(value: &str) -> Result<MimeContentTypeHeader, ()> {
+        let content_type = mailparse::parse_content_type(value);
+        let mut type_parts = content_type.mimetype.splitn(2, '/');
+        match (type_parts.next(), type_parts.next()) {
+            (Some(major), Some(minor)) if !major.is_empty() && !minor.is_empty() => {
+                Ok(MimeContentTypeHeader {
+                    content_type: (major.to_string(), minor.to_string()),
+                    params: content_type.params.into_iter().collect(),
+                })
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+/// The longest parameter value emitted on one line, before RFC 2231
+/// continuations are used
+const MAX_PARAM_SECTION_LENGTH: usize = 60;
+
+/// The disposition type of a part (RFC 2183)
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DispositionType {
+    /// Shown as part of the message
+    Inline,
+    /// Shown as a separate file
+    Attachment,
+}
+
+/// Special header type for the Content-Disposition header.
+///
+/// A filename which is not printable ASCII, or contains characters that
+/// break naive parsers, is emitted RFC 2231 encoded as `filename*`, split
+/// into continuations if long.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ContentDisposition {
+    /// How the part is shown
+    pub disposition: DispositionType,
+    /// The suggested filename, if any
+    pub filename: Option<String>,
+}
+
+impl ContentDisposition {
+    /// An attachment named `filename`
+    pub fn attachment<S: Into<String>>(filename: S) -> ContentDisposition {
+        ContentDisposition {
+            disposition: DispositionType::Attachment,
+            filename: Some(filename.into()),
+        }
+    }
+
+    /// An inline part without a filename
+    pub fn inline() -> ContentDisposition {
+        ContentDisposition {
+            disposition: DispositionType::Inline,
+            filename: None,
+        }
+    }
+}
+
+impl ToHeader for ContentDisposition {
+    type Error = ();
+
+    fn to_header(value: ContentDisposition) -> Result<String, ()> {
+        let mut result = match value.disposition {
+            DispositionType::Inline => "inline".to_string(),
+            DispositionType::Attachment => "attachment".to_string(),
+        };
+        if let Some(filename) = value.filename {
+            result.push_str(&encode_param("filename", &filename));
+        }
+        Ok(result)
+    }
+}
+
+/// Other disposition types are read as `Attachment`, as RFC 2183 asks.
+/// RFC 2231 encoded filenames are decoded.
+impl FromHeader for ContentDisposition {
+    type Error = ();
+
+    fn from_header(value: &str) -> Result<ContentDisposition, ()> {
+        let mut parsed = mailparse::parse_content_disposition(value);
+        let disposition = match parsed.disposition {
+            mailparse::DispositionType::Inline => DispositionType::Inline,
+            _ => DispositionType::Attachment,
+        };
+        Ok(ContentDisposition {
+            disposition,
+            filename: parsed.params.remove("filename"),
+        })
+    }
+}
+
+/// Formats the parameter `name` with `value` including the leading `;`,
+/// quoted or RFC 2231 encoded
+fn encode_param(name: &str, value: &str) -> String {
+    let plain = !value.is_empty()
+        && value.len() <= MAX_PARAM_SECTION_LENGTH
+        && value
+            .chars()
+            .all(|c| (' '..='~').contains(&c) && !matches!(c, '"' | '\\' | ';'));
+    if plain {
+        return format!("; {}=\"{}\"", name, value);
+    }
+
+    let mut sections = vec![String::new()];
+    for c in value.chars() {
+        let mut encoded = String::new();
+        let mut buf = [0; 4];
+        for &byte in c.encode_utf8(&mut buf).as_bytes() {
+            if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+                encoded.push(byte as char);
+            } else {
+                encoded.push_str(&format!("%{:02X}", byte));
+            }
+        }
+        let section = sections.last_mut().unwrap();
+        if section.len() + encoded.len() > MAX_PARAM_SECTION_LENGTH {
+            sections.push(encoded);
+        } else {
+            section.push_str(&encoded);
+        }
+    }
+    if sections.len() == 1 {
+        return format!("; {}*=utf-8''{}", name, sections[0]);
+    }
+    sections
+        .iter()
+        .enumerate()
+        .map(|(i, section)| {
+            let charset = if i == 0 { "utf-8''" } else { "" };
+            format!(";\r\n {}*{}*={}{}", name, i, charset, section)
+        })
+        .collect()
+}
+
 /// Special header type for the Content-Transfer-Encoding header.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum MimeContentTransferEncoding {
@@ -42,3 +216,64 @@ pub enum MimeContentTransferEncoding {
     /// This encoding is defined in RFC 2045 Section 6.8
     Base64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Header;
+
+    fn roundtrip(filename: &str) -> (String, ContentDisposition) {
+        let header = Header::new_with_value(
+            "Content-Disposition".to_string(),
+            ContentDisposition::attachment(filename),
+        )
+        .unwrap();
+        let parsed = header.parse_value::<ContentDisposition>().unwrap();
+        (header.get_raw_value().to_string(), parsed)
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        let (value, parsed) = roundtrip("report.txt");
+        assert_eq!(value, "attachment; filename=\"report.txt\"");
+        assert_eq!(parsed, ContentDisposition::attachment("report.txt"));
+
+        let (value, parsed) = roundtrip("a \"b\"; c.txt");
+        assert_eq!(value, "attachment; filename*=utf-8''a%20%22b%22%3B%20c.txt");
+        assert_eq!(parsed.filename.unwrap(), "a \"b\"; c.txt");
+
+        let long = "Übersicht der Änderungen im dritten Quartal.pdf";
+        let (value, parsed) = roundtrip(long);
+        assert!(value.starts_with("attachment;\r\n filename*0*=utf-8''%C3%9Cbersicht"));
+        assert!(value.contains(";\r\n filename*1*="));
+        assert_eq!(parsed.filename.unwrap(), long);
+
+        let header = Header::new("Content-Disposition".into(), "INLINE".into());
+        assert_eq!(
+            header.parse_value::<ContentDisposition>().unwrap(),
+            ContentDisposition::inline()
+        );
+    }
+
+    #[test]
+    fn test_content_type_roundtrip() {
+        let mut params = HashMap::new();
+        params.insert("boundary".to_string(), "=_a b?c".to_string());
+        params.insert("charset".to_string(), "utf-8".to_string());
+        params.insert("name".to_string(), "say \"hi\".txt".to_string());
+        let value = MimeContentTypeHeader::to_header(MimeContentTypeHeader {
+            content_type: ("multipart".to_string(), "mixed".to_string()),
+            params: params.clone(),
+        })
+        .unwrap();
+        assert_eq!(
+            value,
+            "multipart/mixed; boundary=\"=_a b?c\"; charset=utf-8; name*=utf-8''say%20%22hi%22.txt"
+        );
+
+        let parsed = value.parse::<MimeContentTypeHeader>().unwrap();
+        assert_eq!(parsed.content_type, ("multipart".into(), "mixed".into()));
+        assert_eq!(parsed.params, params);
+        assert!("no-subtype".parse::<MimeContentTypeHeader>().is_err());
+    }
+}
@@ -0,0 +1,210 @@
+use std::fmt;
+use std::sync::Arc;
+
+use regex::Regex;
+
+lazy_static::lazy_static! {
+    static ref BOUNDARY_RE: Regex = Regex::new(r#"(?i)\bboundary=(?:"([^"]+)"|([^;\s"]+))"#).unwrap();
+}
+
+/// Placeholder for the values of `Date` headers
+pub const DATE_PLACEHOLDER: &str = "DATE";
+
+/// Placeholder for the values of `Message-ID` headers
+pub const MESSAGE_ID_PLACEHOLDER: &str = "<MESSAGE-ID>";
+
+/// Prefix of the placeholders for boundaries, which are numbered in the
+/// order they appear, starting at 1
+pub const BOUNDARY_PLACEHOLDER: &str = "BOUNDARY-";
+
+/// Replaces the generated parts of a serialized message with stable
+/// placeholders, e.g. for golden-file tests, see
+/// `Email::to_normalized_string`.
+///
+/// By default, the values of `Date` and `Message-ID` headers and all
+/// multipart boundaries are replaced, including those of attached
+/// messages.
+#[derive(Clone)]
+pub struct Normalizer {
+    dates: bool,
+    message_ids: bool,
+    boundaries: bool,
+    /// Further headers whose values are replaced, with their placeholder
+    headers: Vec<(String, String)>,
+    /// Applied to the result in the order they were added
+    callbacks: Vec<Arc<dyn Fn(String) -> String + Send + Sync>>,
+}
+
+impl Default for Normalizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Normalizer {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Normalizer")
+            .field("dates", &self.dates)
+            .field("message_ids", &self.message_ids)
+            .field("boundaries", &self.boundaries)
+            .field("headers", &self.headers)
+            .field("callbacks", &self.callbacks.len())
+            .finish()
+    }
+}
+
+impl Normalizer {
+    /// Creates a normalizer replacing dates, message ids and boundaries
+    pub fn new() -> Normalizer {
+        Normalizer {
+            dates: true,
+            message_ids: true,
+            boundaries: true,
+            headers: vec![],
+            callbacks: vec![],
+        }
+    }
+
+    /// Sets whether the values of `Date` headers are replaced
+    pub fn dates(mut self, enabled: bool) -> Normalizer {
+        self.dates = enabled;
+        self
+    }
+
+    /// Sets whether the values of `Message-ID` headers are replaced
+    pub fn message_ids(mut self, enabled: bool) -> Normalizer {
+        self.message_ids = enabled;
+        self
+    }
+
+    /// Sets whether multipart boundaries are replaced
+    pub fn boundaries(mut self, enabled: bool) -> Normalizer {
+        self.boundaries = enabled;
+        self
+    }
+
+    /// Replaces the values of the headers called `name` with `placeholder`,
+    /// e.g. for `X-Request-Id`
+    pub fn header<S: Into<String>, T: Into<String>>(
+        mut self,
+        name: S,
+        placeholder: T,
+    ) -> Normalizer {
+        self.headers.push((name.into(), placeholder.into()));
+        self
+    }
+
+    /// Adds a callback applied to the normalized message, for anything
+    /// else that varies between runs
+    pub fn callback<F>(mut self, callback: F) -> Normalizer
+    where
+        F: Fn(String) -> String + Send + Sync + 'static,
+    {
+        self.callbacks.push(Arc::new(callback));
+        self
+    }
+
+    /// Normalizes the serialized `message`
+    pub fn normalize(&self, message: &str) -> String {
+        let mut message = message.to_string();
+        if self.boundaries {
+            message = replace_boundaries(&message);
+        }
+        let mut headers = vec![];
+        if self.dates {
+            headers.push(("Date", DATE_PLACEHOLDER));
+        }
+        if self.message_ids {
+            headers.push(("Message-ID", MESSAGE_ID_PLACEHOLDER));
+        }
+        headers.extend(
+            self.headers
+                .iter()
+                .map(|(name, placeholder)| (name.as_str(), placeholder.as_str())),
+        );
+        for (name, placeholder) in headers {
+            message = replace_header(&message, name, placeholder);
+        }
+        for callback in self.callbacks.iter() {
+            message = callback(message);
+        }
+        message
+    }
+}
+
+/// Replaces the values of the possibly folded headers called `name`
+fn replace_header(message: &str, name: &str, placeholder: &str) -> String {
+    let re = Regex::new(&format!(
+        r"(?im)^({}:)[ \t]*[^\r\n]*(?:\r?\n[ \t][^\r\n]*)*",
+        regex::escape(name)
+    ))
+    .unwrap();
+    re.replace_all(message, |caps: &regex::Captures| {
+        format!("{} {}", &caps[1], placeholder)
+    })
+    .into_owned()
+}
+
+/// Replaces every boundary declared in `message` with a numbered
+/// placeholder
+fn replace_boundaries(message: &str) -> String {
+    let mut boundaries: Vec<&str> = vec![];
+    for caps in BOUNDARY_RE.captures_iter(message) {
+        let boundary = caps.get(1).or_else(|| caps.get(2)).unwrap().as_str();
+        if !boundaries.contains(&boundary) {
+            boundaries.push(boundary);
+        }
+    }
+    let mut numbered = boundaries
+        .into_iter()
+        .enumerate()
+        .map(|(i, boundary)| (boundary, format!("{}{}", BOUNDARY_PLACEHOLDER, i + 1)))
+        .collect::<Vec<_>>();
+    // Longer boundaries first, in case one is a prefix of another
+    numbered.sort_by_key(|(boundary, _)| std::cmp::Reverse(boundary.len()));
+
+    let mut result = message.to_string();
+    for (boundary, placeholder) in numbered {
+        result = result.replace(boundary, &placeholder);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmailBuilder;
+
+    #[test]
+    fn test_to_normalized_string() {
+        let build = || {
+            EmailBuilder::new()
+                .from("joe@example.org")
+                .to("jane@example.org")
+                .subject("Hello")
+                .header(("X-Request-Id", "1234"))
+                .alternative("<p>Hello</p>", "Hello")
+                .build()
+                .unwrap()
+        };
+        let normalizer = Normalizer::new()
+            .header("X-Request-Id", "ID")
+            .callback(|message| message.replace("Hello", "Hi"));
+        let first = build().to_normalized_string(&normalizer);
+        assert_eq!(first, build().to_normalized_string(&normalizer));
+        assert!(first.contains("Date: DATE\r\n"));
+        assert!(first.contains("Message-ID: <MESSAGE-ID>\r\n"));
+        assert!(first.contains("X-Request-Id: ID\r\n"));
+        assert!(first.contains("Subject: Hi\r\n"));
+        assert!(first.contains("multipart/mixed; boundary=BOUNDARY-1\r\n"));
+        assert!(first.contains("\r\n--BOUNDARY-2--\r\n"));
+
+        let email = build();
+        let unchanged = Normalizer::new()
+            .dates(false)
+            .message_ids(false)
+            .boundaries(false)
+            .normalize(&email.clone().message_to_string().unwrap());
+        assert_eq!(unchanged, email.message_to_string().unwrap());
+    }
+}
@@ -0,0 +1,251 @@
+//! PGP/MIME (RFC 3156) message structures.
+//!
+//! Like the `smime` module, this leaves the cryptography to the caller and
+//! only assembles the MIME structures around its output. Binary signatures
+//! and ciphertexts get ASCII armored, armored ones are used as they are.
+
+use std::collections::HashMap;
+
+use crate::smime::fresh_boundary;
+use crate::{MimeMessage, MimeMultipartType, PartBuilder};
+
+/// Length of the base64 lines within ASCII armor
+const ARMOR_LINE_LENGTH: usize = 64;
+
+/// The `micalg` used if the signature doesn't tell its hash algorithm
+const DEFAULT_MICALG: &str = "pgp-sha256";
+
+/// Wraps `content` into a `multipart/signed` structure with the detached
+/// `signature`, which was computed over `content.as_string()`.
+///
+/// The `micalg` parameter is taken from the hash algorithm of the
+/// signature.
+pub fn signed(content: MimeMessage, signature: &[u8]) -> MimeMessage {
+    let (armored, binary) = armor("SIGNATURE", signature);
+    let micalg = binary
+        .as_deref()
+        .and_then(signature_micalg)
+        .unwrap_or(DEFAULT_MICALG);
+
+    let signature = PartBuilder::new()
+        .header((
+            "Content-Type",
+            "application/pgp-signature; name=\"signature.asc\"",
+        ))
+        .header(("Content-Description", "OpenPGP digital signature"))
+        .header((
+            "Content-Disposition",
+            "attachment; filename=\"signature.asc\"",
+        ))
+        .body(armored)
+        .build();
+
+    let mut params = HashMap::new();
+    params.insert(
+        "protocol".to_string(),
+        "application/pgp-signature".to_string(),
+    );
+    params.insert("micalg".to_string(), micalg.to_string());
+
+    let boundary = fresh_boundary(&content.as_string());
+    MimeMessage::new_with_boundary_and_params(
+        String::new(),
+        MimeMultipartType::Signed,
+        vec![content, signature],
+        boundary,
+        Some(params),
+    )
+}
+
+/// Builds the `multipart/encrypted` structure holding `ciphertext`
+pub fn encrypted(ciphertext: &[u8]) -> MimeMessage {
+    let (armored, _) = armor("MESSAGE", ciphertext);
+
+    let version = PartBuilder::new()
+        .header(("Content-Type", "application/pgp-encrypted"))
+        .header(("Content-Description", "PGP/MIME version identification"))
+        .body("Version: 1")
+        .build();
+    let encrypted = PartBuilder::new()
+        .header((
+            "Content-Type",
+            "application/octet-stream; name=\"encrypted.asc\"",
+        ))
+        .header(("Content-Description", "OpenPGP encrypted message"))
+        .header(("Content-Disposition", "inline; filename=\"encrypted.asc\""))
+        .body(armored)
+        .build();
+
+    let mut params = HashMap::new();
+    params.insert(
+        "protocol".to_string(),
+        "application/pgp-encrypted".to_string(),
+    );
+
+    MimeMessage::new_with_boundary_and_params(
+        String::new(),
+        MimeMultipartType::Encrypted,
+        vec![version, encrypted],
+        MimeMessage::random_boundary(),
+        Some(params),
+    )
+}
+
+/// Returns the ASCII armored form of `data` together with the binary form,
+/// if it could be determined.
+fn armor(kind: &str, data: &[u8]) -> (String, Option<Vec<u8>>) {
+    if data.starts_with(b"-----BEGIN PGP ") {
+        let armored = String::from_utf8_lossy(data).trim_end().to_string();
+        let binary = dearmor(&armored);
+        return (armored, binary);
+    }
+
+    let mut armored = format!("-----BEGIN PGP {}-----\r\n\r\n", kind);
+    let encoded = base64::encode(data);
+    for line in encoded.as_bytes().chunks(ARMOR_LINE_LENGTH) {
+        armored.push_str(std::str::from_utf8(line).unwrap());
+        armored.push_str("\r\n");
+    }
+    let crc = crc24(data);
+    armored.push('=');
+    armored.push_str(&base64::encode([
+        (crc >> 16) as u8,
+        (crc >> 8) as u8,
+        crc as u8,
+    ]));
+    armored.push_str(&format!("\r\n-----END PGP {}-----", kind));
+    (armored, Some(data.to_vec()))
+}
+
+/// Decodes the base64 data of ASCII armor, skipping the armor headers and
+/// the checksum
+fn dearmor(armored: &str) -> Option<Vec<u8>> {
+    let mut lines = armored.lines().map(str::trim_end);
+    lines.find(|line| line.is_empty())?;
+    let data = lines
+        .take_while(|line| !line.starts_with('=') && !line.starts_with("-----"))
+        .collect::<String>();
+    base64::decode(&data).ok()
+}
+
+/// The CRC-24 checksum of ASCII armor (RFC 4880, section 6.1)
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0x00B7_04CE;
+    for byte in data {
+        crc ^= u32::from(*byte) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x0100_0000 != 0 {
+                crc ^= 0x0186_4CFB;
+            }
+        }
+    }
+    crc & 0x00FF_FFFF
+}
+
+/// The `micalg` parameter for the hash algorithm of a binary signature
+/// packet (RFC 4880, sections 4.2 and 5.2)
+fn signature_micalg(packet: &[u8]) -> Option<&'static str> {
+    let first = *packet.first()?;
+    if first & 0x80 == 0 {
+        return None;
+    }
+    let (tag, header_length) = if first & 0x40 != 0 {
+        let length_octet = *packet.get(1)?;
+        let length_length = match length_octet {
+            0..=191 => 1,
+            192..=223 => 2,
+            255 => 5,
+            _ => 1,
+        };
+        (first & 0x3f, 1 + length_length)
+    } else {
+        let length_length = match first & 0x03 {
+            0 => 1,
+            1 => 2,
+            2 => 4,
+            _ => 0,
+        };
+        ((first >> 2) & 0x0f, 1 + length_length)
+    };
+    if tag != 2 {
+        return None;
+    }
+
+    let body = packet.get(header_length..)?;
+    let hash_algorithm = match body.first()? {
+        3 => body.get(16)?,
+        4 | 5 => body.get(3)?,
+        _ => return None,
+    };
+    match hash_algorithm {
+        1 => Some("pgp-md5"),
+        2 => Some("pgp-sha1"),
+        3 => Some("pgp-ripemd160"),
+        8 => Some("pgp-sha256"),
+        9 => Some("pgp-sha384"),
+        10 => Some("pgp-sha512"),
+        11 => Some("pgp-sha224"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Start of a v4 signature packet using SHA-512
+    const SIGNATURE: &[u8] = &[0xc2, 0x05, 0x04, 0x00, 0x01, 0x0a, 0x00];
+
+    fn content_type(message: &MimeMessage) -> String {
+        message
+            .headers
+            .get("Content-Type".to_string())
+            .unwrap()
+            .get_value()
+    }
+
+    #[test]
+    fn test_armor() {
+        assert_eq!(crc24(b"123456789"), 0x0021_CF02);
+        let (armored, _) = armor("SIGNATURE", SIGNATURE);
+        assert_eq!(
+            armored,
+            "-----BEGIN PGP SIGNATURE-----\r\n\r\nwgUEAAEKAA==\r\n=BfwE\r\n-----END PGP SIGNATURE-----"
+        );
+        assert_eq!(
+            armor("SIGNATURE", armored.as_bytes()),
+            (armored, Some(SIGNATURE.to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_signed() {
+        let content = PartBuilder::new()
+            .header(("Content-Type", "text/plain"))
+            .body("Hello")
+            .build();
+        let signed = signed(content.clone(), SIGNATURE);
+
+        let ct = content_type(&signed);
+        assert!(ct.starts_with("multipart/signed"));
+        assert!(ct.contains("protocol=\"application/pgp-signature\""));
+        assert!(ct.contains("micalg=pgp-sha512"));
+        assert_eq!(signed.children[0], content);
+        assert!(signed.children[1]
+            .body
+            .starts_with("-----BEGIN PGP SIGNATURE-----"));
+    }
+
+    #[test]
+    fn test_encrypted() {
+        let encrypted =
+            encrypted(b"-----BEGIN PGP MESSAGE-----\n\nAAAA\n-----END PGP MESSAGE-----\n");
+        assert!(content_type(&encrypted).contains("protocol=\"application/pgp-encrypted\""));
+        assert_eq!(encrypted.children[0].body, "Version: 1");
+        assert_eq!(
+            encrypted.children[1].body,
+            "-----BEGIN PGP MESSAGE-----\r\n\r\nAAAA\r\n-----END PGP MESSAGE-----"
+        );
+    }
+}
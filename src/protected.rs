@@ -0,0 +1,142 @@
+use crate::body::header_value;
+#[cfg(feature = "crypto")]
+use crate::HeaderMap;
+use crate::{Header, MimeMessage, MimeMultipartType};
+
+/// The outer `Subject` of an encrypted message whose real subject is
+/// protected
+pub const OBSCURED_SUBJECT: &str = "...";
+
+/// The Content-Type parameter marking the root of the protected content
+/// (draft-ietf-lamps-header-protection)
+const PROTECTED_HEADERS_PARAM: &str = "protected-headers";
+
+/// Marks `content` as carrying protected headers
+pub(crate) fn mark_protected(content: &mut MimeMessage) {
+    if is_protected(content) {
+        return;
+    }
+    if content.message_type.is_some() {
+        content
+            .message_type_params
+            .get_or_insert_with(Default::default)
+            .insert(PROTECTED_HEADERS_PARAM.into(), "v1".into());
+        content.update_headers();
+    } else {
+        let content_type = header_value(content, "Content-Type")
+            .unwrap_or_else(|| "text/plain; charset=us-ascii".into());
+        content.headers.replace(Header::new(
+            "Content-Type".into(),
+            format!(
+                "{}; {}=\"v1\"",
+                content_type.trim_end_matches(|c: char| c == ';' || c.is_whitespace()),
+                PROTECTED_HEADERS_PARAM
+            ),
+        ));
+    }
+}
+
+/// Whether the Content-Type of `part` has the `protected-headers`
+/// parameter
+pub(crate) fn is_protected(part: &MimeMessage) -> bool {
+    match header_value(part, "Content-Type") {
+        Some(value) => mailparse::parse_content_type(&value)
+            .params
+            .contains_key(PROTECTED_HEADERS_PARAM),
+        None => false,
+    }
+}
+
+/// Replaces the `Subject` within `headers`, if any, by `OBSCURED_SUBJECT`
+#[cfg(feature = "crypto")]
+pub(crate) fn obscure_subject(headers: &mut HeaderMap) {
+    if headers.find("Subject").is_some() {
+        headers.replace(Header::new("Subject".into(), OBSCURED_SUBJECT.into()));
+    }
+}
+
+/// The part of `part` carrying protected headers, if any.
+///
+/// Only the root and the content of `multipart/signed` parts are looked
+/// at, as the protected part has to be the root of the cryptographic
+/// payload.
+pub(crate) fn protected_part(part: &MimeMessage) -> Option<&MimeMessage> {
+    if is_protected(part) {
+        return Some(part);
+    }
+    match part.message_type {
+        Some(MimeMultipartType::Signed) => part.children.first().and_then(protected_part),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PartBuilder;
+
+    #[test]
+    fn test_mark_protected() {
+        let mut part = PartBuilder::new()
+            .header(("Subject", "Secret"))
+            .header(("Content-Type", "text/plain; charset=utf-8"))
+            .body("Hello")
+            .build();
+        mark_protected(&mut part);
+        mark_protected(&mut part);
+        assert_eq!(
+            header_value(&part, "Content-Type").unwrap(),
+            "text/plain; charset=utf-8; protected-headers=\"v1\""
+        );
+
+        let mut multipart = PartBuilder::new()
+            .message_type(MimeMultipartType::Mixed)
+            .child(part)
+            .build();
+        assert_eq!(protected_part(&multipart), None);
+        mark_protected(&mut multipart);
+        assert!(is_protected(&multipart));
+        assert!(protected_part(&multipart).unwrap().message_type.is_some());
+    }
+
+    #[test]
+    fn test_effective_headers() {
+        let message = MimeMessage::parse(
+            "From: mallory@example.org\r\n\
+             To: jane@example.org\r\n\
+             Subject: ...\r\n\
+             Content-Type: multipart/signed; boundary=\"b\"; protocol=\"application/pgp-signature\"\r\n\
+             \r\n\
+             --b\r\n\
+             Content-Type: text/plain; protected-headers=\"v1\"\r\n\
+             From: joe@example.org\r\n\
+             Subject: Quarterly numbers\r\n\
+             \r\n\
+             Hello\r\n\
+             --b\r\n\
+             Content-Type: application/pgp-signature\r\n\
+             \r\n\
+             AAAA\r\n\
+             --b--\r\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            message.effective_subject(true).unwrap(),
+            "Quarterly numbers"
+        );
+        assert_eq!(message.effective_from(true).unwrap(), "joe@example.org");
+        assert_eq!(message.effective_to(true).unwrap(), "jane@example.org");
+        assert_eq!(message.protected_subject().unwrap(), "Quarterly numbers");
+
+        // The signature wasn't verified, so the forged copy isn't trusted
+        assert_eq!(message.effective_subject(false).unwrap(), "...");
+        assert_eq!(
+            message.effective_from(false).unwrap(),
+            "mallory@example.org"
+        );
+
+        let plain = MimeMessage::parse("Subject: Hi\r\n\r\nHello").unwrap();
+        assert_eq!(plain.effective_subject(true).unwrap(), "Hi");
+    }
+}
@@ -0,0 +1,150 @@
+use time::OffsetDateTime;
+
+use crate::body::escape_html;
+use crate::{English, Messages};
+
+lazy_static::lazy_static! {
+    static ref BODY_RE: regex::Regex = regex::Regex::new(r"(?is)<body\b[^>]*>(.*)</body\s*>").unwrap();
+}
+
+/// The line separating a plain text signature from the message (RFC 3676)
+pub const SIGNATURE_DELIMITER: &str = "-- \r\n";
+
+/// The conventional "On DATE, NAME wrote:" attribution line, see
+/// `Messages::attribution` for other languages
+pub fn attribution(date: &OffsetDateTime, name: &str) -> String {
+    English.attribution(date, name)
+}
+
+/// Quotes a plain text message for a reply, prefixing every line with `> `
+/// below the `attribution` line.
+///
+/// Lines which are already quoted only get another `>`, so nested quotes
+/// read `>> `.
+pub fn quote_text(original: &str, attribution: &str) -> String {
+    let mut quoted = String::from(attribution);
+    quoted.push_str("\r\n");
+    for line in original.trim_end_matches(&['\r', '\n'][..]).lines() {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() || line.starts_with('>') {
+            quoted.push('>');
+        } else {
+            quoted.push_str("> ");
+        }
+        quoted.push_str(line);
+        quoted.push_str("\r\n");
+    }
+    quoted
+}
+
+/// Quotes an HTML message for a reply, wrapping it into a `<blockquote>`
+/// below the `attribution` line.
+///
+/// If `original` is a full document, only the content of its body is quoted.
+pub fn quote_html(original: &str, attribution: &str) -> String {
+    let content = match BODY_RE.captures(original) {
+        Some(captures) => captures.get(1).unwrap().as_str(),
+        None => original,
+    };
+    format!(
+        "<p>{}</p>\r\n<blockquote type=\"cite\">{}</blockquote>",
+        escape_html(attribution),
+        content
+    )
+}
+
+/// Splits a line into its quote depth and its unquoted content
+fn unquote(line: &str) -> (usize, &str) {
+    if !line.starts_with('>') {
+        return (0, line);
+    }
+    let content = line.trim_start_matches(&['>', ' '][..]);
+    let prefix = &line[..line.len() - content.len()];
+    (prefix.matches('>').count(), content)
+}
+
+/// Returns true for signature delimiter lines. Quoting often drops the
+/// trailing space, so a bare `--` is accepted as well.
+fn is_signature_delimiter(line: &str) -> bool {
+    line.trim_end_matches('\r').trim_end() == "--"
+}
+
+/// Returns the byte offset of the first signature delimiter line in `text`,
+/// quoted or not.
+pub fn find_signature(text: &str) -> Option<usize> {
+    let mut offset = 0;
+    for line in text.split('\n') {
+        if is_signature_delimiter(unquote(line).1) {
+            return Some(offset);
+        }
+        offset += line.len() + 1;
+    }
+    None
+}
+
+/// Removes the signatures from `text`, including those within quotes.
+///
+/// A signature runs from its delimiter line up to the end of the quote it
+/// appears in, or up to the end of the text if it isn't quoted.
+pub fn strip_signatures(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut signature_depth = None;
+    for line in text.split_inclusive('\n') {
+        let (depth, content) = unquote(line);
+        match signature_depth {
+            Some(signature_depth) if depth == signature_depth => continue,
+            _ => signature_depth = None,
+        }
+        if is_signature_delimiter(content) {
+            signature_depth = Some(depth);
+            continue;
+        }
+        stripped.push_str(line);
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::date::parse_date;
+
+    #[test]
+    fn test_quote_text() {
+        let date = parse_date("Tue, 1 Sep 2020 12:00:00 +0000").unwrap();
+        let attribution = attribution(&date, "Joe");
+        assert_eq!(
+            attribution,
+            "On Tue, 01 Sep 2020 12:00:00 +0000, Joe wrote:"
+        );
+        assert_eq!(
+            quote_text("Hello\n\n> Earlier\r\nBye\r\n", &attribution),
+            "On Tue, 01 Sep 2020 12:00:00 +0000, Joe wrote:\r\n> Hello\r\n>\r\n>> Earlier\r\n> Bye\r\n"
+        );
+    }
+
+    #[test]
+    fn test_quote_html() {
+        assert_eq!(
+            quote_html("<html><body class=x><p>Hi</p></body></html>", "Joe <joe@example.org> wrote:"),
+            "<p>Joe &lt;joe@example.org&gt; wrote:</p>\r\n<blockquote type=\"cite\"><p>Hi</p></blockquote>"
+        );
+        assert_eq!(
+            quote_html("<p>Hi</p>", "Joe wrote:"),
+            "<p>Joe wrote:</p>\r\n<blockquote type=\"cite\"><p>Hi</p></blockquote>"
+        );
+    }
+
+    #[test]
+    fn test_strip_signatures() {
+        let reply =
+            "Thanks!\r\n\r\n> Hello\r\n> -- \r\n> Joe\r\n> Example Inc.\r\n\r\n-- \r\nJane\r\n";
+        assert_eq!(find_signature(reply), Some(20));
+        assert_eq!(strip_signatures(reply), "Thanks!\r\n\r\n> Hello\r\n\r\n");
+        assert_eq!(find_signature("--no signature"), None);
+        assert_eq!(
+            strip_signatures("> > Hi\n> > --\n> > Joe\n> Bye\n"),
+            "> > Hi\n> Bye\n"
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! Redaction of messages into copies that are safe to hand out for
+//! analytics or bug reports.
+
+use std::collections::HashMap;
+
+use crate::{Address, Header, HeaderMap, Mailbox, MimeMessage};
+
+/// Headers which are stripped by default, as they leak network details.
+const DEFAULT_STRIPPED_HEADERS: &[&str] = &["Received", "X-Originating-IP"];
+
+/// Headers carrying addresses, which are pseudonymized.
+const ADDRESS_HEADERS: &[&str] = &[
+    "From",
+    "To",
+    "Cc",
+    "Bcc",
+    "Reply-To",
+    "Sender",
+    "Return-Path",
+    "Delivered-To",
+];
+
+/// Domain used for generated pseudonyms.
+const PSEUDONYM_DOMAIN: &str = "redacted.invalid";
+
+/// Applies a set of redaction rules to messages.
+///
+/// The same `Redactor` maps a given address to the same pseudonym every time,
+/// so redacting several messages with one instance keeps conversations
+/// linkable without revealing who took part in them.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Redactor {
+    /// Names of the headers to drop
+    stripped_headers: Vec<String>,
+    /// Replace addresses with pseudonyms
+    pseudonymize_addresses: bool,
+    /// Drop the content of attachments, keeping their headers
+    drop_attachment_bodies: bool,
+    /// Pseudonyms handed out so far, keyed by lowercased address
+    pseudonyms: HashMap<String, String>,
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Redactor {
+    /// Creates a redactor stripping `Received` and `X-Originating-IP`,
+    /// pseudonymizing addresses and dropping attachment bodies.
+    pub fn new() -> Redactor {
+        Redactor {
+            stripped_headers: DEFAULT_STRIPPED_HEADERS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            pseudonymize_addresses: true,
+            drop_attachment_bodies: true,
+            pseudonyms: HashMap::new(),
+        }
+    }
+
+    /// Adds a header which will be removed from every part
+    pub fn strip_header<S: Into<String>>(mut self, name: S) -> Redactor {
+        self.stripped_headers.push(name.into());
+        self
+    }
+
+    /// Keeps a header which would otherwise be removed
+    pub fn keep_header(mut self, name: &str) -> Redactor {
+        self.stripped_headers
+            .retain(|stripped| !stripped.eq_ignore_ascii_case(name));
+        self
+    }
+
+    /// Sets whether addresses are replaced with pseudonyms
+    pub fn pseudonymize_addresses(mut self, enabled: bool) -> Redactor {
+        self.pseudonymize_addresses = enabled;
+        self
+    }
+
+    /// Sets whether the bodies of attachments are dropped
+    pub fn drop_attachment_bodies(mut self, enabled: bool) -> Redactor {
+        self.drop_attachment_bodies = enabled;
+        self
+    }
+
+    /// Returns a redacted copy of `message`.
+    pub fn redact(&mut self, message: &MimeMessage) -> MimeMessage {
+        let mut redacted = message.clone();
+        self.redact_part(&mut redacted);
+        redacted
+    }
+
+    fn redact_part(&mut self, part: &mut MimeMessage) {
+        let mut headers = HeaderMap::new();
+        for header in part.headers.iter() {
+            if self
+                .stripped_headers
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(&header.name))
+            {
+                continue;
+            }
+            if self.pseudonymize_addresses
+                && ADDRESS_HEADERS
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(&header.name))
+            {
+                headers.insert(self.pseudonymize_header(header));
+            } else {
+                headers.insert(header.clone());
+            }
+        }
+        part.headers = headers;
+
+        if self.drop_attachment_bodies && part.is_attachment() {
+            part.body.clear();
+        }
+
+        for child in part.children.iter_mut() {
+            self.redact_part(child);
+        }
+    }
+
+    fn pseudonymize_header(&mut self, header: &Header) -> Header {
+        let addresses = match mailparse::addrparse(&header.get_value()) {
+            Ok(addresses) if !addresses.is_empty() => addresses,
+            // Never leak something we failed to understand.
+            _ => return Header::new(header.name.clone(), "redacted".to_string()),
+        };
+
+        let addresses = addresses
+            .iter()
+            .map(|addr| match addr {
+                mailparse::MailAddr::Single(info) => {
+                    Address::Mailbox(self.pseudonymize_mailbox(&info.addr))
+                }
+                mailparse::MailAddr::Group(group) => Address::Group(
+                    "redacted".to_string(),
+                    group
+                        .addrs
+                        .iter()
+                        .map(|info| self.pseudonymize_mailbox(&info.addr))
+                        .collect(),
+                ),
+            })
+            .collect::<Vec<_>>();
+
+        // The conversion only fails for empty lists, which we ruled out above.
+        Header::new_with_value(header.name.clone(), addresses).unwrap()
+    }
+
+    fn pseudonymize_mailbox(&mut self, address: &str) -> Mailbox {
+        let next = self.pseudonyms.len() + 1;
+        let pseudonym = self
+            .pseudonyms
+            .entry(address.to_lowercase())
+            .or_insert_with(|| format!("user{}@{}", next, PSEUDONYM_DOMAIN));
+        Mailbox::new(pseudonym.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PartBuilder;
+
+    fn sample_message() -> MimeMessage {
+        let attachment = PartBuilder::new()
+            .header(("Content-Disposition", "attachment; filename=\"a.pdf\""))
+            .header(("Content-Type", "application/pdf"))
+            .body("c2VjcmV0")
+            .build();
+        PartBuilder::new()
+            .header(("Received", "from mx.example.org by mx.example.net"))
+            .header(("X-Originating-IP", "[192.0.2.1]"))
+            .header(("From", "Joe Blogs <joe@example.org>"))
+            .header(("To", "john@example.org, Joe <JOE@example.org>"))
+            .header(("Subject", "Hello"))
+            .child(attachment)
+            .build()
+    }
+
+    #[test]
+    fn test_strip_headers() {
+        let redacted = Redactor::new().redact(&sample_message());
+        let names = redacted
+            .headers
+            .iter()
+            .map(|h| h.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["From", "To", "Subject", "Content-Type"]);
+
+        let redacted = Redactor::new()
+            .keep_header("received")
+            .strip_header("Subject")
+            .redact(&sample_message());
+        assert!(redacted.headers.get("Received".to_string()).is_some());
+        assert!(redacted.headers.get("Subject".to_string()).is_none());
+    }
+
+    #[test]
+    fn test_pseudonymize_consistently() {
+        let mut redactor = Redactor::new();
+        let message = sample_message();
+        let redacted = redactor.redact(&message);
+        assert_eq!(
+            redacted
+                .headers
+                .get("From".to_string())
+                .unwrap()
+                .get_value(),
+            "<user1@redacted.invalid>"
+        );
+        assert_eq!(
+            redacted.headers.get("To".to_string()).unwrap().get_value(),
+            "<user2@redacted.invalid>, <user1@redacted.invalid>"
+        );
+
+        // The mapping is kept across messages.
+        let again = redactor.redact(&message);
+        assert_eq!(again.headers, redacted.headers);
+
+        let untouched = Redactor::new()
+            .pseudonymize_addresses(false)
+            .redact(&sample_message());
+        assert_eq!(
+            untouched
+                .headers
+                .get("From".to_string())
+                .unwrap()
+                .get_value(),
+            "Joe Blogs <joe@example.org>"
+        );
+    }
+
+    #[test]
+    fn test_drop_attachment_bodies() {
+        let redacted = Redactor::new().redact(&sample_message());
+        let attachment = &redacted.children[0];
+        assert!(attachment.body.is_empty());
+        assert_eq!(attachment.headers.len(), 2);
+
+        let kept = Redactor::new()
+            .drop_attachment_bodies(false)
+            .redact(&sample_message());
+        assert_eq!(kept.children[0].body, "c2VjcmV0");
+    }
+}
@@ -0,0 +1,477 @@
+use regex::Regex;
+
+use crate::body::wrap_text;
+use crate::rfc5322::MIME_LINE_LENGTH;
+
+lazy_static::lazy_static! {
+    static ref TOKEN_RE: Regex =
+        Regex::new(r"(?s)<!--.*?-->|<(/?)([a-zA-Z][a-zA-Z0-9]*)\b([^>]*)>").unwrap();
+    static ref RENDER_ATTRIBUTE_RE: Regex = Regex::new(
+        r#"(?i)\b(href|alt)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))"#
+    ).unwrap();
+    static ref ENTITY_RE: Regex = Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[a-zA-Z]+);").unwrap();
+}
+
+/// Elements whose content is not rendered
+const SKIPPED_ELEMENTS: &[&str] = &["head", "script", "style", "template", "title"];
+
+/// Elements separated from their surroundings by an empty line
+const PARAGRAPH_ELEMENTS: &[&str] = &[
+    "p",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "ul",
+    "ol",
+    "dl",
+    "table",
+    "pre",
+    "blockquote",
+];
+
+/// Elements starting on a new line
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address", "article", "aside", "body", "dd", "div", "dt", "figure", "footer", "form", "header",
+    "hr", "li", "main", "nav", "section", "tr",
+];
+
+/// How `render_text` shows links
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum LinkStyle {
+    /// The URL follows the link text in parentheses
+    Inline,
+    /// The link text is followed by a number, and the URLs are listed at
+    /// the end
+    Footnotes,
+    /// Only the link text is kept
+    Strip,
+}
+
+/// Options for `render_text`
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct RenderOptions {
+    width: Option<usize>,
+    links: LinkStyle,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RenderOptions {
+    /// Wraps lines at 78 characters and shows URLs inline
+    pub fn new() -> RenderOptions {
+        RenderOptions {
+            width: Some(MIME_LINE_LENGTH),
+            links: LinkStyle::Inline,
+        }
+    }
+
+    /// Sets the width lines are wrapped to, `None` disables wrapping
+    pub fn width(mut self, width: Option<usize>) -> RenderOptions {
+        self.width = width;
+        self
+    }
+
+    /// Sets how links are shown
+    pub fn links(mut self, links: LinkStyle) -> RenderOptions {
+        self.links = links;
+        self
+    }
+}
+
+/// Renders an HTML document or fragment as plain text with CRLF line
+/// endings, e.g. for the text alternative of a message or for previews.
+///
+/// Blocks are separated by line breaks, list items get bullets or numbers,
+/// blockquotes are quoted with `> ` and the cells of table rows are joined
+/// with ` | `. Scripts, styles and the document head are dropped.
+pub fn render_text(html: &str, options: &RenderOptions) -> String {
+    let mut renderer = Renderer::new(options);
+    let mut position = 0;
+    for token in TOKEN_RE.captures_iter(html) {
+        let whole = token.get(0).unwrap();
+        renderer.text(&decode_entities(&html[position..whole.start()]));
+        position = whole.end();
+        if let Some(name) = token.get(2) {
+            let name = name.as_str().to_ascii_lowercase();
+            if token[1].is_empty() {
+                renderer.open(&name, &token[3]);
+            } else {
+                renderer.close(&name);
+            }
+        }
+    }
+    renderer.text(&decode_entities(&html[position..]));
+    renderer.finish()
+}
+
+struct Renderer<'a> {
+    options: &'a RenderOptions,
+    out: String,
+    pending_breaks: usize,
+    /// The lowest quote depth since the pending breaks were requested
+    break_quote_depth: usize,
+    pending_space: bool,
+    skip_depth: usize,
+    pre_depth: usize,
+    quote_depth: usize,
+    lists: Vec<Option<usize>>,
+    cells: usize,
+    link: Option<(String, usize)>,
+    footnotes: Vec<String>,
+}
+
+impl<'a> Renderer<'a> {
+    fn new(options: &'a RenderOptions) -> Renderer<'a> {
+        Renderer {
+            options,
+            out: String::new(),
+            pending_breaks: 0,
+            break_quote_depth: 0,
+            pending_space: false,
+            skip_depth: 0,
+            pre_depth: 0,
+            quote_depth: 0,
+            lists: vec![],
+            cells: 0,
+            link: None,
+            footnotes: vec![],
+        }
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.out.is_empty() || self.out.ends_with('\n')
+    }
+
+    /// Requests `breaks` line breaks before the next content
+    fn block(&mut self, breaks: usize) {
+        self.break_quote_depth = if self.pending_breaks == 0 {
+            self.quote_depth
+        } else {
+            self.break_quote_depth.min(self.quote_depth)
+        };
+        self.pending_breaks = self.pending_breaks.max(breaks);
+        self.pending_space = false;
+    }
+
+    fn newline(&mut self) {
+        let trimmed = self.out.trim_end_matches(' ').len();
+        self.out.truncate(trimmed);
+        self.out.push('\n');
+    }
+
+    /// Writes `s` as content, after any pending line breaks and the quote
+    /// prefix
+    fn write(&mut self, s: &str) {
+        if self.pending_breaks > 0 && !self.out.is_empty() {
+            // Empty lines are only quoted within a quote
+            let depth = self.break_quote_depth.min(self.quote_depth);
+            let existing = self.out.len() - self.out.trim_end_matches('\n').len();
+            for _ in existing..self.pending_breaks {
+                if self.at_line_start() {
+                    self.out.push_str(&">".repeat(depth));
+                }
+                self.newline();
+            }
+        }
+        self.pending_breaks = 0;
+        if self.at_line_start() {
+            if self.quote_depth > 0 {
+                self.out.push_str(&">".repeat(self.quote_depth));
+                self.out.push(' ');
+            }
+            self.pending_space = false;
+        } else if self.pending_space {
+            self.out.push(' ');
+        }
+        self.pending_space = false;
+        self.out.push_str(s);
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.skip_depth > 0 || text.is_empty() {
+            return;
+        }
+        if self.pre_depth > 0 {
+            let mut lines = text.split('\n');
+            if let Some(first) = lines.next() {
+                self.write(first.trim_end_matches('\r'));
+            }
+            for line in lines {
+                self.newline();
+                self.write(line.trim_end_matches('\r'));
+            }
+            return;
+        }
+
+        for (i, word) in text.split_whitespace().enumerate() {
+            if i > 0 || text.starts_with(char::is_whitespace) {
+                self.pending_space = true;
+            }
+            self.write(word);
+        }
+        if text.ends_with(char::is_whitespace) {
+            self.pending_space = true;
+        }
+    }
+
+    fn open(&mut self, name: &str, attributes: &str) {
+        if SKIPPED_ELEMENTS.contains(&name) {
+            if !attributes.trim_end().ends_with('/') {
+                self.skip_depth += 1;
+            }
+            return;
+        }
+        if self.skip_depth > 0 {
+            return;
+        }
+
+        if PARAGRAPH_ELEMENTS.contains(&name) {
+            // Nested lists continue right on the next line
+            let nested_list = matches!(name, "ul" | "ol") && !self.lists.is_empty();
+            self.block(if nested_list { 1 } else { 2 });
+        } else if BLOCK_ELEMENTS.contains(&name) {
+            self.block(1);
+        }
+
+        match name {
+            "br" => {
+                self.write("");
+                self.newline();
+            }
+            "hr" => {
+                self.write("----");
+                self.block(1);
+            }
+            "pre" => self.pre_depth += 1,
+            "blockquote" => self.quote_depth += 1,
+            "ul" => self.lists.push(None),
+            "ol" => self.lists.push(Some(0)),
+            "li" => {
+                let indent = "  ".repeat(self.lists.len().saturating_sub(1));
+                let bullet = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}{}.", indent, number)
+                    }
+                    _ => format!("{}*", indent),
+                };
+                self.write(&bullet);
+                self.pending_space = true;
+            }
+            "tr" => self.cells = 0,
+            "td" | "th" => {
+                if self.cells > 0 {
+                    self.write(" |");
+                    self.pending_space = true;
+                }
+                self.cells += 1;
+            }
+            "a" => {
+                if let Some(href) = attribute(attributes, "href") {
+                    self.link = Some((href, self.out.len()));
+                }
+            }
+            "img" => {
+                if let Some(alt) = attribute(attributes, "alt").filter(|alt| !alt.is_empty()) {
+                    self.write(&format!("[{}]", alt));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn close(&mut self, name: &str) {
+        if SKIPPED_ELEMENTS.contains(&name) {
+            self.skip_depth = self.skip_depth.saturating_sub(1);
+            return;
+        }
+        if self.skip_depth > 0 {
+            return;
+        }
+
+        match name {
+            "pre" => self.pre_depth = self.pre_depth.saturating_sub(1),
+            "blockquote" => {
+                self.block(2);
+                self.quote_depth = self.quote_depth.saturating_sub(1);
+            }
+            "ul" | "ol" => {
+                self.lists.pop();
+            }
+            "a" => self.close_link(),
+            _ => {}
+        }
+
+        if PARAGRAPH_ELEMENTS.contains(&name) {
+            let nested_list = matches!(name, "ul" | "ol") && !self.lists.is_empty();
+            self.block(if nested_list { 1 } else { 2 });
+        } else if BLOCK_ELEMENTS.contains(&name) {
+            self.block(1);
+        }
+    }
+
+    fn close_link(&mut self) {
+        let (href, start) = match self.link.take() {
+            Some(link) => link,
+            None => return,
+        };
+        let href = href.trim();
+        let text = self.out[start..].trim();
+        let target = href.strip_prefix("mailto:").unwrap_or(href);
+        if href.is_empty()
+            || href.starts_with('#')
+            || href.to_ascii_lowercase().starts_with("javascript:")
+            || text == target
+        {
+            return;
+        }
+        match self.options.links {
+            LinkStyle::Inline => {
+                self.pending_space = true;
+                self.write(&format!("({})", href));
+            }
+            LinkStyle::Footnotes => {
+                self.footnotes.push(href.to_string());
+                let marker = format!("[{}]", self.footnotes.len());
+                // The marker directly follows the link text
+                self.pending_space = false;
+                self.write(&marker);
+            }
+            LinkStyle::Strip => {}
+        }
+    }
+
+    fn finish(mut self) -> String {
+        let mut text = self
+            .out
+            .lines()
+            .map(str::trim_end)
+            .collect::<Vec<_>>()
+            .join("\r\n");
+        text.truncate(text.trim_end().len());
+        if !self.footnotes.is_empty() {
+            text.push_str("\r\n");
+            for (i, url) in self.footnotes.drain(..).enumerate() {
+                text.push_str(&format!("\r\n[{}] {}", i + 1, url));
+            }
+        }
+        match self.options.width {
+            Some(width) => wrap_text(&text, width),
+            None => text,
+        }
+    }
+}
+
+fn attribute(attributes: &str, name: &str) -> Option<String> {
+    RENDER_ATTRIBUTE_RE
+        .captures_iter(attributes)
+        .find(|captures| captures[1].eq_ignore_ascii_case(name))
+        .map(|captures| {
+            let value = captures
+                .get(2)
+                .or_else(|| captures.get(3))
+                .or_else(|| captures.get(4))
+                .unwrap()
+                .as_str();
+            decode_entities(value)
+        })
+}
+
+/// Decodes character references, leaving unknown ones untouched
+fn decode_entities(s: &str) -> String {
+    ENTITY_RE
+        .replace_all(s, |captures: &regex::Captures| {
+            let entity = &captures[1];
+            let decoded = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ if entity.starts_with("#x") || entity.starts_with("#X") => {
+                    u32::from_str_radix(&entity[2..], 16)
+                        .ok()
+                        .and_then(std::char::from_u32)
+                }
+                _ if entity.starts_with('#') => {
+                    entity[1..].parse().ok().and_then(std::char::from_u32)
+                }
+                _ => None,
+            };
+            match decoded {
+                Some(c) => c.to_string(),
+                None => captures[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_text() {
+        let html = "<html><head><title>Ignored</title><style>p { color: red }</style></head>\
+            <body><h1>News &amp; updates</h1>\
+            <p>Read the <a href=\"https://example.org/post\">full post</a>\n   or \
+            <a href=\"https://example.org\">https://example.org</a>.<br>Thanks!</p>\
+            <ul><li>One</li><li>Two<ol><li>Nested</li></ol></li></ul>\
+            <table><tr><th>Name</th><th>Price</th></tr><tr><td>Tea</td><td>2&nbsp;€</td></tr></table>\
+            <blockquote><p>Quoted</p><p>text</p></blockquote>\
+            <p><img src=\"logo.png\" alt=\"Logo\"></p></body></html>";
+
+        assert_eq!(
+            render_text(html, &RenderOptions::new()),
+            "News & updates\r\n\
+             \r\n\
+             Read the full post (https://example.org/post) or https://example.org.\r\n\
+             Thanks!\r\n\
+             \r\n\
+             * One\r\n\
+             * Two\r\n\
+             \x20 1. Nested\r\n\
+             \r\n\
+             Name | Price\r\n\
+             Tea | 2 €\r\n\
+             \r\n\
+             > Quoted\r\n\
+             >\r\n\
+             > text\r\n\
+             \r\n\
+             [Logo]"
+        );
+    }
+
+    #[test]
+    fn test_render_text_options() {
+        let html = "<p>See <a href=\"https://example.org/a\">this</a> and \
+                    <a href='https://example.org/b'>that</a> for a rather long explanation.</p>";
+
+        assert_eq!(
+            render_text(
+                html,
+                &RenderOptions::new()
+                    .links(LinkStyle::Footnotes)
+                    .width(Some(30))
+            ),
+            "See this[1] and that[2] for a\r\n\
+             rather long explanation.\r\n\
+             \r\n\
+             [1] https://example.org/a\r\n\
+             [2] https://example.org/b"
+        );
+        assert_eq!(
+            render_text(html, &RenderOptions::new().links(LinkStyle::Strip)),
+            "See this and that for a rather long explanation."
+        );
+    }
+}
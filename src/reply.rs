@@ -0,0 +1,284 @@
+use crate::body::{header_value, text_bodies, TextKind};
+use crate::date::DateHeader;
+use crate::email::MessageId;
+use crate::quote::{attribution, quote_html, quote_text};
+use crate::{
+    normalize_subject_prefixes, Address, EmailBuilder, Error, Mailbox, MimeMessage, SubjectPrefix,
+};
+
+/// Who a reply built by `EmailBuilder::reply_to_message` is addressed to
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ReplyMode {
+    /// Only the author, or their `Reply-To` addresses
+    Sender,
+    /// The author, with the other recipients of the original in `Cc`
+    All,
+}
+
+/// How `EmailBuilder::forward` includes the original message
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ForwardMode {
+    /// Quotes the text and HTML bodies below an attribution line, leaving
+    /// out attachments
+    Inline,
+    /// Attaches the complete original as a `message/rfc822` part
+    Attachment,
+}
+
+/// The subject of a reply to `original`: `Re: ` is added unless the
+/// subject already starts with a reply prefix, which is canonicalized
+pub(crate) fn reply_subject(original: &MimeMessage) -> String {
+    prefixed_subject(original, SubjectPrefix::Reply)
+}
+
+/// Adds `prefix` to the subject of `original` unless it is already there
+fn prefixed_subject(original: &MimeMessage, prefix: SubjectPrefix) -> String {
+    let subject = header_value(original, "Subject").unwrap_or_default();
+    let normalized = normalize_subject_prefixes(&subject);
+    if normalized.prefix == Some(prefix) {
+        normalized.to_string()
+    } else {
+        format!("{}{}", prefix.as_str(), subject.trim())
+    }
+}
+
+/// A builder forwarding `original`, see `EmailBuilder::forward`
+pub(crate) fn forward(original: &MimeMessage, mode: ForwardMode) -> EmailBuilder {
+    let builder = EmailBuilder::new().subject(prefixed_subject(original, SubjectPrefix::Forward));
+    if mode == ForwardMode::Attachment {
+        return builder.attach_message(original);
+    }
+
+    let author = original
+        .headers
+        .nth("From", 0)
+        .and_then(|header| header.parse_value::<Vec<Address>>().ok())
+        .and_then(|addresses| addresses.into_iter().next())
+        .map(|address| match address {
+            Address::Mailbox(mailbox) => mailbox.name.unwrap_or(mailbox.address),
+            Address::Group(name, _) => name,
+        })
+        .unwrap_or_default();
+    let line = match original
+        .headers
+        .nth("Date", 0)
+        .and_then(|header| header.parse_value::<DateHeader>().ok())
+    {
+        Some(date) => attribution(&date.date(), &author),
+        None => format!("{} wrote:", author),
+    };
+
+    let bodies = text_bodies(original);
+    let body = |kind: TextKind| {
+        bodies
+            .iter()
+            .find(|(found, _)| *found == kind)
+            .map(|(_, body)| body.as_str())
+    };
+    match (body(TextKind::Html), body(TextKind::Plain)) {
+        (Some(html), Some(text)) => {
+            builder.alternative(quote_html(html, &line), quote_text(text, &line))
+        }
+        (Some(html), None) => builder.html(quote_html(html, &line)),
+        (None, text) => builder.text(quote_text(text.unwrap_or_default(), &line)),
+    }
+}
+
+/// The `To` and `Cc` addresses of a reply to `original`.
+///
+/// Addresses are deduplicated ignoring case, and the `Cc` of `All` leaves
+/// out those already in `To`.
+pub(crate) fn reply_recipients(
+    original: &MimeMessage,
+    mode: ReplyMode,
+) -> Result<(Vec<Address>, Vec<Address>), Error> {
+    let mut seen = vec![];
+    let mut to = addresses(original, "Reply-To")?;
+    if to.is_empty() {
+        to = addresses(original, "From")?;
+    }
+    let to = dedup(to, &mut seen);
+    let cc = match mode {
+        ReplyMode::Sender => vec![],
+        ReplyMode::All => {
+            let mut cc = addresses(original, "To")?;
+            cc.extend(addresses(original, "Cc")?);
+            dedup(cc, &mut seen)
+        }
+    };
+    Ok((to, cc))
+}
+
+/// The `In-Reply-To` and `References` ids of a reply to `original`
+/// (RFC 5322, section 3.6.4).
+///
+/// `References` continues the original's, or starts from its single
+/// `In-Reply-To` id if it has none. Malformed ids are skipped.
+pub(crate) fn reply_thread(original: &MimeMessage) -> (Option<MessageId>, Vec<MessageId>) {
+    let ids = |name: &str| {
+        original
+            .headers
+            .nth(name, 0)
+            .and_then(|header| header.parse_value::<Vec<MessageId>>().ok())
+            .unwrap_or_default()
+    };
+    let message_id = ids("Message-ID").into_iter().next();
+    let mut references = ids("References");
+    if references.is_empty() {
+        references = ids("In-Reply-To");
+        if references.len() > 1 {
+            references.clear();
+        }
+    }
+    references.extend(message_id.clone());
+    (message_id, references)
+}
+
+/// Drops the `own` mailboxes of the replying user from the `To` and `Cc`
+/// addresses of a reply. `To` is kept if none of its mailboxes would
+/// remain, as when replying to one's own message.
+pub(crate) fn drop_own_addresses(to: &mut Vec<Address>, cc: &mut Vec<Address>, own: &[&Mailbox]) {
+    let own = own
+        .iter()
+        .map(|mailbox| mailbox.address.to_lowercase())
+        .collect::<Vec<_>>();
+    let others = dedup(to.clone(), &mut own.clone());
+    if !others.is_empty() {
+        *to = others;
+    }
+    *cc = dedup(std::mem::take(cc), &mut own.clone());
+}
+
+fn addresses(original: &MimeMessage, name: &str) -> Result<Vec<Address>, Error> {
+    match original.headers.nth(name, 0) {
+        Some(header) => header
+            .parse_value::<Vec<Address>>()
+            .map_err(Error::address(header.get_raw_value())),
+        None => Ok(vec![]),
+    }
+}
+
+/// Drops the mailboxes within `addresses` which are in `seen`, adding the
+/// others. Groups are kept with their remaining mailboxes.
+fn dedup(addresses: Vec<Address>, seen: &mut Vec<String>) -> Vec<Address> {
+    let mut first_time = |address: &str| {
+        let address = address.to_lowercase();
+        if seen.contains(&address) {
+            false
+        } else {
+            seen.push(address);
+            true
+        }
+    };
+    addresses
+        .into_iter()
+        .filter_map(|address| match address {
+            Address::Mailbox(mailbox) if first_time(&mailbox.address) => {
+                Some(Address::Mailbox(mailbox))
+            }
+            Address::Mailbox(_) => None,
+            Address::Group(name, mailboxes) => {
+                let mailboxes = mailboxes
+                    .into_iter()
+                    .filter(|mailbox| first_time(&mailbox.address))
+                    .collect::<Vec<_>>();
+                if mailboxes.is_empty() {
+                    None
+                } else {
+                    Some(Address::Group(name, mailboxes))
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmailBuilder;
+
+    fn original() -> MimeMessage {
+        MimeMessage::parse(
+            "From: Joe <joe@example.org>\r\n\
+             To: me@example.org, Jane <jane@example.org>\r\n\
+             Cc: JOE@example.org, bob@example.org\r\n\
+             Subject: AW: Lunch\r\n\
+             Message-ID: <3@example.org>\r\n\
+             References: <1@example.org> <2@example.org>\r\n\
+             \r\n\
+             Hello\r\n",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_forward() {
+        let mut original = original();
+        original
+            .headers
+            .insert(("Date", "Tue, 01 Jul 2003 08:52:37 +0000").into());
+        let message = EmailBuilder::forward(&original, ForwardMode::Inline)
+            .from("me@example.org")
+            .to("bob@example.org")
+            .build()
+            .unwrap()
+            .message_to_string()
+            .unwrap();
+        assert!(message.contains("Subject: Fwd: AW: Lunch\r\n"));
+        assert!(message.contains("On Tue, 01 Jul 2003 08:52:37 +0000, Joe wrote:\r\n> Hello\r\n"));
+
+        let message = EmailBuilder::forward(&original, ForwardMode::Attachment)
+            .from("me@example.org")
+            .to("bob@example.org")
+            .build()
+            .unwrap()
+            .message_to_string()
+            .unwrap();
+        assert!(message.contains("Content-Type: message/rfc822\r\n"));
+        assert!(message.contains("Message-ID: <3@example.org>\r\n"));
+    }
+
+    #[test]
+    fn test_reply_to_message() {
+        let message = EmailBuilder::reply_to_message(&original(), ReplyMode::All)
+            .unwrap()
+            .from("me@example.org")
+            .body("Sure")
+            .build()
+            .unwrap()
+            .message_to_string()
+            .unwrap();
+        assert!(message.contains("Subject: Re: Lunch\r\n"));
+        assert!(message.contains("To: Joe <joe@example.org>\r\n"));
+        assert!(message.contains("Cc: Jane <jane@example.org>, <bob@example.org>\r\n"));
+        assert!(message.contains("In-Reply-To: <3@example.org>\r\n"));
+        assert!(message.contains("References: <1@example.org> <2@example.org> <3@example.org>\r\n"));
+
+        let message = EmailBuilder::reply_to_message(&original(), ReplyMode::All)
+            .unwrap()
+            .from("Joe <JOE@example.org>")
+            .body("Sure")
+            .build()
+            .unwrap()
+            .message_to_string()
+            .unwrap();
+        assert!(message.contains("To: Joe <joe@example.org>\r\n"));
+        assert!(message
+            .contains("Cc: <me@example.org>, Jane <jane@example.org>, <bob@example.org>\r\n"));
+
+        let mut original = original();
+        original
+            .headers
+            .replace(("Reply-To", "list@example.org").into());
+        original.headers.replace(("Subject", "Fwd: Lunch").into());
+        original.headers.remove("References");
+        let (to, cc) = reply_recipients(&original, ReplyMode::Sender).unwrap();
+        assert_eq!(to, vec![Address::new_mailbox("list@example.org".into())]);
+        assert!(cc.is_empty());
+        assert_eq!(reply_subject(&original), "Re: Fwd: Lunch");
+        assert_eq!(
+            reply_thread(&original).1,
+            vec!["3@example.org".parse::<MessageId>().unwrap()]
+        );
+    }
+}
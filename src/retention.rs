@@ -0,0 +1,80 @@
+use std::fmt;
+
+use time::OffsetDateTime;
+
+use crate::body::header_value;
+use crate::date::parse_date;
+use crate::MimeMessage;
+
+/// The value of the `Sensitivity` header (RFC 2156), which clients show and
+/// gateways use for retention handling
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Sensitivity {
+    /// Personal, e.g. not related to work
+    Personal,
+    /// Private, only for the recipient
+    Private,
+    /// Confidential within the company
+    CompanyConfidential,
+}
+
+impl Sensitivity {
+    /// The header value
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Sensitivity::Personal => "Personal",
+            Sensitivity::Private => "Private",
+            Sensitivity::CompanyConfidential => "Company-Confidential",
+        }
+    }
+
+    /// Parses a header value, ignoring case
+    pub fn from_value(value: &str) -> Option<Sensitivity> {
+        let value = value.trim();
+        [
+            Sensitivity::Personal,
+            Sensitivity::Private,
+            Sensitivity::CompanyConfidential,
+        ]
+        .iter()
+        .copied()
+        .find(|sensitivity| sensitivity.as_str().eq_ignore_ascii_case(value))
+    }
+}
+
+impl fmt::Display for Sensitivity {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(self.as_str())
+    }
+}
+
+/// The date of the `Expires` header, or the `Expiry-Date` header used by
+/// X.400 gateways
+pub(crate) fn expires(message: &MimeMessage) -> Option<OffsetDateTime> {
+    header_value(message, "Expires")
+        .or_else(|| header_value(message, "Expiry-Date"))
+        .and_then(|value| parse_date(&value))
+}
+
+/// The `Sensitivity` of a message, `None` if unset or unknown
+pub(crate) fn sensitivity(message: &MimeMessage) -> Option<Sensitivity> {
+    header_value(message, "Sensitivity").and_then(|value| Sensitivity::from_value(&value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sensitivity_from_value() {
+        assert_eq!(
+            Sensitivity::from_value(" company-confidential "),
+            Some(Sensitivity::CompanyConfidential)
+        );
+        assert_eq!(
+            Sensitivity::from_value("Private"),
+            Some(Sensitivity::Private)
+        );
+        assert_eq!(Sensitivity::from_value("secret"), None);
+    }
+}
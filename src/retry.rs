@@ -0,0 +1,243 @@
+use time::OffsetDateTime;
+
+use crate::date::{format_date, parse_date};
+use crate::{encode_header_value, Header, HeaderMap};
+
+/// The namespace of the retry headers if none is given, resulting in e.g.
+/// `X-Retry-Count`
+pub const DEFAULT_RETRY_NAMESPACE: &str = "X-Retry";
+
+/// Delivery-retry state of a queued message, kept in `<namespace>-Count`,
+/// `<namespace>-Next-Attempt` and `<namespace>-Last-Error` headers
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct RetryMetadata {
+    /// The number of failed delivery attempts
+    pub count: u32,
+    /// When delivery is attempted next
+    pub next_attempt: Option<OffsetDateTime>,
+    /// Why the last attempt failed, e.g. the SMTP reply
+    pub last_error: Option<String>,
+}
+
+/// An error stamping or reading retry headers
+#[derive(PartialEq, Eq, Clone, Debug, thiserror::Error)]
+pub enum RetryMetadataError {
+    /// The namespace isn't an `X-` header name prefix
+    #[error("invalid retry header namespace: {0}")]
+    InvalidNamespace(String),
+    /// The last error contains line breaks or other control characters
+    #[error("control character in the last retry error")]
+    ControlCharacter,
+    /// A retry header can't be parsed
+    #[error("invalid {name} header: {value}")]
+    InvalidHeader { name: String, value: String },
+}
+
+impl RetryMetadata {
+    /// Metadata after `count` failed attempts
+    pub fn new(count: u32) -> RetryMetadata {
+        RetryMetadata {
+            count,
+            next_attempt: None,
+            last_error: None,
+        }
+    }
+
+    /// Sets when delivery is attempted next
+    pub fn next_attempt(mut self, next_attempt: OffsetDateTime) -> RetryMetadata {
+        self.next_attempt = Some(next_attempt);
+        self
+    }
+
+    /// Sets why the last attempt failed
+    pub fn last_error<S: Into<String>>(mut self, last_error: S) -> RetryMetadata {
+        self.last_error = Some(last_error.into());
+        self
+    }
+
+    /// Replaces the retry headers of `headers` in the default namespace
+    pub fn stamp(&self, headers: &mut HeaderMap) -> Result<(), RetryMetadataError> {
+        self.stamp_namespaced(DEFAULT_RETRY_NAMESPACE, headers)
+    }
+
+    /// Replaces the retry headers of `headers` in `namespace`, e.g.
+    /// `X-MyQueue-Retry`. Headers of unset fields are removed.
+    pub fn stamp_namespaced(
+        &self,
+        namespace: &str,
+        headers: &mut HeaderMap,
+    ) -> Result<(), RetryMetadataError> {
+        let names = RetryHeaderNames::new(namespace)?;
+        if let Some(ref last_error) = self.last_error {
+            if last_error.chars().any(char::is_control) {
+                return Err(RetryMetadataError::ControlCharacter);
+            }
+        }
+
+        headers.replace(Header::new(names.count.clone(), self.count.to_string()));
+        match self.next_attempt {
+            Some(ref next_attempt) => {
+                headers.replace(Header::new(
+                    names.next_attempt.clone(),
+                    format_date(next_attempt),
+                ));
+            }
+            None => headers.remove(&names.next_attempt),
+        }
+        match self.last_error {
+            Some(ref last_error) => {
+                headers.replace(Header::new(
+                    names.last_error.clone(),
+                    encode_header_value(last_error).into_owned(),
+                ));
+            }
+            None => headers.remove(&names.last_error),
+        }
+        Ok(())
+    }
+
+    /// Reads the retry headers of the default namespace, `None` if the
+    /// message hasn't been retried
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<RetryMetadata>, RetryMetadataError> {
+        Self::from_headers_namespaced(DEFAULT_RETRY_NAMESPACE, headers)
+    }
+
+    /// Reads the retry headers of `namespace`, `None` if there is no count
+    /// header
+    pub fn from_headers_namespaced(
+        namespace: &str,
+        headers: &HeaderMap,
+    ) -> Result<Option<RetryMetadata>, RetryMetadataError> {
+        let names = RetryHeaderNames::new(namespace)?;
+        let count = match last_value(headers, &names.count) {
+            Some(count) => count
+                .trim()
+                .parse()
+                .map_err(|_| invalid_header(&names.count, &count))?,
+            None => return Ok(None),
+        };
+        let next_attempt = match last_value(headers, &names.next_attempt) {
+            Some(value) => Some(
+                parse_date(&value).ok_or_else(|| invalid_header(&names.next_attempt, &value))?,
+            ),
+            None => None,
+        };
+
+        Ok(Some(RetryMetadata {
+            count,
+            next_attempt,
+            last_error: last_value(headers, &names.last_error),
+        }))
+    }
+}
+
+struct RetryHeaderNames {
+    count: String,
+    next_attempt: String,
+    last_error: String,
+}
+
+impl RetryHeaderNames {
+    fn new(namespace: &str) -> Result<RetryHeaderNames, RetryMetadataError> {
+        let valid = namespace.len() > 2
+            && namespace[..2].eq_ignore_ascii_case("X-")
+            && !namespace.ends_with('-')
+            && !namespace.contains("--")
+            && namespace
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-');
+        if !valid {
+            return Err(RetryMetadataError::InvalidNamespace(namespace.to_string()));
+        }
+        Ok(RetryHeaderNames {
+            count: format!("{}-Count", namespace),
+            next_attempt: format!("{}-Next-Attempt", namespace),
+            last_error: format!("{}-Last-Error", namespace),
+        })
+    }
+}
+
+/// The decoded value of the last header of `name`, in any case
+fn last_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name.to_string())
+        .map(|header| header.get_value())
+}
+
+fn invalid_header(name: &str, value: &str) -> RetryMetadataError {
+    RetryMetadataError::InvalidHeader {
+        name: name.to_string(),
+        value: value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::{Date, Time};
+
+    #[test]
+    fn test_stamp_retry_metadata() {
+        let next_attempt = Date::try_from_ymd(2020, 6, 1)
+            .unwrap()
+            .with_time(Time::try_from_hms(12, 30, 0).unwrap())
+            .assume_utc();
+        let mut headers = HeaderMap::new();
+        headers.insert(Header::new("X-Retry-Next-Attempt".into(), "stale".into()));
+
+        RetryMetadata::new(1)
+            .last_error("451 Try again später")
+            .stamp(&mut headers)
+            .unwrap();
+        assert_eq!(
+            headers.to_block_string(),
+            "X-Retry-Count: 1\r\nX-Retry-Last-Error: =?utf-8?q?451_Try_again_sp=C3=A4ter?=\r\n"
+        );
+        assert_eq!(
+            RetryMetadata::from_headers(&headers).unwrap(),
+            Some(RetryMetadata::new(1).last_error("451 Try again später"))
+        );
+
+        let metadata = RetryMetadata::new(2).next_attempt(next_attempt);
+        metadata
+            .stamp_namespaced("X-Outbox-Retry", &mut headers)
+            .unwrap();
+        assert_eq!(
+            headers
+                .get("X-Outbox-Retry-Next-Attempt".into())
+                .unwrap()
+                .get_value(),
+            "Mon, 01 Jun 2020 12:30:00 +0000"
+        );
+        assert_eq!(
+            RetryMetadata::from_headers_namespaced("X-Outbox-Retry", &headers).unwrap(),
+            Some(metadata)
+        );
+    }
+
+    #[test]
+    fn test_retry_metadata_errors() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            RetryMetadata::new(1).stamp_namespaced("Retry", &mut headers),
+            Err(RetryMetadataError::InvalidNamespace("Retry".into()))
+        );
+        assert_eq!(
+            RetryMetadata::new(1)
+                .last_error("failed\r\nBcc: eve@example.org")
+                .stamp(&mut headers),
+            Err(RetryMetadataError::ControlCharacter)
+        );
+        assert!(headers.is_empty());
+        assert_eq!(RetryMetadata::from_headers(&headers), Ok(None));
+
+        headers.insert(Header::new("x-retry-count".into(), "many".into()));
+        assert_eq!(
+            RetryMetadata::from_headers(&headers),
+            Err(RetryMetadataError::InvalidHeader {
+                name: "X-Retry-Count".into(),
+                value: "many".into()
+            })
+        );
+    }
+}
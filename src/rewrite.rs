@@ -0,0 +1,194 @@
+use regex::Regex;
+
+use crate::email::Envelope;
+use crate::email_builder::{BuildHook, Error};
+use crate::{Header, HeaderMap, MimeMessage};
+
+/// Selects the headers a rule of a `HeaderRewrite` applies to
+#[derive(Clone, Debug)]
+pub enum HeaderMatcher {
+    /// Headers with this name, ignoring case
+    Name(String),
+    /// Headers whose name the pattern matches, e.g. `(?i)^X-Internal-`
+    Pattern(Regex),
+}
+
+impl HeaderMatcher {
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            HeaderMatcher::Name(matched) => matched.eq_ignore_ascii_case(name),
+            HeaderMatcher::Pattern(pattern) => pattern.is_match(name),
+        }
+    }
+}
+
+/// Patterns are compared by their source
+impl PartialEq for HeaderMatcher {
+    fn eq(&self, other: &HeaderMatcher) -> bool {
+        match (self, other) {
+            (HeaderMatcher::Name(a), HeaderMatcher::Name(b)) => a == b,
+            (HeaderMatcher::Pattern(a), HeaderMatcher::Pattern(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for HeaderMatcher {}
+
+impl<'a> From<&'a str> for HeaderMatcher {
+    fn from(name: &'a str) -> HeaderMatcher {
+        HeaderMatcher::Name(name.to_string())
+    }
+}
+
+impl From<String> for HeaderMatcher {
+    fn from(name: String) -> HeaderMatcher {
+        HeaderMatcher::Name(name)
+    }
+}
+
+impl From<Regex> for HeaderMatcher {
+    fn from(pattern: Regex) -> HeaderMatcher {
+        HeaderMatcher::Pattern(pattern)
+    }
+}
+
+/// What a rule of a `HeaderRewrite` does with the headers it matches
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum RewriteAction {
+    /// Removes the header
+    Drop,
+    /// Keeps the value under another name
+    Rename(String),
+    /// Keeps the name with another value
+    Replace(String),
+}
+
+/// A set of rules rewriting the headers of messages in one pass, e.g. for
+/// the policies of a gateway stripping internal headers on egress.
+///
+/// Each header is rewritten by the first rule matching it and kept as it
+/// is if none does. The added headers are appended afterwards. Only the
+/// headers of the message itself are rewritten, not those of its parts.
+///
+/// A rewrite can be registered as a `BuildHook`.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct HeaderRewrite {
+    /// The rules, in the order they are tried
+    rules: Vec<(HeaderMatcher, RewriteAction)>,
+    /// Headers appended to every message
+    added: Vec<Header>,
+}
+
+impl HeaderRewrite {
+    /// Creates a rewrite without rules
+    pub fn new() -> HeaderRewrite {
+        HeaderRewrite::default()
+    }
+
+    /// Adds a rule applying `action` to the headers `matcher` selects
+    pub fn rule<M: Into<HeaderMatcher>>(
+        mut self,
+        matcher: M,
+        action: RewriteAction,
+    ) -> HeaderRewrite {
+        self.rules.push((matcher.into(), action));
+        self
+    }
+
+    /// Adds a rule removing the headers `matcher` selects
+    pub fn drop_header<M: Into<HeaderMatcher>>(self, matcher: M) -> HeaderRewrite {
+        self.rule(matcher, RewriteAction::Drop)
+    }
+
+    /// Adds a rule renaming the headers `matcher` selects to `name`
+    pub fn rename_header<M: Into<HeaderMatcher>, S: Into<String>>(
+        self,
+        matcher: M,
+        name: S,
+    ) -> HeaderRewrite {
+        self.rule(matcher, RewriteAction::Rename(name.into()))
+    }
+
+    /// Adds a rule replacing the values of the headers `matcher` selects
+    pub fn replace_header<M: Into<HeaderMatcher>, S: Into<String>>(
+        self,
+        matcher: M,
+        value: S,
+    ) -> HeaderRewrite {
+        self.rule(matcher, RewriteAction::Replace(value.into()))
+    }
+
+    /// Appends `header` to every message, e.g. a classification banner
+    pub fn add_header<H: Into<Header>>(mut self, header: H) -> HeaderRewrite {
+        self.added.push(header.into());
+        self
+    }
+
+    /// Rewrites the headers of `message`
+    pub fn apply(&self, message: &mut MimeMessage) {
+        let mut headers = HeaderMap::new();
+        for header in message.headers.iter() {
+            let action = self
+                .rules
+                .iter()
+                .find(|(matcher, _)| matcher.matches(&header.name))
+                .map(|(_, action)| action);
+            match action {
+                None => headers.insert(header.clone()),
+                Some(RewriteAction::Drop) => {}
+                Some(RewriteAction::Rename(name)) => headers.insert(Header::new(
+                    name.clone(),
+                    header.get_raw_value().to_string(),
+                )),
+                Some(RewriteAction::Replace(value)) => {
+                    headers.insert(Header::new(header.name.clone(), value.clone()))
+                }
+            }
+        }
+        for header in self.added.iter() {
+            headers.insert(header.clone());
+        }
+        message.headers = headers;
+    }
+}
+
+impl BuildHook for HeaderRewrite {
+    fn before_finalize(&self, message: &mut MimeMessage, _: &mut Envelope) -> Result<(), Error> {
+        self.apply(message);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmailBuilder;
+
+    #[test]
+    fn test_header_rewrite() {
+        let rewrite = HeaderRewrite::new()
+            .drop_header(Regex::new("(?i)^X-Internal-").unwrap())
+            .rename_header("X-Mailer", "X-Original-Mailer")
+            .replace_header("Organization", "Example Corp")
+            .add_header(("X-Classification", "Public"));
+        let message = EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .header(("X-Internal-Route", "hr-7"))
+            .header(("x-internal-ticket", "1234"))
+            .header(("X-Mailer", "Intranet 2.1"))
+            .header(("Organization", "Example Corp, HR"))
+            .body("Hello")
+            .build_hook(rewrite)
+            .build()
+            .unwrap()
+            .message_to_string()
+            .unwrap();
+        assert!(!message.to_ascii_lowercase().contains("x-internal"));
+        assert!(message.contains("X-Original-Mailer: Intranet 2.1\r\n"));
+        assert!(message.contains("Organization: Example Corp\r\n"));
+        assert!(message.contains("X-Classification: Public\r\n"));
+        assert!(message.contains("From: <joe@example.org>\r\n"));
+    }
+}
@@ -1,7 +1,136 @@
 //! Module with helpers for dealing with RFC 5322.
 
+use std::borrow::Cow;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
 pub const MIME_LINE_LENGTH: usize = 78;
 
+lazy_static! {
+    static ref FOLD_RE: Regex = Regex::new(r"[ \t]*\r?\n[ \t]+").unwrap();
+}
+
+/// The whitespace continuation lines of folded headers start with
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum FoldStyle {
+    /// A tab, the default
+    Tab,
+    /// A space, which some legacy parsers expect
+    Space,
+}
+
+/// The line ending of serialized messages
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum LineEnding {
+    /// CRLF, as required on the wire
+    Crlf,
+    /// A bare LF, e.g. for mbox files or handing to `sendmail`
+    Lf,
+}
+
+impl LineEnding {
+    /// The line ending as a string
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Lf => "\n",
+        }
+    }
+}
+
+/// How messages are serialized: the length headers are folded at, the
+/// whitespace continuation lines start with and the line ending.
+///
+/// The default folds at 78 characters with a tab and ends lines in CRLF.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct SerializationConfig {
+    pub(crate) max_line_len: usize,
+    fold_style: FoldStyle,
+    line_ending: LineEnding,
+}
+
+impl SerializationConfig {
+    /// Creates the default configuration
+    pub fn new() -> SerializationConfig {
+        SerializationConfig {
+            max_line_len: MIME_LINE_LENGTH,
+            fold_style: FoldStyle::Tab,
+            line_ending: LineEnding::Crlf,
+        }
+    }
+
+    /// Sets the length headers are folded at, without the line ending.
+    /// Tokens longer than that are still kept on one line.
+    pub fn max_line_len(mut self, max_line_len: usize) -> SerializationConfig {
+        self.max_line_len = max_line_len;
+        self
+    }
+
+    /// Sets the whitespace continuation lines start with
+    pub fn fold_style(mut self, fold_style: FoldStyle) -> SerializationConfig {
+        self.fold_style = fold_style;
+        self
+    }
+
+    /// Sets the line ending, of the headers as well as of the bodies
+    pub fn line_ending(mut self, line_ending: LineEnding) -> SerializationConfig {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// The line break and whitespace a header is folded with
+    fn fold(&self) -> &'static str {
+        match (self.line_ending, self.fold_style) {
+            (LineEnding::Crlf, FoldStyle::Tab) => "\r\n\t",
+            (LineEnding::Crlf, FoldStyle::Space) => "\r\n ",
+            (LineEnding::Lf, FoldStyle::Tab) => "\n\t",
+            (LineEnding::Lf, FoldStyle::Space) => "\n ",
+        }
+    }
+
+    /// Prepares the header line `line` for folding. Header values may have
+    /// been folded with the defaults when they were set, e.g. address
+    /// lists, so other configurations unfold them to fold them anew,
+    /// keeping a single space for the whitespace around each fold.
+    pub(crate) fn unfold<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        if *self == SerializationConfig::default() {
+            Cow::Borrowed(line)
+        } else {
+            FOLD_RE.replace_all(line, " ")
+        }
+    }
+
+    /// Folds the header line `line` as configured, but always with CRLF,
+    /// as the line endings are converted once the message is serialized
+    pub(crate) fn fold_line(&self, line: &str) -> String {
+        let config = self.line_ending(LineEnding::Crlf);
+        let mut builder = Rfc5322Builder::with_config(config);
+        builder.emit_folded(&config.unfold(line));
+        builder.result().clone()
+    }
+
+    /// Converts the CRLF line endings of `chunk` to the configured ones
+    pub(crate) fn convert_line_endings<'a>(&self, chunk: Cow<'a, [u8]>) -> Cow<'a, [u8]> {
+        if self.line_ending == LineEnding::Crlf || !chunk.contains(&b'\r') {
+            return chunk;
+        }
+        let mut converted = Vec::with_capacity(chunk.len());
+        for (index, &byte) in chunk.iter().enumerate() {
+            if byte != b'\r' || chunk.get(index + 1) != Some(&b'\n') {
+                converted.push(byte);
+            }
+        }
+        Cow::Owned(converted)
+    }
+}
+
+impl Default for SerializationConfig {
+    fn default() -> Self {
+        SerializationConfig::new()
+    }
+}
+
 trait Rfc5322Character {
     /// Is considered a special character by RFC 5322 Section 3.2.3
     fn is_special(&self) -> bool;
@@ -17,32 +146,71 @@ trait Rfc5322Character {
 
 impl Rfc5322Character for char {
     fn is_ftext(&self) -> bool {
-        match *self {
-            '!'..='9' | ';'..='~' => true,
-            _ => false,
-        }
+        matches!(*self, '!'..='9' | ';'..='~')
     }
 
     fn is_special(&self) -> bool {
-        match *self {
-            '(' | ')' | '<' | '>' | '[' | ']' | ':' | ';' | '@' | '\\' | ',' | '.' | '\"' | ' ' => {
-                true
-            }
-            _ => false,
-        }
+        matches!(
+            *self,
+            '(' | ')' | '<' | '>' | '[' | ']' | ':' | ';' | '@' | '\\' | ',' | '.' | '\"' | ' '
+        )
     }
 
     fn is_vchar(&self) -> bool {
-        match *self {
-            '!'..='~' => true,
-            _ => false,
+        matches!(*self, '!'..='~')
+    }
+}
+
+/// Whether `name` is a valid header field name, a non-empty sequence of
+/// ftext (RFC 5322, section 3.6.8)
+pub fn is_field_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ftext())
+}
+
+/// Whether the raw header value `value` only breaks lines to fold them,
+/// each line break being a CRLF followed by whitespace and more text, so
+/// it can't start another header or the body
+pub fn is_safely_folded(value: &str) -> bool {
+    let unbroken = |line: &str| !line.contains(&['\r', '\n'][..]);
+    let mut lines = value.split("\r\n");
+    unbroken(lines.next().unwrap_or_default())
+        && lines.all(|line| {
+            unbroken(line) && line.starts_with(&[' ', '\t'][..]) && !line.trim().is_empty()
+        })
+}
+
+/// Writes `phrase`, e.g. a display name, as it is if it is made of atoms,
+/// or as a quoted-string (RFC 5322, section 3.2.5). Returns `None` if it
+/// has characters other than printable ASCII and spaces, which need
+/// encoded-words instead.
+pub fn format_phrase(phrase: &str) -> Option<String> {
+    if !phrase.chars().all(|c| c == ' ' || c.is_vchar()) {
+        return None;
+    }
+    // Leading, trailing or repeated spaces would get lost, and encoded-word
+    // lookalikes decoded, outside of quotes
+    let atoms = phrase
+        .split(' ')
+        .all(|atom| !atom.is_empty() && !atom.contains("=?") && atom.chars().all(|c| c.is_atext()));
+    if atoms {
+        return Some(phrase.to_string());
+    }
+    let mut quoted = String::with_capacity(phrase.len() + 2);
+    quoted.push('"');
+    for c in phrase.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
         }
+        quoted.push(c);
     }
+    quoted.push('"');
+    Some(quoted)
 }
 
 /// Type for constructing RFC 5322 messages
 pub struct Rfc5322Builder {
     result: String,
+    config: SerializationConfig,
 }
 
 impl Rfc5322Builder {
@@ -50,6 +218,15 @@ impl Rfc5322Builder {
     pub fn new() -> Rfc5322Builder {
         Rfc5322Builder {
             result: "".to_string(),
+            config: SerializationConfig::default(),
+        }
+    }
+
+    /// Make a new builder folding as `config` says
+    pub fn with_config(config: SerializationConfig) -> Rfc5322Builder {
+        Rfc5322Builder {
+            result: "".to_string(),
+            config,
         }
     }
 
@@ -61,43 +238,63 @@ impl Rfc5322Builder {
         self.result.push_str(s);
     }
 
+    /// Emits `s`, folding it onto continuation lines to keep within the line
+    /// length.
+    ///
+    /// Lines are only folded at whitespace outside of quoted strings and
+    /// angle brackets, so encoded-words, addresses and message ids are never
+    /// split. A token which doesn't fit on a line on its own is emitted as
+    /// an over-length line.
     pub fn emit_folded(&mut self, s: &str) {
-        let mut cur_len = 0;
-        let mut last_space = 0;
-        let mut last_cut = 0;
+        let mut line_start = 0;
+        let mut line_len = 0;
+        let mut last_cut: Option<usize> = None;
+        let mut quoted = false;
+        let mut escaped = false;
+        let mut angle_depth = 0usize;
+        let mut prev: Option<char> = None;
 
         for (pos, c) in s.char_indices() {
+            let next = s[pos + c.len_utf8()..].chars().next();
             match c {
-                ' ' => {
-                    last_space = pos;
-                }
-                '\r' => {
-                    cur_len = 0;
+                _ if escaped => escaped = false,
+                '\\' if quoted => escaped = true,
+                '"' => quoted = !quoted,
+                '<' if !quoted => angle_depth += 1,
+                '>' if !quoted => angle_depth = angle_depth.saturating_sub(1),
+                '\r' | '\n' => {
+                    line_len = 0;
+                    last_cut = None;
                 }
-                '\n' => {
-                    cur_len = 0;
+                ' ' | '\t' if !quoted && angle_depth == 0 => {
+                    // Neither line may end up empty or whitespace only
+                    let between_tokens = matches!(prev, Some(p) if !p.is_whitespace())
+                        && matches!(next, Some(n) if !n.is_whitespace());
+                    if between_tokens {
+                        last_cut = Some(pos);
+                    }
                 }
                 _ => {}
             }
+            prev = Some(c);
+            if c == '\r' || c == '\n' {
+                continue;
+            }
 
-            cur_len += 1;
-            // We've reached our line length, so
-            if cur_len >= MIME_LINE_LENGTH && last_space > 0 {
-                // Emit the string from the last place we cut it to the
-                // last space that we saw
-                self.emit_raw(&s[last_cut..last_space]);
-                // ... and get us ready to put out the continuation
-                self.emit_raw("\r\n\t");
-
-                // Reset our counters
-                cur_len = 0;
-                last_cut = last_space + s[last_space..].chars().next().unwrap().len_utf8();
-                last_space = 0;
+            line_len += 1;
+            if line_len > self.config.max_line_len {
+                if let Some(cut) = last_cut.filter(|&cut| cut > line_start) {
+                    self.emit_raw(&s[line_start..cut]);
+                    self.emit_raw(self.config.fold());
+                    // The tab takes the place of the folding whitespace
+                    line_start = cut + 1;
+                    line_len = s[line_start..=pos].chars().count();
+                    last_cut = None;
+                }
             }
         }
 
-        // Finally, emit everything left in the string
-        self.emit_raw(&s[last_cut..]);
+        self.emit_raw(&s[line_start..]);
     }
 }
 
@@ -138,4 +335,37 @@ mod tests {
             assert_eq!(gen.result(), &test.expected.to_string());
         }
     }
+
+    #[test]
+    fn test_builder_folding_tokens() {
+        let encoded =
+            "=?utf-8?q?Ein_sehr_langer_Betreff_mit_=C3=BCberl=C3=A4ngen_W=C3=B6rtern_und_mehr?=";
+        let tests = vec![
+            // Encoded-words and message ids stay intact on over-length lines
+            (
+                format!("Subject: {}", encoded),
+                format!("Subject:\r\n\t{}", encoded),
+            ),
+            (
+                "References: <a-very-long-message-id-from-some-mail-client-1234567890@mail.example.org> <short@example.org>".to_string(),
+                "References:\r\n\t<a-very-long-message-id-from-some-mail-client-1234567890@mail.example.org>\r\n\t<short@example.org>".to_string(),
+            ),
+            // No folding inside quoted strings or angle brackets
+            (
+                "To: \"Some rather long display name, which has spaces in it and goes on and on\" <joe@example.org>".to_string(),
+                "To: \"Some rather long display name, which has spaces in it and goes on and on\"\r\n\t<joe@example.org>".to_string(),
+            ),
+            // Nor at trailing whitespace, which would leave an empty line
+            (
+                format!("X-Long: {}   ", "x".repeat(80)),
+                format!("X-Long:\r\n\t{}   ", "x".repeat(80)),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let mut gen = Rfc5322Builder::new();
+            gen.emit_folded(&input);
+            assert_eq!(gen.result(), &expected);
+        }
+    }
 }
@@ -0,0 +1,138 @@
+use crate::email::{Email, Envelope};
+use crate::email_builder::{EmailBuilder, Error};
+use crate::{Address, Mailbox};
+
+/// The coordinated copies of a message built by `SendSet::build`
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct SentEmails {
+    /// The message delivered to the recipients
+    pub message: Email,
+    /// The copy delivered only to the archive address, if one was set
+    pub archive: Option<Email>,
+    /// The copy for the sender's "sent items", with the `Bcc` recipients
+    /// visible
+    pub sent_copy: Email,
+}
+
+/// Builds the copies a message is sent with from a single base builder:
+/// the message for the recipients, a blind copy for a compliance archive
+/// and a copy for the sender's "sent items".
+///
+/// All copies share the `Date` and `Message-ID` of the message, so they
+/// can be matched up later.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct SendSet {
+    /// Builder of the message for the recipients
+    base: EmailBuilder,
+    /// The address the archive copy is delivered to
+    archive_to: Option<Mailbox>,
+    /// Headers added only to the archive copy
+    archive_headers: Vec<(String, String)>,
+}
+
+impl SendSet {
+    /// Creates a set of copies of the message built by `base`
+    pub fn new(base: EmailBuilder) -> SendSet {
+        SendSet {
+            base,
+            archive_to: None,
+            archive_headers: vec![],
+        }
+    }
+
+    /// Sets the address an archive copy is delivered to. Its envelope
+    /// only has this recipient, the headers are those of the message.
+    pub fn archive_to<A: Into<Mailbox>>(mut self, address: A) -> SendSet {
+        self.archive_to = Some(address.into());
+        self
+    }
+
+    /// Adds a header to the archive copy only, e.g. `X-Archive-Policy`
+    pub fn archive_header<S: Into<String>, T: Into<String>>(
+        mut self,
+        name: S,
+        value: T,
+    ) -> SendSet {
+        self.archive_headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Builds the message and its copies
+    pub fn build(&self) -> Result<SentEmails, Error> {
+        let base = self.base.clone().pin_generated_headers();
+        let message = base.clone().build()?;
+
+        let mut sent_copy = base.clone();
+        if let Some(bcc) = base.bcc_header() {
+            sent_copy = sent_copy.header(bcc);
+        }
+        let sent_copy = sent_copy.build()?;
+
+        let archive = match self.archive_to {
+            Some(ref archive_to) => {
+                let envelope = Envelope::new(
+                    message.envelope.from().cloned(),
+                    vec![Address::Mailbox(archive_to.clone())],
+                )?;
+                let mut archive = base.envelope(envelope);
+                for (name, value) in self.archive_headers.iter() {
+                    archive = archive.header((name.as_str(), value.as_str()));
+                }
+                Some(archive.build()?)
+            }
+            None => None,
+        };
+
+        Ok(SentEmails {
+            message,
+            archive,
+            sent_copy,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_set() {
+        let sent = SendSet::new(
+            EmailBuilder::new()
+                .from("joe@example.org")
+                .to("jane@example.org")
+                .bcc("bob@example.org")
+                .subject("Quarterly report")
+                .body("See attached"),
+        )
+        .archive_to("archive@example.org")
+        .archive_header("X-Archive-Policy", "7y")
+        .build()
+        .unwrap();
+
+        let archive = sent.archive.unwrap();
+        assert_eq!(archive.message_id, sent.message.message_id);
+        assert_eq!(sent.sent_copy.message_id, sent.message.message_id);
+        assert_eq!(sent.message.envelope.to().len(), 2);
+        let message = sent.message.message_to_string().unwrap();
+        let sent_copy = sent.sent_copy.message_to_string().unwrap();
+        assert!(!message.contains("Bcc:"));
+        assert!(!message.contains("X-Archive-Policy"));
+        assert!(sent_copy.contains("Bcc: <bob@example.org>\r\n"));
+        assert_eq!(
+            archive.envelope.to(),
+            &[Address::new_mailbox("archive@example.org".into())]
+        );
+        let archive = archive.message_to_string().unwrap();
+        assert!(archive.contains("X-Archive-Policy: 7y\r\n"));
+        assert!(!archive.contains("Bcc:"));
+        let date = |message: &str| {
+            message
+                .lines()
+                .find(|line| line.starts_with("Date:"))
+                .map(String::from)
+        };
+        assert_eq!(date(&archive), date(&message));
+        assert_eq!(date(&sent_copy), date(&message));
+    }
+}
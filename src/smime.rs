@@ -0,0 +1,376 @@
+//! S/MIME (RFC 8551) message structures.
+//!
+//! The cryptography itself is left to the caller, through the `SmimeSigner`
+//! and `SmimeEncryptor` traits; this module takes care of assembling the
+//! MIME structures around their output.
+
+use std::collections::HashMap;
+
+use crate::email_builder::Error;
+use crate::protected;
+use crate::{Header, HeaderMap, MimeMessage, MimeMultipartType, PartBuilder};
+
+/// Headers copied into the protected content by default when triple
+/// wrapping.
+const DEFAULT_PROTECTED_HEADERS: &[&str] = &["From", "To", "Cc", "Reply-To", "Subject", "Date"];
+
+/// Produces detached S/MIME signatures
+pub trait SmimeSigner {
+    /// The `micalg` parameter matching the digest algorithm used,
+    /// e.g. `sha-256`
+    fn micalg(&self) -> &str;
+
+    /// Returns the DER encoded detached `SignedData` over `content`
+    fn sign(&self, content: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// Encrypts content for the recipients of a message
+pub trait SmimeEncryptor {
+    /// Returns the DER encoded `EnvelopedData` for `content`
+    fn encrypt(&self, content: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+impl<F: Fn(&[u8]) -> Result<Vec<u8>, Error>> SmimeEncryptor for F {
+    fn encrypt(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        self(content)
+    }
+}
+
+/// Wraps `content` into a `multipart/signed` structure, signing it exactly
+/// as it will be serialized.
+pub fn sign(content: MimeMessage, signer: &dyn SmimeSigner) -> Result<MimeMessage, Error> {
+    let signature = signer.sign(content.as_string().as_bytes())?;
+    Ok(signed(content, &signature, signer.micalg()))
+}
+
+/// Assembles the `multipart/signed` structure from a detached `signature`,
+/// which was computed elsewhere over `content.as_string()`.
+pub fn signed(content: MimeMessage, signature: &[u8], micalg: &str) -> MimeMessage {
+    let content_string = content.as_string();
+    let signature = PartBuilder::new()
+        .header((
+            "Content-Type",
+            "application/pkcs7-signature; name=\"smime.p7s\"",
+        ))
+        .header(("Content-Transfer-Encoding", "base64"))
+        .header(("Content-Disposition", "attachment; filename=\"smime.p7s\""))
+        .body(base64::encode(signature))
+        .build();
+
+    let mut params = HashMap::new();
+    params.insert(
+        "protocol".to_string(),
+        "application/pkcs7-signature".to_string(),
+    );
+    params.insert("micalg".to_string(), micalg.to_string());
+
+    MimeMessage::new_with_boundary_and_params(
+        String::new(),
+        MimeMultipartType::Signed,
+        vec![content, signature],
+        fresh_boundary(&content_string),
+        Some(params),
+    )
+}
+
+/// Encrypts `content` into an `application/pkcs7-mime` enveloped-data part.
+pub fn encrypt(content: MimeMessage, encryptor: &dyn SmimeEncryptor) -> Result<MimeMessage, Error> {
+    let encrypted = encryptor.encrypt(content.as_string().as_bytes())?;
+
+    Ok(PartBuilder::new()
+        .header((
+            "Content-Type",
+            "application/pkcs7-mime; smime-type=enveloped-data; name=\"smime.p7m\"",
+        ))
+        .header(("Content-Transfer-Encoding", "base64"))
+        .header(("Content-Disposition", "attachment; filename=\"smime.p7m\""))
+        .body(base64::encode(&encrypted))
+        .build())
+}
+
+/// Signs a complete message. The transport headers (From, Subject, ...)
+/// stay on the outermost part, while the content headers get signed along
+/// with the body.
+pub fn sign_message(message: MimeMessage, signer: &dyn SmimeSigner) -> Result<MimeMessage, Error> {
+    wrap_content(message, &|_| false, false, |content| sign(content, signer))
+}
+
+/// Encrypts a complete message. The transport headers (From, Subject, ...)
+/// stay on the outermost part, while the content headers get encrypted
+/// along with the body.
+pub fn encrypt_message(
+    message: MimeMessage,
+    encryptor: &dyn SmimeEncryptor,
+) -> Result<MimeMessage, Error> {
+    wrap_content(message, &|_| false, true, |content| {
+        encrypt(content, encryptor)
+    })
+}
+
+/// Moves the content headers of `message` into the content passed to
+/// `wrap`, keeping the transport headers on the result. Transport headers
+/// for which `protect` returns true are copied into the content as well.
+///
+/// Content marked by `EmailBuilder::protect_subject` gets the default
+/// protected headers copied, and its outer Subject obscured if `encrypted`.
+fn wrap_content<F>(
+    message: MimeMessage,
+    protect: &dyn Fn(&Header) -> bool,
+    encrypted: bool,
+    wrap: F,
+) -> Result<MimeMessage, Error>
+where
+    F: FnOnce(MimeMessage) -> Result<MimeMessage, Error>,
+{
+    let mut content = message;
+    let marked = protected::is_protected(&content);
+    let mut outer_headers = HeaderMap::new();
+    let mut content_headers = HeaderMap::new();
+    for header in content.headers.iter() {
+        if is_content_header(header) {
+            content_headers.insert(header.clone());
+        } else {
+            outer_headers.insert(header.clone());
+            if protect(header) || (marked && is_default_protected(header)) {
+                content_headers.insert(header.clone());
+            }
+        }
+    }
+    content.headers = content_headers;
+
+    let mut outer = wrap(content)?;
+    for header in outer.headers.iter() {
+        outer_headers.replace(header.clone());
+    }
+    if marked && encrypted {
+        protected::obscure_subject(&mut outer_headers);
+    }
+    outer.headers = outer_headers;
+    Ok(outer)
+}
+
+fn is_default_protected(header: &Header) -> bool {
+    DEFAULT_PROTECTED_HEADERS
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(&header.name))
+}
+
+/// Applies the sign, encrypt, sign sequence of RFC 2634 triple wrapping.
+///
+/// The transport headers of the message (From, Subject, ...) stay on the
+/// outermost layer, while the selected headers are also copied into the
+/// innermost content so that they are covered by the inner signature and
+/// the encryption.
+pub struct TripleWrap<'a> {
+    inner_signer: &'a dyn SmimeSigner,
+    encryptor: &'a dyn SmimeEncryptor,
+    outer_signer: &'a dyn SmimeSigner,
+    protected_headers: Vec<String>,
+}
+
+impl<'a> std::fmt::Debug for TripleWrap<'a> {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.debug_struct("TripleWrap")
+            .field("protected_headers", &self.protected_headers)
+            .finish()
+    }
+}
+
+impl<'a> TripleWrap<'a> {
+    /// Creates a triple wrapping pipeline protecting From, To, Cc,
+    /// Reply-To, Subject and Date.
+    pub fn new(
+        inner_signer: &'a dyn SmimeSigner,
+        encryptor: &'a dyn SmimeEncryptor,
+        outer_signer: &'a dyn SmimeSigner,
+    ) -> TripleWrap<'a> {
+        TripleWrap {
+            inner_signer,
+            encryptor,
+            outer_signer,
+            protected_headers: DEFAULT_PROTECTED_HEADERS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        }
+    }
+
+    /// Also copies the header `name` into the protected content
+    pub fn protect_header<S: Into<String>>(mut self, name: S) -> TripleWrap<'a> {
+        self.protected_headers.push(name.into());
+        self
+    }
+
+    /// Only copies the given headers into the protected content
+    pub fn protected_headers(mut self, names: Vec<String>) -> TripleWrap<'a> {
+        self.protected_headers = names;
+        self
+    }
+
+    /// Triple wraps `message`
+    pub fn wrap(&self, message: MimeMessage) -> Result<MimeMessage, Error> {
+        let protect = |header: &Header| self.is_protected(header);
+        wrap_content(message, &protect, true, |content| {
+            let inner = sign(content, self.inner_signer)?;
+            let encrypted = encrypt(inner, self.encryptor)?;
+            sign(encrypted, self.outer_signer)
+        })
+    }
+
+    fn is_protected(&self, header: &Header) -> bool {
+        self.protected_headers
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(&header.name))
+    }
+}
+
+pub(crate) fn is_content_header(header: &Header) -> bool {
+    header.name.len() > 8 && header.name[..8].eq_ignore_ascii_case("Content-")
+}
+
+/// A random boundary which doesn't occur within `content`
+pub(crate) fn fresh_boundary(content: &str) -> String {
+    loop {
+        let boundary = MimeMessage::random_boundary();
+        if !content.contains(&boundary) {
+            return boundary;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestSigner(&'static str);
+
+    impl SmimeSigner for TestSigner {
+        fn micalg(&self) -> &str {
+            "sha-256"
+        }
+
+        fn sign(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+            Ok(format!("{}:{}", self.0, content.len()).into_bytes())
+        }
+    }
+
+    fn reverse(content: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(content.iter().rev().cloned().collect())
+    }
+
+    fn content_type(message: &MimeMessage) -> String {
+        message
+            .headers
+            .get("Content-Type".to_string())
+            .unwrap()
+            .get_value()
+    }
+
+    #[test]
+    fn test_sign() {
+        let content = PartBuilder::new()
+            .header(("Content-Type", "text/plain"))
+            .body("Hello")
+            .build();
+        let content_string = content.as_string();
+        let signed = sign(content, &TestSigner("inner")).unwrap();
+
+        assert_eq!(signed.message_type, Some(MimeMultipartType::Signed));
+        let ct = content_type(&signed);
+        assert!(ct.starts_with("multipart/signed"));
+        assert!(ct.contains("protocol=\"application/pkcs7-signature\""));
+        assert!(ct.contains("micalg=sha-256"));
+        assert_eq!(signed.children[0].as_string(), content_string);
+        assert_eq!(
+            signed.children[1].body,
+            base64::encode(format!("inner:{}", content_string.len()))
+        );
+
+        // The signed content is serialized verbatim.
+        let serialized = signed.as_string();
+        assert!(serialized.contains(&format!("--{}\r\n{}\r\n", signed.boundary, content_string)));
+    }
+
+    #[test]
+    fn test_encrypt() {
+        let content = PartBuilder::new().body("Hello").build();
+        let encrypted = encrypt(content.clone(), &reverse).unwrap();
+        assert!(content_type(&encrypted).contains("smime-type=enveloped-data"));
+        assert_eq!(
+            base64::decode(&encrypted.body).unwrap(),
+            reverse(content.as_string().as_bytes()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_triple_wrap() {
+        let message = PartBuilder::new()
+            .header(("From", "joe@example.org"))
+            .header(("Subject", "Secret"))
+            .header(("X-Mailer", "test"))
+            .header(("Content-Type", "text/plain"))
+            .body("Hello")
+            .build();
+
+        let inner = TestSigner("inner");
+        let outer = TestSigner("outer");
+        let wrapped = TripleWrap::new(&inner, &reverse, &outer)
+            .wrap(message)
+            .unwrap();
+
+        // Transport headers stay outside, next to the new Content-Type.
+        let names = wrapped
+            .headers
+            .iter()
+            .map(|h| h.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["From", "Subject", "X-Mailer", "Content-Type"]);
+        assert!(content_type(&wrapped).starts_with("multipart/signed"));
+
+        let encrypted = &wrapped.children[0];
+        assert!(content_type(encrypted).starts_with("application/pkcs7-mime"));
+        assert_eq!(
+            wrapped.children[1].body,
+            base64::encode(format!("outer:{}", encrypted.as_string().len()))
+        );
+
+        let decrypted = reverse(&base64::decode(&encrypted.body).unwrap()).unwrap();
+        let decrypted = String::from_utf8(decrypted).unwrap();
+        assert!(decrypted.starts_with("Content-Type: multipart/signed"));
+        assert!(decrypted.contains(
+            "\r\nFrom: joe@example.org\r\nSubject: Secret\r\nContent-Type: text/plain\r\n\r\nHello\r\n"
+        ));
+        assert!(!decrypted.contains("X-Mailer"));
+    }
+
+    #[test]
+    fn test_sign_message() {
+        let email = crate::EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .subject("Signed")
+            .text("Hello")
+            .smime_sign(TestSigner("signer"))
+            .build()
+            .unwrap();
+        let message = MimeMessage::parse(&email.message_to_string().unwrap()).unwrap();
+
+        assert_eq!(message.message_type, Some(MimeMultipartType::Signed));
+        assert!(message.headers.get("Subject".to_string()).is_some());
+        let content = &message.children[0];
+        assert!(content.headers.get("Subject".to_string()).is_none());
+        assert!(content_type(content).starts_with("multipart/mixed"));
+        assert_eq!(
+            message.children[1].body,
+            base64::encode(format!("signer:{}", content.as_string().len()))
+        );
+    }
+
+    #[test]
+    fn test_signed() {
+        let content = PartBuilder::new().body("Hello").build();
+        let signed = signed(content, b"signature", "sha-512");
+        assert!(content_type(&signed).contains("micalg=sha-512"));
+        assert_eq!(signed.children[1].body, base64::encode("signature"));
+    }
+}
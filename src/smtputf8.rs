@@ -0,0 +1,156 @@
+//! Internationalized email addresses (RFC 6530, RFC 6531)
+
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+
+/// Converts the non-ASCII labels of `domain` to lowercase A-labels, e.g.
+/// `bücher.example` to `xn--bcher-kva.example`.
+///
+/// This is the Punycode step of IDNA (RFC 3492), without the mapping and
+/// validation of RFC 5891 beyond lowercasing.
+pub fn punycode_domain(domain: &str) -> String {
+    domain
+        .split('.')
+        .map(|label| {
+            if label.is_ascii() {
+                label.to_string()
+            } else {
+                let label = label.to_lowercase();
+                match punycode_encode(&label) {
+                    Some(encoded) => format!("xn--{}", encoded),
+                    None => label,
+                }
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Converts the domain of `address` with `punycode_domain`
+pub(crate) fn punycode_address(address: &str) -> String {
+    match address.rfind('@') {
+        Some(at) => format!("{}@{}", &address[..at], punycode_domain(&address[at + 1..])),
+        None => address.to_string(),
+    }
+}
+
+/// Whether `address` may be used in the envelope: non-ASCII addresses
+/// need `utf8`, and must have a local part and domain without whitespace
+/// or control characters.
+///
+/// ASCII addresses are left to the parser, as before SMTPUTF8 support.
+pub(crate) fn is_valid_address(address: &str, utf8: bool) -> bool {
+    if address.is_ascii() {
+        return true;
+    }
+    if !utf8 || address.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return false;
+    }
+    match address.rfind('@') {
+        Some(at) => at > 0 && at + 1 < address.len(),
+        None => false,
+    }
+}
+
+fn adapt(mut delta: u32, num_points: u32, first_time: bool) -> u32 {
+    delta /= if first_time { DAMP } else { 2 };
+    delta += delta / num_points;
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+    k + (BASE - T_MIN + 1) * delta / (delta + SKEW)
+}
+
+fn encode_digit(digit: u32) -> char {
+    if digit < 26 {
+        (b'a' + digit as u8) as char
+    } else {
+        (b'0' + (digit - 26) as u8) as char
+    }
+}
+
+/// Encodes `input` as Punycode (RFC 3492, section 6.3), `None` on overflow
+fn punycode_encode(input: &str) -> Option<String> {
+    let code_points = input.chars().map(|c| c as u32).collect::<Vec<_>>();
+    let mut output = input.chars().filter(char::is_ascii).collect::<String>();
+    let basic = output.len() as u32;
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let mut n = INITIAL_N;
+    let mut delta: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut handled = basic;
+    while (handled as usize) < code_points.len() {
+        let m = code_points.iter().copied().filter(|&c| c >= n).min()?;
+        delta = delta.checked_add((m - n).checked_mul(handled + 1)?)?;
+        n = m;
+        for &c in code_points.iter() {
+            if c < n {
+                delta = delta.checked_add(1)?;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = if k <= bias {
+                        T_MIN
+                    } else if k >= bias + T_MAX {
+                        T_MAX
+                    } else {
+                        k - bias
+                    };
+                    if q < t {
+                        break;
+                    }
+                    output.push(encode_digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(encode_digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta = delta.checked_add(1)?;
+        n += 1;
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_punycode_domain() {
+        assert_eq!(punycode_domain("bücher.example"), "xn--bcher-kva.example");
+        assert_eq!(punycode_domain("MÜNCHEN.de"), "xn--mnchen-3ya.de");
+        assert_eq!(punycode_domain("例え.テスト"), "xn--r8jz45g.xn--zckzah");
+        assert_eq!(punycode_domain("example.org"), "example.org");
+        assert_eq!(
+            punycode_address("jörg@bücher.example"),
+            "jörg@xn--bcher-kva.example"
+        );
+    }
+
+    #[test]
+    fn test_is_valid_address() {
+        assert!(is_valid_address("joe@localhost", false));
+        assert!(!is_valid_address("jörg@example.org", false));
+        assert!(!is_valid_address("joe@bücher.example", false));
+        assert!(is_valid_address("jörg@bücher.example", true));
+        assert!(is_valid_address("用户@例子.广告", true));
+        assert!(!is_valid_address("jö rg@example.org", true));
+        assert!(!is_valid_address("jörg", true));
+    }
+}
@@ -0,0 +1,116 @@
+use crate::body::{self, header_value};
+use crate::MimeMessage;
+
+/// Declared types which are interchangeable with the detected type, as
+/// (declared, detected)
+const COMPATIBLE_TYPES: &[(&str, &str)] = &[
+    ("image/jpg", "image/jpeg"),
+    ("image/pjpeg", "image/jpeg"),
+    ("application/x-pdf", "application/pdf"),
+    ("application/x-zip-compressed", "application/zip"),
+];
+
+/// Prefixes of zip based container formats, which sniff as plain zip files
+const ZIP_CONTAINER_PREFIXES: &[&str] = &[
+    "application/vnd.openxmlformats-officedocument.",
+    "application/vnd.oasis.opendocument.",
+    "application/epub+zip",
+    "application/java-archive",
+];
+
+/// An attachment whose content doesn't match its declared type
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct TypeMismatch {
+    /// The filename of the attachment, if any
+    pub filename: Option<String>,
+    /// The MIME type from the Content-Type header
+    pub declared: String,
+    /// The MIME type detected from the content
+    pub detected: String,
+}
+
+/// Compares the declared Content-Type of every attachment in `message` with
+/// the type detected from its magic bytes, returning the mismatches.
+///
+/// Attachments of types which can't be detected are skipped.
+pub fn verify_attachment_types(message: &MimeMessage) -> Vec<TypeMismatch> {
+    let mut mismatches = vec![];
+    verify_part(message, &mut mismatches);
+    mismatches
+}
+
+fn verify_part(part: &MimeMessage, mismatches: &mut Vec<TypeMismatch>) {
+    for child in part.children.iter() {
+        verify_part(child, mismatches);
+    }
+    if !part.is_attachment() {
+        return;
+    }
+
+    let content = match body::decode_body(part) {
+        Some(content) => content,
+        None => return,
+    };
+    let detected = match infer::get(&content) {
+        Some(detected) => detected.mime_type(),
+        None => return,
+    };
+    let declared = body::mime_type(part);
+    if !is_compatible(&declared, detected) {
+        mismatches.push(TypeMismatch {
+            filename: header_value(part, "Content-Disposition").and_then(|value| {
+                mailparse::parse_content_disposition(&value)
+                    .params
+                    .remove("filename")
+            }),
+            declared,
+            detected: detected.to_string(),
+        });
+    }
+}
+
+fn is_compatible(declared: &str, detected: &str) -> bool {
+    declared == detected
+        || COMPATIBLE_TYPES.contains(&(declared, detected))
+        || (detected == "application/zip"
+            && ZIP_CONTAINER_PREFIXES
+                .iter()
+                .any(|prefix| declared.starts_with(prefix)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email_builder::attachment_part;
+    use crate::PartBuilder;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    #[test]
+    fn test_verify_attachment_types() {
+        let message = PartBuilder::new()
+            .child(attachment_part(PNG, "image.png", &mime::IMAGE_PNG))
+            .child(attachment_part(PNG, "invoice.pdf", &mime::APPLICATION_PDF))
+            .child(attachment_part(b"plain text", "a.txt", &mime::TEXT_PLAIN))
+            .build();
+
+        assert_eq!(
+            verify_attachment_types(&message),
+            vec![TypeMismatch {
+                filename: Some("invoice.pdf".to_string()),
+                declared: "application/pdf".to_string(),
+                detected: "image/png".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_compatible_types() {
+        assert!(is_compatible("image/jpg", "image/jpeg"));
+        assert!(is_compatible(
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+            "application/zip"
+        ));
+        assert!(!is_compatible("application/pdf", "application/zip"));
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use crate::hash::ContentHash;
+
+/// Source of attachment payloads which parts reference by content hash.
+///
+/// Payloads are kept transfer-encoded, so an attachment shared by many
+/// messages is stored and encoded only once.
+pub trait AttachmentStore {
+    /// Returns the base64 encoded payload stored under `hash`
+    fn get(&self, hash: &ContentHash) -> Option<&str>;
+}
+
+/// In-memory `AttachmentStore`
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct MemoryAttachmentStore {
+    payloads: HashMap<ContentHash, String>,
+}
+
+impl MemoryAttachmentStore {
+    /// Creates an empty store
+    pub fn new() -> MemoryAttachmentStore {
+        MemoryAttachmentStore {
+            payloads: HashMap::new(),
+        }
+    }
+
+    /// Adds `data` to the store, encoding it only if it isn't stored yet.
+    pub fn insert(&mut self, data: &[u8]) -> ContentHash {
+        let hash = ContentHash::of(data);
+        self.payloads
+            .entry(hash)
+            .or_insert_with(|| base64::encode(data));
+        hash
+    }
+
+    /// Returns true if a payload is stored under `hash`
+    pub fn contains(&self, hash: &ContentHash) -> bool {
+        self.payloads.contains_key(hash)
+    }
+
+    /// Removes the payload stored under `hash`
+    pub fn remove(&mut self, hash: &ContentHash) -> bool {
+        self.payloads.remove(hash).is_some()
+    }
+
+    /// Get the number of payloads within this store.
+    pub fn len(&self) -> usize {
+        self.payloads.len()
+    }
+
+    /// Returns true if there are no payloads in this store.
+    pub fn is_empty(&self) -> bool {
+        self.payloads.is_empty()
+    }
+}
+
+impl AttachmentStore for MemoryAttachmentStore {
+    fn get(&self, hash: &ContentHash) -> Option<&str> {
+        self.payloads.get(hash).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_deduplicates() {
+        let mut store = MemoryAttachmentStore::new();
+        let first = store.insert(b"report");
+        let second = store.insert(b"report");
+        assert_eq!(first, second);
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get(&first), Some("cmVwb3J0"));
+
+        assert!(store.remove(&first));
+        assert!(store.get(&first).is_none());
+        assert!(store.is_empty());
+    }
+}
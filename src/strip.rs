@@ -0,0 +1,178 @@
+use std::sync::Arc;
+
+use crate::body::{self, header_value};
+use crate::email_builder::text_part;
+use crate::messages::Catalog;
+use crate::{ContentHash, Messages, MimeMessage};
+
+/// Placeholder in `StripPolicy::location` replaced by the content hash
+const HASH_PLACEHOLDER: &str = "{hash}";
+
+/// Which attachments `MimeMessage::strip_attachments` removes, and where
+/// their content is said to be stored
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct StripPolicy {
+    max_size: usize,
+    location: Option<String>,
+    catalog: Option<Catalog>,
+}
+
+/// An attachment removed by `MimeMessage::strip_attachments`
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct RemovedAttachment {
+    /// The filename of the attachment, if it had one
+    pub filename: Option<String>,
+    /// The declared MIME type of the attachment
+    pub content_type: String,
+    /// The decoded size in bytes
+    pub size: usize,
+    /// The hash of the decoded content, e.g. to archive it under
+    pub hash: ContentHash,
+    /// Where the content is stored, as given in the placeholder
+    pub location: Option<String>,
+    /// The decoded content
+    pub content: Vec<u8>,
+}
+
+impl StripPolicy {
+    /// Removes attachments larger than `max_size` bytes, after decoding
+    pub fn new(max_size: usize) -> StripPolicy {
+        StripPolicy {
+            max_size,
+            location: None,
+            catalog: None,
+        }
+    }
+
+    /// Sets the location the placeholders point to, where `{hash}` is
+    /// replaced by the content hash, e.g.
+    /// `https://archive.example.org/{hash}`
+    pub fn location<S: Into<String>>(mut self, location: S) -> StripPolicy {
+        self.location = Some(location.into());
+        self
+    }
+
+    /// Sets the catalog the placeholder text is taken from
+    pub fn catalog<M: Messages + 'static>(mut self, messages: M) -> StripPolicy {
+        self.catalog = Some(Catalog(Arc::new(messages)));
+        self
+    }
+}
+
+/// Replaces the attachments within `part` exceeding the policy's size with
+/// placeholders
+pub(crate) fn strip_attachments(
+    part: &mut MimeMessage,
+    policy: &StripPolicy,
+    removed: &mut Vec<RemovedAttachment>,
+) {
+    if !part.children.is_empty() {
+        for child in part.children.iter_mut() {
+            strip_attachments(child, policy, removed);
+        }
+        return;
+    }
+    if !part.is_attachment() || part.body_ref.is_some() {
+        return;
+    }
+    let content = match body::decode_body(part) {
+        Some(content) if content.len() > policy.max_size => content,
+        _ => return,
+    };
+
+    let hash = ContentHash::of(&content);
+    let attachment = RemovedAttachment {
+        filename: filename(part),
+        content_type: body::mime_type(part),
+        size: content.len(),
+        hash,
+        location: policy
+            .location
+            .as_ref()
+            .map(|location| location.replace(HASH_PLACEHOLDER, &hash.to_string())),
+        content,
+    };
+    *part = text_part(Catalog::or_english(&policy.catalog).attachment_removed(
+        attachment.filename.as_deref(),
+        attachment.size,
+        attachment.location.as_deref(),
+    ));
+    removed.push(attachment);
+}
+
+fn filename(part: &MimeMessage) -> Option<String> {
+    header_value(part, "Content-Disposition")
+        .and_then(|value| {
+            mailparse::parse_content_disposition(&value)
+                .params
+                .remove("filename")
+        })
+        .or_else(|| {
+            header_value(part, "Content-Type")
+                .and_then(|value| mailparse::parse_content_type(&value).params.remove("name"))
+        })
+}
+
+pub(crate) fn format_size(size: usize) -> String {
+    const KB: usize = 1024;
+    const MB: usize = 1024 * KB;
+    if size >= MB {
+        format!("{:.1} MB", size as f64 / MB as f64)
+    } else if size >= KB {
+        format!("{:.1} KB", size as f64 / KB as f64)
+    } else {
+        format!("{} bytes", size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email_builder::attachment_part;
+    use crate::{MimeMultipartType, PartBuilder};
+
+    #[test]
+    fn test_strip_attachments() {
+        let large = vec![0; 3 * 1024 * 1024 / 2];
+        let mut message = PartBuilder::new()
+            .message_type(MimeMultipartType::Mixed)
+            .child(text_part("Hello"))
+            .child(attachment_part(b"small", "small.txt", &mime::TEXT_PLAIN))
+            .child(attachment_part(
+                &large,
+                "report.pdf",
+                &"application/pdf".parse().unwrap(),
+            ))
+            .build();
+
+        let removed = message.strip_attachments(
+            &StripPolicy::new(1024).location("https://archive.example.org/{hash}"),
+        );
+        assert_eq!(removed.len(), 1);
+        let removed = &removed[0];
+        assert_eq!(removed.filename.as_deref(), Some("report.pdf"));
+        assert_eq!(removed.content_type, "application/pdf");
+        assert_eq!(removed.content, large);
+        assert_eq!(
+            removed.location,
+            Some(format!("https://archive.example.org/{}", removed.hash))
+        );
+
+        assert_eq!(message.children.len(), 3);
+        assert!(message.children[1].is_attachment());
+        assert_eq!(
+            message.children[2].body,
+            format!(
+                "Attachment removed: report.pdf, 1.5 MB, stored at <https://archive.example.org/{}>",
+                removed.hash
+            )
+        );
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(12), "12 bytes");
+        assert_eq!(format_size(2048), "2.0 KB");
+        assert_eq!(format_size(12 * 1024 * 1024), "12.0 MB");
+    }
+}
@@ -0,0 +1,112 @@
+use std::fmt;
+
+/// Localized reply prefixes, lowercased
+const REPLY_PREFIXES: &[&str] = &["re", "aw", "sv", "antw", "odp"];
+
+/// Localized forward prefixes, lowercased
+const FORWARD_PREFIXES: &[&str] = &["fwd", "fw", "wg", "tr", "rv"];
+
+/// The kind of a subject prefix
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum SubjectPrefix {
+    /// `Re:` and its translations
+    Reply,
+    /// `Fwd:` and its translations
+    Forward,
+}
+
+impl SubjectPrefix {
+    /// The canonical form of the prefix, including the trailing space
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SubjectPrefix::Reply => "Re: ",
+            SubjectPrefix::Forward => "Fwd: ",
+        }
+    }
+}
+
+/// A subject split into a canonical prefix and the bare subject
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct NormalizedSubject<'a> {
+    /// The outermost prefix, if there was any
+    pub prefix: Option<SubjectPrefix>,
+    /// The subject without any prefixes, e.g. for threading
+    pub subject: &'a str,
+}
+
+impl<'a> fmt::Display for NormalizedSubject<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(prefix) = self.prefix {
+            fmt.write_str(prefix.as_str())?;
+        }
+        fmt.write_str(self.subject)
+    }
+}
+
+/// Strips all stacked and localized `Re:`/`Fwd:` prefixes like `AW:`,
+/// `SV:`, `WG:` or `Re[2]:` from `subject`, keeping the outermost one in
+/// canonical form.
+///
+/// Displaying the result yields e.g. `Re: Lunch` for `RE: AW: Re: Lunch`.
+pub fn normalize_subject_prefixes(subject: &str) -> NormalizedSubject<'_> {
+    let mut prefix = None;
+    let mut rest = subject.trim();
+    while let Some((kind, remaining)) = strip_prefix(rest) {
+        prefix = prefix.or(Some(kind));
+        rest = remaining;
+    }
+    NormalizedSubject {
+        prefix,
+        subject: rest,
+    }
+}
+
+/// Strips a single prefix, returning its kind and the remaining subject
+fn strip_prefix(subject: &str) -> Option<(SubjectPrefix, &str)> {
+    let colon = subject.find(':')?;
+    let mut word = subject[..colon].trim_end();
+    // Counters like `Re[2]:` or `Re(2):`
+    if word.ends_with(']') || word.ends_with(')') {
+        let open = word.rfind(&['[', '('][..])?;
+        if !word[open + 1..word.len() - 1]
+            .chars()
+            .all(|c| c.is_ascii_digit())
+        {
+            return None;
+        }
+        word = &word[..open];
+    }
+
+    let word = word.to_lowercase();
+    let kind = if REPLY_PREFIXES.contains(&word.as_str()) {
+        SubjectPrefix::Reply
+    } else if FORWARD_PREFIXES.contains(&word.as_str()) {
+        SubjectPrefix::Forward
+    } else {
+        return None;
+    };
+    Some((kind, subject[colon + 1..].trim_start()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_subject_prefixes() {
+        let normalized = normalize_subject_prefixes("RE: AW: Re[2]: SV:Lunch");
+        assert_eq!(normalized.prefix, Some(SubjectPrefix::Reply));
+        assert_eq!(normalized.subject, "Lunch");
+        assert_eq!(normalized.to_string(), "Re: Lunch");
+
+        assert_eq!(
+            normalize_subject_prefixes("WG: Re: Lunch").to_string(),
+            "Fwd: Lunch"
+        );
+        assert_eq!(
+            normalize_subject_prefixes(" Lunch: today ").to_string(),
+            "Lunch: today"
+        );
+        assert_eq!(normalize_subject_prefixes("Re[x]: Lunch").prefix, None);
+    }
+}
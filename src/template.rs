@@ -0,0 +1,56 @@
+use crate::email_builder::Error;
+
+/// Renders a body from a context of type `C`, e.g. a wrapper around a
+/// Handlebars or Tera template, for `EmailBuilder::text_template` and
+/// `EmailBuilder::html_template`.
+///
+/// Failures are reported as `Error::Template`.
+pub trait BodyTemplate<C: ?Sized> {
+    /// Returns the body rendered with `context`
+    fn render(&self, context: &C) -> Result<String, Error>;
+}
+
+impl<C: ?Sized, F> BodyTemplate<C> for F
+where
+    F: Fn(&C) -> Result<String, Error>,
+{
+    fn render(&self, context: &C) -> Result<String, Error> {
+        self(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmailBuilder;
+
+    struct Greeting(&'static str);
+
+    impl BodyTemplate<str> for Greeting {
+        fn render(&self, name: &str) -> Result<String, Error> {
+            if name.is_empty() {
+                return Err(Error::Template("missing name".to_string()));
+            }
+            Ok(self.0.replace("{{name}}", name))
+        }
+    }
+
+    #[test]
+    fn test_body_templates() {
+        let email = EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .text_template(&Greeting("Hello {{name}}"), "Jane")
+            .unwrap()
+            .html_template(&|name: &str| Ok(format!("<p>Hello {}</p>", name)), "Jane")
+            .unwrap()
+            .build()
+            .unwrap();
+        let email = email.message_to_string().unwrap();
+        assert!(email.contains("Hello Jane\r\n"));
+        assert!(email.contains("<p>Hello Jane</p>"));
+
+        let result = EmailBuilder::new().text_template(&Greeting("Hello {{name}}"), "");
+        assert!(matches!(result, Err(Error::Template(_))));
+    }
+}
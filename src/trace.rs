@@ -0,0 +1,389 @@
+use std::net::IpAddr;
+
+use time::OffsetDateTime;
+
+use crate::date::format_date;
+use crate::rfc5322::MIME_LINE_LENGTH;
+use crate::{ToFoldedHeader, ToHeader};
+
+/// The result of an SPF check (RFC 7208, section 2.6)
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum SpfResult {
+    /// No SPF record was found, or no domain could be checked
+    None,
+    /// The domain owner makes no assertion about the client
+    Neutral,
+    /// The client is authorized to use the domain
+    Pass,
+    /// The client is not authorized to use the domain
+    Fail,
+    /// The client is probably not authorized to use the domain
+    SoftFail,
+    /// A transient error, e.g. a DNS timeout, happened during the check
+    TempError,
+    /// The domain's SPF record couldn't be interpreted
+    PermError,
+}
+
+impl SpfResult {
+    /// The result as written in `Received-SPF` headers, e.g. `softfail`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SpfResult::None => "none",
+            SpfResult::Neutral => "neutral",
+            SpfResult::Pass => "pass",
+            SpfResult::Fail => "fail",
+            SpfResult::SoftFail => "softfail",
+            SpfResult::TempError => "temperror",
+            SpfResult::PermError => "permerror",
+        }
+    }
+}
+
+/// The value of a `Received-SPF` header (RFC 7208, section 9.1), which a
+/// receiving gateway adds to record the SPF check of a message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct ReceivedSpf {
+    result: SpfResult,
+    comment: Option<String>,
+    receiver: Option<String>,
+    client_ip: Option<IpAddr>,
+    envelope_from: Option<String>,
+    helo: Option<String>,
+    identity: Option<String>,
+}
+
+impl ReceivedSpf {
+    /// Creates a header recording `result`
+    pub fn new(result: SpfResult) -> ReceivedSpf {
+        ReceivedSpf {
+            result,
+            comment: None,
+            receiver: None,
+            client_ip: None,
+            envelope_from: None,
+            helo: None,
+            identity: None,
+        }
+    }
+
+    /// Sets the human readable comment following the result
+    pub fn comment<S: Into<String>>(mut self, comment: S) -> ReceivedSpf {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    /// Sets the host name of the receiver which did the check
+    pub fn receiver<S: Into<String>>(mut self, receiver: S) -> ReceivedSpf {
+        self.receiver = Some(receiver.into());
+        self
+    }
+
+    /// Sets the IP address of the SMTP client
+    pub fn client_ip(mut self, client_ip: IpAddr) -> ReceivedSpf {
+        self.client_ip = Some(client_ip);
+        self
+    }
+
+    /// Sets the envelope sender given in `MAIL FROM`
+    pub fn envelope_from<S: Into<String>>(mut self, envelope_from: S) -> ReceivedSpf {
+        self.envelope_from = Some(envelope_from.into());
+        self
+    }
+
+    /// Sets the host name given in `HELO` or `EHLO`
+    pub fn helo<S: Into<String>>(mut self, helo: S) -> ReceivedSpf {
+        self.helo = Some(helo.into());
+        self
+    }
+
+    /// Sets the identity which was checked, `mailfrom` or `helo`
+    pub fn identity<S: Into<String>>(mut self, identity: S) -> ReceivedSpf {
+        self.identity = Some(identity.into());
+        self
+    }
+}
+
+/// Fails if a value contains a line break
+impl ToHeader for ReceivedSpf {
+    type Error = ();
+
+    fn to_header(value: ReceivedSpf) -> Result<String, ()> {
+        let mut result = value.result.as_str().to_string();
+        if let Some(comment) = value.comment {
+            result.push_str(&format!(" ({})", escape(&comment, &['(', ')', '\\'])?));
+        }
+        let client_ip = value.client_ip.map(|ip| ip.to_string());
+        let pairs = [
+            ("receiver", value.receiver),
+            ("client-ip", client_ip),
+            ("envelope-from", value.envelope_from),
+            ("helo", value.helo),
+            ("identity", value.identity),
+        ];
+        let mut separator = " ";
+        for (key, value) in pairs.iter() {
+            if let Some(value) = value {
+                result.push_str(&format!("{}{}={}", separator, key, quote(value)?));
+                separator = "; ";
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// The value of an `X-Originating-IP` header, the address of the client
+/// which submitted a message, written in brackets
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct OriginatingIp(pub IpAddr);
+
+impl ToHeader for OriginatingIp {
+    type Error = ();
+
+    fn to_header(value: OriginatingIp) -> Result<String, ()> {
+        Ok(format!("[{}]", value.0))
+    }
+}
+
+/// The value of a `Received` trace header (RFC 5321, section 4.4), which
+/// every server relaying a message prepends
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Received {
+    from: Option<String>,
+    from_ip: Option<IpAddr>,
+    by: Option<String>,
+    via: Option<String>,
+    with: Option<String>,
+    id: Option<String>,
+    recipient: Option<String>,
+    timestamp: OffsetDateTime,
+}
+
+impl Received {
+    /// Creates a header for a message received at `timestamp`
+    pub fn new(timestamp: OffsetDateTime) -> Received {
+        Received {
+            from: None,
+            from_ip: None,
+            by: None,
+            via: None,
+            with: None,
+            id: None,
+            recipient: None,
+            timestamp,
+        }
+    }
+
+    /// Sets the host name the client gave in `HELO` or `EHLO`
+    pub fn from<S: Into<String>>(mut self, host: S) -> Received {
+        self.from = Some(host.into());
+        self
+    }
+
+    /// Sets the IP address the client connected from, written as a comment
+    /// after its host name
+    pub fn from_ip(mut self, ip: IpAddr) -> Received {
+        self.from_ip = Some(ip);
+        self
+    }
+
+    /// Sets the host name of the receiving server
+    pub fn by<S: Into<String>>(mut self, host: S) -> Received {
+        self.by = Some(host.into());
+        self
+    }
+
+    /// Sets the link the message was received over, e.g. `TCP`
+    pub fn via<S: Into<String>>(mut self, link: S) -> Received {
+        self.via = Some(link.into());
+        self
+    }
+
+    /// Sets the protocol the message was received with, e.g. `ESMTPS`
+    pub fn with<S: Into<String>>(mut self, protocol: S) -> Received {
+        self.with = Some(protocol.into());
+        self
+    }
+
+    /// Sets the receiving server's id of the message, e.g. its queue id
+    pub fn id<S: Into<String>>(mut self, id: S) -> Received {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Sets the envelope recipient the message was received for
+    pub fn recipient<S: Into<String>>(mut self, address: S) -> Received {
+        self.recipient = Some(address.into());
+        self
+    }
+}
+
+/// Each clause is kept on one line, folding between clauses. Fails if a
+/// value is empty or contains whitespace.
+impl ToFoldedHeader for Received {
+    type Error = ();
+
+    fn to_folded_header(start_pos: usize, value: Received) -> Result<String, ()> {
+        let token = |value: &str| {
+            if value.is_empty() || value.chars().any(|c| c.is_whitespace() || c.is_control()) {
+                Err(())
+            } else {
+                Ok(value.to_string())
+            }
+        };
+        let mut clauses = vec![];
+        if let Some(from) = value.from {
+            let mut clause = format!("from {}", token(&from)?);
+            if let Some(ip) = value.from_ip {
+                clause.push_str(&format!(" ([{}])", ip));
+            }
+            clauses.push(clause);
+        }
+        let fields = [
+            ("by", value.by),
+            ("via", value.via),
+            ("with", value.with),
+            ("id", value.id),
+        ];
+        for (name, field) in fields.iter() {
+            if let Some(field) = field {
+                clauses.push(format!("{} {}", name, token(field)?));
+            }
+        }
+        if let Some(recipient) = value.recipient {
+            clauses.push(format!("for <{}>", token(&recipient)?));
+        }
+        // The date follows the last clause after a semicolon
+        let date = format_date(&value.timestamp);
+        match clauses.last_mut() {
+            Some(last) => last.push(';'),
+            None => clauses.push(";".to_string()),
+        }
+
+        let mut result = String::new();
+        let mut line_len = start_pos;
+        for clause in clauses
+            .iter()
+            .map(String::as_str)
+            .chain(Some(date.as_str()))
+        {
+            if !result.is_empty() {
+                if line_len + 1 + clause.len() > MIME_LINE_LENGTH {
+                    result.push_str("\r\n\t");
+                    line_len = 1;
+                } else {
+                    result.push(' ');
+                    line_len += 1;
+                }
+            }
+            result.push_str(clause);
+            line_len += clause.len();
+        }
+        Ok(result)
+    }
+}
+
+/// Writes `value` as a dot-atom, or as a quoted-string if it isn't one
+fn quote(value: &str) -> Result<String, ()> {
+    let atom = !value.is_empty()
+        && !value.starts_with('.')
+        && !value.ends_with('.')
+        && !value.contains("..")
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~.".contains(c));
+    if atom {
+        Ok(value.to_string())
+    } else {
+        Ok(format!("\"{}\"", escape(value, &['"', '\\'])?))
+    }
+}
+
+/// Escapes `specials` within `value` with backslashes, failing on line
+/// breaks
+fn escape(value: &str, specials: &[char]) -> Result<String, ()> {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '\r' || c == '\n' {
+            return Err(());
+        }
+        if specials.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    Ok(escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Header;
+
+    #[test]
+    fn test_received_spf() {
+        let spf = ReceivedSpf::new(SpfResult::Pass)
+            .comment("mx.example.org: domain of joe@example.com designates 192.0.2.1 (mail)")
+            .receiver("mx.example.org")
+            .client_ip("192.0.2.1".parse().unwrap())
+            .envelope_from("joe@example.com")
+            .helo("mail.example.com")
+            .identity("mailfrom");
+        assert_eq!(
+            ToHeader::to_header(spf).unwrap(),
+            "pass (mx.example.org: domain of joe@example.com designates 192.0.2.1 \\(mail\\)) \
+             receiver=mx.example.org; client-ip=192.0.2.1; envelope-from=\"joe@example.com\"; \
+             helo=mail.example.com; identity=mailfrom"
+        );
+        assert_eq!(
+            ToHeader::to_header(ReceivedSpf::new(SpfResult::SoftFail)).unwrap(),
+            "softfail"
+        );
+        assert!(ToHeader::to_header(
+            ReceivedSpf::new(SpfResult::Fail).helo("evil\r\nBcc: victim@example.org")
+        )
+        .is_err());
+
+        let header = Header::new_with_value(
+            "X-Originating-IP".into(),
+            OriginatingIp("2001:db8::1".parse().unwrap()),
+        )
+        .unwrap();
+        assert_eq!(header.to_string(), "X-Originating-IP: [2001:db8::1]");
+    }
+    #[test]
+    fn test_received() {
+        let timestamp = crate::date::parse_date("Tue, 1 Sep 2020 12:00:00 +0000").unwrap();
+        let received = Received::new(timestamp)
+            .from("mail.example.com")
+            .from_ip("192.0.2.1".parse().unwrap())
+            .by("mx.example.org")
+            .with("ESMTPS")
+            .id("4BgX2k1Zz9")
+            .recipient("jane@example.org");
+        let header = Header::new_with_value("Received".into(), received).unwrap();
+        assert_eq!(
+            header.to_string(),
+            "Received: from mail.example.com ([192.0.2.1]) by mx.example.org with ESMTPS\r\n\
+             \tid 4BgX2k1Zz9 for <jane@example.org>; Tue, 01 Sep 2020 12:00:00 +0000"
+        );
+        assert_eq!(
+            crate::date::SkewChecker::new()
+                .check_at(
+                    &crate::PartBuilder::new()
+                        .header(header)
+                        .header(("Date", "Tue, 1 Sep 2020 11:59:00 +0000"))
+                        .build(),
+                    timestamp
+                )
+                .len(),
+            0
+        );
+
+        let header = Header::new_with_value("Received".into(), Received::new(timestamp)).unwrap();
+        assert_eq!(header.get_value(), "; Tue, 01 Sep 2020 12:00:00 +0000");
+        assert!(
+            Header::new_with_value("Received".into(), Received::new(timestamp).by("a b")).is_err()
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use mime::Mime;
+use uuid::Uuid;
+
+use crate::Error;
+
+/// An attachment whose content arrives in chunks, e.g. from a webmail
+/// client uploading a file while the user is still composing.
+///
+/// Chunks may arrive in any order and are buffered to disk. Once all of
+/// them are there, `EmailBuilder::chunked_attachment` assembles the file
+/// and attaches it by path. Chunk files left over from an upload which is
+/// dropped unfinished are removed.
+#[derive(Debug)]
+pub struct ChunkedUpload {
+    /// The directory the chunks and the assembled file are written to
+    dir: PathBuf,
+    /// Unique prefix of the files of this upload
+    id: String,
+    /// Filename of the attachment
+    pub(crate) filename: String,
+    /// Content type of the attachment
+    pub(crate) content_type: Mime,
+    /// Whether each chunk was received, by index
+    received: Vec<bool>,
+}
+
+impl ChunkedUpload {
+    /// Starts an upload of `total` chunks, buffered within `dir`
+    pub fn new<S: Into<String>>(
+        dir: &Path,
+        filename: S,
+        content_type: &Mime,
+        total: usize,
+    ) -> ChunkedUpload {
+        ChunkedUpload {
+            dir: dir.to_path_buf(),
+            id: Uuid::new_v4().to_simple().to_string(),
+            filename: filename.into(),
+            content_type: content_type.clone(),
+            received: vec![false; total],
+        }
+    }
+
+    /// Stores the chunk at `index`. A chunk received again, e.g. when the
+    /// client retries, replaces the earlier one.
+    pub fn add_chunk(&mut self, index: usize, data: &[u8]) -> Result<(), Error> {
+        if index >= self.received.len() {
+            return Err(Error::ChunkOutOfRange(index));
+        }
+        let chunk = self.chunk_path(index);
+        fs::write(&chunk, data).map_err(Error::file(&chunk))?;
+        self.received[index] = true;
+        Ok(())
+    }
+
+    /// Whether all chunks were received
+    pub fn is_complete(&self) -> bool {
+        self.received.iter().all(|&received| received)
+    }
+
+    /// The indices of the chunks not received yet
+    pub fn missing(&self) -> Vec<usize> {
+        (0..self.received.len())
+            .filter(|&index| !self.received[index])
+            .collect()
+    }
+
+    /// The path the chunks get assembled into by
+    /// `EmailBuilder::chunked_attachment`. The file is kept for building,
+    /// so remove it once the message is sent.
+    pub fn assembled_path(&self) -> PathBuf {
+        self.dir.join(&self.id)
+    }
+
+    /// Concatenates the chunks into a single file, returning its path
+    pub(crate) fn assemble(&mut self) -> Result<PathBuf, Error> {
+        let missing = self.missing();
+        if !missing.is_empty() {
+            return Err(Error::IncompleteUpload(missing));
+        }
+        let path = self.assembled_path();
+        let mut file = File::create(&path).map_err(Error::file(&path))?;
+        for index in 0..self.received.len() {
+            let chunk = self.chunk_path(index);
+            let mut chunk_file = File::open(&chunk).map_err(Error::file(&chunk))?;
+            io::copy(&mut chunk_file, &mut file).map_err(Error::file(&path))?;
+        }
+        self.remove_chunks();
+        Ok(path)
+    }
+
+    fn chunk_path(&self, index: usize) -> PathBuf {
+        self.dir.join(format!("{}.{}.part", self.id, index))
+    }
+
+    fn remove_chunks(&mut self) {
+        for index in 0..self.received.len() {
+            if self.received[index] {
+                let _ = fs::remove_file(self.chunk_path(index));
+                self.received[index] = false;
+            }
+        }
+    }
+}
+
+impl Drop for ChunkedUpload {
+    fn drop(&mut self) {
+        self.remove_chunks();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmailBuilder;
+
+    #[test]
+    fn test_chunked_attachment() {
+        let dir = std::env::temp_dir();
+        let mut upload = ChunkedUpload::new(&dir, "notes.txt", &mime::TEXT_PLAIN, 3);
+        upload.add_chunk(2, b"three").unwrap();
+        upload.add_chunk(0, b"one, ").unwrap();
+        assert!(!upload.is_complete());
+        assert_eq!(upload.missing(), vec![1]);
+        assert!(matches!(
+            upload.add_chunk(3, b"four"),
+            Err(Error::ChunkOutOfRange(3))
+        ));
+        upload.add_chunk(1, b"two, ").unwrap();
+        assert!(upload.is_complete());
+        let chunk = upload.chunk_path(1);
+        let assembled = upload.assembled_path();
+
+        let email = EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .body("See attached")
+            .chunked_attachment(upload)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(!chunk.exists());
+        let message = email.message_to_string().unwrap();
+        assert!(message.contains("filename=\"notes.txt\""));
+        assert!(message.contains(&base64::encode("one, two, three")));
+        fs::remove_file(assembled).unwrap();
+
+        let mut upload = ChunkedUpload::new(&dir, "notes.txt", &mime::TEXT_PLAIN, 2);
+        upload.add_chunk(1, b"two").unwrap();
+        let chunk = upload.chunk_path(1);
+        assert!(matches!(
+            EmailBuilder::new().chunked_attachment(upload),
+            Err(Error::IncompleteUpload(missing)) if missing == vec![0]
+        ));
+        assert!(!chunk.exists());
+    }
+}
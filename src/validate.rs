@@ -0,0 +1,242 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Headers which may appear at most once (RFC 5322, section 3.6)
+const SINGLETON_HEADERS: &[&str] = &[
+    "Date",
+    "From",
+    "Sender",
+    "Reply-To",
+    "To",
+    "Cc",
+    "Bcc",
+    "Message-ID",
+    "In-Reply-To",
+    "References",
+    "Subject",
+];
+
+/// Maximum length of a line without the CRLF (RFC 5322, section 2.1.1)
+const MAX_LINE_LENGTH: usize = 998;
+
+lazy_static! {
+    static ref BOUNDARY_RE: Regex =
+        Regex::new(r#"(?i)\bboundary=(?:"([^"]+)"|([^;\s"]+))"#).unwrap();
+}
+
+/// A violation of RFC 5322 or RFC 2045 found by `Email::validate`.
+///
+/// Lines are numbered from 1, counting from the start of the message.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum ValidationIssue {
+    /// There is no `Date` header
+    MissingDate,
+    /// A header which may only appear once appears more often, with its
+    /// name
+    DuplicateHeader(String),
+    /// A part declared as 7bit contains 8-bit data, at the first such line
+    EightBitWithoutEncoding {
+        /// The line with 8-bit data
+        line: usize,
+    },
+    /// A line longer than 998 characters
+    LineTooLong {
+        /// The long line
+        line: usize,
+        /// Its length without the line break
+        length: usize,
+    },
+    /// A line ending in LF without CR
+    BareLineFeed {
+        /// The line ending in a bare LF
+        line: usize,
+    },
+}
+
+/// Checks the serialized `message` for common violations
+pub(crate) fn validate(message: &[u8]) -> Vec<ValidationIssue> {
+    let mut issues = vec![];
+    let mut lines = vec![];
+    let mut rest = message;
+    while !rest.is_empty() {
+        let (line, next) = match rest.iter().position(|&byte| byte == b'\n') {
+            Some(position) => (&rest[..position], &rest[position + 1..]),
+            None => (rest, &rest[rest.len()..]),
+        };
+        let line = match line.split_last() {
+            Some((b'\r', line)) => line,
+            _ => {
+                if line.len() < rest.len() {
+                    issues.push(ValidationIssue::BareLineFeed {
+                        line: lines.len() + 1,
+                    });
+                }
+                line
+            }
+        };
+        if line.len() > MAX_LINE_LENGTH {
+            issues.push(ValidationIssue::LineTooLong {
+                line: lines.len() + 1,
+                length: line.len(),
+            });
+        }
+        lines.push(line);
+        rest = next;
+    }
+
+    let headers = headers(&lines, 0);
+    if !headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("Date"))
+    {
+        issues.push(ValidationIssue::MissingDate);
+    }
+    for singleton in SINGLETON_HEADERS {
+        let found = headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case(singleton))
+            .count();
+        if found > 1 {
+            issues.push(ValidationIssue::DuplicateHeader(singleton.to_string()));
+        }
+    }
+    check_part(&lines, 0, &mut issues);
+    issues
+}
+
+/// The unfolded headers of the part starting at `start`
+fn headers(lines: &[&[u8]], start: usize) -> Vec<(String, String)> {
+    let mut headers: Vec<(String, String)> = vec![];
+    for line in lines[start..].iter() {
+        if line.is_empty() {
+            break;
+        }
+        let line = String::from_utf8_lossy(line);
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(last) = headers.last_mut() {
+                last.1.push_str(&line);
+            }
+        } else if let Some(colon) = line.find(':') {
+            headers.push((
+                line[..colon].trim().to_string(),
+                line[colon + 1..].to_string(),
+            ));
+        }
+    }
+    headers
+}
+
+/// Checks the part within `lines` starting at `start` for 8-bit data
+/// without a transfer encoding, recursing into multiparts
+fn check_part(lines: &[&[u8]], start: usize, issues: &mut Vec<ValidationIssue>) {
+    let part = &lines[start..];
+    let headers = headers(lines, start);
+    let body = start
+        + part
+            .iter()
+            .position(|line| line.is_empty())
+            .map_or(part.len(), |position| position + 1);
+    let value = |name: &str| {
+        headers
+            .iter()
+            .find(|(found, _)| found.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim().to_string())
+    };
+
+    let boundary = value("Content-Type")
+        .filter(|content_type| content_type.to_ascii_lowercase().starts_with("multipart/"))
+        .and_then(|content_type| {
+            BOUNDARY_RE.captures(&content_type).map(|caps| {
+                caps.get(1)
+                    .or_else(|| caps.get(2))
+                    .unwrap()
+                    .as_str()
+                    .to_string()
+            })
+        });
+    if let Some(boundary) = boundary {
+        let delimiter = format!("--{}", boundary);
+        let close = format!("--{}--", boundary);
+        let mut child: Option<usize> = None;
+        for index in body..lines.len() {
+            let line = String::from_utf8_lossy(lines[index]);
+            let line = line.trim_end();
+            if line == delimiter || line == close {
+                if let Some(child) = child {
+                    check_part(&lines[..index], child, issues);
+                }
+                if line == close {
+                    return;
+                }
+                child = Some(index + 1);
+            }
+        }
+        return;
+    }
+
+    let encoding = value("Content-Transfer-Encoding")
+        .unwrap_or_else(|| "7bit".to_string())
+        .to_ascii_lowercase();
+    if encoding != "7bit" {
+        return;
+    }
+    if let Some(position) = lines[body..]
+        .iter()
+        .position(|line| line.iter().any(|&byte| byte >= 0x80))
+    {
+        issues.push(ValidationIssue::EightBitWithoutEncoding {
+            line: body + position + 1,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmailBuilder;
+
+    #[test]
+    fn test_validate() {
+        let issues = EmailBuilder::new()
+            .from("joe@example.org")
+            .to("jane@example.org")
+            .subject("Hello")
+            .header(("Subject", "Hello again"))
+            .alternative("<p>Hello</p>", "Hello")
+            .validate()
+            .unwrap();
+        assert_eq!(
+            issues,
+            vec![ValidationIssue::DuplicateHeader("Subject".to_string())]
+        );
+
+        let long = "a".repeat(999);
+        let message = format!(
+            "From: joe@example.org\r\n\
+             Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+             \r\n\
+             --b\r\n\
+             Content-Transfer-Encoding: 8bit\r\n\
+             \r\n\
+             Grüße\r\n\
+             --b\r\n\
+             \r\n\
+             {}\r\n\
+             Grüße\n\
+             --b--\r\n",
+            long
+        );
+        assert_eq!(
+            validate(message.as_bytes()),
+            vec![
+                ValidationIssue::LineTooLong {
+                    line: 10,
+                    length: 999
+                },
+                ValidationIssue::BareLineFeed { line: 11 },
+                ValidationIssue::MissingDate,
+                ValidationIssue::EightBitWithoutEncoding { line: 11 },
+            ]
+        );
+    }
+}
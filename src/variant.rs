@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::body::escape_html;
+use crate::email::Email;
+use crate::email_builder::{alternative_part, html_part, text_part, EmailBuilder, Error};
+use crate::Mailbox;
+
+/// Content which differs between the variants of a `VariantSet`
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct Variant {
+    /// Replaces the subject of the base builder
+    subject: Option<String>,
+    /// HTML body
+    html: Option<String>,
+    /// Plain text body
+    text: Option<String>,
+}
+
+impl Variant {
+    /// Creates a variant which doesn't change anything
+    pub fn new() -> Variant {
+        Variant::default()
+    }
+
+    /// Sets the subject of this variant
+    pub fn subject<S: Into<String>>(mut self, subject: S) -> Variant {
+        self.subject = Some(subject.into());
+        self
+    }
+
+    /// Sets the HTML body of this variant
+    pub fn html<S: Into<String>>(mut self, html: S) -> Variant {
+        self.html = Some(html.into());
+        self
+    }
+
+    /// Sets the plain text body of this variant
+    pub fn text<S: Into<String>>(mut self, text: S) -> Variant {
+        self.text = Some(text.into());
+        self
+    }
+}
+
+/// Placeholder in variant bodies replaced by the recipient's unsubscribe URL
+pub const UNSUBSCRIBE_URL_PLACEHOLDER: &str = "{{unsubscribe_url}}";
+
+/// Generates the unsubscribe URL of a recipient
+#[derive(Clone)]
+struct UnsubscribeHook(Arc<dyn Fn(&Mailbox) -> String + Send + Sync>);
+
+impl fmt::Debug for UnsubscribeHook {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("UnsubscribeHook")
+    }
+}
+
+/// Named variants of a message built from a shared base, e.g. for A/B
+/// testing.
+///
+/// Everything set on the base builder, including attachments, is encoded
+/// once and shared between all rendered emails.
+#[derive(Clone, Debug)]
+pub struct VariantSet {
+    base: EmailBuilder,
+    variants: BTreeMap<String, Variant>,
+    unsubscribe: Option<UnsubscribeHook>,
+}
+
+impl VariantSet {
+    /// Creates a set without variants on top of `base`
+    pub fn new(base: EmailBuilder) -> VariantSet {
+        VariantSet {
+            base,
+            variants: BTreeMap::new(),
+            unsubscribe: None,
+        }
+    }
+
+    /// Sets a hook generating a per-recipient unsubscribe URL.
+    ///
+    /// When rendering, the URL is emitted in the `List-Unsubscribe` header
+    /// and replaces every `UNSUBSCRIBE_URL_PLACEHOLDER` in the variant's
+    /// text and HTML bodies, so header and body links always agree.
+    pub fn unsubscribe_url<F>(mut self, hook: F) -> VariantSet
+    where
+        F: Fn(&Mailbox) -> String + Send + Sync + 'static,
+    {
+        self.unsubscribe = Some(UnsubscribeHook(Arc::new(hook)));
+        self
+    }
+
+    /// Adds or replaces the variant called `key`
+    pub fn variant<S: Into<String>>(mut self, key: S, variant: Variant) -> VariantSet {
+        self.variants.insert(key.into(), variant);
+        self
+    }
+
+    /// The keys of all variants, in order
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.variants.keys().map(String::as_str)
+    }
+
+    /// Builds the variant called `variant_key` addressed to `recipient`
+    pub fn render<A: Into<Mailbox>>(
+        &self,
+        recipient: A,
+        variant_key: &str,
+    ) -> Result<Email, Error> {
+        let variant = self
+            .variants
+            .get(variant_key)
+            .ok_or_else(|| Error::UnknownVariant(variant_key.to_string()))?;
+
+        let recipient = recipient.into();
+        let mut html = variant.html.clone();
+        let mut text = variant.text.clone();
+        let mut builder = self.base.clone();
+        if let Some(UnsubscribeHook(ref hook)) = self.unsubscribe {
+            let url = hook(&recipient);
+            html = html.map(|html| html.replace(UNSUBSCRIBE_URL_PLACEHOLDER, &escape_html(&url)));
+            text = text.map(|text| text.replace(UNSUBSCRIBE_URL_PLACEHOLDER, &url));
+            builder = builder.replace_header(("List-Unsubscribe", format!("<{}>", url)));
+        }
+
+        builder = builder.to(recipient);
+        if let Some(ref subject) = variant.subject {
+            builder = builder.replace_header(("Subject", subject.as_str()));
+        }
+        // The variant content goes in front of the shared parts.
+        builder = match (&html, &text) {
+            (Some(html), Some(text)) => builder.prepend_child(alternative_part(html, text)),
+            (Some(html), None) => builder.prepend_child(html_part(html)),
+            (None, Some(text)) => builder.prepend_child(text_part(text)),
+            (None, None) => builder,
+        };
+
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant_set() -> VariantSet {
+        let base = EmailBuilder::new()
+            .from("news@example.org")
+            .subject("Base")
+            .attachment(b"shared", "shared.txt", &mime::TEXT_PLAIN)
+            .unwrap();
+        VariantSet::new(base)
+            .variant("a", Variant::new().subject("Subject A").html("<p>A</p>"))
+            .variant("b", Variant::new().text("B"))
+    }
+
+    #[test]
+    fn test_render_variants() {
+        let set = variant_set();
+        assert_eq!(set.keys().collect::<Vec<_>>(), vec!["a", "b"]);
+
+        let a = set.render("joe@example.org", "a").unwrap();
+        let a = a.message_to_string().unwrap();
+        assert!(a.contains("Subject: Subject A\r\n"));
+        assert!(!a.contains("Subject: Base"));
+        assert!(a.contains("To: <joe@example.org>\r\n"));
+        assert!(a.find("<p>A</p>").unwrap() < a.find("c2hhcmVk").unwrap());
+
+        let b = set.render("john@example.org", "b").unwrap();
+        let b = b.message_to_string().unwrap();
+        assert!(b.contains("Subject: Base\r\n"));
+        assert!(b.contains("text/plain; charset=utf-8\r\n\r\nB\r\n"));
+        assert!(b.contains("c2hhcmVk"));
+    }
+
+    #[test]
+    fn test_render_unsubscribe_url() {
+        let set = VariantSet::new(EmailBuilder::new().from("news@example.org"))
+            .variant(
+                "a",
+                Variant::new()
+                    .html("<a href=\"{{unsubscribe_url}}\">Unsubscribe</a>")
+                    .text("Unsubscribe: {{unsubscribe_url}}"),
+            )
+            .unsubscribe_url(|recipient| {
+                format!("https://example.org/u?r={}&t=1", recipient.address)
+            });
+
+        let email = set.render("joe@example.org", "a").unwrap();
+        let email = email.message_to_string().unwrap();
+        assert!(
+            email.contains("List-Unsubscribe: <https://example.org/u?r=joe@example.org&t=1>\r\n")
+        );
+        assert!(email.contains("<a href=\"https://example.org/u?r=joe@example.org&amp;t=1\">"));
+        assert!(email.contains("Unsubscribe: https://example.org/u?r=joe@example.org&t=1\r\n"));
+    }
+
+    #[test]
+    fn test_render_unknown_variant() {
+        assert!(matches!(
+            variant_set().render("joe@example.org", "c"),
+            Err(Error::UnknownVariant(key)) if key == "c"
+        ));
+    }
+}